@@ -0,0 +1,83 @@
+// Datagen game adjudication
+// Games are cut short once the outcome is clear, most of the time spent in decided or dead
+// drawn positions would otherwise only produce more of the same data.
+use engine::api::{adjudicate_scores, AdjudicationRules, GameResult};
+
+use cozy_chess::{Color, GameStatus};
+
+// Win adjudication: the score stays beyond the threshold for this many consecutive plies,
+// so both sides agree on it.
+const WIN_SCORE: i32 = 2000;
+const WIN_PLIES: u32 = 8;
+
+// Draw adjudication: past the opening, the score stays near zero for this many consecutive plies
+const DRAW_SCORE: i32 = 10;
+const DRAW_PLIES: u32 = 12;
+const DRAW_MIN_PLY: u32 = 80;
+
+// Games reaching this many plies are adjudicated as draws
+const MAX_PLIES: u32 = 400;
+
+const RULES: AdjudicationRules = AdjudicationRules {
+    resign_score: WIN_SCORE,
+    resign_plies: WIN_PLIES as usize,
+    draw_score: DRAW_SCORE,
+    draw_plies: DRAW_PLIES as usize,
+    draw_min_ply: DRAW_MIN_PLY as usize,
+    max_plies: MAX_PLIES as usize,
+};
+
+#[derive(Default)]
+pub struct Adjudicator {
+    scores: Vec<i32>,
+}
+
+impl Adjudicator {
+    /// Records the white relative score of the next ply, returning the adjudicated result if any
+    pub fn update(&mut self, white_score: i32) -> Option<(GameStatus, Option<Color>)> {
+        self.scores.push(white_score);
+
+        adjudicate_scores(&self.scores, &RULES).map(|(result, _)| match result {
+            GameResult::WhiteWins => (GameStatus::Won, Some(Color::White)),
+            GameResult::BlackWins => (GameStatus::Won, Some(Color::Black)),
+            GameResult::Draw => (GameStatus::Drawn, None),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjudication() {
+        let mut adj = Adjudicator::default();
+        for _ in 1..WIN_PLIES {
+            assert_eq!(adj.update(-WIN_SCORE), None);
+        }
+        assert_eq!(
+            adj.update(-WIN_SCORE),
+            Some((GameStatus::Won, Some(Color::Black)))
+        );
+
+        // A single disagreeing ply resets the count
+        let mut adj = Adjudicator::default();
+        for ply in 0..2 * WIN_PLIES {
+            let score = if ply == WIN_PLIES - 1 { 0 } else { WIN_SCORE };
+            assert_eq!(adj.update(score).is_some(), ply == 2 * WIN_PLIES - 1);
+        }
+
+        // Dead draws are only adjudicated past the opening
+        let mut adj = Adjudicator::default();
+        for _ in 1..DRAW_MIN_PLY {
+            assert_eq!(adj.update(0), None);
+        }
+        assert_eq!(adj.update(0), Some((GameStatus::Drawn, None)));
+
+        let mut adj = Adjudicator::default();
+        for ply in 1..=MAX_PLIES {
+            let result = adj.update(if ply.is_multiple_of(2) { 100 } else { -100 });
+            assert_eq!(result.is_some(), ply == MAX_PLIES);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// Bulletformat
+// The 32 byte position record read directly by the bullet trainer. Everything is stored from
+// the side to move's perspective: the board is flipped vertically for black, and the score and
+// result are negated accordingly.
+//
+//  occupancy: u64      all occupied squares
+//  pieces:    [u8; 16] one nibble per occupied square in ascending order,
+//                      piece type (0-5, pawn to king) with bit 3 set for the opponent
+//  score:     i16      centipawns
+//  result:    u8       0 loss, 1 draw, 2 win
+//  king:      u8       our king square
+//  opp_king:  u8       opponent king square, flipped
+//  extra:     [u8; 3]  unused
+use cozy_chess::{Board, Color, Piece};
+
+pub const RECORD_SIZE: usize = 32;
+
+/// Packs a position with its white relative score and result (1.0 white win, 0.5 draw, 0.0 black win)
+#[must_use]
+pub fn pack(board: &Board, score: i32, result: f32) -> [u8; RECORD_SIZE] {
+    let stm = board.side_to_move();
+    let flip = |bb: u64| {
+        if stm == Color::White {
+            bb
+        } else {
+            bb.swap_bytes()
+        }
+    };
+
+    let ours = flip(board.colors(stm).0);
+    let theirs = flip(board.colors(!stm).0);
+    let pieces = Piece::ALL.map(|p| flip(board.pieces(p).0));
+
+    let occupancy = ours | theirs;
+    let mut nibbles = [0u8; 16];
+    let mut occ = occupancy;
+    let mut idx = 0;
+    while occ != 0 {
+        let bit = occ & occ.wrapping_neg();
+        occ &= occ - 1;
+
+        let piece = pieces.iter().position(|&bb| bb & bit != 0).unwrap() as u8;
+        let colour = u8::from(theirs & bit != 0) << 3;
+        nibbles[idx / 2] |= (colour | piece) << (4 * (idx & 1));
+        idx += 1;
+    }
+
+    let kings = pieces[Piece::King as usize];
+    let (score, result) = if stm == Color::White {
+        (score, result)
+    } else {
+        (-score, 1. - result)
+    };
+    let score = score.clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16;
+
+    let mut record = [0u8; RECORD_SIZE];
+    record[0..8].copy_from_slice(&occupancy.to_le_bytes());
+    record[8..24].copy_from_slice(&nibbles);
+    record[24..26].copy_from_slice(&score.to_le_bytes());
+    record[26] = (2. * result) as u8;
+    record[27] = (kings & ours).trailing_zeros() as u8;
+    record[28] = (kings & theirs).trailing_zeros() as u8 ^ 56;
+
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulletformat_pack() {
+        let board = Board::default();
+        let record = pack(&board, 35, 1.);
+
+        assert_eq!(&record[0..8], &0xFFFF_0000_0000_FFFFu64.to_le_bytes());
+        // a1 rook, b1 knight for us, a8 rook for the opponent
+        assert_eq!(record[8], 0x13);
+        assert_eq!(record[23] >> 4, 0x8 | 3);
+        assert_eq!(i16::from_le_bytes([record[24], record[25]]), 35);
+        assert_eq!(record[26], 2);
+        assert_eq!(record[27], 4);
+        assert_eq!(record[28], 4);
+
+        // Black to move sees the mirrored position with everything negated
+        let board = Board::from_fen(
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+            false,
+        )
+        .unwrap();
+        let record = pack(&board, 35, 1.);
+
+        assert_eq!(i16::from_le_bytes([record[24], record[25]]), -35);
+        assert_eq!(record[26], 0);
+        assert_eq!(
+            u64::from_le_bytes(record[0..8].try_into().unwrap()) >> 16 & 0xFF,
+            0
+        );
+        assert_eq!(record[8], 0x13);
+    }
+}
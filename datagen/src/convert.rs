@@ -0,0 +1,142 @@
+// PGN to training data conversion
+// Positions are labelled with the game's `[%eval ...]` annotations, or with a fixed-node search
+// of each position when --nodes is given, and written in one of the per-position formats.
+use crate::{
+    filter::Filter,
+    script::{write_record, OutputFormat},
+};
+
+use engine::{
+    body::{history::History, nnue::inference::NNUEState, search::Search, tt::TT},
+    uci::{handler::SearchType, pgn::PgnReader},
+};
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use cozy_chess::{Board, Color};
+
+const USAGE: &str = "usage: datagen convert <pgn> [--output <file>] [--format text|bullet] \
+[--nodes <n>] [--filter \"<settings>\"]";
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Some(input) = args.first() else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+
+    let mut output: Option<PathBuf> = None;
+    let mut format = OutputFormat::Text;
+    let mut nodes: Option<u64> = None;
+    let mut filter = Filter::default();
+
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let Some(value) = rest.next() else {
+            println!("Missing value for {flag}\n{USAGE}");
+            return Ok(());
+        };
+
+        match flag.as_str() {
+            "--output" => output = Some(PathBuf::from(value)),
+            "--format" => {
+                format = match OutputFormat::parse(value) {
+                    Some(OutputFormat::Viri) | None => {
+                        println!(
+                            "Invalid format {value}, conversion writes text or bullet records"
+                        );
+                        return Ok(());
+                    }
+                    Some(f) => f,
+                };
+            }
+            "--nodes" => nodes = Some(value.parse()?),
+            "--filter" => filter = Filter::parse(value)?,
+            _ => {
+                println!("Unknown argument {flag}\n{USAGE}");
+                return Ok(());
+            }
+        }
+    }
+
+    let output = output.unwrap_or_else(|| PathBuf::from(input).with_extension(format.extension()));
+    let reader = PgnReader::new(BufReader::new(File::open(input)?));
+    let mut out = BufWriter::new(File::create(&output)?);
+
+    let tt = TT::new(16);
+    let nnue = NNUEState::from_board(&Board::default());
+    let history = History::new();
+    let mut search = nodes.map(|_| Search::new(&tt, nnue, &history, &vec![]));
+
+    let (mut games, mut skipped, mut positions) = (0, 0, 0);
+    for game in reader {
+        let Ok(game) = game? else {
+            skipped += 1;
+            continue;
+        };
+        // Unfinished games have no result to train on
+        let Some(result) = game.result else {
+            skipped += 1;
+            continue;
+        };
+        games += 1;
+
+        if let Some(search) = &mut search {
+            search.game_reset();
+        }
+
+        let mut board = game.start.clone();
+        let mut hashes = vec![board.hash()];
+        for (ply, &(mv, _)) in game.moves.iter().enumerate() {
+            // White relative score of this position, and the move considered best in it
+            let label = match (&mut search, nodes) {
+                (Some(search), Some(nodes)) => {
+                    search.go_reset();
+                    search.info.game_history = hashes.clone();
+                    search.nnue.refresh(&board);
+
+                    let (score, best_move) = search.data_search(&board, SearchType::Nodes(nodes));
+                    let score = if board.side_to_move() == Color::White {
+                        score
+                    } else {
+                        -score
+                    };
+                    Some((score, best_move))
+                }
+                // The annotation on the previous move evaluates the position it led to
+                _ => ply
+                    .checked_sub(1)
+                    .and_then(|prev| game.moves[prev].1)
+                    .map(|eval| (eval, mv)),
+            };
+
+            if let Some((score, best_move)) = label {
+                let stm_score = if board.side_to_move() == Color::White {
+                    score
+                } else {
+                    -score
+                };
+
+                if filter.keep(&board, best_move, stm_score) {
+                    write_record(&mut out, format, &board, score, result)?;
+                    positions += 1;
+                }
+            }
+
+            board.play_unchecked(mv);
+            hashes.push(board.hash());
+        }
+    }
+    out.flush()?;
+
+    println!(
+        "Converted {games} games into {positions} positions in {}, skipped {skipped} games",
+        output.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+// Position filtering for per-position datagen output
+// Positions whose label doesn't reflect a quiet static evaluation make for noisy training data.
+// Filters are configured as a list of key=value pairs, e.g. `min_ply=16 max_score=3000 noisy=off`.
+use engine::{body::position::is_quiet, definitions::KNOWN_WIN};
+
+use cozy_chess::{Board, Color, Move};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter {
+    // Skip positions where the side to move is in check
+    pub in_check: bool,
+    // Skip positions where the best move is a capture or a promotion
+    pub noisy: bool,
+    // Skip positions before this game ply, counted from the start position
+    pub min_ply: u32,
+    // Skip positions scored at or beyond this, mates and won endgames aren't evaluated by the net
+    pub max_score: i32,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            in_check: true,
+            noisy: true,
+            min_ply: 16,
+            max_score: KNOWN_WIN,
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(config: &str) -> Result<Self, String> {
+        let mut filter = Self::default();
+
+        for pair in config.split_whitespace() {
+            let invalid = || format!("Invalid filter setting: {pair}");
+            let (key, value) = pair.split_once('=').ok_or_else(invalid)?;
+            let switch = || match value {
+                "on" => Ok(true),
+                "off" => Ok(false),
+                _ => Err(invalid()),
+            };
+
+            match key {
+                "check" => filter.in_check = switch()?,
+                "noisy" => filter.noisy = switch()?,
+                "min_ply" => filter.min_ply = value.parse().map_err(|_| invalid())?,
+                "max_score" => filter.max_score = value.parse().map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    #[must_use]
+    pub fn keep(&self, board: &Board, best_move: Move, score: i32) -> bool {
+        let ply = 2 * (u32::from(board.fullmove_number()) - 1)
+            + u32::from(board.side_to_move() == Color::Black);
+
+        (!self.in_check || board.checkers().is_empty())
+            && (!self.noisy || is_quiet(board, best_move))
+            && ply >= self.min_ply
+            && score.abs() < self.max_score
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let switch = |on: bool| if on { "on" } else { "off" };
+        write!(
+            f,
+            "check={} noisy={} min_ply={} max_score={}",
+            switch(self.in_check),
+            switch(self.noisy),
+            self.min_ply,
+            self.max_score
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_filter() {
+        let filter = Filter::parse("min_ply=2 max_score=3000").unwrap();
+        assert_eq!(Filter::parse(&filter.to_string()), Ok(filter));
+        assert!(Filter::parse("check=maybe").is_err());
+        assert!(Filter::parse("depth=3").is_err());
+
+        let board = Board::from_fen(
+            "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+            false,
+        )
+        .unwrap();
+        let quiet = "g1f3".parse().unwrap();
+        let capture = "e4d5".parse().unwrap();
+
+        assert!(filter.keep(&board, quiet, 0));
+        assert!(!filter.keep(&board, capture, 0));
+        assert!(!filter.keep(&board, quiet, 3000));
+        assert!(!Filter::default().keep(&board, quiet, 0));
+
+        let off = Filter::parse("noisy=off min_ply=0").unwrap();
+        assert!(off.keep(&board, capture, 0));
+
+        let check = Board::from_fen(
+            "rnbqkbnr/ppp1pppp/8/1B1p4/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 1 2",
+            false,
+        )
+        .unwrap();
+        assert!(!off.keep(&check, "c7c6".parse().unwrap(), 0));
+    }
+}
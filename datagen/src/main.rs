@@ -1,10 +1,17 @@
+mod adjudication;
+mod bulletformat;
+mod convert;
+mod filter;
+mod matches;
 mod script;
+mod sprt;
 mod tables;
+mod viriformat;
 
 fn main() {
     #![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
-    let binding = std::env::args().nth(1);
-    let arg = binding.as_deref();
+    let args: Vec<String> = std::env::args().collect();
+    let arg = args.get(1).map(String::as_str);
     match arg {
         Some("table") => {
             tables::print_net_history();
@@ -14,6 +21,14 @@ fn main() {
             script::root().unwrap();
             return;
         }
+        Some("convert") => {
+            convert::run(&args[2..]).unwrap();
+            return;
+        }
+        Some("match") => {
+            matches::run(&args[2..]).unwrap();
+            return;
+        }
         _ => {}
     }
 
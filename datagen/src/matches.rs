@@ -0,0 +1,654 @@
+// Engine-vs-engine match runner
+// Plays game pairs between two UCI engines (or two option sets of the same engine) from an
+// opening book, reporting the Elo difference and stopping early once the SPRT is decided. Both
+// go by the pentanomial statistics of the pairs once there are any.
+// Time controls take increments and movestogo cycles, and can differ between the engines for
+// time odds. Engines running out of time lose the game and are counted apart.
+// With --nodes every move is searched to that many nodes instead, on a single thread, and the book
+// is shuffled with a fixed seed unless given one: the whole match plays out the same every time.
+use crate::sprt::{Decision, Estimate, Pentanomial, Score, Sprt};
+
+use engine::{
+    api::{game_over, GameResult},
+    uci::{genfens::read_book, handler::check_castling_move},
+};
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use cozy_chess::{Board, Color, Move};
+
+// Engines are allowed to overstep their clock by this many milliseconds
+const TIME_MARGIN: i64 = 10;
+
+// Book order of fixed-node matches without a seed
+const FIXED_SEED: u64 = 0;
+
+const DEFAULT_TC: TimeControl = TimeControl {
+    moves: None,
+    base: 8000,
+    inc: 80,
+};
+
+const USAGE: &str = "usage: datagen match --engine1 <path> [--engine2 <path>] \
+[--option1 <name>=<value>]... [--option2 <name>=<value>]... \
+[--tc [<moves>/]<seconds>[+<inc>]] [--tc1 <tc>] [--tc2 <tc>] [--nodes <n>] [--book <file>] [--games <n>] [--concurrency <n>] [--sprt <elo0> <elo1>] \
+[--alpha <a>] [--beta <b>] [--seed <n>]";
+
+#[derive(Debug, Clone)]
+struct EngineConfig {
+    path: String,
+    options: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimeControl {
+    // Moves per cycle, after which the base time is added again
+    moves: Option<i64>,
+    // Both in milliseconds
+    base: i64,
+    inc: i64,
+}
+
+// One side's clock during a game
+#[derive(Debug, Clone, Copy)]
+struct Clock {
+    tc: TimeControl,
+    left: i64,
+    played: i64,
+}
+
+impl Clock {
+    const fn new(tc: TimeControl) -> Self {
+        Self {
+            tc,
+            left: tc.base,
+            played: 0,
+        }
+    }
+
+    const fn moves_to_go(&self) -> Option<i64> {
+        match self.tc.moves {
+            Some(moves) => Some(moves - self.played % moves),
+            None => None,
+        }
+    }
+
+    // Charges a move's time, false if the flag fell
+    fn spend(&mut self, elapsed: i64) -> bool {
+        self.left -= elapsed;
+        if self.left < -TIME_MARGIN {
+            return false;
+        }
+
+        self.left = self.left.max(0) + self.tc.inc;
+        self.played += 1;
+        if self.moves_to_go() == self.tc.moves {
+            self.left += self.tc.base;
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ending {
+    Rules(&'static str),
+    TimeForfeit,
+    IllegalMove,
+}
+
+impl Ending {
+    const fn description(self) -> &'static str {
+        match self {
+            Self::Rules(reason) => reason,
+            Self::TimeForfeit => "loses on time",
+            Self::IllegalMove => "illegal move",
+        }
+    }
+}
+
+// The score from the first engine's perspective, and the games either engine lost on time
+#[derive(Debug, Default, Clone)]
+struct Results {
+    score: Score,
+    pairs: Pentanomial,
+    // Half points the first engine scored in pairs with only one game finished, by pair
+    unpaired: HashMap<usize, usize>,
+    time_forfeits: [u64; 2],
+}
+
+impl Results {
+    fn estimate(&self) -> Option<Estimate> {
+        if self.pairs.total() > 0 {
+            self.pairs.estimate()
+        } else {
+            self.score.estimate()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MatchConfig {
+    engines: [EngineConfig; 2],
+    // Per engine, the same unless playing with time odds
+    tcs: [TimeControl; 2],
+    // Searched per move in fixed-node matches, which have no clocks
+    nodes: Option<u64>,
+    book: Vec<Board>,
+    games: usize,
+    concurrency: usize,
+    sprt: Option<Sprt>,
+}
+
+struct Engine {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Engine {
+    fn new(config: &EngineConfig) -> io::Result<Self> {
+        let mut child = Command::new(&config.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut engine = Self {
+            name: config.path.clone(),
+            child,
+            stdin,
+            stdout,
+        };
+
+        engine.send("uci")?;
+        loop {
+            let line = engine.read_line()?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if line.trim() == "uciok" {
+                break;
+            }
+        }
+
+        for (name, value) in &config.options {
+            engine.send(&format!("setoption name {name} value {value}"))?;
+        }
+        engine.sync()?;
+
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "engine exited"));
+        }
+
+        Ok(line)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.send("isready")?;
+        while self.read_line()?.trim() != "readyok" {}
+
+        Ok(())
+    }
+
+    fn new_game(&mut self) -> io::Result<()> {
+        self.send("ucinewgame")?;
+        self.sync()
+    }
+
+    // Returns the move string and the time it took in milliseconds
+    fn go(&mut self, position: &str, go: &str) -> io::Result<(String, i64)> {
+        self.send(position)?;
+        let timer = Instant::now();
+        self.send(go)?;
+
+        loop {
+            let line = self.read_line()?;
+            if let Some(rest) = line.strip_prefix("bestmove") {
+                let elapsed = timer.elapsed().as_millis() as i64;
+                let mv = rest.split_whitespace().next().unwrap_or_default();
+
+                return Ok((mv.to_string(), elapsed));
+            }
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let config = match parse_args(args) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{e}");
+            println!("{USAGE}");
+            return Ok(());
+        }
+    };
+
+    let results = Mutex::new(Results::default());
+    let next_game = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+
+    std::thread::scope(|s| {
+        for _ in 0..config.concurrency {
+            s.spawn(|| {
+                if let Err(e) = worker(&config, &next_game, &results, &stop) {
+                    println!("Worker stopped: {e}");
+                    stop.store(true, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap();
+    println!("\nFinished after {} games", results.score.games());
+    report(&config, &results);
+
+    Ok(())
+}
+
+fn worker(
+    config: &MatchConfig,
+    next_game: &AtomicUsize,
+    results: &Mutex<Results>,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let mut engines = [
+        Engine::new(&config.engines[0])?,
+        Engine::new(&config.engines[1])?,
+    ];
+
+    while !stop.load(Ordering::SeqCst) {
+        let idx = next_game.fetch_add(1, Ordering::SeqCst);
+        if idx >= config.games {
+            break;
+        }
+
+        // Each opening is played twice with colors reversed
+        let opening = &config.book[(idx / 2) % config.book.len()];
+        let first_is_white = idx.is_multiple_of(2);
+
+        let [first, second] = &mut engines;
+        let [first_tc, second_tc] = config.tcs;
+        let ((white, white_tc), (black, black_tc)) = if first_is_white {
+            ((first, first_tc), (second, second_tc))
+        } else {
+            ((second, second_tc), (first, first_tc))
+        };
+
+        let (outcome, ending) =
+            play_game(white, black, opening, [white_tc, black_tc], config.nodes)?;
+        let result = match outcome {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        };
+        println!(
+            "Game {} {} vs {}: {result} {{{}}}",
+            idx + 1,
+            white.name,
+            black.name,
+            ending.description()
+        );
+
+        let mut results = results.lock().unwrap();
+        let first_won = match (outcome, first_is_white) {
+            (GameResult::Draw, _) => {
+                results.score.draws += 1;
+                None
+            }
+            (GameResult::WhiteWins, true) | (GameResult::BlackWins, false) => {
+                results.score.wins += 1;
+                Some(true)
+            }
+            _ => {
+                results.score.losses += 1;
+                Some(false)
+            }
+        };
+        if let (Ending::TimeForfeit, Some(first_won)) = (ending, first_won) {
+            results.time_forfeits[usize::from(first_won)] += 1;
+        }
+
+        let half_points = first_won.map_or(1, |won| 2 * usize::from(won));
+        match results.unpaired.remove(&(idx / 2)) {
+            Some(other) => results.pairs.add(half_points + other),
+            None => {
+                results.unpaired.insert(idx / 2, half_points);
+            }
+        }
+        report(config, &results);
+
+        if let Some(sprt) = config.sprt {
+            if let Some(decision) = sprt.decision(results.estimate()) {
+                match decision {
+                    Decision::AcceptH1 => println!("SPRT: H1 accepted"),
+                    Decision::AcceptH0 => println!("SPRT: H0 accepted"),
+                }
+                stop.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn play_game(
+    white: &mut Engine,
+    black: &mut Engine,
+    opening: &Board,
+    tcs: [TimeControl; 2],
+    nodes: Option<u64>,
+) -> io::Result<(GameResult, Ending)> {
+    white.new_game()?;
+    black.new_game()?;
+
+    let mut board = opening.clone();
+    let mut moves: Vec<String> = vec![];
+    let mut hashes = vec![board.hash()];
+    let mut clocks = tcs.map(Clock::new);
+
+    loop {
+        let stm = board.side_to_move();
+        let loss = if stm == Color::White {
+            GameResult::BlackWins
+        } else {
+            GameResult::WhiteWins
+        };
+
+        if let Some((result, reason)) = game_over(&board, &hashes) {
+            return Ok((result, Ending::Rules(reason.description())));
+        }
+
+        let position = if moves.is_empty() {
+            format!("position fen {opening}")
+        } else {
+            format!("position fen {opening} moves {}", moves.join(" "))
+        };
+        let [w, b] = clocks;
+        let mut go = match nodes {
+            Some(nodes) => format!("go nodes {nodes}"),
+            None => format!(
+                "go wtime {} btime {} winc {} binc {}",
+                w.left, b.left, w.tc.inc, b.tc.inc
+            ),
+        };
+        if let (None, Some(moves)) = (nodes, clocks[stm as usize].moves_to_go()) {
+            go.push_str(&format!(" movestogo {moves}"));
+        }
+
+        let engine = if stm == Color::White {
+            &mut *white
+        } else {
+            &mut *black
+        };
+        let (mv_string, elapsed) = engine.go(&position, &go)?;
+
+        if nodes.is_none() && !clocks[stm as usize].spend(elapsed) {
+            return Ok((loss, Ending::TimeForfeit));
+        }
+
+        let Ok(mv) = mv_string.parse::<Move>() else {
+            return Ok((loss, Ending::IllegalMove));
+        };
+        let mv = check_castling_move(&board, mv);
+        if !board.is_legal(mv) {
+            return Ok((loss, Ending::IllegalMove));
+        }
+
+        board.play_unchecked(mv);
+        moves.push(mv_string);
+        hashes.push(board.hash());
+    }
+}
+
+fn report(config: &MatchConfig, results: &Results) {
+    let Some(estimate) = results.estimate() else {
+        return;
+    };
+
+    let (elo, error) = estimate.elo();
+    println!(
+        "Elo   | {elo:.2} +- {error:.2} (95%), LOS {:.1}%",
+        estimate.los() * 100.
+    );
+    if let Some(sprt) = config.sprt {
+        let (lower, upper) = sprt.bounds();
+        println!(
+            "LLR   | {:.2} ({lower:.2}, {upper:.2}) [{:.2}, {:.2}]",
+            sprt.llr(Some(estimate)),
+            sprt.elo0,
+            sprt.elo1
+        );
+    }
+
+    let score = &results.score;
+    println!(
+        "Games | N: {} W: {} L: {} D: {}",
+        score.games(),
+        score.wins,
+        score.losses,
+        score.draws
+    );
+    if results.pairs.total() > 0 {
+        let [ll, ld, even, wd, ww] = results.pairs.pairs;
+        println!("Penta | [{ll}, {ld}, {even}, {wd}, {ww}]");
+    }
+    if results.time_forfeits != [0, 0] {
+        let [first, second] = results.time_forfeits;
+        println!("Flags | {first} - {second}");
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<MatchConfig, String> {
+    let mut paths: [Option<String>; 2] = [None, None];
+    let mut options: [Vec<(String, String)>; 2] = [vec![], vec![]];
+    let mut tcs: [Option<TimeControl>; 3] = [None; 3];
+    let mut book_path: Option<String> = None;
+    let mut games = 1000;
+    let mut concurrency = 1;
+    let mut elos: Option<(f64, f64)> = None;
+    let mut alpha = 0.05;
+    let mut beta = 0.05;
+    let mut seed: Option<u64> = None;
+    let mut nodes: Option<u64> = None;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(String::as_str)
+                .ok_or_else(|| format!("Missing value for {flag}"))
+        };
+        let invalid = |v: &str| format!("Invalid value for {flag}: {v}");
+
+        match flag.as_str() {
+            "--engine1" => paths[0] = Some(value()?.to_string()),
+            "--engine2" => paths[1] = Some(value()?.to_string()),
+            "--option1" | "--option2" => {
+                let v = value()?;
+                let (name, val) = v.split_once('=').ok_or_else(|| invalid(v))?;
+                let idx = usize::from(flag == "--option2");
+                options[idx].push((name.to_string(), val.to_string()));
+            }
+            "--tc" | "--tc1" | "--tc2" => {
+                let v = value()?;
+                let idx = ["--tc", "--tc1", "--tc2"]
+                    .iter()
+                    .position(|f| f == flag)
+                    .unwrap();
+                tcs[idx] = Some(parse_tc(v).ok_or_else(|| invalid(v))?);
+            }
+            "--nodes" => {
+                let v = value()?;
+                nodes = Some(
+                    v.parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| invalid(v))?,
+                );
+            }
+            "--book" => book_path = Some(value()?.to_string()),
+            "--games" => {
+                let v = value()?;
+                games = v.parse().map_err(|_| invalid(v))?;
+            }
+            "--concurrency" => {
+                let v = value()?;
+                concurrency = v.parse().map_err(|_| invalid(v))?;
+            }
+            "--sprt" => {
+                let v0 = value()?;
+                let elo0 = v0.parse().map_err(|_| invalid(v0))?;
+                let v1 = value()?;
+                let elo1 = v1.parse().map_err(|_| invalid(v1))?;
+                elos = Some((elo0, elo1));
+            }
+            "--alpha" => {
+                let v = value()?;
+                alpha = v.parse().map_err(|_| invalid(v))?;
+            }
+            "--beta" => {
+                let v = value()?;
+                beta = v.parse().map_err(|_| invalid(v))?;
+            }
+            "--seed" => {
+                let v = value()?;
+                seed = Some(v.parse().map_err(|_| invalid(v))?);
+            }
+            _ => return Err(format!("Unknown argument {flag}")),
+        }
+    }
+
+    let Some(path1) = paths[0].clone() else {
+        return Err("Missing --engine1".to_string());
+    };
+    let path2 = paths[1].clone().unwrap_or_else(|| path1.clone());
+    let [mut options1, mut options2] = options;
+    if nodes.is_some() {
+        for options in [&mut options1, &mut options2] {
+            if options
+                .iter()
+                .any(|(name, value)| name == "Threads" && value != "1")
+            {
+                return Err("Fixed-node matches run on a single thread".to_string());
+            }
+            options.push(("Threads".to_string(), "1".to_string()));
+        }
+    }
+    let [tc, tc1, tc2] = tcs;
+    let tc = tc.unwrap_or(DEFAULT_TC);
+
+    let mut book = match book_path {
+        Some(path) => read_book(&path).map_err(|e| format!("Could not read book {path}: {e}"))?,
+        None => vec![],
+    };
+    if book.is_empty() {
+        book.push(Board::default());
+    }
+
+    let seed = seed.or(nodes.map(|_| FIXED_SEED));
+    let rng = seed.map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+    rng.shuffle(&mut book);
+
+    Ok(MatchConfig {
+        engines: [
+            EngineConfig {
+                path: path1,
+                options: options1,
+            },
+            EngineConfig {
+                path: path2,
+                options: options2,
+            },
+        ],
+        tcs: [tc1.unwrap_or(tc), tc2.unwrap_or(tc)],
+        nodes,
+        book,
+        // Games are always played in pairs
+        games: games + games % 2,
+        concurrency: concurrency.max(1),
+        sprt: elos.map(|(elo0, elo1)| Sprt {
+            elo0,
+            elo1,
+            alpha,
+            beta,
+        }),
+    })
+}
+
+// [<moves>/]<seconds>[+<increment seconds>], e.g. 8+0.08 or 40/60
+fn parse_tc(tc: &str) -> Option<TimeControl> {
+    let (moves, tc) = match tc.split_once('/') {
+        Some((moves, tc)) => (Some(moves.parse().ok().filter(|&m| m > 0)?), tc),
+        None => (None, tc),
+    };
+    let (base, inc) = tc.split_once('+').unwrap_or((tc, "0"));
+    let base: f64 = base.parse().ok()?;
+    let inc: f64 = inc.parse().ok()?;
+
+    Some(TimeControl {
+        moves,
+        base: (base * 1000.) as i64,
+        inc: (inc * 1000.) as i64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_controls() {
+        assert_eq!(parse_tc("8+0.08"), Some(DEFAULT_TC));
+        assert_eq!(
+            parse_tc("40/60"),
+            Some(TimeControl {
+                moves: Some(40),
+                base: 60_000,
+                inc: 0,
+            })
+        );
+        assert_eq!(parse_tc("0/60"), None);
+        assert_eq!(parse_tc("60+"), None);
+
+        // The base time comes back every cycle, on top of the increment
+        let mut clock = Clock::new(parse_tc("2/1+0.1").unwrap());
+        assert_eq!(clock.moves_to_go(), Some(2));
+        assert!(clock.spend(400));
+        assert_eq!((clock.left, clock.moves_to_go()), (700, Some(1)));
+        assert!(clock.spend(400));
+        assert_eq!((clock.left, clock.moves_to_go()), (1400, Some(2)));
+
+        // Overstepping by more than the margin loses
+        assert!(clock.spend(1400 + TIME_MARGIN));
+        assert!(!clock.spend(10_000));
+    }
+}
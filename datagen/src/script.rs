@@ -1,16 +1,17 @@
+use crate::{adjudication::Adjudicator, bulletformat, filter::Filter, viriformat};
+
 use engine::{
-    body::{movegen, nnue::inference::NNUEState, position::is_quiet, search::Search, tt::TT},
-    definitions,
+    body::{movegen, nnue::inference::NNUEState, search::Search, tt::TT},
     uci::handler::SearchType,
 };
 
 use std::{
     error::Error,
-    fs::File,
-    io::{stdin, stdout, BufWriter, Write},
+    fs::{self, OpenOptions},
+    io::{self, stdin, stdout, BufWriter, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     sync::atomic::{AtomicBool, AtomicU64, Ordering},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use cozy_chess::{Board, Color, GameStatus, Piece};
@@ -26,6 +27,49 @@ static FENS: AtomicU64 = AtomicU64::new(0);
 static WHITE_WINS: AtomicU64 = AtomicU64::new(0);
 static BLACK_WINS: AtomicU64 = AtomicU64::new(0);
 static DRAWS: AtomicU64 = AtomicU64::new(0);
+// Game slots handled and still to handle in this session, including discarded openings
+static PROGRESS: AtomicU64 = AtomicU64::new(0);
+static TARGET: AtomicU64 = AtomicU64::new(0);
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(30);
+const PARAMS_FILE: &str = "params.txt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    // fen | score | result
+    Text,
+    // Packed 32 byte records for the bullet trainer
+    Bullet,
+    // Whole games as a starting position and a move/eval sequence
+    Viri,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "bullet" => Some(Self::Bullet),
+            "viri" => Some(Self::Viri),
+            _ => None,
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Bullet => "bullet",
+            Self::Viri => "viri",
+        }
+    }
+
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Text => "txt",
+            Self::Bullet => "bin",
+            Self::Viri => "vf",
+        }
+    }
+}
 
 #[derive(Debug)]
 struct Parameters {
@@ -35,15 +79,124 @@ struct Parameters {
     threads: usize,
     // Search type for the evaluations
     st: SearchType,
+    // Node searches stop at exactly that many nodes and games start from an empty hash table,
+    // every game then plays out the same given its seed
+    fixed: bool,
+    // Output data format
+    format: OutputFormat,
+    // Base seed, every game derives its own RNG from it
+    seed: u64,
+    // Which positions are written in per-position formats
+    filter: Filter,
 }
 
 impl Parameters {
-    fn new(games: usize, threads: usize, st: SearchType) -> Self {
-        Self { games, threads, st }
+    fn new(
+        games: usize,
+        threads: usize,
+        st: SearchType,
+        fixed: bool,
+        format: OutputFormat,
+        seed: u64,
+        filter: Filter,
+    ) -> Self {
+        Self {
+            games,
+            threads,
+            st,
+            fixed,
+            format,
+            seed,
+            filter,
+        }
+    }
+
+    fn search(&self) -> String {
+        match (self.st, self.fixed) {
+            (SearchType::Depth(d), _) => format!("depth {d}"),
+            (SearchType::Nodes(n), false) => format!("nodes {n}"),
+            (SearchType::Nodes(n), true) => format!("fixed {n}"),
+            _ => unreachable!(),
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "\n{GREEN}Confirmed parameters: {DEFAULT}[games: {WHITE}{}{DEFAULT}, threads: {WHITE}{}{DEFAULT}, search: {WHITE}{}{DEFAULT}, format: {WHITE}{:?}{DEFAULT}, seed: {WHITE}{}{DEFAULT}, filter: {WHITE}{}{DEFAULT}]",
+            self.games, self.threads, self.search(), self.format, self.seed, self.filter
+        );
+    }
+
+    // Stored with the data so that an interrupted run can be resumed with the same settings
+    fn save(&self, data_dir: &Path) -> io::Result<()> {
+        let st = self.search();
+
+        fs::write(
+            data_dir.join(PARAMS_FILE),
+            format!(
+                "games {}\nthreads {}\nsearch {st}\nformat {}\nseed {}\nfilter {}\n",
+                self.games,
+                self.threads,
+                self.format.name(),
+                self.seed,
+                self.filter
+            ),
+        )
+    }
+
+    fn load(data_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(data_dir.join(PARAMS_FILE))?;
+        let (mut games, mut threads, mut st, mut format, mut seed) = (None, None, None, None, None);
+        let mut fixed = false;
+        let mut filter = Filter::default();
+
+        for line in text.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["games", n] => games = Some(n.parse()?),
+                ["threads", n] => threads = Some(n.parse()?),
+                ["search", "depth", d] => st = Some(SearchType::Depth(d.parse()?)),
+                ["search", "nodes", n] => st = Some(SearchType::Nodes(n.parse()?)),
+                ["search", "fixed", n] => {
+                    st = Some(SearchType::Nodes(n.parse()?));
+                    fixed = true;
+                }
+                ["format", f] => format = OutputFormat::parse(f),
+                ["seed", n] => seed = Some(n.parse()?),
+                ["filter", settings @ ..] => filter = Filter::parse(&settings.join(" "))?,
+                _ => return Err(format!("Invalid parameter line: {line}").into()),
+            }
+        }
+
+        Ok(Self::new(
+            games.ok_or("Missing games")?,
+            threads.ok_or("Missing threads")?,
+            st.ok_or("Missing search type")?,
+            fixed,
+            format.ok_or("Missing format")?,
+            seed.ok_or("Missing seed")?,
+            filter,
+        ))
     }
 }
 
 pub fn root() -> Result<(), Box<dyn Error>> {
+    // Resuming picks up the parameters of the interrupted run
+    println!("Would you like to resume a previous run? [run directory, empty for a new run]");
+    let mut inp_resume = String::new();
+    stdin().read_line(&mut inp_resume).unwrap();
+
+    if !inp_resume.trim().is_empty() {
+        let data_dir = PathBuf::from(inp_resume.trim());
+        let params = Parameters::load(&data_dir)?;
+        params.print();
+
+        generate_main(&params, &data_dir);
+
+        println!("We're done B)");
+        return Ok(());
+    }
+
     // Get the number of games to generate
     println!("How many games would you like to gen? [1, 100M]");
     let mut inp_games = String::new();
@@ -64,12 +217,13 @@ pub fn root() -> Result<(), Box<dyn Error>> {
         panic!("Invalid thread range! {threads}, needs to be between [1, 64]")
     }
 
-    // Get the search type for the search evaluations
-    println!("What search type would you like to use? [depth, nodes]");
+    // Get the search type for the search evaluations, fixed being reproducible node searches
+    println!("What search type would you like to use? [depth, nodes, fixed]");
     let mut inp_st = String::new();
     stdin().read_line(&mut inp_st).unwrap();
 
-    let st = match inp_st.trim().to_lowercase().as_str() {
+    let search = inp_st.trim().to_lowercase();
+    let st = match search.as_str() {
         "depth" => {
             println!("What depth would you like to use? [1, 100]");
             let mut inp_depth = String::new();
@@ -82,7 +236,7 @@ pub fn root() -> Result<(), Box<dyn Error>> {
 
             SearchType::Depth(depth)
         }
-        "nodes" => {
+        "nodes" | "fixed" => {
             println!("What nodes would you like to use? [1, 100M]");
             let mut inp_nodes = String::new();
             stdin().read_line(&mut inp_nodes).unwrap();
@@ -99,50 +253,161 @@ pub fn root() -> Result<(), Box<dyn Error>> {
         }
     };
 
-    // Let the user confirm the parameters
-    let params = Parameters::new(games, threads, st);
+    // Get the output format
+    println!("What output format would you like to use? [text, bullet, viri]");
+    let mut inp_format = String::new();
+    stdin().read_line(&mut inp_format).unwrap();
+
+    let Some(format) = OutputFormat::parse(inp_format.trim().to_lowercase().as_str()) else {
+        panic!("Invalid output format! {inp_format}")
+    };
+
+    // Get the seed, runs with the same seed play the same openings
+    println!("What seed would you like to use? [any number, empty for a random seed]");
+    let mut inp_seed = String::new();
+    stdin().read_line(&mut inp_seed).unwrap();
+
+    let seed = if inp_seed.trim().is_empty() {
+        fastrand::u64(..)
+    } else {
+        inp_seed.trim().parse::<u64>()?
+    };
+
+    // Get the position filters, only used by the per-position formats
     println!(
-        "\n{GREEN}Confirmed parameters: {DEFAULT}[games: {WHITE}{}{DEFAULT}, threads: {WHITE}{}{DEFAULT}, search type: {WHITE}{:?}{DEFAULT}]",
-        params.games, params.threads, params.st
+        "Which position filters would you like to use? [empty for defaults: {}]",
+        Filter::default()
     );
-    if params.games % params.threads != 0 {
+    let mut inp_filter = String::new();
+    stdin().read_line(&mut inp_filter).unwrap();
+
+    let filter = Filter::parse(&inp_filter)?;
+
+    // Let the user confirm the parameters
+    let params = Parameters::new(games, threads, st, search == "fixed", format, seed, filter);
+    params.print();
+    if !params.games.is_multiple_of(params.threads) {
         println!("{ORANGE}WARNING: {DEFAULT}The number of games is not divisible by the number of threads!");
     }
     println!("Press enter to continue...");
     stdin().read_line(&mut String::new()).unwrap();
 
-    generate_main(params);
+    let run_id = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+    let data_dir = PathBuf::from("data").join(run_id);
+    fs::create_dir_all(&data_dir)?;
+    params.save(&data_dir)?;
+    println!(
+        "{RED}ATTENTION: {DEFAULT}This run will be saved to {}",
+        data_dir.display()
+    );
+
+    generate_main(&params, &data_dir);
 
     println!("We're done B)");
     Ok(())
 }
 
-fn generate_main(params: Parameters) {
+fn generate_main(params: &Parameters, data_dir: &Path) {
     ctrlc::set_handler(move || {
         STOP_FLAG.store(true, Ordering::SeqCst);
         println!("Stopping generation...");
     })
     .expect("Failed to set CTRL+C handler.");
 
-    let run_id = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
-    println!("{RED}ATTENTION: {DEFAULT}This run will be saved to data-{run_id}");
     println!(
         "{GREEN}Generating {DEFAULT}{} games with {} threads on {:?}...",
         params.games, params.threads, params.st
     );
 
-    let data_dir = PathBuf::from("data").join(run_id);
-    std::fs::create_dir_all(&data_dir).unwrap();
-
+    let timer = Instant::now();
     std::thread::scope(|s| {
-        for i in 0..params.threads {
-            let path = &data_dir;
-            let tprms = &params;
-            s.spawn(move || {
-                generate_thread(i, path, tprms);
-            });
+        let handles: Vec<_> = (0..params.threads)
+            .map(|i| s.spawn(move || generate_thread(i, data_dir, params)))
+            .collect();
+
+        // Periodic progress report from the main thread
+        let mut last_report = Instant::now();
+        while handles.iter().any(|h| !h.is_finished()) {
+            std::thread::sleep(Duration::from_millis(100));
+
+            if last_report.elapsed() >= REPORT_INTERVAL {
+                report(timer.elapsed());
+                last_report = Instant::now();
+            }
         }
-    })
+    });
+
+    report(timer.elapsed());
+}
+
+fn report(elapsed: Duration) {
+    let elapsed = elapsed.as_secs_f64();
+    let fens = FENS.load(Ordering::Relaxed);
+    let ww = WHITE_WINS.load(Ordering::Relaxed);
+    let bw = BLACK_WINS.load(Ordering::Relaxed);
+    let dr = DRAWS.load(Ordering::Relaxed);
+    let tot_games = ww + bw + dr;
+
+    let progress = PROGRESS.load(Ordering::Relaxed);
+    let target = TARGET.load(Ordering::Relaxed).max(1);
+    let percentage = (progress as f64 / target as f64) * 100.0;
+    let etr = if progress == 0 {
+        0.
+    } else {
+        elapsed / progress as f64 * target.saturating_sub(progress) as f64
+    };
+
+    println!(
+        "\n{GREEN}Generated {DEFAULT}{fens} FENs [{:.2} FEN/s] from {tot_games} games [{:.2} games/s]",
+        fens as f64 / elapsed,
+        tot_games as f64 / elapsed
+    );
+    println!("In total: {percentage:.2}% of this session is done.");
+    println!("White wins: {ww}, Black wins: {bw}, Draws: {dr}");
+    println!("Elapsed time: {elapsed:.2}s. {RED}ETR: {etr:.2}s{DEFAULT}");
+
+    stdout().flush().unwrap();
+}
+
+/// Writes a single position in one of the per-position formats, scores and results are white relative
+pub fn write_record(
+    out: &mut impl Write,
+    format: OutputFormat,
+    position: &Board,
+    score: i32,
+    result: f32,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => writeln!(out, "{position} | {score} | {result}"),
+        OutputFormat::Bullet => out.write_all(&bulletformat::pack(position, score, result)),
+        // Whole games are written separately
+        OutputFormat::Viri => Ok(()),
+    }
+}
+
+// Every game gets its own RNG, so resumed runs replay exactly the games they skip
+fn game_seed(seed: u64, thread: usize, game: usize) -> u64 {
+    // splitmix64 finalizer
+    let mut z = seed ^ (thread as u64) << 40 ^ game as u64;
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// A checkpoint holds the number of game slots a thread handled and the size of its output at
+// that point. Anything past that size is an unfinished write and gets truncated on resume.
+fn read_checkpoint(path: &Path) -> Option<(usize, u64)> {
+    let text = fs::read_to_string(path).ok()?;
+    let mut words = text.split_whitespace();
+    Some((words.next()?.parse().ok()?, words.next()?.parse().ok()?))
+}
+
+fn write_checkpoint(path: &Path, games: usize, written: u64) -> io::Result<()> {
+    // Written to the side first so that a crash can't leave a torn checkpoint
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, format!("{games} {written}"))?;
+    fs::rename(tmp, path)
 }
 
 fn generate_thread(id: usize, data_dir: &Path, options: &Parameters) {
@@ -151,48 +416,43 @@ fn generate_thread(id: usize, data_dir: &Path, options: &Parameters) {
 
     // not implemented properly
     let history = engine::body::history::History::new();
-    let mut search = Search::new(&tt, &nnue, &history, &vec![]);
-    let rng = fastrand::Rng::new();
+    let mut search = Search::new(&tt, nnue, &history, &vec![]);
 
     let mut board;
-    let mut game_buffer: Vec<(i32, String)> = vec![];
+    let mut game_buffer: Vec<(i32, Board)> = vec![];
     let mut hashes: Vec<u64>;
 
-    let mut output_file = File::create(data_dir.join(format!("thread_{id}.txt"))).unwrap();
+    let output_path = data_dir.join(format!("thread_{id}.{}", options.format.extension()));
+    let checkpoint_path = data_dir.join(format!("thread_{id}.checkpoint"));
+    let (start, written) = read_checkpoint(&checkpoint_path).unwrap_or((0, 0));
+
+    let mut output_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(output_path)
+        .unwrap();
+    output_file.set_len(written).unwrap();
+    output_file.seek(SeekFrom::End(0)).unwrap();
     let mut output_buffer = BufWriter::new(&mut output_file);
 
     let games_per_thread = (options.games / options.threads).max(1); // at least one game
-    let timer = Instant::now();
+    TARGET.fetch_add(
+        games_per_thread.saturating_sub(start) as u64,
+        Ordering::Relaxed,
+    );
 
-    'main: for games_played in 0..games_per_thread {
-        // Information print from main thread
-        if id == 0 && games_played != 0 && games_played % 64 == 0 {
-            let fens = FENS.load(Ordering::Relaxed);
-            let elapsed = timer.elapsed().as_secs_f64();
-            let fens_per_sec = fens as f64 / elapsed;
-
-            let ww = WHITE_WINS.load(Ordering::Relaxed);
-            let bw = BLACK_WINS.load(Ordering::Relaxed);
-            let dr = DRAWS.load(Ordering::Relaxed);
-            let tot_games = ww + bw + dr;
-            let percentage = (games_played as f64 / games_per_thread as f64) * 100.0;
-
-            let time_per_game = elapsed / games_played as f64;
-            let etr = (games_per_thread - games_played) as f64 * time_per_game;
-
-            println!("\n{GREEN}Generated {DEFAULT}{fens} FENs [{fens_per_sec:.2} FEN/s]");
-            println!("{GREEN}Time per game: {DEFAULT}{time_per_game:.2}s.");
-            println!("In total: {tot_games} [{percentage:.2}%] games are done.");
-            println!("White wins: {ww}, Black wins: {bw}, Draws: {dr}");
-            println!("Elapsed time: {elapsed:.2}s. {RED}ETR: {etr:.2}s{DEFAULT}");
-
-            stdout().flush().unwrap();
-        }
+    'main: for games_played in start..games_per_thread {
+        PROGRESS.fetch_add(1, Ordering::Relaxed);
+        let rng = fastrand::Rng::with_seed(game_seed(options.seed, id, games_played));
 
         // Reset everything from previous game
         output_buffer.flush().unwrap();
         board = Board::default();
         search.game_reset();
+        if options.fixed {
+            tt.reset();
+        }
         hashes = vec![];
 
         // Play a new game
@@ -217,6 +477,8 @@ fn generate_thread(id: usize, data_dir: &Path, options: &Parameters) {
         }
 
         // ... play the rest of the game
+        let mut game = viriformat::Game::new(&board);
+        let mut adjudicator = Adjudicator::default();
 
         // REMINDER TO SELF
         // the game history of the search struct might not be taken care of properly?
@@ -231,22 +493,30 @@ fn generate_thread(id: usize, data_dir: &Path, options: &Parameters) {
             }
 
             search.go_reset();
+            if options.fixed {
+                search.info.time_manager.start(options.st);
+            }
             //search.tt.age();
-            let (mut score, best_move) = search.data_search(&board, options.st);
-
-            // filter noisy positions
-            let not_in_check = board.checkers().is_empty();
-            let okay_score = score.abs() < definitions::TB_WIN_IN_PLY;
-            let okay_move = is_quiet(&board, best_move);
-            if not_in_check && okay_score && okay_move {
-                // Always report scores from white's perspective
-                score = if board.side_to_move() == Color::White {
-                    score
-                } else {
-                    -score
-                };
-
-                game_buffer.push((score, format!("{}", board)));
+            let (score, best_move) = search.data_search(&board, options.st);
+
+            // Always report scores from white's perspective
+            let white_score = if board.side_to_move() == Color::White {
+                score
+            } else {
+                -score
+            };
+
+            // Games are stored whole, filtering is left to the consumer
+            if options.format == OutputFormat::Viri {
+                game.push(&board, best_move, white_score);
+            }
+
+            if options.filter.keep(&board, best_move, score) {
+                game_buffer.push((white_score, board.clone()));
+            }
+
+            if let Some(result) = adjudicator.update(white_score) {
+                break result;
             }
 
             board.play_unchecked(best_move);
@@ -254,28 +524,35 @@ fn generate_thread(id: usize, data_dir: &Path, options: &Parameters) {
         };
 
         // Always report wins from white's perspective
-        let result_output = match (game_result, winner) {
+        let result: f32 = match (game_result, winner) {
             (GameStatus::Drawn, _) => {
                 DRAWS.fetch_add(1, Ordering::Relaxed);
-                "0.5".to_string()
+                0.5
             }
             (GameStatus::Won, Some(Color::White)) => {
                 WHITE_WINS.fetch_add(1, Ordering::Relaxed);
-                "1".to_string()
+                1.
             }
             (GameStatus::Won, Some(Color::Black)) => {
                 BLACK_WINS.fetch_add(1, Ordering::Relaxed);
-                "0".to_string()
+                0.
             }
             _ => unreachable!(),
         };
 
         // Write the result
         FENS.fetch_add(game_buffer.len() as u64, Ordering::Relaxed);
-        for (score, fen) in game_buffer.drain(..) {
-            writeln!(output_buffer, "{fen} | {score} | {result_output}").unwrap();
+        for (score, position) in game_buffer.drain(..) {
+            write_record(&mut output_buffer, options.format, &position, score, result).unwrap();
+        }
+        if options.format == OutputFormat::Viri {
+            game.write(&mut output_buffer, result).unwrap();
         }
 
+        output_buffer.flush().unwrap();
+        let written = output_buffer.stream_position().unwrap();
+        write_checkpoint(&checkpoint_path, games_played + 1, written).unwrap();
+
         // Safely abort with CTRLC handler since otherwise
         // our files could get truncated and the data get lost.
         if STOP_FLAG.load(Ordering::SeqCst) {
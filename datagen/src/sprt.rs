@@ -0,0 +1,274 @@
+// Match statistics
+// Elo estimates with 95% error bars, the likelihood of superiority and the generalized SPRT
+// (GSPRT) used by fishtest and OpenBench. They're computed from the trinomial win/draw/loss
+// distribution of single games, or from the pentanomial distribution of game pairs when the
+// games are played in pairs from the same opening with colors reversed. Pairs are correlated
+// through their opening, and treating their games as independent overstates the confidence.
+
+use std::cmp::Ordering;
+
+// Two-sided 95% quantile of the normal distribution
+const Z_95: f64 = 1.959_964;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Score {
+    pub wins: u64,
+    pub losses: u64,
+    pub draws: u64,
+}
+
+/// Game pairs by the points the first engine scored in them: 0, 0.5, 1, 1.5 and 2
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Pentanomial {
+    pub pairs: [u64; 5],
+}
+
+/// Mean and variance of the score of a sample, a game or a pair of games scored out of 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub mean: f64,
+    pub variance: f64,
+    pub samples: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    AcceptH0,
+    AcceptH1,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Score {
+    #[must_use]
+    pub fn games(&self) -> u64 {
+        self.wins + self.losses + self.draws
+    }
+
+    #[must_use]
+    pub fn estimate(&self) -> Option<Estimate> {
+        Estimate::new(&[(0., self.losses), (0.5, self.draws), (1., self.wins)])
+    }
+}
+
+impl Pentanomial {
+    /// Adds a pair in which the first engine scored `half_points`, from 0 to 4
+    pub fn add(&mut self, half_points: usize) {
+        self.pairs[half_points] += 1;
+    }
+
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.pairs.iter().sum()
+    }
+
+    #[must_use]
+    pub fn estimate(&self) -> Option<Estimate> {
+        let outcomes: Vec<(f64, u64)> = (0..5)
+            .map(|half_points| (half_points as f64 / 4., self.pairs[half_points]))
+            .collect();
+        Estimate::new(&outcomes)
+    }
+}
+
+impl Estimate {
+    // From how often each score came up
+    fn new(outcomes: &[(f64, u64)]) -> Option<Self> {
+        let samples = outcomes.iter().map(|&(_, n)| n).sum::<u64>() as f64;
+        if samples == 0. {
+            return None;
+        }
+
+        let mean = outcomes
+            .iter()
+            .map(|&(score, n)| score * n as f64)
+            .sum::<f64>()
+            / samples;
+        let variance = outcomes
+            .iter()
+            .map(|&(score, n)| (score - mean).powi(2) * n as f64)
+            .sum::<f64>()
+            / samples;
+
+        Some(Self {
+            mean,
+            variance,
+            samples,
+        })
+    }
+
+    fn stderr(&self) -> f64 {
+        (self.variance / self.samples).sqrt()
+    }
+
+    /// Elo difference and the half-width of its 95% confidence interval
+    #[must_use]
+    pub fn elo(&self) -> (f64, f64) {
+        let stderr = self.stderr();
+        let elo = score_to_elo(self.mean);
+        let lower = score_to_elo(self.mean - Z_95 * stderr);
+        let upper = score_to_elo(self.mean + Z_95 * stderr);
+
+        (elo, (upper - lower) / 2.)
+    }
+
+    /// Likelihood of superiority, the probability the first engine is the stronger one
+    #[must_use]
+    pub fn los(&self) -> f64 {
+        let stderr = self.stderr();
+        if stderr == 0. {
+            return match self.mean.partial_cmp(&0.5) {
+                Some(Ordering::Greater) => 1.,
+                Some(Ordering::Less) => 0.,
+                _ => 0.5,
+            };
+        }
+
+        0.5 * (1. + erf((self.mean - 0.5) / (stderr * std::f64::consts::SQRT_2)))
+    }
+}
+
+impl Sprt {
+    /// Log-likelihood ratio of H1 (elo1) against H0 (elo0)
+    #[must_use]
+    pub fn llr(&self, estimate: Option<Estimate>) -> f64 {
+        // Not enough information to say anything yet
+        let Some(estimate) = estimate.filter(|e| e.variance > 0.) else {
+            return 0.;
+        };
+
+        let s0 = elo_to_score(self.elo0);
+        let s1 = elo_to_score(self.elo1);
+        estimate.samples * (s1 - s0) * (2. * estimate.mean - s0 - s1) / (2. * estimate.variance)
+    }
+
+    #[must_use]
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1. - self.alpha)).ln(),
+            ((1. - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    #[must_use]
+    pub fn decision(&self, estimate: Option<Estimate>) -> Option<Decision> {
+        let llr = self.llr(estimate);
+        let (lower, upper) = self.bounds();
+
+        if llr >= upper {
+            Some(Decision::AcceptH1)
+        } else if llr <= lower {
+            Some(Decision::AcceptH0)
+        } else {
+            None
+        }
+    }
+}
+
+fn elo_to_score(elo: f64) -> f64 {
+    1. / (1. + 10f64.powf(-elo / 400.))
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1. - 1e-6);
+    -400. * (1. / score - 1.).log10()
+}
+
+// Abramowitz and Stegun 7.1.26, within 1.5e-7
+fn erf(x: f64) -> f64 {
+    let t = 1. / (1. + 0.327_591_1 * x.abs());
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+
+    (1. - poly * (-x * x).exp()).copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_statistics() {
+        let even = Score {
+            wins: 100,
+            losses: 100,
+            draws: 200,
+        };
+        let (elo, error) = even.estimate().unwrap().elo();
+        assert!(elo.abs() < 1e-9);
+        assert!(error > 0. && error < 30.);
+        assert!((even.estimate().unwrap().los() - 0.5).abs() < 1e-9);
+
+        // 60% score is about 70 Elo
+        let ahead = Score {
+            wins: 200,
+            losses: 100,
+            draws: 200,
+        };
+        let (elo, _) = ahead.estimate().unwrap().elo();
+        assert!((elo - 70.4).abs() < 0.1);
+        assert!(ahead.estimate().unwrap().los() > 0.99);
+
+        let sprt = Sprt {
+            elo0: 0.,
+            elo1: 10.,
+            alpha: 0.05,
+            beta: 0.05,
+        };
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 2.944).abs() < 1e-3 && (lower + 2.944).abs() < 1e-3);
+
+        assert_eq!(sprt.decision(ahead.estimate()), Some(Decision::AcceptH1));
+        let behind = Score {
+            wins: ahead.losses,
+            losses: ahead.wins,
+            draws: ahead.draws,
+        };
+        assert_eq!(sprt.decision(behind.estimate()), Some(Decision::AcceptH0));
+        assert_eq!(sprt.decision(Score::default().estimate()), None);
+
+        assert!(erf(0.).abs() < 1e-7);
+        assert!((erf(1.) - 0.842_700_8).abs() < 1e-6);
+        assert!((erf(-1.) + 0.842_700_8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pentanomial_statistics() {
+        // Pairs split by their openings: each engine wins with white, the games alone look
+        // decisive but the pairs are all even
+        let pairs = Pentanomial {
+            pairs: [0, 0, 100, 0, 0],
+        };
+        let games = Score {
+            wins: 100,
+            losses: 100,
+            draws: 0,
+        };
+        assert_eq!(pairs.total(), 100);
+        assert!(pairs.estimate().unwrap().variance == 0.);
+        assert!(games.estimate().unwrap().variance > 0.);
+
+        // Games scored as pairs, some of them won both ways
+        let mut pairs = Pentanomial::default();
+        for half_points in [0, 1, 2, 2, 2, 3, 3, 4, 4, 4] {
+            pairs.add(half_points);
+        }
+        let games = Score {
+            wins: 10,
+            losses: 5,
+            draws: 5,
+        };
+        let (paired, single) = (pairs.estimate().unwrap(), games.estimate().unwrap());
+        assert!((paired.mean - single.mean).abs() < 1e-9);
+        assert!((paired.elo().0 - single.elo().0).abs() < 1e-9);
+        assert!(paired.los() > 0.5 && paired.los() < 1.);
+    }
+}
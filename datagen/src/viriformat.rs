@@ -0,0 +1,179 @@
+// Viriformat
+// Whole games stored as a packed starting position followed by every move played with its
+// evaluation. This compresses far better than per-position records and keeps the game
+// context around, positions are reconstructed by replaying the moves.
+//
+// A game is laid out as
+//  start:  32 byte marlinformat board, see `pack_board`
+//  moves:  (move: u16, eval: i16) for every move played
+//  end:    4 zero bytes
+//
+// Moves are packed as from | to << 6 | promotion << 12 | kind << 14, with the promotion
+// piece counted from the knight and castling encoded as the king capturing its rook.
+// All scores and results are from white's perspective.
+use cozy_chess::{Board, Color, Move, Piece, Rank, Square};
+use std::io::{self, Write};
+
+// Piece nibble used for rooks that still carry a castling right
+const UNMOVED_ROOK: u8 = 6;
+
+const EN_PASSANT: u16 = 1;
+const CASTLE: u16 = 2;
+const PROMOTION: u16 = 3;
+
+pub struct Game {
+    start: [u8; 32],
+    moves: Vec<(u16, i16)>,
+}
+
+impl Game {
+    #[must_use]
+    pub fn new(board: &Board) -> Self {
+        Self {
+            start: pack_board(board, 0, 1),
+            moves: vec![],
+        }
+    }
+
+    /// Records a move before it's played on the board, along with the white relative eval
+    pub fn push(&mut self, board: &Board, mv: Move, eval: i32) {
+        self.moves.push((encode_move(board, mv), clamp_eval(eval)));
+    }
+
+    /// Writes the game with its result (1.0 white win, 0.5 draw, 0.0 black win)
+    pub fn write(&mut self, writer: &mut impl Write, result: f32) -> io::Result<()> {
+        self.start[30] = (2. * result) as u8;
+        writer.write_all(&self.start)?;
+
+        for &(mv, eval) in &self.moves {
+            writer.write_all(&mv.to_le_bytes())?;
+            writer.write_all(&eval.to_le_bytes())?;
+        }
+
+        writer.write_all(&[0; 4])
+    }
+}
+
+fn clamp_eval(eval: i32) -> i16 {
+    eval.clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16
+}
+
+// Marlinformat
+//  occupancy:    u64
+//  pieces:       [u8; 16]  one nibble per occupied square in ascending order,
+//                          piece type (0-5, pawn to king, or an unmoved rook) with bit 3 set for black
+//  stm_ep:       u8        side to move in the top bit, en passant square or 64 below
+//  halfmove:     u8
+//  fullmove:     u16
+//  eval:         i16
+//  wdl:          u8        0 black win, 1 draw, 2 white win
+//  extra:        u8
+fn pack_board(board: &Board, eval: i32, wdl: u8) -> [u8; 32] {
+    let mut unmoved_rooks = 0u64;
+    for color in [Color::White, Color::Black] {
+        let rights = board.castle_rights(color);
+        let back_rank = Rank::First.relative_to(color);
+
+        for file in [rights.short, rights.long].into_iter().flatten() {
+            unmoved_rooks |= 1 << Square::new(file, back_rank) as u64;
+        }
+    }
+
+    let occupancy = board.occupied().0;
+    let mut nibbles = [0u8; 16];
+    for (idx, sq) in board.occupied().into_iter().enumerate() {
+        let piece = board.piece_on(sq).unwrap();
+        let colour = u8::from(board.color_on(sq) == Some(Color::Black)) << 3;
+
+        let piece = if unmoved_rooks & (1 << sq as u64) != 0 && piece == Piece::Rook {
+            UNMOVED_ROOK
+        } else {
+            piece as u8
+        };
+        nibbles[idx / 2] |= (colour | piece) << (4 * (idx & 1));
+    }
+
+    let stm = board.side_to_move();
+    let ep = board.en_passant().map_or(64, |file| {
+        Square::new(file, Rank::Sixth.relative_to(stm)) as u8
+    });
+
+    let mut record = [0u8; 32];
+    record[0..8].copy_from_slice(&occupancy.to_le_bytes());
+    record[8..24].copy_from_slice(&nibbles);
+    record[24] = u8::from(stm == Color::Black) << 7 | ep;
+    record[25] = board.halfmove_clock();
+    record[26..28].copy_from_slice(&board.fullmove_number().to_le_bytes());
+    record[28..30].copy_from_slice(&clamp_eval(eval).to_le_bytes());
+    record[30] = wdl;
+
+    record
+}
+
+fn encode_move(board: &Board, mv: Move) -> u16 {
+    let from = mv.from as u16;
+    let to = mv.to as u16;
+    let moving = board.piece_on(mv.from);
+
+    let (promotion, kind) = if let Some(piece) = mv.promotion {
+        (piece as u16 - Piece::Knight as u16, PROMOTION)
+    } else if moving == Some(Piece::King) && board.colors(board.side_to_move()).has(mv.to) {
+        (0, CASTLE)
+    } else if moving == Some(Piece::Pawn)
+        && mv.from.file() != mv.to.file()
+        && board.piece_on(mv.to).is_none()
+    {
+        (0, EN_PASSANT)
+    } else {
+        (0, 0)
+    };
+
+    from | to << 6 | promotion << 12 | kind << 14
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn viriformat_game() {
+        let board = Board::default();
+        let start = pack_board(&board, 0, 1);
+
+        // Castling rooks are marked as unmoved, black pieces have bit 3 set
+        assert_eq!(start[8], UNMOVED_ROOK | (Piece::Knight as u8) << 4);
+        assert_eq!(
+            start[23],
+            (8 | (Piece::Knight as u8)) | (8 | UNMOVED_ROOK) << 4
+        );
+        assert_eq!(start[24], 64);
+        assert_eq!(u16::from_le_bytes([start[26], start[27]]), 1);
+
+        let castling = Board::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1", false).unwrap();
+        let mv = Move {
+            from: Square::E1,
+            to: Square::H1,
+            promotion: None,
+        };
+        assert_eq!(encode_move(&castling, mv) >> 14, CASTLE);
+
+        let ep = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", false).unwrap();
+        assert_eq!(ep.en_passant(), Some(cozy_chess::File::D));
+        assert_eq!(pack_board(&ep, 0, 1)[24], Square::D6 as u8);
+        let mv = Move {
+            from: Square::E5,
+            to: Square::D6,
+            promotion: None,
+        };
+        assert_eq!(encode_move(&ep, mv) >> 14, EN_PASSANT);
+
+        let mut game = Game::new(&board);
+        game.push(&board, "e2e4".parse().unwrap(), 30);
+        let mut bytes = vec![];
+        game.write(&mut bytes, 0.5).unwrap();
+
+        assert_eq!(bytes.len(), 32 + 4 + 4);
+        assert_eq!(bytes[30], 1);
+        assert_eq!(&bytes[36..], &[0; 4]);
+    }
+}
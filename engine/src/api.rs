@@ -0,0 +1,1121 @@
+// Embedding API
+// Everything needed to run the engine from another program without going through UCI text.
+// The UCI front-end in src/uci is itself built on top of this.
+//
+//     let mut engine = Engine::new();
+//     engine.set_position(START_FEN, &["e2e4", "e7e5"])?;
+//     let result = engine.search(Limits { depth: Some(10), ..Default::default() });
+//
+// The stop flag is process wide, so only one engine can search at a time. Where there are no
+// threads to set it from, as on wasm32, a stop poll can end the search instead.
+use crate::body::{
+    endgame,
+    history::History,
+    kpk,
+    nnue::{
+        header::NetHeader,
+        inference::{self, NNUEState},
+    },
+    search::{store_stop, Search, StopPoll},
+    trace::{Trace, TraceLimits},
+    tt::{PackedMove, TT},
+    variety::{self, MAX_SEED, MAX_VARIETY, MAX_VARIETY_PLIES},
+};
+use crate::definitions::{MATE, TB_WIN_IN_PLY};
+use crate::uci::{
+    clock::Instant,
+    handler::{check_castling_move, reverse_castling_move, SearchType},
+    san::parse_move,
+    timeman::{store_ponder, time_for_move},
+};
+
+pub use crate::body::{
+    adjudication::{
+        adjudicate, adjudicate_scores, game_over, AdjudicationRules, GameResult, Reason,
+    },
+    report::{IterationReport, SearchReporter},
+    search::SearchResult,
+};
+
+use cozy_chess::{Board, Color, GameStatus, Move};
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError, RwLock, RwLockReadGuard};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Number of Chess960 start positions, 518 is the standard one
+pub const CHESS960_POSITIONS: u32 = 960;
+
+/// Shredder FEN of a double Chess960 start position, white and black set up by their Scharnagl
+/// numbers. Plain Chess960 positions give both sides the same number.
+pub fn chess960_fen(white: u32, black: u32) -> Result<String, String> {
+    if let Some(n) = [white, black]
+        .into_iter()
+        .find(|&n| n >= CHESS960_POSITIONS)
+    {
+        return Err(format!(
+            "Invalid Chess960 position {n}, numbers go up to {}",
+            CHESS960_POSITIONS - 1
+        ));
+    }
+
+    Ok(format!(
+        "{:#}",
+        Board::double_chess960_startpos(white, black)
+    ))
+}
+
+pub const DEFAULT_HASH: u32 = 16;
+
+// Share of the available memory a hash size of 0 takes, in percent
+const AUTO_HASH_PERCENT: u64 = 25;
+
+// Megabytes of memory available when the first engine was set up, where the platform tells
+static AVAILABLE_MEMORY: Lazy<Option<u64>> = Lazy::new(available_memory);
+
+// Epochs the hash table ages by on a new game that keeps it, so that the last game's entries give
+// way to this one's unless much deeper
+const KEEP_HASH_AGING: usize = 8;
+
+/// The EvalFile value standing for the net compiled into the binary
+pub const EMBEDDED_NET: &str = "<embedded>";
+
+// Read by every search and evaluation for as long as it uses the net, which can only be replaced
+// while nothing holds it
+static NET_IN_USE: RwLock<()> = RwLock::new(());
+
+fn hold_net() -> RwLockReadGuard<'static, ()> {
+    NET_IN_USE.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub const MAX_HASH: u32 = 1_000_000;
+#[cfg(not(target_arch = "wasm32"))]
+pub const MAX_THREADS: usize = 256;
+
+// 4 GB of linear memory and no threads
+#[cfg(target_arch = "wasm32")]
+pub const MAX_HASH: u32 = 2048;
+#[cfg(target_arch = "wasm32")]
+pub const MAX_THREADS: usize = 1;
+
+pub const MAX_NODESTIME: u64 = 10_000;
+
+/// Search limits, mirroring the UCI `go` command. Times are in milliseconds and the first
+/// limit set out of depth, nodes, movetime and the side to move's clock is used. With none
+/// set, the search runs until stopped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<u64>,
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: u64,
+    pub binc: u64,
+    pub movestogo: Option<u8>,
+}
+
+impl Limits {
+    #[must_use]
+    pub fn search_type(&self, stm: Color) -> SearchType {
+        let (time, inc) = match stm {
+            Color::White => (self.wtime, self.winc),
+            Color::Black => (self.btime, self.binc),
+        };
+
+        if let Some(depth) = self.depth {
+            SearchType::Depth(depth)
+        } else if let Some(nodes) = self.nodes {
+            SearchType::Nodes(nodes)
+        } else if let Some(movetime) = self.movetime {
+            SearchType::Time(movetime, movetime)
+        } else if let Some(time) = time {
+            let (soft, hard) = time_for_move(time, inc, self.movestogo);
+            SearchType::Time(soft, hard)
+        } else {
+            SearchType::Infinite
+        }
+    }
+}
+
+/// Stops a running search from another thread
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StopHandle;
+
+impl StopHandle {
+    pub fn stop(&self) {
+        store_ponder(false);
+        store_stop(true);
+    }
+}
+
+pub struct Engine {
+    board: Board,
+    game_history: Vec<u64>,
+    // The positions before every move played, for undo
+    previous: Vec<Board>,
+    tt: TT,
+    // An accumulator stack per search thread, allocated up front and reused by every search. The
+    // main thread's also evaluates the current position.
+    stacks: Vec<Box<NNUEState>>,
+    history: History,
+    last_score: Option<i32>,
+    hash: u32,
+    // Segment the hash table is shared through with other processes
+    shared_hash: Option<String>,
+    threads: usize,
+    nodestime: u64,
+    variety: i32,
+    variety_plies: u32,
+    seed: u64,
+    debug: bool,
+    ponder: bool,
+    show_refutations: bool,
+    keep_hash: bool,
+    reporter: Option<Box<dyn SearchReporter>>,
+    stop_poll: Option<Box<StopPoll>>,
+    // Enabled until the search it is for has been recorded
+    trace: Option<Trace>,
+}
+
+impl Engine {
+    /// A silent single threaded engine with a 16 MB hash table, set up at the start position
+    #[must_use]
+    pub fn new() -> Self {
+        // Generate the KPK bitbase up front instead of during the first search that needs it
+        kpk::init();
+        Lazy::force(&AVAILABLE_MEMORY);
+
+        let board = Board::default();
+        Self {
+            game_history: vec![board.hash()],
+            previous: vec![],
+            tt: TT::new(DEFAULT_HASH),
+            stacks: vec![NNUEState::from_board(&board)],
+            history: History::new(),
+            last_score: None,
+            hash: DEFAULT_HASH,
+            shared_hash: None,
+            threads: 1,
+            nodestime: 0,
+            variety: 0,
+            variety_plies: variety::DEFAULT_VARIETY_PLIES,
+            seed: 0,
+            debug: false,
+            ponder: false,
+            show_refutations: false,
+            keep_hash: false,
+            reporter: None,
+            stop_poll: None,
+            trace: None,
+            board,
+        }
+    }
+
+    #[must_use]
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// Static network evaluation of the current position, from the side to move's perspective
+    pub fn evaluate(&mut self) -> i32 {
+        let _net = hold_net();
+        let nnue = &mut self.stacks[0];
+        nnue.refresh(&self.board);
+        nnue.evaluate(self.board.side_to_move())
+    }
+
+    /// Evaluates with the net file at `path` from now on, or with the embedded net for an empty
+    /// path or `<embedded>`. Nets are shared by every engine in the process, the others pick up
+    /// the new one at their next search or evaluation. Fails while any engine is searching.
+    pub fn load_net(&mut self, path: &str) -> Result<NetHeader, String> {
+        let _replacing = NET_IN_USE
+            .try_write()
+            .map_err(|_| "cannot change the net while a search is running".to_string())?;
+
+        if path.is_empty() || path == EMBEDDED_NET {
+            inference::use_embedded_net();
+        } else {
+            let bytes = std::fs::read(path).map_err(|e| format!("could not read {path}: {e}"))?;
+            inference::load_net(&bytes).map_err(|e| format!("could not load {path}: {e}"))?;
+        }
+
+        Ok(inference::net_header())
+    }
+
+    /// Header of the net evaluations are made with
+    #[must_use]
+    pub fn net(&self) -> NetHeader {
+        inference::net_header()
+    }
+
+    /// Resizes and clears the hash table, in megabytes. 0 picks the size from the memory
+    /// available with `auto_hash`.
+    pub fn set_hash(&mut self, mb: u32) -> Result<(), String> {
+        if mb > MAX_HASH {
+            return Err(format!(
+                "Hash size must be at most {MAX_HASH} MB, or 0 to fit the memory available"
+            ));
+        }
+        let mb = if mb == 0 { auto_hash() } else { mb };
+
+        self.tt = match &self.shared_hash {
+            Some(name) => TT::shared(mb, name)?,
+            None => TT::new(mb),
+        };
+        self.hash = mb;
+        Ok(())
+    }
+
+    /// Moves the hash table into the shared memory segment `name`, so that other engine
+    /// processes using the same name and Hash size search with the same entries. An empty name
+    /// goes back to a hash table of its own. A shared table is only aged by `new_game` and
+    /// `clear_hash`, never cleared, and the segment is left behind when the processes exit.
+    pub fn set_shared_hash(&mut self, name: &str) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() {
+            self.shared_hash = None;
+            self.tt = TT::new(self.hash);
+        } else {
+            self.tt = TT::shared(self.hash, name)?;
+            self.shared_hash = Some(name.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), String> {
+        if !(1..=MAX_THREADS).contains(&threads) {
+            return Err(format!("Thread count must be between 1 and {MAX_THREADS}"));
+        }
+
+        self.threads = threads;
+        self.stacks.truncate(threads);
+        let board = &self.board;
+        self.stacks
+            .resize_with(threads, || NNUEState::from_board(board));
+        Ok(())
+    }
+
+    #[must_use]
+    pub const fn hash(&self) -> u32 {
+        self.hash
+    }
+
+    #[must_use]
+    pub const fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Nodes per millisecond of the virtual clock used for time controls, zero for the wall clock
+    pub fn set_nodestime(&mut self, nodestime: u64) -> Result<(), String> {
+        if nodestime > MAX_NODESTIME {
+            return Err(format!("nodestime must be at most {MAX_NODESTIME}"));
+        }
+
+        self.nodestime = nodestime;
+        Ok(())
+    }
+
+    /// How far behind the best score, in centipawns, a move can be and still be played instead
+    /// of it in the opening. Zero always plays the best move.
+    pub fn set_variety(&mut self, margin: i32) -> Result<(), String> {
+        if !(0..=MAX_VARIETY).contains(&margin) {
+            return Err(format!("Variety must be between 0 and {MAX_VARIETY}"));
+        }
+
+        self.variety = margin;
+        Ok(())
+    }
+
+    /// Plies from the start of the game, going by the move number, that variety applies to
+    pub fn set_variety_plies(&mut self, plies: u32) -> Result<(), String> {
+        if plies > MAX_VARIETY_PLIES {
+            return Err(format!("Variety plies must be at most {MAX_VARIETY_PLIES}"));
+        }
+
+        self.variety_plies = plies;
+        Ok(())
+    }
+
+    /// Seeds the variety draws. Zero draws them at random, any other seed makes each depend only
+    /// on it and the position: a single threaded engine with node limits then plays the same
+    /// moves, searching the same nodes, every time it's given the same commands.
+    pub fn set_seed(&mut self, seed: u64) -> Result<(), String> {
+        if seed > MAX_SEED {
+            return Err(format!("Seed must be at most {MAX_SEED}"));
+        }
+
+        self.seed = seed;
+        Ok(())
+    }
+
+    /// Reports the search statistics through the reporter's messages before every bestmove
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+
+    /// Reports the move expected in reply along with every bestmove, for the GUI to ponder on
+    pub fn set_ponder(&mut self, ponder: bool) {
+        self.ponder = ponder;
+    }
+
+    /// Reports the line refuting each root move that can't beat the best one
+    pub fn set_show_refutations(&mut self, show: bool) {
+        self.show_refutations = show;
+    }
+
+    /// Keeps the hash table between games, aged so that the new game's entries replace it
+    pub fn set_keep_hash(&mut self, keep: bool) {
+        self.keep_hash = keep;
+    }
+
+    /// Where the main thread's reports go, `None` for a silent engine
+    pub fn set_reporter(&mut self, reporter: Option<Box<dyn SearchReporter>>) {
+        self.reporter = reporter;
+    }
+
+    /// Sets a function the searching thread calls every few thousand nodes, the search stops
+    /// once it returns true
+    pub fn set_stop_poll(&mut self, poll: Option<Box<StopPoll>>) {
+        self.stop_poll = poll;
+    }
+
+    /// Records the main thread's search tree during the next search, up to the limits
+    pub fn trace_next_search(&mut self, limits: TraceLimits) {
+        self.trace = Some(Trace::new(limits));
+    }
+
+    /// The tree recorded during the last search asked to be traced
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take_if(|trace| !trace.enabled)
+    }
+
+    /// Forgets everything learned in previous searches and goes back to the start position. With
+    /// `set_keep_hash` or a shared hash table, the hash table is only aged instead.
+    pub fn new_game(&mut self) {
+        self.board = Board::default();
+        self.game_history = vec![self.board.hash()];
+        self.previous.clear();
+
+        if self.keep_hash {
+            self.age_hash();
+            self.history = History::new();
+            self.last_score = None;
+        } else {
+            self.clear_hash();
+        }
+    }
+
+    /// Forgets everything learned in previous searches, keeping the position. The hash table is
+    /// cleared by all the search threads at once, or only aged if shared.
+    pub fn clear_hash(&mut self) {
+        // The other processes sharing the table are still using it
+        if self.tt.is_shared() {
+            self.age_hash();
+        } else {
+            self.tt.clear(self.threads);
+        }
+        self.history = History::new();
+        self.last_score = None;
+    }
+
+    fn age_hash(&self) {
+        for _ in 0..KEEP_HASH_AGING {
+            self.tt.age();
+        }
+    }
+
+    /// Sets the position from a FEN and the moves played from it, in UCI or SAN notation. Nothing
+    /// changes if either is invalid. Castling rights of Chess960 positions are given in Shredder
+    /// notation.
+    pub fn set_position(&mut self, fen: &str, moves: &[&str]) -> Result<(), String> {
+        let mut board = Board::from_fen(fen, false)
+            .or_else(|_| Board::from_fen(fen, true))
+            .map_err(|_| format!("Invalid FEN {fen}"))?;
+        let mut game_history = vec![board.hash()];
+        let mut previous = Vec::with_capacity(moves.len());
+
+        for &mv in moves {
+            let parsed = parse_move(&board, mv).ok_or_else(|| format!("Illegal move {mv}"))?;
+
+            previous.push(board.clone());
+            board.play_unchecked(parsed);
+            game_history.push(board.hash());
+        }
+
+        self.board = board;
+        self.game_history = game_history;
+        self.previous = previous;
+        Ok(())
+    }
+
+    /// Plays a move in UCI or SAN notation from the current position
+    pub fn make_move(&mut self, mv: &str) -> Result<(), String> {
+        let parsed = parse_move(&self.board, mv).ok_or_else(|| format!("Illegal move {mv}"))?;
+
+        self.previous.push(self.board.clone());
+        self.board.play_unchecked(parsed);
+        self.game_history.push(self.board.hash());
+        Ok(())
+    }
+
+    /// Takes back the last move played, by `make_move` or in the moves of `set_position`
+    pub fn undo(&mut self) -> Result<(), String> {
+        let board = self
+            .previous
+            .pop()
+            .ok_or_else(|| "No moves to undo".to_string())?;
+
+        self.board = board;
+        self.game_history.pop();
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle
+    }
+
+    /// Searches the current position, blocking until the limits are reached or a `StopHandle`
+    /// stops it. Returns `None` if there are no legal moves.
+    pub fn search(&mut self, limits: Limits) -> Option<SearchResult> {
+        let _net = hold_net();
+        let timer = Instant::now();
+        let st = limits.search_type(self.board.side_to_move());
+        let board = &self.board;
+
+        let mut stacks = std::mem::take(&mut self.stacks)
+            .into_iter()
+            .map(|mut nnue| {
+                nnue.refresh(board);
+                nnue
+            });
+
+        let mut search = Search::new(
+            &self.tt,
+            stacks.next().unwrap(),
+            &self.history,
+            &self.game_history,
+        );
+        search
+            .info
+            .time_manager
+            .set_prev_move_score(self.last_score);
+        search.info.time_manager.set_nodestime(self.nodestime);
+
+        let mut helpers: Vec<Search> = stacks
+            .map(|nnue| Search::new(&self.tt, nnue, &self.history, &self.game_history))
+            .collect();
+
+        if let Some(trace) = self.trace.take() {
+            search.info.trace = trace;
+        }
+        search.stop_poll = self.stop_poll.as_deref_mut();
+        search.show_refutations = self.show_refutations;
+
+        // Variety is for the opening, going by the move number
+        let ply = u32::from(board.fullmove_number() - 1) * 2
+            + u32::from(board.side_to_move() == Color::Black);
+        if ply < self.variety_plies {
+            search.variety_margin = self.variety;
+        }
+        search.reporter = self
+            .reporter
+            .as_deref_mut()
+            .map(|r| r as &mut dyn SearchReporter);
+
+        // Single threaded searches stay on this thread, wasm32 can't spawn any
+        let mut result = if helpers.is_empty() {
+            search.iterative_deepening::<true>(board, st)
+        } else {
+            std::thread::scope(|s| {
+                for helper in &mut helpers {
+                    s.spawn(|| helper.iterative_deepening::<false>(board, st));
+                }
+
+                search.iterative_deepening::<true>(board, st)
+            })
+        };
+
+        if search.info.trace.enabled {
+            let mut trace = std::mem::take(&mut search.info.trace);
+            trace.enabled = false;
+            self.trace = Some(trace);
+        }
+
+        // Won and lost positions are left alone. An interrupted iteration can have found a
+        // better move than the last completed one knew of, it is played then.
+        let candidates = &search.info.candidates;
+        if let Some(result) = result.as_mut().filter(|r| {
+            search.variety_margin > 0
+                && r.score.abs() < TB_WIN_IN_PLY
+                && candidates.iter().any(|&(mv, _)| mv == r.best_move)
+        }) {
+            let random = match self.seed {
+                0 => fastrand::f64(),
+                seed => variety::seeded_random(seed, board.hash()),
+            };
+
+            if let Some(mv) = variety::pick(candidates, self.variety, random) {
+                if mv != result.best_move {
+                    result.best_move = mv;
+                    result.score = candidates.iter().find(|&&(c, _)| c == mv).unwrap().1;
+                    result.pv = vec![mv];
+
+                    // The last info line is the one for the move played
+                    if let Some(reporter) = search.reporter.as_deref_mut() {
+                        reporter.depth_completed(&IterationReport {
+                            depth: result.depth,
+                            seldepth: result.seldepth,
+                            score: result.score,
+                            lower_bound: false,
+                            nodes: result.nodes,
+                            time: timer.elapsed().as_millis() as u64,
+                            pv: &result.pv,
+                            board,
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(reporter) = search.reporter {
+            if self.debug {
+                for line in search.info.stats.summary() {
+                    reporter.message(&line);
+                }
+            }
+            let ponder = result
+                .as_ref()
+                .filter(|_| self.ponder)
+                .and_then(|r| ponder_move(&self.tt, board, r));
+            reporter.bestmove(result.as_ref().map(|r| r.best_move), ponder);
+        }
+
+        self.stacks = std::iter::once(search.nnue)
+            .chain(helpers.into_iter().map(|helper| helper.nnue))
+            .collect();
+        self.last_score = search.info.time_manager.last_score();
+        self.history = search.info.history;
+        self.history.age_table();
+        self.tt.age();
+        store_stop(false);
+
+        result
+    }
+}
+
+/// The hash size a quarter of the memory available at startup allows, rounded down to a power
+/// of two, and the default where the platform doesn't tell how much is available
+#[must_use]
+pub fn auto_hash() -> u32 {
+    AVAILABLE_MEMORY.map_or(DEFAULT_HASH, |mb| {
+        let share = (mb * AUTO_HASH_PERCENT / 100).clamp(1, u64::from(MAX_HASH));
+        (1 << share.ilog2()).max(DEFAULT_HASH)
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kb: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kb / 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+const fn available_memory() -> Option<u64> {
+    None
+}
+
+// The reply expected to the best move: the second move of its PV, or the TT move of the position
+// after it when the PV ends there
+fn ponder_move(tt: &TT, board: &Board, result: &SearchResult) -> Option<Move> {
+    if let Some(&mv) = result.pv.get(1) {
+        return Some(mv);
+    }
+
+    let mut child = board.clone();
+    child.play_unchecked(check_castling_move(board, result.best_move));
+    let entry = tt.probe(child.hash());
+    if entry.key != child.hash() as u16 || entry.mv == PackedMove::new(None) {
+        return None;
+    }
+
+    let mv = PackedMove::unpack(entry.mv);
+    child
+        .is_legal(mv)
+        .then(|| reverse_castling_move(&child, mv))
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What to compute for each position of a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchJob {
+    Evaluate,
+    Depth(usize),
+    Nodes(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchResult {
+    /// `None` for evaluations and positions without legal moves
+    pub best_move: Option<Move>,
+    /// From the side to move's perspective
+    pub score: i32,
+}
+
+/// Evaluates or searches every position on its own, spread over `threads` threads with a hash
+/// table of `hash` MB each. Every position starts from a cleared state, so the results don't
+/// depend on the order of the positions or the number of threads. Node limits are checked
+/// between iterations, like in datagen.
+#[must_use]
+pub fn batch(
+    fens: &[&str],
+    job: BatchJob,
+    threads: usize,
+    hash: u32,
+) -> Vec<Result<BatchResult, String>> {
+    kpk::init();
+    let _net = hold_net();
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![Err(String::new()); fens.len()]);
+    let hash = hash.clamp(1, MAX_HASH);
+
+    let worker = || {
+        let board = Board::default();
+        let tt = TT::new(hash);
+        let nnue = NNUEState::from_board(&board);
+        let history = History::new();
+        let mut search = Search::new(&tt, nnue, &history, &vec![]);
+
+        loop {
+            let idx = next.fetch_add(1, Ordering::Relaxed);
+            let Some(fen) = fens.get(idx) else {
+                break;
+            };
+
+            let result = Board::from_fen(fen, false)
+                .map_err(|_| format!("Invalid FEN {fen}"))
+                .map(|board| {
+                    tt.reset();
+                    search.game_reset();
+                    search.info.game_history = vec![board.hash()];
+                    batch_position(&mut search, &board, job)
+                });
+            results.lock().unwrap()[idx] = result;
+        }
+    };
+
+    // Single threaded batches stay on this thread, wasm32 can't spawn any
+    let threads = threads.clamp(1, MAX_THREADS).min(fens.len().max(1));
+    if threads == 1 {
+        worker();
+    } else {
+        std::thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(worker);
+            }
+        });
+    }
+
+    results.into_inner().unwrap()
+}
+
+fn batch_position(search: &mut Search, board: &Board, job: BatchJob) -> BatchResult {
+    search.nnue.refresh(board);
+
+    let st = match job {
+        BatchJob::Evaluate => {
+            let eval = search.nnue.evaluate(board.side_to_move());
+            return BatchResult {
+                best_move: None,
+                score: endgame::evaluate(board, eval),
+            };
+        }
+        BatchJob::Depth(depth) => SearchType::Depth(depth.max(1)),
+        BatchJob::Nodes(nodes) => SearchType::Nodes(nodes),
+    };
+
+    match board.status() {
+        GameStatus::Won => BatchResult {
+            best_move: None,
+            score: -MATE,
+        },
+        GameStatus::Drawn => BatchResult {
+            best_move: None,
+            score: 0,
+        },
+        GameStatus::Ongoing => {
+            let (score, best_move) = search.data_search(board, st);
+            BatchResult {
+                best_move: Some(best_move),
+                score,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_api() {
+        let mut engine = Engine::new();
+        assert!(engine.set_position(START_FEN, &["e2e4", "e7e6"]).is_ok());
+        assert_eq!(engine.board().fullmove_number(), 2);
+
+        // Invalid positions leave the current one in place
+        assert!(engine.set_position(START_FEN, &["e2e5"]).is_err());
+        assert!(engine.set_position("8/8/8 w - - 0 1", &[]).is_err());
+        assert_eq!(engine.board().fullmove_number(), 2);
+
+        engine
+            .set_position(
+                START_FEN,
+                &["e2e4", "e7e5", "g1f3", "g8f6", "f1e2", "f8e7", "e1g1"],
+            )
+            .unwrap();
+        assert!(engine.board().castle_rights(Color::White).short.is_none());
+
+        // SAN reaches the same position, and mixes with UCI moves
+        let uci = engine.board().clone();
+        let san = ["e4", "e5", "Nf3", "Nf6", "Be2", "Be7", "O-O"];
+        engine.set_position(START_FEN, &san).unwrap();
+        assert_eq!(engine.board(), &uci);
+        let mixed = ["e4", "e7e5", "Nf3", "g8f6", "f1e2", "Be7", "e1g1"];
+        engine.set_position(START_FEN, &mixed).unwrap();
+        assert_eq!(engine.board(), &uci);
+
+        assert!(engine.set_hash(MAX_HASH + 1).is_err());
+        assert!(engine.set_threads(MAX_THREADS + 1).is_err());
+        assert!(engine.set_nodestime(MAX_NODESTIME + 1).is_err());
+
+        let limits = Limits {
+            nodes: Some(1000),
+            movetime: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(limits.search_type(Color::White), SearchType::Nodes(1000));
+        assert_eq!(
+            Limits::default().search_type(Color::White),
+            SearchType::Infinite
+        );
+
+        let clock = Limits {
+            wtime: Some(10_000),
+            ..Default::default()
+        };
+        assert!(matches!(
+            clock.search_type(Color::White),
+            SearchType::Time(..)
+        ));
+        assert_eq!(clock.search_type(Color::Black), SearchType::Infinite);
+    }
+
+    #[test]
+    fn make_and_undo() {
+        // Moves are played and taken back one at a time, repetitions included
+        let mut engine = Engine::new();
+        engine
+            .set_position(START_FEN, &["e4", "e5", "Nf3"])
+            .unwrap();
+        for mv in ["b8c6", "Ng1", "Nb8", "Nf3"] {
+            engine.make_move(mv).unwrap();
+        }
+        assert!(engine.make_move("e2e4").is_err());
+        assert_eq!(engine.game_history.len(), 8);
+        assert_eq!(engine.game_history[7], engine.game_history[3]);
+
+        for _ in 0..6 {
+            engine.undo().unwrap();
+        }
+        assert_eq!(engine.board().fullmove_number(), 1);
+        assert_eq!(engine.game_history.len(), 2);
+        engine.undo().unwrap();
+        assert_eq!(engine.board(), &Board::default());
+        assert!(engine.undo().is_err());
+    }
+
+    #[test]
+    fn load_net() {
+        // The net can't be swapped under a running search or evaluation
+        let mut engine = Engine::new();
+        let eval = engine.evaluate();
+        {
+            let _net = hold_net();
+            assert_eq!(
+                engine.load_net(EMBEDDED_NET),
+                Err("cannot change the net while a search is running".to_string())
+            );
+        }
+        assert!(engine.load_net("missing.nnue").is_err());
+        assert_eq!(engine.evaluate(), eval);
+    }
+
+    #[test]
+    fn clear_hash() {
+        // Clear Hash forgets the searches, not the position
+        let mut engine = Engine::new();
+        engine.set_position(START_FEN, &["e4"]).unwrap();
+        engine.search(Limits {
+            depth: Some(4),
+            ..Default::default()
+        });
+        assert!(engine.tt.probe(engine.board().hash()).depth > 0);
+        engine.clear_hash();
+        assert_eq!(engine.tt.probe(engine.board().hash()).depth, 0);
+        assert_eq!(engine.board().fullmove_number(), 1);
+    }
+
+    #[test]
+    fn keep_hash() {
+        // KeepHash carries the table over to the next game, aged
+        let mut engine = Engine::new();
+        engine.set_keep_hash(true);
+        engine.search(Limits {
+            depth: Some(4),
+            ..Default::default()
+        });
+        let (searched, epoch) = (engine.board().hash(), engine.tt.epoch());
+        engine.new_game();
+        assert!(engine.tt.probe(searched).depth > 0);
+        assert_eq!(engine.tt.epoch(), epoch + KEEP_HASH_AGING as u8);
+        engine.set_keep_hash(false);
+        engine.new_game();
+        assert_eq!(engine.tt.probe(searched).depth, 0);
+    }
+
+    #[test]
+    fn accumulator_stacks() {
+        // Every thread's accumulator stack outlives the searches, the main thread's evaluating the
+        // position in between
+        let mut engine = Engine::new();
+        let eval = engine.evaluate();
+        engine.set_threads(2).unwrap();
+        let stacks: Vec<*const NNUEState> = engine.stacks.iter().map(|s| &**s as _).collect();
+        assert_eq!(stacks.len(), 2);
+        engine.search(Limits {
+            depth: Some(3),
+            ..Default::default()
+        });
+        let after: Vec<*const NNUEState> = engine.stacks.iter().map(|s| &**s as _).collect();
+        assert_eq!(after, stacks);
+        assert_eq!(engine.evaluate(), eval);
+        engine.set_threads(1).unwrap();
+        assert_eq!(engine.stacks.len(), 1);
+    }
+
+    #[test]
+    fn auto_hash_size() {
+        let mut engine = Engine::new();
+        engine.set_hash(0).unwrap();
+        assert_eq!(engine.hash(), auto_hash());
+        assert!(auto_hash().is_power_of_two() && (DEFAULT_HASH..=MAX_HASH).contains(&auto_hash()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_hash() {
+        // Engines sharing a hash table see each other's searches, across games too
+        let name = format!("svart-api-test-{}", std::process::id());
+        let mut engine = Engine::new();
+        let mut other = Engine::new();
+        engine.set_shared_hash(&name).unwrap();
+        other.set_shared_hash(&name).unwrap();
+        engine.search(Limits {
+            depth: Some(4),
+            ..Default::default()
+        });
+        other.new_game();
+        other.clear_hash();
+        assert!(other.tt.probe(engine.board().hash()).depth > 0);
+        // The segment has the size it was made with
+        assert!(engine.set_hash(1).is_err());
+        assert!(engine.tt.is_shared() && engine.hash() == DEFAULT_HASH);
+
+        other.set_shared_hash("").unwrap();
+        engine.set_shared_hash("").unwrap();
+        assert!(!other.tt.is_shared() && !engine.tt.is_shared());
+        crate::body::shm::unlink(&name).unwrap();
+    }
+
+    struct Refutations(std::sync::Arc<Mutex<Vec<Vec<Move>>>>);
+
+    impl SearchReporter for Refutations {
+        fn refutation(&mut self, line: &[Move]) {
+            self.0.lock().unwrap().push(line.to_vec());
+        }
+    }
+
+    #[test]
+    fn refutation_lines() {
+        let lines = std::sync::Arc::new(Mutex::new(vec![]));
+        let mut engine = Engine::new();
+        engine.set_reporter(Some(Box::new(Refutations(lines.clone()))));
+
+        // Hanging the queen is refuted by taking it
+        engine
+            .set_position("4k3/8/8/8/8/8/r7/3QK3 w - - 0 1", &[])
+            .unwrap();
+        let limits = Limits {
+            depth: Some(4),
+            ..Default::default()
+        };
+        engine.search(limits);
+        assert!(lines.lock().unwrap().is_empty());
+
+        engine.set_show_refutations(true);
+        let result = engine.search(limits).unwrap();
+        let lines = lines.lock().unwrap();
+        assert!(!lines.is_empty());
+        assert!(lines.iter().all(|line| line[0] != result.best_move));
+
+        let queen_move = lines.iter().find(|line| line[0].to_string() == "d1a4");
+        assert_eq!(
+            queen_move.map(|line| line[1].to_string()),
+            Some("a2a4".into())
+        );
+    }
+
+    #[test]
+    fn ponder_fallback() {
+        let mut engine = Engine::new();
+        let mut result = engine
+            .search(Limits {
+                depth: Some(6),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            ponder_move(&engine.tt, &engine.board, &result),
+            Some(result.pv[1])
+        );
+
+        // Without a PV past the best move, its reply comes from the TT
+        result.pv.truncate(1);
+        let ponder = ponder_move(&engine.tt, &engine.board, &result).unwrap();
+        let mut board = engine.board.clone();
+        board.play(result.best_move);
+        assert!(board.is_legal(ponder));
+
+        engine.clear_hash();
+        assert_eq!(ponder_move(&engine.tt, &engine.board, &result), None);
+    }
+
+    // Score and PV of every iteration reported
+    type Reports = std::sync::Arc<Mutex<Vec<(i32, Vec<Move>)>>>;
+
+    struct Iterations(Reports);
+
+    impl SearchReporter for Iterations {
+        fn depth_completed(&mut self, report: &IterationReport) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((report.score, report.pv.to_vec()));
+        }
+    }
+
+    #[test]
+    fn interrupted_iterations() {
+        let reports = std::sync::Arc::new(Mutex::new(vec![]));
+        let mut engine = Engine::new();
+        engine.set_reporter(Some(Box::new(Iterations(reports.clone()))));
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+
+        // Wherever the search is stopped, the last report is what gets played
+        for polls in [3, 10, 25, 60] {
+            let mut count = 0;
+            engine.set_stop_poll(Some(Box::new(move || {
+                count += 1;
+                count > polls
+            })));
+            engine.new_game();
+            engine.set_position(fen, &[]).unwrap();
+
+            let result = engine.search(Limits::default()).unwrap();
+            let reports = reports.lock().unwrap();
+            let (score, pv) = reports.last().unwrap();
+            assert_eq!((*score, pv[0]), (result.score, result.best_move));
+            assert_eq!(pv, &result.pv);
+        }
+    }
+
+    #[test]
+    fn variety_moves() {
+        let reports: Reports = std::sync::Arc::new(Mutex::new(vec![]));
+
+        // Whether different seeds play different moves, each one reported last
+        let varied = |engine: &mut Engine| {
+            let moves: Vec<Move> = (1..=6)
+                .map(|seed| {
+                    engine.set_seed(seed).unwrap();
+                    engine.new_game();
+                    let limits = Limits {
+                        depth: Some(5),
+                        ..Default::default()
+                    };
+                    let result = engine.search(limits).unwrap();
+                    let reports = reports.lock().unwrap();
+                    assert_eq!(reports.last().unwrap().1, result.pv);
+                    result.best_move
+                })
+                .collect();
+            moves.iter().any(|&mv| mv != moves[0])
+        };
+
+        // The root moves close to the best are scored by the search itself
+        let mut engine = Engine::new();
+        engine.set_reporter(Some(Box::new(Iterations(reports.clone()))));
+        assert!(!varied(&mut engine));
+        engine.set_variety(MAX_VARIETY).unwrap();
+        assert!(varied(&mut engine));
+        engine.set_variety_plies(0).unwrap();
+        assert!(!varied(&mut engine));
+    }
+
+    #[test]
+    fn batch_positions() {
+        let fens = [
+            "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1",
+            "not a fen",
+            "R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 1 1",
+            "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1",
+            START_FEN,
+        ];
+
+        let results = batch(&fens, BatchJob::Depth(4), 1, 1);
+        assert_eq!(
+            results[0].as_ref().unwrap().best_move,
+            Some("a1a8".parse().unwrap())
+        );
+        assert!(results[1].is_err());
+        assert_eq!(
+            results[2],
+            Ok(BatchResult {
+                best_move: None,
+                score: -MATE
+            })
+        );
+        assert_eq!(results[3].as_ref().unwrap().score, 0);
+
+        // Every position starts from scratch, so threads don't change anything
+        assert_eq!(batch(&fens, BatchJob::Depth(4), 3, 1), results);
+
+        let evals = batch(&fens, BatchJob::Evaluate, 2, 1);
+        assert!(evals[0].as_ref().unwrap().score > 0);
+        assert!(evals[4].as_ref().unwrap().best_move.is_none());
+    }
+}
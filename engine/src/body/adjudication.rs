@@ -0,0 +1,239 @@
+// Game adjudication
+// Whether a game is over by the rules, or decided enough to be called off by its scores.
+// Meant for whatever plays games with the engine: bots, datagen and the match runner.
+use super::kpk;
+
+use cozy_chess::{Board, Color, GameStatus, Piece};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Checkmate,
+    Stalemate,
+    Repetition,
+    FiftyMoves,
+    InsufficientMaterial,
+    // The KPK bitbase knows the result
+    Bitbase,
+    Resign,
+    DrawScore,
+    MaxPlies,
+}
+
+impl Reason {
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Checkmate => "checkmate",
+            Self::Stalemate => "stalemate",
+            Self::Repetition => "3-fold repetition",
+            Self::FiftyMoves => "50-move rule",
+            Self::InsufficientMaterial => "insufficient material",
+            Self::Bitbase => "bitbase result",
+            Self::Resign => "resign adjudication",
+            Self::DrawScore => "draw adjudication",
+            Self::MaxPlies => "maximum game length",
+        }
+    }
+}
+
+/// Score based adjudication, zero plies turn a rule off.
+/// Scores are white relative, one per ply from the first scored position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdjudicationRules {
+    /// The side the last `resign_plies` scores are all at least this much against loses
+    pub resign_score: i32,
+    pub resign_plies: usize,
+    /// Drawn once the last `draw_plies` scores are all within this of zero, from `draw_min_ply` on
+    pub draw_score: i32,
+    pub draw_plies: usize,
+    pub draw_min_ply: usize,
+    /// Drawn once this many plies have been scored
+    pub max_plies: usize,
+}
+
+impl AdjudicationRules {
+    /// Only the rules of chess, scores never end a game
+    pub const NONE: Self = Self {
+        resign_score: 0,
+        resign_plies: 0,
+        draw_score: 0,
+        draw_plies: 0,
+        draw_min_ply: 0,
+        max_plies: 0,
+    };
+}
+
+/// Whether the game is over, by the rules first and by its scores otherwise.
+/// `history` holds the hashes of every position of the game, the current one included.
+#[must_use]
+pub fn adjudicate(
+    board: &Board,
+    history: &[u64],
+    scores: &[i32],
+    rules: &AdjudicationRules,
+) -> Option<(GameResult, Reason)> {
+    game_over(board, history).or_else(|| adjudicate_scores(scores, rules))
+}
+
+/// Checkmate, stalemate, repetition, the 50-move rule, insufficient material and bitbase results
+#[must_use]
+pub fn game_over(board: &Board, history: &[u64]) -> Option<(GameResult, Reason)> {
+    let loss = match board.side_to_move() {
+        Color::White => GameResult::BlackWins,
+        Color::Black => GameResult::WhiteWins,
+    };
+
+    match board.status() {
+        GameStatus::Won => return Some((loss, Reason::Checkmate)),
+        GameStatus::Drawn if board.checkers().is_empty() && board.halfmove_clock() < 100 => {
+            return Some((GameResult::Draw, Reason::Stalemate));
+        }
+        GameStatus::Drawn => return Some((GameResult::Draw, Reason::FiftyMoves)),
+        GameStatus::Ongoing => {}
+    }
+
+    let hash = board.hash();
+    if history.iter().filter(|&&h| h == hash).count() >= 3 {
+        return Some((GameResult::Draw, Reason::Repetition));
+    }
+
+    if insufficient_material(board) {
+        return Some((GameResult::Draw, Reason::InsufficientMaterial));
+    }
+
+    // The side with the pawn is the only one that can win
+    let won = kpk::probe(board)?;
+    let result = match board.color_on(board.pieces(Piece::Pawn).next_square()?) {
+        _ if !won => GameResult::Draw,
+        Some(Color::White) => GameResult::WhiteWins,
+        _ => GameResult::BlackWins,
+    };
+
+    Some((result, Reason::Bitbase))
+}
+
+/// Resign, draw and game length adjudication by the scores alone
+#[must_use]
+pub fn adjudicate_scores(
+    scores: &[i32],
+    rules: &AdjudicationRules,
+) -> Option<(GameResult, Reason)> {
+    // Whether the last `plies` scores all satisfy the condition
+    let agree = |plies: usize, condition: &dyn Fn(i32) -> bool| {
+        plies > 0
+            && scores.len() >= plies
+            && scores[scores.len() - plies..].iter().all(|&s| condition(s))
+    };
+
+    if agree(rules.resign_plies, &|s| s >= rules.resign_score) {
+        Some((GameResult::WhiteWins, Reason::Resign))
+    } else if agree(rules.resign_plies, &|s| s <= -rules.resign_score) {
+        Some((GameResult::BlackWins, Reason::Resign))
+    } else if scores.len() >= rules.draw_min_ply
+        && agree(rules.draw_plies, &|s| s.abs() <= rules.draw_score)
+    {
+        Some((GameResult::Draw, Reason::DrawScore))
+    } else if rules.max_plies > 0 && scores.len() >= rules.max_plies {
+        Some((GameResult::Draw, Reason::MaxPlies))
+    } else {
+        None
+    }
+}
+
+// Bare kings, or a single minor piece left
+fn insufficient_material(board: &Board) -> bool {
+    let pieces = board.occupied().len();
+    let minors = board.pieces(Piece::Bishop) | board.pieces(Piece::Knight);
+
+    pieces == 2 || (pieces == 3 && !minors.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_result(fen: &str) -> Option<(GameResult, Reason)> {
+        let board = Board::from_fen(fen, false).unwrap();
+        game_over(&board, &[board.hash()])
+    }
+
+    #[test]
+    fn rules_of_chess() {
+        assert_eq!(
+            rules_result("R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 1 1"),
+            Some((GameResult::WhiteWins, Reason::Checkmate))
+        );
+        assert_eq!(
+            rules_result("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"),
+            Some((GameResult::Draw, Reason::Stalemate))
+        );
+        assert_eq!(
+            rules_result("4k3/8/8/8/8/8/4P3/R3K3 w - - 100 80"),
+            Some((GameResult::Draw, Reason::FiftyMoves))
+        );
+        assert_eq!(
+            rules_result("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"),
+            Some((GameResult::Draw, Reason::InsufficientMaterial))
+        );
+        assert_eq!(
+            rules_result("8/8/8/8/8/2k5/4p3/6K1 b - - 0 1"),
+            Some((GameResult::BlackWins, Reason::Bitbase))
+        );
+        assert_eq!(
+            rules_result("k7/8/K7/P7/8/8/8/8 w - - 0 1"),
+            Some((GameResult::Draw, Reason::Bitbase))
+        );
+        assert_eq!(rules_result(crate::api::START_FEN), None);
+
+        let board = Board::default();
+        let history = [board.hash(), 1, board.hash(), 2, board.hash()];
+        assert_eq!(
+            game_over(&board, &history),
+            Some((GameResult::Draw, Reason::Repetition))
+        );
+    }
+
+    #[test]
+    fn score_adjudication() {
+        let rules = AdjudicationRules {
+            resign_score: 1000,
+            resign_plies: 4,
+            draw_score: 10,
+            draw_plies: 4,
+            draw_min_ply: 10,
+            max_plies: 20,
+        };
+
+        assert_eq!(adjudicate_scores(&[-1000; 3], &rules), None);
+        assert_eq!(
+            adjudicate_scores(&[0, -1000, -1200, -1000, -5000], &rules),
+            Some((GameResult::BlackWins, Reason::Resign))
+        );
+        assert_eq!(
+            adjudicate_scores(&[1000, 1000, 0, 1000, 1000], &rules),
+            None
+        );
+
+        // Dead draws only count from the minimum ply on
+        assert_eq!(adjudicate_scores(&[0; 9], &rules), None);
+        assert_eq!(
+            adjudicate_scores(&[0; 10], &rules),
+            Some((GameResult::Draw, Reason::DrawScore))
+        );
+        assert_eq!(
+            adjudicate_scores(&[100; 20], &rules),
+            Some((GameResult::Draw, Reason::MaxPlies))
+        );
+        assert_eq!(
+            adjudicate_scores(&[5000; 50], &AdjudicationRules::NONE),
+            None
+        );
+    }
+}
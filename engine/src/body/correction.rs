@@ -0,0 +1,123 @@
+// Correction histories
+// Running averages of how far search results landed from the raw static eval, in positions
+// sharing the pawn structure, the pieces of either side, the material or the minor pieces. The
+// static eval is corrected by a weighted sum of them before the search prunes on it.
+use super::keys::Keys;
+use crate::tunables;
+
+use cozy_chess::{Board, Color};
+
+// Entries per side to move in every table
+const SIZE: usize = 16384;
+// Averages are kept in 1/GRAIN centipawns, and within what an i16 holds
+const GRAIN: i32 = 128;
+const LIMIT: i32 = i16::MAX as i32;
+// Weight of a new result in the averages, out of WEIGHT_SCALE, growing with its depth
+const WEIGHT_SCALE: i32 = 256;
+const MAX_WEIGHT: i32 = 16;
+
+#[derive(Clone)]
+pub struct CorrectionHistory {
+    pawn: Vec<i16>,
+    non_pawn: [Vec<i16>; Color::NUM],
+    material: Vec<i16>,
+    minor: Vec<i16>,
+}
+
+impl CorrectionHistory {
+    #[must_use]
+    pub fn new() -> Self {
+        let table = || vec![0; SIZE * Color::NUM];
+        Self {
+            pawn: table(),
+            non_pawn: [table(), table()],
+            material: table(),
+            minor: table(),
+        }
+    }
+
+    // Every table with the key it is indexed by and its weight in percent
+    fn tables(&mut self, keys: &Keys) -> [(&mut Vec<i16>, u64, i32); 5] {
+        let [white, black] = &mut self.non_pawn;
+        [
+            (&mut self.pawn, keys.pawn, tunables::corr_pawn_weight()),
+            (
+                white,
+                keys.non_pawn[Color::White as usize],
+                tunables::corr_non_pawn_weight(),
+            ),
+            (
+                black,
+                keys.non_pawn[Color::Black as usize],
+                tunables::corr_non_pawn_weight(),
+            ),
+            (
+                &mut self.material,
+                keys.material,
+                tunables::corr_material_weight(),
+            ),
+            (&mut self.minor, keys.minor, tunables::corr_minor_weight()),
+        ]
+    }
+
+    /// The raw static eval of `board` corrected by the averages of positions like it
+    #[must_use]
+    pub fn correct(&mut self, board: &Board, keys: &Keys, eval: i32) -> i32 {
+        let stm = board.side_to_move();
+        let correction: i32 = self
+            .tables(keys)
+            .into_iter()
+            .map(|(table, key, weight)| i32::from(table[index(stm, key)]) * weight)
+            .sum();
+
+        eval + correction / (GRAIN * 100)
+    }
+
+    /// Moves the averages towards the difference between a search result and the raw eval
+    pub fn update(&mut self, board: &Board, keys: &Keys, depth: i32, score: i32, eval: i32) {
+        let stm = board.side_to_move();
+        let target = (score - eval) * GRAIN;
+        let weight = (depth + 1).min(MAX_WEIGHT);
+
+        for (table, key, _) in self.tables(keys) {
+            let entry = &mut table[index(stm, key)];
+            let average =
+                (i32::from(*entry) * (WEIGHT_SCALE - weight) + target * weight) / WEIGHT_SCALE;
+            *entry = average.clamp(-LIMIT, LIMIT) as i16;
+        }
+    }
+}
+
+impl Default for CorrectionHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn index(stm: Color, key: u64) -> usize {
+    stm as usize * SIZE + key as usize % SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrections() {
+        let board = Board::default();
+        let keys = Keys::new(&board);
+        let mut correction = CorrectionHistory::new();
+        assert_eq!(correction.correct(&board, &keys, 20), 20);
+
+        // Searches keep finding the position better than its eval
+        for _ in 0..200 {
+            correction.update(&board, &keys, 10, 120, 20);
+        }
+        let corrected = correction.correct(&board, &keys, 20);
+        assert!(corrected > 100, "{corrected}");
+
+        // The other side to move is a different entry
+        let black = board.null_move().unwrap();
+        assert_eq!(correction.correct(&black, &keys, 20), 20);
+    }
+}
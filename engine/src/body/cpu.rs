@@ -0,0 +1,116 @@
+// CPU dispatch
+// The hot kernels are compiled a second time for wider instruction sets, and the widest one the
+// CPU running the engine supports is picked the first time one is needed. A release built for
+// the x86-64 baseline then runs the network and SEE with AVX2 or AVX-512 wherever they're
+// available, instead of needing a build per CPU with target-cpu=native. On aarch64 NEON is part
+// of the baseline, the generic kernels already use it.
+use once_cell::sync::Lazy;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kernels {
+    Generic,
+    /// AVX2 along with the POPCNT and BMI instructions every AVX2 CPU has
+    Avx2,
+    /// AVX-512 with byte and word instructions, on top of AVX2
+    Avx512,
+}
+
+impl Kernels {
+    pub const ALL: [Self; 3] = [Self::Generic, Self::Avx2, Self::Avx512];
+}
+
+static DETECTED: Lazy<Kernels> = Lazy::new(detect);
+
+// The kernels in use, the detected ones until set otherwise
+static SELECTED: AtomicU8 = AtomicU8::new(UNSET);
+const UNSET: u8 = u8::MAX;
+
+/// The kernels in use
+#[inline]
+#[must_use]
+pub fn kernels() -> Kernels {
+    match SELECTED.load(Ordering::Relaxed) {
+        UNSET => detected(),
+        selected => Kernels::ALL[selected as usize],
+    }
+}
+
+/// The widest kernels the CPU runs
+#[must_use]
+pub fn detected() -> Kernels {
+    *DETECTED
+}
+
+/// Every set of kernels the CPU runs, the generic ones first
+#[must_use]
+pub fn available() -> Vec<Kernels> {
+    // Each set of kernels needs what the previous ones do
+    Kernels::ALL
+        .into_iter()
+        .filter(|&k| k <= detected())
+        .collect()
+}
+
+/// Runs the given kernels from now on, for comparing them
+pub fn set_kernels(kernels: Kernels) -> Result<(), String> {
+    if !available().contains(&kernels) {
+        return Err(format!("{kernels} kernels are not supported by this CPU"));
+    }
+
+    SELECTED.store(kernels as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect() -> Kernels {
+    let avx2 = is_x86_feature_detected!("avx2")
+        && is_x86_feature_detected!("popcnt")
+        && is_x86_feature_detected!("bmi1")
+        && is_x86_feature_detected!("bmi2");
+
+    if avx2 && is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") {
+        Kernels::Avx512
+    } else if avx2 {
+        Kernels::Avx2
+    } else {
+        Kernels::Generic
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+const fn detect() -> Kernels {
+    Kernels::Generic
+}
+
+impl fmt::Display for Kernels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Self::Generic => "generic",
+            Self::Avx2 => "avx2",
+            Self::Avx512 => "avx512",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_selection() {
+        let available = available();
+        assert_eq!(available[0], Kernels::Generic);
+        assert_eq!(available.last(), Some(&detected()));
+
+        // Kernels are only ever switched to ones computing the same evals
+        assert_eq!(set_kernels(Kernels::Generic), Ok(()));
+        assert_eq!(kernels(), Kernels::Generic);
+        assert_eq!(set_kernels(detected()), Ok(()));
+        assert_eq!(kernels(), detected());
+        if detected() != Kernels::Avx512 {
+            assert!(set_kernels(Kernels::Avx512).is_err());
+        }
+    }
+}
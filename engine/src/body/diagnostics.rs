@@ -0,0 +1,128 @@
+// Search explosion diagnostics
+// Watches the main thread's iterations for pathological behavior and describes it, so that
+// the GUI log of a search stuck at some depth says why. Only the counters are updated in the
+// search itself, the checks run at the root.
+
+// Iterations deeper than this are compared to the previous one
+const MIN_DEPTH: i32 = 6;
+// Iterations smaller than this are too noisy to judge
+const MIN_NODES: u64 = 100_000;
+// An iteration searching this many times the nodes of the previous one has exploded
+const BRANCHING_LIMIT: u64 = 10;
+// Check extended nodes beyond this fraction of the iteration, in percent
+const EXTENSION_LIMIT: u64 = 25;
+// Aspiration window re-searches of a single iteration
+const RESEARCH_LIMIT: u32 = 6;
+
+#[derive(Default)]
+pub struct Diagnostics {
+    pub enabled: bool,
+    pub extensions: u64,
+    depth: i32,
+    start_nodes: u64,
+    prev_iteration_nodes: u64,
+    researches: u32,
+    // Each warning is given at most once per iteration
+    warned_branching: bool,
+    warned_extensions: bool,
+}
+
+impl Diagnostics {
+    pub fn start_iteration(&mut self, depth: i32, nodes: u64) {
+        if depth == 1 {
+            self.prev_iteration_nodes = 0;
+        }
+
+        self.depth = depth;
+        self.start_nodes = nodes;
+        self.extensions = 0;
+        self.researches = 0;
+        self.warned_branching = false;
+        self.warned_extensions = false;
+    }
+
+    pub fn finish_iteration(&mut self, nodes: u64) {
+        self.prev_iteration_nodes = nodes - self.start_nodes;
+    }
+
+    /// Records an aspiration window re-search
+    pub fn research(&mut self) -> Option<String> {
+        self.researches += 1;
+
+        (self.enabled && self.researches == RESEARCH_LIMIT).then(|| {
+            format!(
+                "{} aspiration re-searches at depth {}",
+                self.researches, self.depth
+            )
+        })
+    }
+
+    /// Checks the iteration so far, given the nodes searched since the start of the search
+    pub fn check(&mut self, nodes: u64) -> Option<String> {
+        let iteration_nodes = nodes - self.start_nodes;
+        if !self.enabled || iteration_nodes < MIN_NODES {
+            return None;
+        }
+
+        if !self.warned_branching
+            && self.depth >= MIN_DEPTH
+            && self.prev_iteration_nodes > 0
+            && iteration_nodes > BRANCHING_LIMIT * self.prev_iteration_nodes
+        {
+            self.warned_branching = true;
+            return Some(format!(
+                "depth {} has searched {}x the nodes of depth {}",
+                self.depth,
+                iteration_nodes / self.prev_iteration_nodes,
+                self.depth - 1
+            ));
+        }
+
+        if !self.warned_extensions && self.extensions * 100 > EXTENSION_LIMIT * iteration_nodes {
+            self.warned_extensions = true;
+            return Some(format!(
+                "{}% of the nodes at depth {} are check extended",
+                self.extensions * 100 / iteration_nodes,
+                self.depth
+            ));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explosion_warnings() {
+        let mut diag = Diagnostics {
+            enabled: true,
+            ..Default::default()
+        };
+
+        diag.start_iteration(MIN_DEPTH, 0);
+        diag.finish_iteration(MIN_NODES);
+        diag.start_iteration(MIN_DEPTH + 1, MIN_NODES);
+        assert_eq!(diag.check(2 * MIN_NODES), None);
+
+        // Warnings are given once per iteration
+        assert!(diag.check(20 * MIN_NODES).is_some());
+        assert_eq!(diag.check(30 * MIN_NODES), None);
+
+        diag.extensions = 10 * MIN_NODES;
+        assert!(diag.check(30 * MIN_NODES).is_some());
+
+        for _ in 1..RESEARCH_LIMIT {
+            assert_eq!(diag.research(), None);
+        }
+        assert!(diag.research().is_some());
+        assert_eq!(diag.research(), None);
+
+        diag.enabled = false;
+        diag.start_iteration(MIN_DEPTH + 2, 30 * MIN_NODES);
+        diag.extensions = 10 * MIN_NODES;
+        assert_eq!(diag.check(60 * MIN_NODES), None);
+    }
+}
@@ -0,0 +1,259 @@
+// Endgame knowledge
+// Exact or near-exact knowledge about basic endings, applied on top of the network evaluation.
+// The network has seen very few of these positions and would otherwise rely on deep search
+// to find the mate, often shuffling until the 50-move rule comes close. Elsewhere the side to
+// move gets a small tempo bonus, which the network underrates in quiet middlegames, and endings
+// known to be drawish have the network's score scaled down.
+use super::{kpk, position::Material};
+use crate::definitions::KNOWN_WIN;
+use crate::tunables;
+
+use cozy_chess::{BitBoard, Board, Color, File, Piece, Rank, Square};
+
+const PIECE_VALUES: [i32; 6] = [100, 300, 300, 500, 900, 0];
+
+// Mate-driving weights
+const PUSH_TO_EDGE: i32 = 20;
+const PUSH_CLOSE: i32 = 10;
+const PUSH_TO_CORNER: i32 = 40;
+
+// Game phase weights of the pieces, the phase being MAX_PHASE with every piece on the board
+const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+const MAX_PHASE: i32 = 24;
+
+/// Static evaluation corrected by endgame knowledge, from the side to move's perspective
+#[must_use]
+pub fn evaluate(board: &Board, eval: i32) -> i32 {
+    if let Some(score) = kpk::evaluate(board, eval) {
+        return score;
+    }
+
+    if let Some(score) = bare_king(board) {
+        return score;
+    }
+
+    scale(board, eval) + tempo(board)
+}
+
+/// The network's score scaled down in drawish endings: opposite colored bishops, rook endings a
+/// pawn up, and rook pawns with a bishop of the wrong color
+#[must_use]
+pub fn scale(board: &Board, eval: i32) -> i32 {
+    // Every rule is for a single piece per side at most
+    let pieces = board.occupied() & !board.pieces(Piece::Pawn) & !board.pieces(Piece::King);
+    if pieces.len() > 2 {
+        return eval;
+    }
+
+    let stm = board.side_to_move();
+    let strong = if eval >= 0 { stm } else { !stm };
+    let material = Material::new(board);
+    let pawns = |color| material.count(color, Piece::Pawn);
+
+    const BISHOP: [u32; 4] = [0, 1, 0, 0];
+    const ROOK: [u32; 4] = [0, 0, 1, 0];
+    let ours = material.pieces(strong);
+    let theirs = material.pieces(!strong);
+
+    let percent = if ours == BISHOP && theirs == BISHOP && opposite_bishops(board) {
+        tunables::scale_opposite_bishops()
+    } else if ours == ROOK && theirs == ROOK && pawns(strong) == pawns(!strong) + 1 {
+        tunables::scale_rook_pawn_up()
+    } else if ours == BISHOP && board.colors(!strong).len() == 1 && wrong_bishop(board, strong) {
+        tunables::scale_wrong_bishop()
+    } else {
+        return eval;
+    };
+
+    eval * percent / 100
+}
+
+fn opposite_bishops(board: &Board) -> bool {
+    let bishops = board.pieces(Piece::Bishop);
+    (bishops & BitBoard::LIGHT_SQUARES).len() == 1 && (bishops & BitBoard::DARK_SQUARES).len() == 1
+}
+
+// Pawns of `strong` all on one rook file, promoting on a square its bishop doesn't cover, with
+// the enemy king next to that square
+fn wrong_bishop(board: &Board, strong: Color) -> bool {
+    let pawns = board.colored_pieces(strong, Piece::Pawn);
+    let Some(file) = [File::A, File::H]
+        .into_iter()
+        .find(|&file| !pawns.is_empty() && (pawns & !file.bitboard()).is_empty())
+    else {
+        return false;
+    };
+
+    let promotion = Square::new(file, Rank::Eighth.relative_to(strong));
+    let squares = if BitBoard::LIGHT_SQUARES.has(promotion) {
+        BitBoard::LIGHT_SQUARES
+    } else {
+        BitBoard::DARK_SQUARES
+    };
+
+    (board.colored_pieces(strong, Piece::Bishop) & squares).is_empty()
+        && distance(board.king(!strong), promotion) <= 1
+}
+
+/// Bonus for the side to move, fading out with the pieces: in simple endgames having to move
+/// is as often a liability
+#[must_use]
+pub fn tempo(board: &Board) -> i32 {
+    let phase: i32 = Piece::ALL
+        .iter()
+        .map(|&p| board.pieces(p).len() as i32 * PHASE_WEIGHTS[p as usize])
+        .sum();
+
+    tunables::eval_tempo() * phase.min(MAX_PHASE) / MAX_PHASE
+}
+
+// Drives the defending king to the edge (or the right corner in KBNK) and brings the attacking
+// king close. Only applies when the strong side has enough material to force mate.
+fn bare_king(board: &Board) -> Option<i32> {
+    let strong = [Color::White, Color::Black]
+        .into_iter()
+        .find(|&c| board.colors(!c).len() == 1 && board.colors(c).len() > 1)?;
+
+    let ours = |piece: Piece| board.pieces(piece) & board.colors(strong);
+    let knights = ours(Piece::Knight).len();
+    let bishops = ours(Piece::Bishop);
+    let light_bishop = !(bishops & BitBoard::LIGHT_SQUARES).is_empty();
+    let dark_bishop = !(bishops & BitBoard::DARK_SQUARES).is_empty();
+
+    let heavy = !(ours(Piece::Queen) | ours(Piece::Rook)).is_empty();
+    let bishop_pair = light_bishop && dark_bishop;
+    let bishop_knight = !bishops.is_empty() && knights > 0;
+    if !(heavy || bishop_pair || bishop_knight) {
+        return None;
+    }
+
+    let strong_king = board.king(strong);
+    let weak_king = board.king(!strong);
+
+    let material: i32 = Piece::ALL
+        .iter()
+        .map(|&p| ours(p).len() as i32 * PIECE_VALUES[p as usize])
+        .sum();
+
+    let mut score = KNOWN_WIN + material;
+    score += PUSH_CLOSE * (7 - distance(strong_king, weak_king));
+
+    // KBNK can only be mated in a corner of the bishop's color
+    if !heavy && !bishop_pair {
+        let corners = if light_bishop {
+            [Square::A8, Square::H1]
+        } else {
+            [Square::A1, Square::H8]
+        };
+        let corner_distance = corners
+            .iter()
+            .map(|&c| manhattan_distance(weak_king, c))
+            .min()
+            .unwrap();
+
+        score += PUSH_TO_CORNER * (14 - corner_distance);
+    } else {
+        score += PUSH_TO_EDGE * center_distance(weak_king);
+    }
+
+    if board.side_to_move() == strong {
+        Some(score)
+    } else {
+        Some(-score)
+    }
+}
+
+fn distance(a: Square, b: Square) -> i32 {
+    let file = (a.file() as i32 - b.file() as i32).abs();
+    let rank = (a.rank() as i32 - b.rank() as i32).abs();
+    file.max(rank)
+}
+
+fn manhattan_distance(a: Square, b: Square) -> i32 {
+    (a.file() as i32 - b.file() as i32).abs() + (a.rank() as i32 - b.rank() as i32).abs()
+}
+
+// 0 in the center, 6 in the corners
+fn center_distance(sq: Square) -> i32 {
+    let file = sq.file() as i32;
+    let rank = sq.rank() as i32;
+    6 - file.min(7 - file) - rank.min(7 - rank)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(fen: &str) -> i32 {
+        evaluate(&Board::from_fen(fen, false).unwrap(), 0)
+    }
+
+    #[test]
+    fn mate_driving() {
+        // Defending king in the corner is better than in the center
+        assert!(eval("7k/8/8/8/8/8/8/K2Q4 w - - 0 1") > eval("8/8/8/4k3/8/8/8/K2Q4 w - - 0 1"));
+        assert!(eval("7k/8/8/8/8/8/8/K2R4 w - - 0 1") > eval("8/8/8/4k3/8/8/8/K2R4 w - - 0 1"));
+
+        // ... and the attacking king should be close
+        assert!(eval("7k/8/5K2/8/8/8/8/3Q4 w - - 0 1") > eval("7k/8/8/8/8/8/8/K2Q4 w - - 0 1"));
+
+        // KBNK with a light squared bishop mates on a8 or h1
+        assert!(eval("k7/8/8/8/8/8/8/3BNK2 w - - 0 1") > eval("7k/8/8/8/8/8/8/3BNK2 w - - 0 1"));
+
+        // Scores are from the side to move's perspective
+        assert!(eval("7k/8/8/8/8/8/8/K2Q4 b - - 0 1") <= -KNOWN_WIN);
+
+        // No knowledge without mating material
+        assert_eq!(eval("7k/8/8/8/8/8/8/K2N4 w - - 0 1"), 0);
+        assert_eq!(eval("7k/8/8/8/8/8/8/K1NN4 w - - 0 1"), 0);
+        assert_eq!(eval("7k/8/8/8/8/8/8/K1B1B3 w - - 0 1"), 0);
+    }
+
+    #[test]
+    fn drawish_scaling() {
+        let scaled = |fen: &str, eval: i32| scale(&fen.parse().unwrap(), eval);
+
+        // Opposite colored bishops, not same colored ones
+        let opposite = "4k3/5p2/8/3b4/8/8/4PP2/2B1K3 w - - 0 1";
+        let same = "4k3/5p2/8/4b3/8/8/4PP2/2B1K3 w - - 0 1";
+        assert_eq!(
+            scaled(opposite, 200),
+            200 * tunables::scale_opposite_bishops() / 100
+        );
+        assert_eq!(scaled(same, 200), 200);
+
+        // A rook ending a pawn up, for whichever side is ahead
+        let rooks = "4k2r/5pp1/8/8/8/8/4PPP1/R3K3 b - - 0 1";
+        assert_eq!(
+            scaled(rooks, -300),
+            -300 * tunables::scale_rook_pawn_up() / 100
+        );
+        assert_eq!(scaled("4k2r/6p1/8/8/8/8/4PPP1/R3K3 w - - 0 1", 300), 300);
+
+        // The light squared bishop can't drive the king out of h8
+        let wrong = "7k/8/8/8/8/8/7P/3BK3 w - - 0 1";
+        assert_eq!(
+            scaled(wrong, 400),
+            400 * tunables::scale_wrong_bishop() / 100
+        );
+        assert_eq!(scaled("7k/8/8/8/8/8/7P/2B1K3 w - - 0 1", 400), 400);
+        assert_eq!(scaled("8/8/8/8/3k4/8/7P/3BK3 w - - 0 1", 400), 400);
+
+        // More pieces on the board leave the network alone
+        assert_eq!(scaled(crate::api::START_FEN, 50), 50);
+    }
+
+    #[test]
+    fn tempo_fading() {
+        assert_eq!(tempo(&Board::default()), tunables::eval_tempo());
+        assert_eq!(eval(crate::api::START_FEN), tunables::eval_tempo());
+
+        // Gone in pawn endings, a little left with a rook each
+        assert_eq!(
+            tempo(&"4k3/pp6/8/8/8/8/PP6/4K3 w - - 0 1".parse().unwrap()),
+            0
+        );
+        let rooks = tempo(&"r3k3/pp6/8/8/8/8/PP6/R3K3 w - - 0 1".parse().unwrap());
+        assert!(rooks < tunables::eval_tempo() / 2, "{rooks}");
+    }
+}
@@ -1,3 +1,6 @@
+use super::correction::CorrectionHistory;
+use crate::tunables;
+
 use cozy_chess::{Board, Move};
 
 pub const MAX_HISTORY: i32 = i16::MAX as i32;
@@ -5,12 +8,14 @@ pub const MAX_HISTORY: i32 = i16::MAX as i32;
 #[derive(Clone)]
 pub struct History {
     pub table: [[[i32; 64]; 64]; 2],
+    pub correction: CorrectionHistory,
 }
 
 impl History {
     pub fn new() -> History {
         History {
             table: [[[0; 64]; 64]; 2],
+            correction: CorrectionHistory::new(),
         }
     }
 
@@ -24,7 +29,7 @@ impl History {
     }
 
     pub fn update_table<const POSITIVE: bool>(&mut self, board: &Board, mv: Move, depth: i32) {
-        let delta = (16 * (depth * depth)).min(1200);
+        let delta = (tunables::hist_bonus_mul() * (depth * depth)).min(tunables::hist_bonus_max());
         let bonus = if POSITIVE { delta } else { -delta };
 
         self.update_score(board, mv, bonus);
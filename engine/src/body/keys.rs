@@ -0,0 +1,217 @@
+// Auxiliary zobrist keys
+// Hashes of parts of the position, for tables indexed by pawn structure, by the pieces of one side,
+// by the minor pieces or by material rather than by the whole position. The search carries them
+// on its stack and updates them with every move, recomputing them at each node would cost more
+// than they save.
+use cozy_chess::{Board, Color, File, Move, Piece, Rank, Square};
+
+// Enough for a side that promoted every pawn to the same piece
+const MAX_COUNT: usize = 10;
+
+// Square keys for every piece, and material keys for the nth piece of a kind
+static PIECE_SQUARE: [[[u64; Square::NUM]; Piece::NUM]; Color::NUM] = piece_square_keys();
+static MATERIAL: [[[u64; MAX_COUNT]; Piece::NUM]; Color::NUM] = material_keys();
+
+// SplitMix64, good enough to fill the tables at compile time
+const fn next(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn piece_square_keys() -> [[[u64; Square::NUM]; Piece::NUM]; Color::NUM] {
+    let mut keys = [[[0; Square::NUM]; Piece::NUM]; Color::NUM];
+    let mut state = 0x5356_4152_5400_0001;
+    let mut c = 0;
+    while c < Color::NUM {
+        let mut p = 0;
+        while p < Piece::NUM {
+            let mut sq = 0;
+            while sq < Square::NUM {
+                keys[c][p][sq] = next(&mut state);
+                sq += 1;
+            }
+            p += 1;
+        }
+        c += 1;
+    }
+    keys
+}
+
+const fn material_keys() -> [[[u64; MAX_COUNT]; Piece::NUM]; Color::NUM] {
+    let mut keys = [[[0; MAX_COUNT]; Piece::NUM]; Color::NUM];
+    let mut state = 0x5356_4152_5400_0002;
+    let mut c = 0;
+    while c < Color::NUM {
+        let mut p = 0;
+        while p < Piece::NUM {
+            let mut n = 0;
+            while n < MAX_COUNT {
+                keys[c][p][n] = next(&mut state);
+                n += 1;
+            }
+            p += 1;
+        }
+        c += 1;
+    }
+    keys
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Keys {
+    /// Pawns of both sides
+    pub pawn: u64,
+    /// Every other piece, kings included, per side
+    pub non_pawn: [u64; Color::NUM],
+    /// Piece counts of both sides
+    pub material: u64,
+    /// Knights, bishops and kings of both sides
+    pub minor: u64,
+}
+
+impl Keys {
+    #[must_use]
+    pub fn new(board: &Board) -> Self {
+        let mut keys = Self::default();
+
+        for color in Color::ALL {
+            for piece in Piece::ALL {
+                let pieces = board.colored_pieces(color, piece);
+                for sq in pieces {
+                    keys.toggle_square(color, piece, sq);
+                }
+                for key in &MATERIAL[color as usize][piece as usize][..pieces.len() as usize] {
+                    keys.material ^= key;
+                }
+            }
+        }
+
+        keys
+    }
+
+    /// Keys of the position after the move, which must be legal in `board`
+    #[must_use]
+    pub fn after(mut self, board: &Board, mv: Move) -> Self {
+        let stm = board.side_to_move();
+        let piece = board.piece_on(mv.from).unwrap();
+        self.toggle_square(stm, piece, mv.from);
+
+        // Castling, encoded as the king capturing its own rook
+        if board.color_on(mv.to) == Some(stm) {
+            let rank = Rank::First.relative_to(stm);
+            let (king, rook) = if mv.from.file() < mv.to.file() {
+                (File::G, File::F)
+            } else {
+                (File::C, File::D)
+            };
+
+            self.toggle_square(stm, Piece::Rook, mv.to);
+            self.toggle_square(stm, Piece::Rook, Square::new(rook, rank));
+            self.toggle_square(stm, Piece::King, Square::new(king, rank));
+            return self;
+        }
+
+        if let Some(victim) = board.piece_on(mv.to) {
+            self.toggle_square(!stm, victim, mv.to);
+            self.remove_material(board, !stm, victim);
+        } else if piece == Piece::Pawn && mv.from.file() != mv.to.file() {
+            // En passant
+            let victim = Square::new(mv.to.file(), mv.from.rank());
+            self.toggle_square(!stm, Piece::Pawn, victim);
+            self.remove_material(board, !stm, Piece::Pawn);
+        }
+
+        match mv.promotion {
+            Some(promotion) => {
+                self.toggle_square(stm, promotion, mv.to);
+                self.remove_material(board, stm, Piece::Pawn);
+                let count = board.colored_pieces(stm, promotion).len() as usize;
+                self.material ^= MATERIAL[stm as usize][promotion as usize][count];
+            }
+            None => self.toggle_square(stm, piece, mv.to),
+        }
+
+        self
+    }
+
+    fn toggle_square(&mut self, color: Color, piece: Piece, sq: Square) {
+        let key = PIECE_SQUARE[color as usize][piece as usize][sq as usize];
+        if piece == Piece::Pawn {
+            self.pawn ^= key;
+        } else {
+            self.non_pawn[color as usize] ^= key;
+        }
+        if matches!(piece, Piece::Knight | Piece::Bishop | Piece::King) {
+            self.minor ^= key;
+        }
+    }
+
+    // Drops the key of the last piece of its kind, counted in the position before the move
+    fn remove_material(&mut self, board: &Board, color: Color, piece: Piece) {
+        let count = board.colored_pieces(color, piece).len() as usize;
+        self.material ^= MATERIAL[color as usize][piece as usize][count - 1];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::movegen::pure_moves;
+
+    #[test]
+    fn incremental_keys() {
+        const FENS: [&str; 5] = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/1pp1ppp1/p7/2PpP2p/8/8/PP1P1PPP/RNBQKBNR w KQkq d6 0 5",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "1r2k3/P7/8/8/8/8/8/4K2R w K - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        ];
+
+        for fen in FENS {
+            let board = Board::from_fen(fen, false).unwrap();
+            let keys = Keys::new(&board);
+
+            // Every move, and a reply to each of them
+            for mv in pure_moves(&board) {
+                let mut child = board.clone();
+                child.play_unchecked(mv);
+                let child_keys = keys.after(&board, mv);
+                assert_eq!(child_keys, Keys::new(&child), "{mv} in {fen}");
+
+                for reply in pure_moves(&child) {
+                    let mut grandchild = child.clone();
+                    grandchild.play_unchecked(reply);
+                    assert_eq!(
+                        child_keys.after(&child, reply),
+                        Keys::new(&grandchild),
+                        "{mv} {reply} in {fen}"
+                    );
+                }
+            }
+        }
+
+        // Same pawns, different pieces
+        let a = Keys::new(&Board::default());
+        let b = Keys::new(
+            &Board::from_fen(
+                "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1",
+                false,
+            )
+            .unwrap(),
+        );
+        assert_eq!(a.pawn, b.pawn);
+        assert_eq!(a.material, b.material);
+        assert_ne!(a.minor, b.minor);
+        assert_eq!(
+            a.non_pawn[Color::Black as usize],
+            b.non_pawn[Color::Black as usize]
+        );
+        assert_ne!(
+            a.non_pawn[Color::White as usize],
+            b.non_pawn[Color::White as usize]
+        );
+    }
+}
@@ -0,0 +1,255 @@
+// KPK bitbase
+// Every king and pawn versus king position is classified by retrograde analysis the first time
+// the bitbase is used. Positions are normalised so that the strong side is white and the pawn
+// is on files A-D, which leaves 2 * 24 * 64 * 64 entries.
+use cozy_chess::{get_king_moves, get_pawn_attacks, Board, Color, File, Piece, Rank, Square};
+use once_cell::sync::Lazy;
+
+const MAX_INDEX: usize = 2 * 24 * 64 * 64;
+
+// Result flags, combined with bitwise or while classifying
+const INVALID: u8 = 0b000;
+const UNKNOWN: u8 = 0b001;
+const DRAW: u8 = 0b010;
+const WIN: u8 = 0b100;
+
+// Bitset of all won positions
+static KPK: Lazy<Vec<u64>> = Lazy::new(generate);
+
+// Won positions are never scored below a clear advantage for the side with the pawn
+const WIN_MIN: i32 = 200;
+
+fn index(stm: Color, bksq: Square, wksq: Square, psq: Square) -> usize {
+    stm as usize
+        | (bksq as usize) << 1
+        | (wksq as usize) << 7
+        | (psq.file() as usize) << 13
+        | (Rank::Seventh as usize - psq.rank() as usize) << 15
+}
+
+struct Position {
+    stm: Color,
+    wksq: Square,
+    bksq: Square,
+    psq: Square,
+    result: u8,
+}
+
+impl Position {
+    fn new(idx: usize) -> Self {
+        let stm = if idx & 1 == 0 {
+            Color::White
+        } else {
+            Color::Black
+        };
+        let bksq = Square::index((idx >> 1) & 0x3F);
+        let wksq = Square::index((idx >> 7) & 0x3F);
+        let psq = Square::new(
+            File::index((idx >> 13) & 0x3),
+            Rank::index(Rank::Seventh as usize - ((idx >> 15) & 0x7)),
+        );
+        let push = psq.offset(0, 1);
+
+        let result = if get_king_moves(wksq).has(bksq)
+            || wksq == bksq
+            || wksq == psq
+            || bksq == psq
+            || (stm == Color::White && get_pawn_attacks(psq, Color::White).has(bksq))
+        {
+            // Kings touching, overlapping pieces or the side not to move in check
+            INVALID
+        } else if stm == Color::White
+            && psq.rank() == Rank::Seventh
+            && wksq != push
+            && bksq != push
+            && (!get_king_moves(bksq).has(push) || get_king_moves(wksq).has(push))
+        {
+            // The pawn promotes without being captured
+            WIN
+        } else if stm == Color::Black
+            && ((get_king_moves(bksq)
+                & !(get_king_moves(wksq) | get_pawn_attacks(psq, Color::White)))
+            .is_empty()
+                || (get_king_moves(bksq) & !get_king_moves(wksq)).has(psq))
+        {
+            // Stalemate or the pawn is captured
+            DRAW
+        } else {
+            UNKNOWN
+        };
+
+        Self {
+            stm,
+            wksq,
+            bksq,
+            psq,
+            result,
+        }
+    }
+
+    // White wins if any move leads to a win, black draws if any move leads to a draw.
+    // Otherwise the position stays unknown as long as any successor is unknown.
+    fn classify(&self, db: &[Position]) -> u8 {
+        let (good, bad) = match self.stm {
+            Color::White => (WIN, DRAW),
+            Color::Black => (DRAW, WIN),
+        };
+
+        let mut r = INVALID;
+        match self.stm {
+            Color::White => {
+                for sq in get_king_moves(self.wksq) {
+                    r |= db[index(Color::Black, self.bksq, sq, self.psq)].result;
+                }
+
+                // Single and double pawn pushes
+                if self.psq.rank() < Rank::Seventh {
+                    let push = self.psq.offset(0, 1);
+                    r |= db[index(Color::Black, self.bksq, self.wksq, push)].result;
+
+                    if self.psq.rank() == Rank::Second && push != self.wksq && push != self.bksq {
+                        let double_push = push.offset(0, 1);
+                        r |= db[index(Color::Black, self.bksq, self.wksq, double_push)].result;
+                    }
+                }
+            }
+            Color::Black => {
+                for sq in get_king_moves(self.bksq) {
+                    r |= db[index(Color::White, sq, self.wksq, self.psq)].result;
+                }
+            }
+        }
+
+        if r & good != 0 {
+            good
+        } else if r & UNKNOWN != 0 {
+            UNKNOWN
+        } else {
+            bad
+        }
+    }
+}
+
+fn generate() -> Vec<u64> {
+    let mut db: Vec<Position> = (0..MAX_INDEX).map(Position::new).collect();
+
+    // Iterate until every reachable position has been classified
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for idx in 0..MAX_INDEX {
+            if db[idx].result == UNKNOWN {
+                let result = db[idx].classify(&db);
+                if result != UNKNOWN {
+                    db[idx].result = result;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut bits = vec![0; MAX_INDEX / 64];
+    for (idx, pos) in db.iter().enumerate() {
+        if pos.result == WIN {
+            bits[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+
+    bits
+}
+
+/// Forces the bitbase to be generated, so the first probe during search doesn't pay for it
+pub fn init() {
+    Lazy::force(&KPK);
+}
+
+/// Probes a KPK position, returning whether the side with the pawn wins.
+/// Positions that aren't KPK return `None`.
+#[must_use]
+pub fn probe(board: &Board) -> Option<bool> {
+    if board.occupied().len() != 3 || board.pieces(Piece::Pawn).len() != 1 {
+        return None;
+    }
+
+    let psq = board.pieces(Piece::Pawn).next_square()?;
+    let strong = board.color_on(psq)?;
+    let mut wksq = board.king(strong);
+    let mut bksq = board.king(!strong);
+    let mut psq = psq;
+
+    // Normalise to white being the strong side...
+    if strong == Color::Black {
+        wksq = wksq.flip_rank();
+        bksq = bksq.flip_rank();
+        psq = psq.flip_rank();
+    }
+
+    // ... with the pawn on files A-D
+    if psq.file() > File::D {
+        wksq = wksq.flip_file();
+        bksq = bksq.flip_file();
+        psq = psq.flip_file();
+    }
+
+    let stm = if board.side_to_move() == strong {
+        Color::White
+    } else {
+        Color::Black
+    };
+
+    let idx = index(stm, bksq, wksq, psq);
+    Some(KPK[idx / 64] & (1 << (idx % 64)) != 0)
+}
+
+/// Corrects the static evaluation of a KPK position, from the side to move's perspective.
+/// Draws are scored exactly, wins keep the evaluation but never below a clear advantage.
+#[must_use]
+pub fn evaluate(board: &Board, eval: i32) -> Option<i32> {
+    if !probe(board)? {
+        return Some(0);
+    }
+
+    let psq = board.pieces(Piece::Pawn).next_square()?;
+    if board.color_on(psq)? == board.side_to_move() {
+        Some(eval.max(WIN_MIN))
+    } else {
+        Some(eval.min(-WIN_MIN))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kpk_probe() {
+        const POSITIONS: [(&str, Option<bool>); 12] = [
+            // Stalemate or a won race depending on the side to move
+            ("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1", Some(false)),
+            ("4k3/4P3/4K3/8/8/8/8/8 w - - 0 1", Some(true)),
+            // Same positions with colors reversed and the board mirrored
+            ("8/8/8/8/8/3k4/3p4/3K4 w - - 0 1", Some(false)),
+            ("8/8/8/8/8/3k4/3p4/3K4 b - - 0 1", Some(true)),
+            // The pawn is captured
+            ("8/8/8/3kP3/8/8/8/K7 b - - 0 1", Some(false)),
+            ("k7/P7/8/8/8/8/8/K7 w - - 0 1", Some(false)),
+            // Rook pawn with the defending king in the corner
+            ("k7/8/8/8/8/8/P7/K7 w - - 0 1", Some(false)),
+            // The defending king can't catch the pawn
+            ("8/8/8/8/8/8/4P3/4K2k w - - 0 1", Some(true)),
+            // ... but it can if it's in the square of the pawn
+            ("8/6k1/8/8/8/8/P7/7K b - - 0 1", Some(false)),
+            // Key square reached
+            ("4k3/8/4K3/8/4P3/8/8/8 w - - 0 1", Some(true)),
+            // Not KPK
+            ("8/8/4k3/8/3K4/4P3/4P3/8 w - - 0 1", None),
+            ("8/8/4k3/8/3K4/4N3/8/8 w - - 0 1", None),
+        ];
+
+        for (fen, expected) in POSITIONS {
+            let board = Board::from_fen(fen, false).unwrap();
+            assert_eq!(probe(&board), expected, "{fen}");
+        }
+    }
+}
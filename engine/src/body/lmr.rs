@@ -1,5 +1,13 @@
-const BASE_REDUCTION: f64 = 0.75;
-const DIVISOR: f64 = 2.25;
+use crate::tunables;
+
+fn formula(depth: usize, move_count: usize) -> i32 {
+    let base = f64::from(tunables::lmr_base()) / 100.;
+    let divisor = f64::from(tunables::lmr_divisor()) / 100.;
+    let ld = f64::ln(depth as f64);
+    let lp = f64::ln(move_count as f64);
+
+    (base + ld * lp / divisor) as i32
+}
 
 pub struct LMRTable {
     pub table: [[i32; 64]; 64],
@@ -13,9 +21,7 @@ impl LMRTable {
 
         for d in 0..64 {
             for m in 0..64 {
-                let ld = f64::ln(d as f64);
-                let lp = f64::ln(m as f64);
-                lmr.table[d][m] = (BASE_REDUCTION + ld * lp / DIVISOR) as i32;
+                lmr.table[d][m] = formula(d, m);
             }
         }
 
@@ -27,6 +33,11 @@ impl LMRTable {
         let d = (depth.min(63)) as usize;
         let c = (move_count.min(63)) as usize;
 
-        self.table[d][c]
+        // The table is built once, tuning builds follow parameter changes instead
+        if cfg!(feature = "tune") {
+            formula(d, c)
+        } else {
+            self.table[d][c]
+        }
     }
 }
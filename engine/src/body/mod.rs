@@ -1,9 +1,23 @@
+pub mod adjudication;
+pub mod correction;
+pub mod cpu;
+pub mod diagnostics;
+pub mod endgame;
 pub mod history;
+pub mod keys;
+pub mod kpk;
 mod lmr;
 pub mod movegen;
 pub mod nnue;
 pub mod position;
 pub mod pv_table;
+pub mod report;
 pub mod search;
+pub mod see;
+#[cfg(unix)]
+pub mod shm;
 mod stat_vec;
+pub mod stats;
+pub mod trace;
 pub mod tt;
+pub mod variety;
@@ -1,13 +1,26 @@
-use super::{position::is_capture, search::Search};
-use crate::definitions::INFINITY;
+use super::{position::is_capture, search::Search, stat_vec::StaticVec};
+use crate::definitions::{INFINITY, MAX_MOVES_POSITION};
 use cozy_chess::{Board, Color, Move, Piece, Rank, Square};
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub struct MoveEntry {
     pub mv: Move,
     pub score: i32,
 }
 
+// Filler for the unused part of a move list
+const EMPTY_ENTRY: MoveEntry = MoveEntry {
+    mv: Move {
+        from: Square::A1,
+        to: Square::A1,
+        promotion: None,
+    },
+    score: 0,
+};
+
+/// Scored moves of a position, kept on the stack so that search nodes never allocate
+pub type MoveList = StaticVec<MoveEntry, MAX_MOVES_POSITION>;
+
 pub fn pure_moves(board: &Board) -> Vec<Move> {
     let mut move_list: Vec<Move> = Vec::new();
 
@@ -19,27 +32,19 @@ pub fn pure_moves(board: &Board) -> Vec<Move> {
     move_list
 }
 
-pub fn all_moves(
-    search: &Search,
-    board: &Board,
-    tt_move: Option<Move>,
-    ply: usize,
-) -> Vec<MoveEntry> {
-    let mut move_list: Vec<Move> = Vec::new();
+pub fn all_moves(search: &Search, board: &Board, tt_move: Option<Move>, ply: usize) -> MoveList {
+    let mut move_list = MoveList::new(EMPTY_ENTRY);
 
     board.generate_moves(|moves| {
-        move_list.extend(moves);
+        for mv in moves {
+            move_list.push(MoveEntry {
+                mv,
+                score: score_moves(search, board, mv, tt_move, ply),
+            });
+        }
         false
     });
 
-    let move_list: Vec<MoveEntry> = move_list
-        .iter()
-        .map(|mv| MoveEntry {
-            mv: *mv,
-            score: score_moves(search, board, *mv, tt_move, ply),
-        })
-        .collect();
-
     move_list
 }
 
@@ -48,9 +53,9 @@ pub fn capture_moves(
     board: &Board,
     tt_move: Option<Move>,
     ply: usize,
-) -> Vec<MoveEntry> {
+) -> MoveList {
     let enemy_pieces = board.colors(!board.side_to_move());
-    let mut captures_list: Vec<Move> = Vec::new();
+    let mut captures_list = MoveList::new(EMPTY_ENTRY);
 
     // Assigns ep_square to the square that can be attacked
     let ep = board.en_passant();
@@ -63,7 +68,7 @@ pub fn capture_moves(
         }
     }
 
-    // Generates all moves and filters out the ones that are not captures
+    // Generates all moves and filters out the ones that are not captures, scoring each one
     board.generate_moves(|mut moves| {
         let mut permissible = enemy_pieces;
         if let Some(epsq) = ep_square {
@@ -72,19 +77,15 @@ pub fn capture_moves(
             }
         }
         moves.to &= permissible;
-        captures_list.extend(moves);
+        for mv in moves {
+            captures_list.push(MoveEntry {
+                mv,
+                score: score_moves(search, board, mv, tt_move, ply),
+            });
+        }
         false
     });
 
-    // Assigns a score to each move based on MVV-LVA
-    let captures_list: Vec<MoveEntry> = captures_list
-        .iter()
-        .map(|mv| MoveEntry {
-            mv: *mv,
-            score: score_moves(search, board, *mv, tt_move, ply),
-        })
-        .collect();
-
     captures_list
 }
 
@@ -156,9 +157,9 @@ pub fn score_moves(
         return mvvlva(board, mv) + 200_000;
     }
 
-    if search.info.killers[ply][0] == Some(mv) {
+    if search.info.stack[ply].killers[0] == Some(mv) {
         return 100_000;
-    } else if search.info.killers[ply][1] == Some(mv) {
+    } else if search.info.stack[ply].killers[1] == Some(mv) {
         return 95_000;
     }
 
@@ -166,17 +167,17 @@ pub fn score_moves(
 }
 
 pub struct Picker {
-    moves: Vec<MoveEntry>,
+    moves: MoveList,
     index: usize,
 }
 
 impl Picker {
-    pub fn new(moves: Vec<MoveEntry>) -> Self {
+    pub fn new(moves: MoveList) -> Self {
         Self { moves, index: 0 }
     }
 
     pub fn pick_move(&mut self) -> Option<Move> {
-        let open_list = &mut self.moves[self.index..];
+        let open_list = &mut self.moves.as_mut_slice()[self.index..];
         let best_index = open_list
             .iter()
             .enumerate()
@@ -0,0 +1,385 @@
+// Network file header
+// Net files start with this header, followed by the parameters in little endian: the feature
+// weights and biases, then the weights and biases of every layer after the feature transformer,
+// the output layer last. A layer's weights are stored an output neuron at a time. The feature
+// weights are i16s or i8s as the header's quantization says, everything else is i16s. The header
+// says what the net is and carries a checksum of the parameters, so an incompatible or truncated
+// net is refused instead of evaluating garbage.
+//
+// Layout, little endian: magic (4 bytes), version (u16), architecture (u16), hidden size (u16),
+// activation (u8), quantization (u8) and the FNV-1a checksum of the parameters (u32). Version 2
+// follows with the number of hidden layers between the feature transformer and the output
+// (u16) and their sizes (u16 each), version 1 headers have none.
+use std::fmt;
+
+pub const MAGIC: [u8; 4] = *b"SVNN";
+pub const VERSION: u16 = 2;
+
+// Size of a version 1 header, the part every version starts with
+const FIXED_SIZE: usize = 16;
+
+/// Most hidden layers a net can have after the feature transformer
+pub const MAX_LAYERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    /// 768 features to a hidden layer per perspective, then through the layers to the output
+    Perspective = 1,
+    /// Only the side to move's hidden layer goes on to the layers, black's seeing the board
+    /// mirrored vertically
+    Single = 2,
+}
+
+impl Architecture {
+    /// Accumulators the layer after the feature transformer reads
+    #[must_use]
+    pub const fn perspectives(self) -> usize {
+        match self {
+            Self::Perspective => 2,
+            Self::Single => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    CReLU = 0,
+    SCReLU = 1,
+}
+
+/// Width of the feature weights
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantization {
+    I16 = 0,
+    I8 = 1,
+}
+
+impl Quantization {
+    /// Bytes per feature weight
+    #[must_use]
+    pub const fn width(self) -> usize {
+        match self {
+            Self::I16 => 2,
+            Self::I8 => 1,
+        }
+    }
+}
+
+/// Sizes of the hidden layers between the feature transformer and the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Layers {
+    sizes: [usize; MAX_LAYERS],
+    len: usize,
+}
+
+impl Layers {
+    /// The feature transformer straight to the output
+    pub const NONE: Self = Self {
+        sizes: [0; MAX_LAYERS],
+        len: 0,
+    };
+
+    pub fn new(sizes: &[usize]) -> Result<Self, String> {
+        if sizes.len() > MAX_LAYERS {
+            return Err(format!(
+                "{} hidden layers, nets have at most {MAX_LAYERS}",
+                sizes.len()
+            ));
+        }
+        if sizes.contains(&0) {
+            return Err("hidden layers can't be empty".to_string());
+        }
+
+        let mut layers = Self::NONE;
+        layers.sizes[..sizes.len()].copy_from_slice(sizes);
+        layers.len = sizes.len();
+        Ok(layers)
+    }
+
+    #[must_use]
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes[..self.len]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetHeader {
+    pub architecture: Architecture,
+    pub hidden: usize,
+    pub layers: Layers,
+    pub activation: Activation,
+    pub quantization: Quantization,
+    pub checksum: u32,
+}
+
+impl NetHeader {
+    /// Header for the parameters in `parts`, read one after the other
+    #[must_use]
+    pub fn new(
+        architecture: Architecture,
+        hidden: usize,
+        layers: Layers,
+        activation: Activation,
+        quantization: Quantization,
+        parts: &[&[u8]],
+    ) -> Self {
+        Self {
+            architecture,
+            hidden,
+            layers,
+            activation,
+            quantization,
+            checksum: checksum(parts),
+        }
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.architecture as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u16).to_le_bytes());
+        bytes.push(self.activation as u8);
+        bytes.push(self.quantization as u8);
+        bytes.extend_from_slice(&self.checksum.to_le_bytes());
+
+        bytes.extend_from_slice(&(self.layers.len as u16).to_le_bytes());
+        for &size in self.layers.sizes() {
+            bytes.extend_from_slice(&(size as u16).to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Reads the header at the start of a net file
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        Self::split(bytes).map(|(header, _)| header)
+    }
+
+    /// Reads the header at the start of a net file, returning it with the parameters after it
+    pub fn split(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
+        if bytes.len() < FIXED_SIZE || bytes[..4] != MAGIC {
+            return Err("not a Svart net file".to_string());
+        }
+
+        let u16_at = |i: usize| -> Result<usize, String> {
+            bytes
+                .get(i..i + 2)
+                .map(|b| usize::from(u16::from_le_bytes([b[0], b[1]])))
+                .ok_or_else(|| "truncated net header".to_string())
+        };
+        let version = u16_at(4)? as u16;
+        if !(1..=VERSION).contains(&version) {
+            return Err(format!(
+                "net file version {version}, this build reads versions 1 to {VERSION}"
+            ));
+        }
+
+        let architecture = match u16_at(6)? {
+            1 => Architecture::Perspective,
+            2 => Architecture::Single,
+            id => return Err(format!("unknown net architecture {id}")),
+        };
+        let activation = match bytes[10] {
+            0 => Activation::CReLU,
+            1 => Activation::SCReLU,
+            id => return Err(format!("unknown activation {id}")),
+        };
+        let quantization = match bytes[11] {
+            0 => Quantization::I16,
+            1 => Quantization::I8,
+            id => return Err(format!("unknown quantization {id}")),
+        };
+
+        let (layers, size) = if version == 1 {
+            (Layers::NONE, FIXED_SIZE)
+        } else {
+            let count = u16_at(FIXED_SIZE)?;
+            if count > MAX_LAYERS {
+                return Err(format!(
+                    "{count} hidden layers, nets have at most {MAX_LAYERS}"
+                ));
+            }
+            let sizes = (0..count)
+                .map(|i| u16_at(FIXED_SIZE + 2 + 2 * i))
+                .collect::<Result<Vec<_>, _>>()?;
+            (Layers::new(&sizes)?, FIXED_SIZE + 2 + 2 * count)
+        };
+
+        let header = Self {
+            architecture,
+            hidden: u16_at(8)?,
+            layers,
+            activation,
+            quantization,
+            checksum: u32::from_le_bytes(bytes[12..16].try_into().unwrap()),
+        };
+        Ok((header, &bytes[size..]))
+    }
+
+    /// Bytes of parameters a net with this header has
+    #[must_use]
+    pub fn parameter_size(&self) -> usize {
+        let features = 768 * self.hidden * self.quantization.width() + self.hidden * 2;
+
+        let mut inputs = self.architecture.perspectives() * self.hidden;
+        let mut layers = 0;
+        for &outputs in self.layers.sizes().iter().chain(&[1]) {
+            layers += (inputs * outputs + outputs) * 2;
+            inputs = outputs;
+        }
+
+        features + layers
+    }
+
+    /// Checks the parameters in `parts`, read one after the other, are all there and unchanged
+    pub fn verify(&self, parts: &[&[u8]]) -> Result<(), String> {
+        let size: usize = parts.iter().map(|part| part.len()).sum();
+        if size != self.parameter_size() {
+            return Err(format!(
+                "{self} should have {} bytes of parameters, found {size}",
+                self.parameter_size()
+            ));
+        }
+
+        let found = checksum(parts);
+        if found != self.checksum {
+            return Err(format!(
+                "checksum {found:08x} does not match the header's {:08x}",
+                self.checksum
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for NetHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let activation = match self.activation {
+            Activation::CReLU => "crelu",
+            Activation::SCReLU => "screlu",
+        };
+        let quantization = match self.quantization {
+            Quantization::I16 => "i16",
+            Quantization::I8 => "i8",
+        };
+
+        match self.architecture {
+            Architecture::Perspective => write!(f, "768->{}x2", self.hidden)?,
+            Architecture::Single => write!(f, "768->{}", self.hidden)?,
+        }
+        for size in self.layers.sizes() {
+            write!(f, "->{size}")?;
+        }
+        write!(
+            f,
+            "->1 {activation} {quantization}, checksum {:08x}",
+            self.checksum
+        )
+    }
+}
+
+/// FNV-1a of the parts, read one after the other
+#[must_use]
+pub fn checksum(parts: &[&[u8]]) -> u32 {
+    let mut hash: u32 = 0x811C_9DC5;
+    for &byte in parts.iter().copied().flatten() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        let parameters = vec![7; (768 * 8 + 8 + 16 + 1) * 2];
+        let header = NetHeader::new(
+            Architecture::Perspective,
+            8,
+            Layers::NONE,
+            Activation::SCReLU,
+            Quantization::I16,
+            &[&parameters],
+        );
+        let bytes = header.to_bytes();
+
+        assert_eq!(NetHeader::parse(&bytes), Ok(header));
+        assert_eq!(header.verify(&[&parameters]), Ok(()));
+        assert_eq!(
+            header.to_string(),
+            format!("768->8x2->1 screlu i16, checksum {:08x}", header.checksum)
+        );
+
+        // i8 feature weights take half the space
+        let i8_parameters = &parameters[768 * 8..];
+        let i8_header = NetHeader {
+            quantization: Quantization::I8,
+            checksum: checksum(&[i8_parameters]),
+            ..header
+        };
+        assert_eq!(NetHeader::parse(&i8_header.to_bytes()), Ok(i8_header));
+        assert_eq!(i8_header.verify(&[i8_parameters]), Ok(()));
+        assert!(i8_header.to_string().starts_with("768->8x2->1 screlu i8,"));
+
+        // Version 1 headers stop before the layers
+        let mut v1 = bytes[..FIXED_SIZE].to_vec();
+        v1[4] = 1;
+        v1.push(42);
+        assert_eq!(NetHeader::split(&v1), Ok((header, &[42][..])));
+
+        // Hidden layers after the feature transformer, 16 inputs to 4 to 2 to the output
+        let layered = NetHeader {
+            layers: Layers::new(&[4, 2]).unwrap(),
+            ..header
+        };
+        let mut file = layered.to_bytes();
+        assert_eq!(file.len(), FIXED_SIZE + 6);
+        file.push(42);
+        assert_eq!(NetHeader::split(&file), Ok((layered, &[42][..])));
+        assert_eq!(
+            layered.parameter_size(),
+            (768 * 8 + 8 + 16 * 4 + 4 + 4 * 2 + 2 + 2 + 1) * 2
+        );
+        assert!(layered.to_string().starts_with("768->8x2->4->2->1 screlu"));
+        assert!(Layers::new(&[8; MAX_LAYERS + 1]).is_err());
+        assert!(NetHeader::parse(&layered.to_bytes()[..FIXED_SIZE + 4]).is_err());
+
+        // Single perspective nets have half the inputs after the feature transformer
+        let single = NetHeader {
+            architecture: Architecture::Single,
+            ..layered
+        };
+        assert_eq!(NetHeader::parse(&single.to_bytes()), Ok(single));
+        assert_eq!(
+            single.parameter_size(),
+            layered.parameter_size() - 8 * 4 * 2
+        );
+        assert!(single.to_string().starts_with("768->8->4->2->1 screlu"));
+
+        // Split parameters hash the same as whole ones
+        let (a, b) = parameters.split_at(100);
+        assert_eq!(checksum(&[a, b]), header.checksum);
+
+        let mut corrupt = parameters.clone();
+        corrupt[5] ^= 1;
+        assert!(header.verify(&[&corrupt]).is_err());
+        assert!(header.verify(&[&parameters[1..]]).is_err());
+
+        let mut wrong = bytes.clone();
+        wrong[0] = b'X';
+        assert!(NetHeader::parse(&wrong).is_err());
+        let mut wrong = bytes.clone();
+        wrong[4] = 9;
+        assert!(NetHeader::parse(&wrong).is_err());
+        let mut wrong = bytes.clone();
+        wrong[6] = 9;
+        assert!(NetHeader::parse(&wrong).is_err());
+        let mut wrong = bytes.clone();
+        wrong[11] = 9;
+        assert!(NetHeader::parse(&wrong).is_err());
+    }
+}
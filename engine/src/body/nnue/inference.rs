@@ -1,84 +1,407 @@
-// Svart uses a 768->512x2->1 perspective NNUE, largely inspired by Viridithas and Carp.
+// Svart uses a 768->Nx2 perspective NNUE, largely inspired by Viridithas and Carp. Nets go
+// from the accumulators to the output through the hidden layers their header lists, if any.
+// The accumulators are sized for the widest net this build loads, and only as much of them as
+// the net in use has is ever read or written. Single perspective nets only pass the side to
+// move's accumulator on, both are still kept up to date since the side to move alternates.
 // A huge thanks to Cosmo and Dede for their help with the implementation.
 //
 // I hope to further improve the network as well as make the code more original in the future.
+use super::header::{Activation, NetHeader, Quantization};
+use crate::body::cpu;
 use crate::definitions::MAX_PLY;
 use cozy_chess::{Board, Color, Piece, Square};
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
 
 const FEATURES: usize = 768;
-const HIDDEN: usize = 512;
+const EMBEDDED_HIDDEN: usize = 512;
+/// Widest feature transformer a net can have
+pub const MAX_HIDDEN: usize = 1536;
+/// Feature transformers come in multiples of this
+pub const HIDDEN_CHUNK: usize = 32;
 
 // clipped relu bounds
 const CR_MIN: i16 = 0;
 const CR_MAX: i16 = 255;
 
 // quantization
-const QAB: i32 = 255 * 64;
+const QA: i32 = 255;
+const QB: i32 = 64;
+const QAB: i32 = QA * QB;
 const SCALE: i32 = 400;
 
+// Largest hidden layer after the feature transformer this build evaluates
+const MAX_LAYER_SIZE: usize = 256;
+
 pub const ACTIVATE: bool = true;
 pub const DEACTIVATE: bool = false;
 
 struct Parameters {
-    feature_weights: [i16; FEATURES * HIDDEN],
-    feature_bias: [i16; HIDDEN],
-    output_weights: [i16; HIDDEN * 2], // perspective aware
-    output_bias: i16,
-}
-
-// the model is read from binary files at compile time
-static MODEL: Parameters = Parameters {
-    feature_weights: unsafe { std::mem::transmute(*include_bytes!("net/feature_weights.bin")) },
-    feature_bias: unsafe { std::mem::transmute(*include_bytes!("net/feature_bias.bin")) },
-    output_weights: unsafe { std::mem::transmute(*include_bytes!("net/output_weights.bin")) },
-    output_bias: unsafe { std::mem::transmute(*include_bytes!("net/output_bias.bin")) },
+    hidden: usize,
+    // Whether the first layer reads both accumulators or only the side to move's
+    perspectives: usize,
+    feature_weights: FeatureWeights,
+    feature_bias: &'static [i16],
+    // The hidden layers after the feature transformer, then the output layer
+    layers: &'static [Layer],
+}
+
+// Feature weights as wide as the net file has them, the accumulators are i16s either way
+enum FeatureWeights {
+    I16(&'static [i16]),
+    I8(&'static [i8]),
+}
+
+// Weights an output neuron at a time, the first layer's inputs are both perspectives' activations
+struct Layer {
+    inputs: usize,
+    weights: &'static [i16],
+    biases: &'static [i16],
+}
+
+// the embedded model is read from binary files at compile time
+const FEATURE_WEIGHTS: &[u8; FEATURES * EMBEDDED_HIDDEN * 2] =
+    include_bytes!("net/feature_weights.bin");
+const FEATURE_BIAS: &[u8; EMBEDDED_HIDDEN * 2] = include_bytes!("net/feature_bias.bin");
+const OUTPUT_WEIGHTS: &[u8; EMBEDDED_HIDDEN * 2 * 2] = include_bytes!("net/output_weights.bin");
+const OUTPUT_BIAS: &[u8; 2] = include_bytes!("net/output_bias.bin");
+
+static EMBEDDED_FEATURE_WEIGHTS: [i16; FEATURES * EMBEDDED_HIDDEN] = unsafe {
+    std::mem::transmute::<[u8; FEATURES * EMBEDDED_HIDDEN * 2], [i16; FEATURES * EMBEDDED_HIDDEN]>(
+        *FEATURE_WEIGHTS,
+    )
 };
 
+static EMBEDDED_OUTPUT_WEIGHTS: [i16; EMBEDDED_HIDDEN * 2] = unsafe {
+    std::mem::transmute::<[u8; EMBEDDED_HIDDEN * 2 * 2], [i16; EMBEDDED_HIDDEN * 2]>(
+        *OUTPUT_WEIGHTS,
+    )
+};
+static EMBEDDED_OUTPUT_BIAS: [i16; 1] = [i16::from_le_bytes(*OUTPUT_BIAS)];
+
+static EMBEDDED_FEATURE_BIAS: [i16; EMBEDDED_HIDDEN] = unsafe {
+    std::mem::transmute::<[u8; EMBEDDED_HIDDEN * 2], [i16; EMBEDDED_HIDDEN]>(*FEATURE_BIAS)
+};
+
+static EMBEDDED: Parameters = Parameters {
+    hidden: EMBEDDED_HIDDEN,
+    perspectives: 2,
+    feature_weights: FeatureWeights::I16(&EMBEDDED_FEATURE_WEIGHTS),
+    feature_bias: &EMBEDDED_FEATURE_BIAS,
+    layers: &[Layer {
+        inputs: EMBEDDED_HIDDEN * 2,
+        weights: &EMBEDDED_OUTPUT_WEIGHTS,
+        biases: &EMBEDDED_OUTPUT_BIAS,
+    }],
+};
+
+static EMBEDDED_HEADER: Lazy<NetHeader> = Lazy::new(|| {
+    let header = NetHeader::parse(include_bytes!("net/header.bin")).and_then(|header| {
+        let parameters = [
+            &FEATURE_WEIGHTS[..],
+            FEATURE_BIAS,
+            OUTPUT_WEIGHTS,
+            OUTPUT_BIAS,
+        ];
+        header.verify(&parameters)?;
+        supported(&header)?;
+        Ok(header)
+    });
+
+    header.unwrap_or_else(|e| panic!("embedded net: {e}"))
+});
+
+// The net every engine in the process evaluates with. Nets loaded from files are leaked when
+// replaced, a search elsewhere in the process may still be reading them.
+static NET: AtomicPtr<Parameters> = AtomicPtr::new(std::ptr::addr_of!(EMBEDDED).cast_mut());
+static LOADED_HEADER: Mutex<Option<NetHeader>> = Mutex::new(None);
+
+#[inline(always)]
+fn model() -> &'static Parameters {
+    // Only ever points to EMBEDDED or a leaked net. Acquire pairs with the Release stores so the
+    // parameters are fully visible before they are read.
+    unsafe { &*NET.load(Ordering::Acquire) }
+}
+
+/// Header of the net in use
+#[must_use]
+pub fn net_header() -> NetHeader {
+    LOADED_HEADER.lock().unwrap().unwrap_or(*EMBEDDED_HEADER)
+}
+
+/// Switches to the net in `bytes`, a header followed by its parameters. Accumulators computed
+/// with the previous net have to be refreshed.
+pub fn load_net(bytes: &[u8]) -> Result<NetHeader, String> {
+    let (header, parameters) = NetHeader::split(bytes)?;
+    header.verify(&[parameters])?;
+    supported(&header)?;
+
+    let net = Box::new(read_parameters(&header, parameters));
+
+    NET.store(Box::into_raw(net), Ordering::Release);
+    *LOADED_HEADER.lock().unwrap() = Some(header);
+    Ok(header)
+}
+
+// The parameters of a verified, supported net. The feature weights are leaked like the net.
+fn read_parameters(header: &NetHeader, parameters: &[u8]) -> Parameters {
+    let i16s = |bytes: &[u8]| -> Vec<i16> {
+        bytes
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    };
+
+    let hidden = header.hidden;
+    let (features, rest) = parameters.split_at(FEATURES * hidden * header.quantization.width());
+    let feature_weights = match header.quantization {
+        Quantization::I16 => FeatureWeights::I16(i16s(features).leak()),
+        Quantization::I8 => {
+            FeatureWeights::I8(features.iter().map(|&b| b as i8).collect::<Vec<_>>().leak())
+        }
+    };
+
+    let rest: &'static [i16] = i16s(rest).leak();
+    let (feature_bias, mut rest) = rest.split_at(hidden);
+
+    let mut layers = vec![];
+    let perspectives = header.architecture.perspectives();
+    let mut inputs = hidden * perspectives;
+    for &outputs in header.layers.sizes().iter().chain(&[1]) {
+        let (weights, after) = rest.split_at(inputs * outputs);
+        let (biases, after) = after.split_at(outputs);
+        layers.push(Layer {
+            inputs,
+            weights,
+            biases,
+        });
+        inputs = outputs;
+        rest = after;
+    }
+
+    Parameters {
+        hidden,
+        perspectives,
+        feature_weights,
+        feature_bias,
+        layers: layers.leak(),
+    }
+}
+
+/// Goes back to the net compiled into the binary
+pub fn use_embedded_net() {
+    NET.store(std::ptr::addr_of!(EMBEDDED).cast_mut(), Ordering::Release);
+    *LOADED_HEADER.lock().unwrap() = None;
+}
+
+// Nets this build can evaluate with
+fn supported(header: &NetHeader) -> Result<(), String> {
+    if !(1..=MAX_HIDDEN).contains(&header.hidden)
+        || !header.hidden.is_multiple_of(HIDDEN_CHUNK)
+        || header.activation != Activation::CReLU
+    {
+        return Err(format!(
+            "{header} is not supported, this build evaluates crelu nets of N a multiple of {HIDDEN_CHUNK} up to {MAX_HIDDEN}"
+        ));
+    }
+
+    if let Some(size) = header.layers.sizes().iter().find(|&&s| s > MAX_LAYER_SIZE) {
+        return Err(format!(
+            "hidden layers of {size} are not supported, this build evaluates up to {MAX_LAYER_SIZE}"
+        ));
+    }
+
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct NNUEState {
     pub accumulators: [Accumulator; MAX_PLY],
     pub current_acc: usize,
+    kernels: &'static Kernels,
 }
 
 // The accumulator represents the
-// hidden layer from both perspectives
-#[derive(Clone, Copy, PartialEq, Debug)]
+// hidden layer from both perspectives, the net in use's first entries of it
+#[derive(Clone, Copy, Debug)]
 pub struct Accumulator {
-    white: [i16; HIDDEN],
-    black: [i16; HIDDEN],
+    white: [i16; MAX_HIDDEN],
+    black: [i16; MAX_HIDDEN],
 }
 
 impl Default for Accumulator {
     fn default() -> Self {
-        Self {
-            white: MODEL.feature_bias,
-            black: MODEL.feature_bias,
-        }
+        let mut acc = Self {
+            white: [0; MAX_HIDDEN],
+            black: [0; MAX_HIDDEN],
+        };
+        acc.reset();
+        acc
+    }
+}
+
+// Accumulators agree on the part the net in use has
+impl PartialEq for Accumulator {
+    fn eq(&self, other: &Self) -> bool {
+        let hidden = model().hidden;
+        self.white[..hidden] == other.white[..hidden]
+            && self.black[..hidden] == other.black[..hidden]
     }
 }
 
 impl Accumulator {
+    // Back to the feature biases, no features active
+    fn reset(&mut self) {
+        let bias = model().feature_bias;
+        self.white[..bias.len()].copy_from_slice(bias);
+        self.black[..bias.len()].copy_from_slice(bias);
+    }
+
     // efficiently update the change of a feature
-    fn efficiently_update<const ACTIVATE: bool>(&mut self, idx: (usize, usize)) {
-        fn update_perspective<const ACTIVATE: bool>(acc: &mut [i16; HIDDEN], idx: usize) {
-            // we iterate over the weights corresponding to the feature that has been changed
-            // and then update the activations in the hidden layer accordingly
-            let feature_weights = acc
-                .iter_mut()
-                // the column of the weight matrix corresponding to the index of the feature
-                .zip(&MODEL.feature_weights[idx..idx + HIDDEN]);
-
-            for (activation, &weight) in feature_weights {
-                if ACTIVATE {
-                    *activation += weight;
-                } else {
-                    *activation -= weight;
+    fn efficiently_update<const ACTIVATE: bool>(&mut self, kernels: &Kernels, idx: (usize, usize)) {
+        fn update_perspective<const ACTIVATE: bool>(
+            kernels: &Kernels,
+            acc: &mut [i16; MAX_HIDDEN],
+            idx: usize,
+        ) {
+            // the column of the weight matrix corresponding to the index of the feature, in
+            // the width the net was loaded with
+            let net = model();
+            let (acc, column) = (
+                &mut acc[..net.hidden],
+                idx * net.hidden..(idx + 1) * net.hidden,
+            );
+            match net.feature_weights {
+                FeatureWeights::I16(weights) => {
+                    kernels.update_column::<ACTIVATE, _>(acc, &weights[column]);
+                }
+                FeatureWeights::I8(weights) => {
+                    kernels.update_column::<ACTIVATE, _>(acc, &weights[column]);
                 }
             }
         }
 
-        update_perspective::<ACTIVATE>(&mut self.white, idx.0);
-        update_perspective::<ACTIVATE>(&mut self.black, idx.1);
+        update_perspective::<ACTIVATE>(kernels, &mut self.white, idx.0);
+        update_perspective::<ACTIVATE>(kernels, &mut self.black, idx.1);
+    }
+}
+
+// The kernels for one instruction set. A state picks them from the CPU dispatch when it is built
+// or refreshed, and then calls through them without checking which ones are in use.
+struct Kernels {
+    activate_i16: unsafe fn(&mut [i16], &[i16]),
+    deactivate_i16: unsafe fn(&mut [i16], &[i16]),
+    activate_i8: unsafe fn(&mut [i16], &[i8]),
+    deactivate_i8: unsafe fn(&mut [i16], &[i8]),
+    crelu_dot: unsafe fn(&[i16], &[i16]) -> i32,
+}
+
+static GENERIC_KERNELS: Kernels = Kernels {
+    activate_i16: update_column_generic::<ACTIVATE, i16>,
+    deactivate_i16: update_column_generic::<DEACTIVATE, i16>,
+    activate_i8: update_column_generic::<ACTIVATE, i8>,
+    deactivate_i8: update_column_generic::<DEACTIVATE, i8>,
+    crelu_dot: crelu_dot_generic,
+};
+
+#[cfg(target_arch = "x86_64")]
+static AVX2_KERNELS: Kernels = Kernels {
+    activate_i16: update_column_avx2::<ACTIVATE, i16>,
+    deactivate_i16: update_column_avx2::<DEACTIVATE, i16>,
+    activate_i8: update_column_avx2::<ACTIVATE, i8>,
+    deactivate_i8: update_column_avx2::<DEACTIVATE, i8>,
+    crelu_dot: crelu_dot_avx2,
+};
+
+#[cfg(target_arch = "x86_64")]
+static AVX512_KERNELS: Kernels = Kernels {
+    activate_i16: update_column_avx512::<ACTIVATE, i16>,
+    deactivate_i16: update_column_avx512::<DEACTIVATE, i16>,
+    activate_i8: update_column_avx512::<ACTIVATE, i8>,
+    deactivate_i8: update_column_avx512::<DEACTIVATE, i8>,
+    crelu_dot: crelu_dot_avx512,
+};
+
+// Feature weights the accumulators are updated with
+trait Weight: Copy + Into<i16> {
+    fn kernel<const ACTIVATE: bool>(kernels: &Kernels) -> unsafe fn(&mut [i16], &[Self]);
+}
+
+impl Weight for i16 {
+    #[inline(always)]
+    fn kernel<const ACTIVATE: bool>(kernels: &Kernels) -> unsafe fn(&mut [i16], &[Self]) {
+        if ACTIVATE {
+            kernels.activate_i16
+        } else {
+            kernels.deactivate_i16
+        }
+    }
+}
+
+impl Weight for i8 {
+    #[inline(always)]
+    fn kernel<const ACTIVATE: bool>(kernels: &Kernels) -> unsafe fn(&mut [i16], &[Self]) {
+        if ACTIVATE {
+            kernels.activate_i8
+        } else {
+            kernels.deactivate_i8
+        }
+    }
+}
+
+impl Kernels {
+    // The ones the CPU dispatch is set to
+    fn selected() -> &'static Self {
+        match cpu::kernels() {
+            #[cfg(target_arch = "x86_64")]
+            cpu::Kernels::Avx512 => &AVX512_KERNELS,
+            #[cfg(target_arch = "x86_64")]
+            cpu::Kernels::Avx2 => &AVX2_KERNELS,
+            _ => &GENERIC_KERNELS,
+        }
+    }
+
+    // we iterate over the weights corresponding to the feature that has been changed
+    // and then update the activations in the hidden layer accordingly
+    #[inline(always)]
+    fn update_column<const ACTIVATE: bool, W: Weight>(&self, acc: &mut [i16], column: &[W]) {
+        // Only kernels the CPU has the features of are ever selected
+        unsafe { W::kernel::<ACTIVATE>(self)(acc, column) }
+    }
+
+    // Clipped ReLU of a layer's inputs, dotted with a neuron's weights
+    #[inline(always)]
+    fn crelu_dot(&self, acc: &[i16], weights: &[i16]) -> i32 {
+        unsafe { (self.crelu_dot)(acc, weights) }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+fn update_column_avx2<const ACTIVATE: bool, W: Weight>(acc: &mut [i16], column: &[W]) {
+    update_column_generic::<ACTIVATE, W>(acc, column);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,avx512f,avx512bw")]
+fn update_column_avx512<const ACTIVATE: bool, W: Weight>(acc: &mut [i16], column: &[W]) {
+    update_column_generic::<ACTIVATE, W>(acc, column);
+}
+
+// Inlined into every kernel, which the compiler vectorizes for its instruction set
+#[inline(always)]
+fn update_column_generic<const ACTIVATE: bool, W: Weight>(acc: &mut [i16], column: &[W]) {
+    // Hidden sizes are a multiple of the chunks, fixed lengths unroll into whole vectors
+    let columns = column.chunks_exact(HIDDEN_CHUNK);
+    for (activations, weights) in acc.chunks_exact_mut(HIDDEN_CHUNK).zip(columns) {
+        for (activation, &weight) in activations.iter_mut().zip(weights) {
+            if ACTIVATE {
+                *activation += weight.into();
+            } else {
+                *activation -= weight.into();
+            }
+        }
     }
 }
 
@@ -92,30 +415,24 @@ impl NNUEState {
     pub fn from_board(board: &Board) -> Box<Self> {
         let mut boxed: Box<NNUEState> = unsafe {
             let layout = std::alloc::Layout::new::<Self>();
-            let ptr = std::alloc::alloc_zeroed(layout);
+            let ptr = std::alloc::alloc_zeroed(layout).cast::<Self>();
             if ptr.is_null() {
                 std::alloc::handle_alloc_error(layout);
             }
-            Box::from_raw(ptr.cast())
+            // Zeroes are valid for everything but the kernels reference
+            std::ptr::addr_of_mut!((*ptr).kernels).write(Kernels::selected());
+            Box::from_raw(ptr)
         };
 
-        // initialize the first state
-        boxed.accumulators[0] = Accumulator::default();
-        for sq in board.occupied() {
-            let piece = board.piece_on(sq).unwrap();
-            let color = board.color_on(sq).unwrap();
-            let idx = weight_column_index(sq, piece, color);
-
-            boxed.accumulators[0].efficiently_update::<ACTIVATE>(idx);
-        }
-
+        boxed.refresh(board);
         boxed
     }
 
     pub fn refresh(&mut self, board: &Board) {
-        // reset the accumulator stack
+        // reset the accumulator stack, with the kernels in use now
         self.current_acc = 0;
-        self.accumulators[self.current_acc] = Accumulator::default();
+        self.kernels = Kernels::selected();
+        self.accumulators[self.current_acc].reset();
 
         // update the first accumulator
         for sq in board.occupied() {
@@ -123,13 +440,17 @@ impl NNUEState {
             let color = board.color_on(sq).unwrap();
             let idx = weight_column_index(sq, piece, color);
 
-            self.accumulators[self.current_acc].efficiently_update::<ACTIVATE>(idx);
+            self.accumulators[self.current_acc].efficiently_update::<ACTIVATE>(self.kernels, idx);
         }
     }
 
     /// Copy and push the current accumulator to the "top"
     pub fn push(&mut self) {
-        self.accumulators[self.current_acc + 1] = self.accumulators[self.current_acc];
+        let hidden = model().hidden;
+        let (below, above) = self.accumulators.split_at_mut(self.current_acc + 1);
+        let (current, next) = (&below[self.current_acc], &mut above[0]);
+        next.white[..hidden].copy_from_slice(&current.white[..hidden]);
+        next.black[..hidden].copy_from_slice(&current.black[..hidden]);
         self.current_acc += 1;
     }
 
@@ -140,39 +461,160 @@ impl NNUEState {
     pub fn update_feature<const ACTIVATE: bool>(&mut self, sq: Square, piece: Piece, color: Color) {
         let idx = weight_column_index(sq, piece, color);
 
-        self.accumulators[self.current_acc].efficiently_update::<ACTIVATE>(idx);
+        self.accumulators[self.current_acc].efficiently_update::<ACTIVATE>(self.kernels, idx);
+    }
+
+    /// Whether the current accumulator matches one computed from scratch for the board
+    #[cfg(feature = "debug_asserts")]
+    pub fn matches_refresh(&self, board: &Board) -> bool {
+        let mut acc = Accumulator::default();
+        for sq in board.occupied() {
+            let piece = board.piece_on(sq).unwrap();
+            let color = board.color_on(sq).unwrap();
+            acc.efficiently_update::<ACTIVATE>(self.kernels, weight_column_index(sq, piece, color));
+        }
+
+        self.accumulators[self.current_acc] == acc
     }
 
     pub fn evaluate(&self, stm: Color) -> i32 {
         let acc = &self.accumulators[self.current_acc];
+        let hidden = model().hidden;
 
         let (us, them) = match stm {
-            Color::White => (acc.white.iter(), acc.black.iter()),
-            Color::Black => (acc.black.iter(), acc.white.iter()),
+            Color::White => (&acc.white[..hidden], &acc.black[..hidden]),
+            Color::Black => (&acc.black[..hidden], &acc.white[..hidden]),
         };
 
-        // Add on the bias
-        let mut output = MODEL.output_bias as i32;
+        forward(model(), self.kernels, us, them)
+    }
+}
 
-        // Add on the activations from one perspective with clipped ReLU
-        for (&value, &weight) in us.zip(&MODEL.output_weights[..HIDDEN]) {
-            output += (value.clamp(CR_MIN, CR_MAX) as i32) * (weight as i32);
+// The layers after the feature transformer, with the side to move's activations first
+fn forward(net: &Parameters, kernels: &Kernels, us: &[i16], them: &[i16]) -> i32 {
+    let mut activations = [0; MAX_LAYER_SIZE];
+    let mut len = 0;
+
+    let (output, hidden) = net.layers.split_last().unwrap();
+    for (i, layer) in hidden.iter().enumerate() {
+        let mut next = [0; MAX_LAYER_SIZE];
+        for (j, weights) in layer.weights.chunks_exact(layer.inputs).enumerate() {
+            let sum = if i == 0 {
+                perspectives_dot(net, kernels, us, them, weights)
+            } else {
+                kernels.crelu_dot(&activations[..len], weights)
+            };
+
+            // Back to the activations' scale, clipped for the next layer
+            let sum = sum + i32::from(layer.biases[j]);
+            next[j] = (sum / QB).clamp(i32::from(CR_MIN), i32::from(CR_MAX)) as i16;
         }
 
-        // ... other perspective
-        for (&value, &weight) in them.zip(&MODEL.output_weights[HIDDEN..]) {
-            output += (value.clamp(CR_MIN, CR_MAX) as i32) * (weight as i32);
-        }
+        activations = next;
+        len = layer.biases.len();
+    }
+
+    // Add on the bias
+    let mut sum = i32::from(output.biases[0]);
+    sum += if hidden.is_empty() {
+        perspectives_dot(net, kernels, us, them, output.weights)
+    } else {
+        kernels.crelu_dot(&activations[..len], output.weights)
+    };
+
+    // Quantization
+    sum * SCALE / QAB
+}
+
+// The activations from one perspective with clipped ReLU, then the other perspective if the net
+// reads both
+fn perspectives_dot(
+    net: &Parameters,
+    kernels: &Kernels,
+    us: &[i16],
+    them: &[i16],
+    weights: &[i16],
+) -> i32 {
+    if net.perspectives == 1 {
+        return kernels.crelu_dot(us, weights);
+    }
+
+    let (ours, theirs) = weights.split_at(us.len());
+    kernels.crelu_dot(us, ours) + kernels.crelu_dot(them, theirs)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+fn crelu_dot_avx2(acc: &[i16], weights: &[i16]) -> i32 {
+    crelu_dot_generic(acc, weights)
+}
 
-        // Quantization
-        output * SCALE / QAB
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,avx512f,avx512bw")]
+fn crelu_dot_avx512(acc: &[i16], weights: &[i16]) -> i32 {
+    crelu_dot_generic(acc, weights)
+}
+
+#[inline(always)]
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+fn crelu_dot_generic(acc: &[i16], weights: &[i16]) -> i32 {
+    acc.iter()
+        .zip(weights)
+        .map(|(&value, &weight)| (value.clamp(CR_MIN, CR_MAX) as i32) * (weight as i32))
+        .sum()
+}
+
+// Eight lanes at a time, for wasm builds with -C target-feature=+simd128.
+// A clipped activation times a weight, summed in pairs, always fits an i32.
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+fn crelu_dot_generic(acc: &[i16], weights: &[i16]) -> i32 {
+    use std::arch::wasm32::*;
+
+    let min = i16x8_splat(CR_MIN);
+    let max = i16x8_splat(CR_MAX);
+    let mut sum = i32x4_splat(0);
+
+    let values = acc.chunks_exact(8);
+    let tail: i32 = values
+        .remainder()
+        .iter()
+        .zip(&weights[acc.len() / 8 * 8..])
+        .map(|(&value, &weight)| (value.clamp(CR_MIN, CR_MAX) as i32) * (weight as i32))
+        .sum();
+
+    for (values, weights) in values.zip(weights.chunks_exact(8)) {
+        // Both chunks hold exactly eight i16s, v128 loads need no alignment
+        let (values, weights) = unsafe {
+            (
+                v128_load(values.as_ptr().cast()),
+                v128_load(weights.as_ptr().cast()),
+            )
+        };
+
+        let clipped = i16x8_min(i16x8_max(values, min), max);
+        sum = i32x4_add(sum, i32x4_dot_i16x8(clipped, weights));
     }
+
+    tail + i32x4_extract_lane::<0>(sum)
+        + i32x4_extract_lane::<1>(sum)
+        + i32x4_extract_lane::<2>(sum)
+        + i32x4_extract_lane::<3>(sum)
 }
 
-// Returns white's and black's feature weight index respectively
-// i.e where the feature's weight column is in the weight matrix.
+// Returns white's and black's feature index respectively
+// i.e which column of the weight matrix is the feature's.
 #[must_use]
 fn weight_column_index(sq: Square, piece: Piece, color: Color) -> (usize, usize) {
+    (
+        feature_index(Color::White, sq, piece, color),
+        feature_index(Color::Black, sq, piece, color),
+    )
+}
+
+// The feature as `view` sees it: black sees the board mirrored vertically with the colors
+// swapped, so that both see their own pieces as white's
+#[must_use]
+fn feature_index(view: Color, sq: Square, piece: Piece, color: Color) -> usize {
     // The jump from one perspective to the other
     const COLOR_STRIDE: usize = 64 * 6;
     // The jump from one piece type to the next
@@ -186,17 +628,18 @@ fn weight_column_index(sq: Square, piece: Piece, color: Color) -> (usize, usize)
         Piece::King => 5,
     };
 
-    let c = color as usize;
-
-    let white_idx = c * COLOR_STRIDE + p * PIECE_STRIDE + sq as usize;
-    let black_idx = (1 ^ c) * COLOR_STRIDE + p * PIECE_STRIDE + sq.flip_rank() as usize;
+    let (c, sq) = match view {
+        Color::White => (color as usize, sq),
+        Color::Black => (1 ^ color as usize, sq.flip_rank()),
+    };
 
-    (white_idx * HIDDEN, black_idx * HIDDEN)
+    c * COLOR_STRIDE + p * PIECE_STRIDE + sq as usize
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::body::nnue::header::{Architecture, Layers};
     use crate::body::{movegen, position::play_move, search::Search, tt::TT};
 
     #[test]
@@ -212,6 +655,213 @@ mod tests {
         assert_eq!(old_acc, state.accumulators[0]);
     }
 
+    #[test]
+    fn i8_nets() {
+        // The embedded net with its feature weights narrowed, stored both ways
+        let narrowed: Vec<i16> = EMBEDDED_FEATURE_WEIGHTS
+            .iter()
+            .map(|&w| w.clamp(i8::MIN.into(), i8::MAX.into()))
+            .collect();
+        let rest = [&FEATURE_BIAS[..], OUTPUT_WEIGHTS, OUTPUT_BIAS].concat();
+        let wide: Vec<u8> = narrowed.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let narrow: Vec<u8> = narrowed.iter().map(|&w| w as i8 as u8).collect();
+
+        let read = |features: &[u8], quantization| {
+            let parameters = [features, &rest].concat();
+            let header = NetHeader::new(
+                Architecture::Perspective,
+                EMBEDDED_HIDDEN,
+                Layers::NONE,
+                Activation::CReLU,
+                quantization,
+                &[&parameters],
+            );
+            assert_eq!(header.verify(&[&parameters]), Ok(()));
+            read_parameters(&header, &parameters)
+        };
+        let (FeatureWeights::I16(wide), FeatureWeights::I8(narrow)) = (
+            read(&wide, Quantization::I16).feature_weights,
+            read(&narrow, Quantization::I8).feature_weights,
+        ) else {
+            panic!("nets read with the wrong width");
+        };
+
+        // Both kernels accumulate the same columns alike
+        let kernels = Kernels::selected();
+        let mut a = [0; EMBEDDED_HIDDEN];
+        let mut b = [0; EMBEDDED_HIDDEN];
+        for idx in [0, 5, 700, 767] {
+            let column = idx * EMBEDDED_HIDDEN..(idx + 1) * EMBEDDED_HIDDEN;
+            kernels.update_column::<ACTIVATE, _>(&mut a, &wide[column.clone()]);
+            kernels.update_column::<ACTIVATE, _>(&mut b, &narrow[column]);
+        }
+        kernels.update_column::<DEACTIVATE, _>(&mut a, &wide[..EMBEDDED_HIDDEN]);
+        kernels.update_column::<DEACTIVATE, _>(&mut b, &narrow[..EMBEDDED_HIDDEN]);
+        assert_eq!(a, b);
+        assert_ne!(a, [0; EMBEDDED_HIDDEN]);
+    }
+
+    #[test]
+    fn kernels_agree() {
+        // Whatever kernels this CPU dispatches to compute what the generic ones do
+        let kernels = Kernels::selected();
+        let column: Vec<i16> = (0..EMBEDDED_HIDDEN)
+            .map(|i| (i as i16 * 37) % 201 - 100)
+            .collect();
+        let mut dispatched = [7; EMBEDDED_HIDDEN];
+        let mut generic = [7; EMBEDDED_HIDDEN];
+        kernels.update_column::<ACTIVATE, _>(&mut dispatched, &column);
+        update_column_generic::<ACTIVATE, _>(&mut generic, &column);
+        kernels.update_column::<DEACTIVATE, _>(&mut dispatched, &column[..EMBEDDED_HIDDEN / 2]);
+        update_column_generic::<DEACTIVATE, _>(&mut generic, &column[..EMBEDDED_HIDDEN / 2]);
+        assert_eq!(dispatched, generic);
+
+        let values: Vec<i16> = (0..EMBEDDED_HIDDEN)
+            .map(|i| (i as i16 * 13) % 400 - 50)
+            .collect();
+        for len in [EMBEDDED_HIDDEN, 8, 13] {
+            assert_eq!(
+                kernels.crelu_dot(&values[..len], &column[..len]),
+                crelu_dot_generic(&values[..len], &column[..len])
+            );
+        }
+    }
+
+    #[test]
+    fn hidden_sizes() {
+        let header = |hidden, parameters: &[u8]| {
+            NetHeader::new(
+                Architecture::Perspective,
+                hidden,
+                Layers::NONE,
+                Activation::CReLU,
+                Quantization::I16,
+                &[parameters],
+            )
+        };
+        assert!(supported(&header(MAX_HIDDEN, &[])).is_ok());
+        assert!(supported(&header(MAX_HIDDEN + HIDDEN_CHUNK, &[])).is_err());
+        assert!(supported(&header(HIDDEN_CHUNK + 1, &[])).is_err());
+
+        // A narrower net than the embedded one, its output weighing their side double
+        let hidden = 64;
+        let output_weights: Vec<u8> = [1i16, 2]
+            .iter()
+            .flat_map(|&w| std::iter::repeat_n(w.to_le_bytes(), hidden))
+            .flatten()
+            .collect();
+        let parameters = [
+            vec![0; (FEATURES * hidden + hidden) * 2],
+            output_weights,
+            vec![0; 2],
+        ]
+        .concat();
+        let header = header(hidden, &parameters);
+        assert_eq!(header.verify(&[&parameters]), Ok(()));
+        let net = read_parameters(&header, &parameters);
+        assert_eq!((net.hidden, net.feature_bias.len()), (hidden, hidden));
+
+        let sum = 64 * 10 + 64 * 20 * 2;
+        assert_eq!(
+            forward(&net, &GENERIC_KERNELS, &[10; 64], &[20; 64]),
+            sum * SCALE / QAB
+        );
+    }
+
+    #[test]
+    fn single_perspective() {
+        // Only the side to move's accumulator reaches the output
+        let hidden = 32;
+        let parameters = [
+            vec![0; (FEATURES * hidden + hidden) * 2],
+            [3i16.to_le_bytes(); 32].concat(),
+            vec![0; 2],
+        ]
+        .concat();
+        let header = NetHeader::new(
+            Architecture::Single,
+            hidden,
+            Layers::NONE,
+            Activation::CReLU,
+            Quantization::I16,
+            &[&parameters],
+        );
+        assert_eq!(header.verify(&[&parameters]), Ok(()));
+        assert_eq!(supported(&header), Ok(()));
+
+        let net = read_parameters(&header, &parameters);
+        assert_eq!(
+            forward(&net, &GENERIC_KERNELS, &[10; 32], &[20; 32]),
+            32 * 30 * SCALE / QAB
+        );
+
+        // Black sees the board mirrored vertically, its pieces as white's
+        for (sq, piece, color) in [
+            (Square::E1, Piece::King, Color::White),
+            (Square::C7, Piece::Pawn, Color::Black),
+            (Square::H5, Piece::Queen, Color::Black),
+        ] {
+            assert_eq!(
+                feature_index(Color::Black, sq, piece, color),
+                feature_index(Color::White, sq.flip_rank(), piece, !color)
+            );
+        }
+    }
+
+    #[test]
+    fn layered_nets() {
+        // 1024 inputs to 8 neurons, each passing one of the side to move's activations on
+        let mut hidden_weights = vec![0i16; EMBEDDED_HIDDEN * 2 * 8];
+        for j in 0..8 {
+            hidden_weights[j * EMBEDDED_HIDDEN * 2 + j] = QB as i16;
+        }
+        let hidden_biases = [0, 0, 0, 0, 0, 0, 0, 64 * 300];
+        let output_weights = [64i16; 8];
+        let output_bias = [100i16];
+
+        let i16_bytes =
+            |values: &[i16]| -> Vec<u8> { values.iter().flat_map(|v| v.to_le_bytes()).collect() };
+        let parameters = [
+            vec![0; FEATURES * EMBEDDED_HIDDEN * 2 + EMBEDDED_HIDDEN * 2],
+            i16_bytes(&hidden_weights),
+            i16_bytes(&hidden_biases),
+            i16_bytes(&output_weights),
+            i16_bytes(&output_bias),
+        ]
+        .concat();
+        let header = NetHeader::new(
+            Architecture::Perspective,
+            EMBEDDED_HIDDEN,
+            Layers::new(&[8]).unwrap(),
+            Activation::CReLU,
+            Quantization::I16,
+            &[&parameters],
+        );
+        assert_eq!(header.verify(&[&parameters]), Ok(()));
+        assert_eq!(supported(&header), Ok(()));
+        let net = read_parameters(&header, &parameters);
+
+        let mut us = [0; EMBEDDED_HIDDEN];
+        let them = [1000; EMBEDDED_HIDDEN];
+        for (j, value) in us.iter_mut().take(8).enumerate() {
+            *value = 10 * j as i16;
+        }
+        us[1] = -40;
+
+        // 0, 0, 20 .. 60, then 70 + 300 clipped to 255, the other perspective unused
+        let sum = 64 * (20 + 30 + 40 + 50 + 60 + 255) + 100;
+        assert_eq!(
+            forward(&net, &GENERIC_KERNELS, &us, &them),
+            sum * SCALE / QAB
+        );
+
+        let too_wide = NetHeader {
+            layers: Layers::new(&[MAX_LAYER_SIZE + 1]).unwrap(),
+            ..header
+        };
+        assert!(supported(&too_wide).is_err());
+    }
+
     #[test]
     fn nnue_moves() {
         let board = Board::default();
@@ -219,13 +869,13 @@ mod tests {
         let nnue = NNUEState::from_board(&board);
         let history = crate::body::history::History::new();
 
-        let mut search = Search::new(&tt, &nnue, &history, &vec![board.hash()]);
+        let mut search = Search::new(&tt, nnue, &history, &vec![board.hash()]);
 
         let moves = movegen::all_moves(&search, &board, None, 0);
         let initial_white = search.nnue.accumulators[0].white;
         let initial_black = search.nnue.accumulators[0].black;
 
-        for mv in moves {
+        for mv in moves.as_slice() {
             let mv = mv.mv;
             let mut new_b = board.clone();
             play_move(&mut new_b, &mut search.nnue, mv);
@@ -261,11 +911,11 @@ mod tests {
             let nnue = NNUEState::from_board(&board);
             let history = crate::body::history::History::new();
 
-            let mut search = Search::new(&tt, &nnue, &history, &vec![board.hash()]);
+            let mut search = Search::new(&tt, nnue, &history, &vec![board.hash()]);
             search.nnue.refresh(&board);
             let moves = movegen::all_moves(&search, &board, None, 0);
 
-            for mv in moves {
+            for mv in moves.as_slice() {
                 let mut board2 = Board::from_fen(fen, false).unwrap();
 
                 board2.play_unchecked(mv.mv);
@@ -1,7 +1,24 @@
 use super::nnue::inference::{NNUEState, ACTIVATE, DEACTIVATE};
-use cozy_chess::{Board, File, Move, Piece, Rank, Square};
+use super::see::SEE_VALUES;
+use cozy_chess::{
+    get_between_rays, get_bishop_moves, get_bishop_rays, get_king_moves, get_knight_moves,
+    get_line_rays, get_pawn_attacks, get_rook_moves, get_rook_rays, BitBoard, Board, Color, File,
+    Move, Piece, Rank, Square,
+};
 
 pub fn play_move(board: &mut Board, nnue: &mut Box<NNUEState>, mv: Move) {
+    #[cfg(feature = "debug_asserts")]
+    let parent = board.clone();
+
+    update_accumulator(board, nnue, mv);
+    board.play_unchecked(mv);
+
+    #[cfg(feature = "debug_asserts")]
+    verify_state(board, nnue, format_args!("after {mv} in {parent}"));
+}
+
+/// Pushes the accumulator of the position after the move, `board` is the one before it
+pub fn update_accumulator(board: &Board, nnue: &mut Box<NNUEState>, mv: Move) {
     nnue.push();
 
     let stm = board.side_to_move();
@@ -48,8 +65,128 @@ pub fn play_move(board: &mut Board, nnue: &mut Box<NNUEState>, mv: Move) {
         let new_piece = mv.promotion.unwrap_or(piece);
         nnue.update_feature::<ACTIVATE>(mv.to, new_piece, stm)
     }
+}
 
-    board.play_unchecked(mv);
+/// Panics if the incrementally updated zobrist key or accumulator of `board` differ from the
+/// ones recomputed from scratch, `context` names the move and position that led to it
+#[cfg(feature = "debug_asserts")]
+pub fn verify_state(board: &Board, nnue: &NNUEState, context: std::fmt::Arguments) {
+    let fresh = Board::from_fen(&board.to_string(), false).unwrap();
+    assert!(board.hash() == fresh.hash(), "zobrist mismatch {context}");
+    assert!(
+        nnue.matches_refresh(board),
+        "accumulator mismatch {context}"
+    );
+}
+
+/// Check and pin information of a position, worked out once per node for everything that needs it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub checkers: BitBoard,
+    /// Pieces pinned to their own king, per color
+    pub pinned: [BitBoard; Color::NUM],
+    /// Squares attacked by the opponent, seen through the side to move's king
+    pub king_danger: BitBoard,
+    /// Squares from which each piece of the side to move attacks the enemy king
+    pub check_squares: [BitBoard; Piece::NUM],
+    /// Side to move's pieces blocking one of its sliders from the enemy king
+    pub discoverers: BitBoard,
+}
+
+impl NodeInfo {
+    #[must_use]
+    pub fn new(board: &Board) -> Self {
+        let stm = board.side_to_move();
+        let occupied = board.occupied();
+        let their_king = board.king(!stm);
+
+        let bishop = get_bishop_moves(their_king, occupied);
+        let rook = get_rook_moves(their_king, occupied);
+        let check_squares = [
+            get_pawn_attacks(their_king, !stm),
+            get_knight_moves(their_king),
+            bishop,
+            rook,
+            bishop | rook,
+            BitBoard::EMPTY,
+        ];
+
+        let mut pinned = [BitBoard::EMPTY; Color::NUM];
+        pinned[stm as usize] = board.pinned() & board.colors(stm);
+        pinned[!stm as usize] = blockers(board, !stm, !stm);
+
+        Self {
+            checkers: board.checkers(),
+            pinned,
+            king_danger: attacked_by(board, !stm, occupied ^ board.king(stm).bitboard()),
+            check_squares,
+            discoverers: blockers(board, !stm, stm),
+        }
+    }
+
+    #[must_use]
+    pub fn in_check(&self) -> bool {
+        !self.checkers.is_empty()
+    }
+
+    /// Whether the move, legal in `board`, checks the enemy king
+    #[must_use]
+    pub fn gives_check(&self, board: &Board, mv: Move) -> bool {
+        let stm = board.side_to_move();
+        let piece = board.piece_on(mv.from).unwrap();
+
+        // Castling, en passant and promotions move more than one piece or change the piece.
+        // They are rare enough to just be played out.
+        if mv.promotion.is_some() || board.color_on(mv.to) == Some(stm) || is_ep(board, mv) {
+            let mut child = board.clone();
+            child.play_unchecked(mv);
+            return !child.checkers().is_empty();
+        }
+
+        self.check_squares[piece as usize].has(mv.to)
+            || (self.discoverers.has(mv.from)
+                && !get_line_rays(board.king(!stm), mv.from).has(mv.to))
+    }
+}
+
+// Pieces of `color` standing alone between the king of `king` and an enemy slider
+fn blockers(board: &Board, king: Color, color: Color) -> BitBoard {
+    let sq = board.king(king);
+    let them = board.colors(!king);
+    let diagonal = (board.pieces(Piece::Bishop) | board.pieces(Piece::Queen)) & them;
+    let orthogonal = (board.pieces(Piece::Rook) | board.pieces(Piece::Queen)) & them;
+    let sliders = (diagonal & get_bishop_rays(sq)) | (orthogonal & get_rook_rays(sq));
+
+    let mut blockers = BitBoard::EMPTY;
+    for slider in sliders {
+        let between = get_between_rays(sq, slider) & board.occupied();
+        if between.len() == 1 && !(between & board.colors(color)).is_empty() {
+            blockers |= between;
+        }
+    }
+
+    blockers
+}
+
+// Every square attacked by the pieces of one side
+fn attacked_by(board: &Board, color: Color, occupied: BitBoard) -> BitBoard {
+    let pieces = board.colors(color);
+    let mut attacked = BitBoard::EMPTY;
+
+    for sq in board.pieces(Piece::Pawn) & pieces {
+        attacked |= get_pawn_attacks(sq, color);
+    }
+    for sq in board.pieces(Piece::Knight) & pieces {
+        attacked |= get_knight_moves(sq);
+    }
+    for sq in (board.pieces(Piece::Bishop) | board.pieces(Piece::Queen)) & pieces {
+        attacked |= get_bishop_moves(sq, occupied);
+    }
+    for sq in (board.pieces(Piece::Rook) | board.pieces(Piece::Queen)) & pieces {
+        attacked |= get_rook_moves(sq, occupied);
+    }
+
+    attacked | get_king_moves(board.king(color))
 }
 
 #[must_use]
@@ -78,13 +215,160 @@ pub fn is_quiet(board: &Board, mv: Move) -> bool {
     mv.promotion.is_none() && !is_capture(board, mv)
 }
 
+// Squares on the ranks in front of `sq` from the perspective of `color`
+fn ranks_ahead(sq: Square, color: Color) -> BitBoard {
+    let rank = sq.rank().relative_to(color) as usize;
+    Rank::ALL[rank + 1..]
+        .iter()
+        .fold(BitBoard::EMPTY, |ahead, r| {
+            ahead | r.relative_to(color).bitboard()
+        })
+}
+
+/// Whether a pawn of `color` on `sq` has no enemy pawns in front of it on its own or the
+/// adjacent files
+#[must_use]
+pub fn is_passed(board: &Board, sq: Square, color: Color) -> bool {
+    let files = sq.file().bitboard() | sq.file().adjacent();
+    let enemy_pawns = board.colored_pieces(!color, Piece::Pawn);
+
+    (enemy_pawns & files & ranks_ahead(sq, color)).is_empty()
+}
+
+/// Whether `mv` pushes a passed pawn to the seventh rank, or out of reach of the enemy king in
+/// a pawn ending (the rule of the square, with the opponent to move)
+#[must_use]
+pub fn is_passed_push(board: &Board, mv: Move) -> bool {
+    let stm = board.side_to_move();
+    if board.piece_on(mv.from) != Some(Piece::Pawn)
+        || mv.promotion.is_some()
+        || !is_passed(board, mv.to, stm)
+    {
+        return false;
+    }
+
+    let rank = mv.to.rank().relative_to(stm);
+    if rank == Rank::Seventh {
+        return true;
+    }
+
+    let them = !stm;
+    let defenders = board.colors(them) & !board.pieces(Piece::Pawn) & !board.pieces(Piece::King);
+    let path = mv.to.file().bitboard() & ranks_ahead(mv.to, stm);
+    if !defenders.is_empty() || !(path & board.occupied()).is_empty() {
+        return false;
+    }
+
+    let promotion = Square::new(mv.to.file(), Rank::Eighth.relative_to(stm));
+    let king = board.king(them);
+    let king_distance = (king.file() as i32 - promotion.file() as i32)
+        .abs()
+        .max((king.rank() as i32 - promotion.rank() as i32).abs());
+
+    king_distance > Rank::Eighth as i32 - rank as i32
+}
+
+/// Piece counts of both sides, for recognizing endgames by their material
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Material {
+    counts: [[u32; Piece::NUM]; Color::NUM],
+}
+
+impl Material {
+    #[must_use]
+    pub fn new(board: &Board) -> Self {
+        let mut counts = [[0; Piece::NUM]; Color::NUM];
+        for color in Color::ALL {
+            for piece in Piece::ALL {
+                counts[color as usize][piece as usize] = board.colored_pieces(color, piece).len();
+            }
+        }
+
+        Self { counts }
+    }
+
+    #[must_use]
+    pub const fn count(&self, color: Color, piece: Piece) -> u32 {
+        self.counts[color as usize][piece as usize]
+    }
+
+    /// Knights, bishops, rooks and queens of `color`
+    #[must_use]
+    pub const fn pieces(&self, color: Color) -> [u32; 4] {
+        let [_, knights, bishops, rooks, queens, _] = self.counts[color as usize];
+        [knights, bishops, rooks, queens]
+    }
+
+    /// Material of `color` minus the opponent's, in SEE values
+    #[must_use]
+    pub fn balance(&self, color: Color) -> i32 {
+        Piece::ALL
+            .iter()
+            .map(|&piece| {
+                let difference = self.count(color, piece) as i32 - self.count(!color, piece) as i32;
+                difference * SEE_VALUES[piece as usize]
+            })
+            .sum()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::body::position::{is_capture, is_quiet};
+    use crate::body::movegen::pure_moves;
+    use crate::body::position::{
+        is_capture, is_passed, is_passed_push, is_quiet, Material, NodeInfo,
+    };
+    use cozy_chess::{BitBoard, Board, Color, Piece, Square};
+
+    #[test]
+    fn node_info() {
+        const FENS: [&str; 6] = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "4k3/8/8/2KPp2r/8/8/8/8 w - e6 0 1",
+            "3k4/8/8/8/3B4/8/3R4/3K4 w - - 0 1",
+        ];
+
+        for fen in FENS {
+            let board = Board::from_fen(fen, false).unwrap();
+            let node = NodeInfo::new(&board);
+            assert_eq!(node.checkers, board.checkers());
+            let stm = board.side_to_move();
+            assert_eq!(
+                node.pinned[stm as usize],
+                board.pinned() & board.colors(stm)
+            );
+
+            for mv in pure_moves(&board) {
+                let mut child = board.clone();
+                child.play_unchecked(mv);
+                assert_eq!(
+                    node.gives_check(&board, mv),
+                    !child.checkers().is_empty(),
+                    "{mv} in {fen}"
+                );
+
+                // The opponent's pins are the side to move's after a null move
+                if let Some(null) = board.null_move() {
+                    let pinned = null.pinned() & null.colors(!stm);
+                    assert_eq!(node.pinned[!stm as usize], pinned);
+                }
+            }
+        }
+
+        // The king can't retreat along the rook's line
+        let board = Board::from_fen("4k3/8/8/8/4K3/8/8/4r3 w - - 0 1", false).unwrap();
+        let node = NodeInfo::new(&board);
+        assert!(node.in_check());
+        assert!(node.king_danger.has(Square::E5));
+        assert!(node.king_danger & Square::D4.bitboard() == BitBoard::EMPTY);
+    }
 
     #[test]
     fn quiet_moves() {
-        use cozy_chess::{Board, Move, Piece, Square};
+        use cozy_chess::{Move, Piece};
 
         let board_1 = Board::from_fen(
             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
@@ -205,4 +489,41 @@ mod tests {
         assert!(!is_capture(&board_3, mv_3));
         assert!(is_capture(&board_3, mv_4));
     }
+
+    #[test]
+    fn passed_pawns() {
+        let board: Board = "4k3/8/2p5/8/1P6/8/8/4K3 w - - 0 1".parse().unwrap();
+        assert!(!is_passed(&board, Square::B4, Color::White));
+        assert!(!is_passed(&board, Square::C6, Color::Black));
+
+        let board: Board = "4k3/8/8/8/1P6/2p5/8/4K3 w - - 0 1".parse().unwrap();
+        assert!(is_passed(&board, Square::B4, Color::White));
+        assert!(is_passed(&board, Square::C3, Color::Black));
+
+        let push = |fen: &str, mv: &str| {
+            let board: Board = fen.parse().unwrap();
+            is_passed_push(&board, mv.parse().unwrap())
+        };
+
+        // To the seventh
+        assert!(push("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1", "b6b7"));
+        assert!(push("4k3/8/8/8/8/1p6/8/4K3 b - - 0 1", "b3b2"));
+
+        // The rule of the square
+        assert!(!push("4k3/8/8/8/8/8/P7/4K3 w - - 0 1", "a2a4"));
+        assert!(push("7k/8/8/8/8/8/P7/4K3 w - - 0 1", "a2a4"));
+        assert!(!push("3k4/8/8/8/8/8/P7/4K3 w - - 0 1", "a2a3"));
+        assert!(!push("7k/8/8/8/8/8/P7/4K2n w - - 0 1", "a2a4"));
+        assert!(!push("7k/8/8/8/8/8/P7/4K3 w - - 0 1", "e1d1"));
+    }
+
+    #[test]
+    fn material() {
+        let board: Board = "4k3/pp6/8/3b4/8/8/PPP5/2R1K3 w - - 0 1".parse().unwrap();
+        let material = Material::new(&board);
+        assert_eq!(material.count(Color::White, Piece::Pawn), 3);
+        assert_eq!(material.count(Color::Black, Piece::Pawn), 2);
+        assert_eq!(material.pieces(Color::White), [0, 0, 1, 0]);
+        assert_eq!(material.pieces(Color::Black), [0, 1, 0, 0]);
+    }
 }
@@ -1,43 +1,60 @@
 use crate::definitions::MAX_PLY;
 use crate::uci::handler::reverse_castling_move;
-use cozy_chess::{Board, Move};
+use cozy_chess::{Board, Move, Square};
 
+/// Triangular PV table, row `ply` holds the principal variation from that ply on.
+/// Rows are stored in full, only the moves from `ply` to `length[ply]` are used.
 pub struct PVTable {
-    pub length: usize,
-    pub table: [Option<Move>; MAX_PLY],
+    length: [usize; MAX_PLY + 1],
+    table: [[Move; MAX_PLY + 1]; MAX_PLY + 1],
 }
 
+const EMPTY_MOVE: Move = Move {
+    from: Square::A1,
+    to: Square::A1,
+    promotion: None,
+};
+
 impl PVTable {
     pub fn new() -> Self {
         PVTable {
-            length: 0,
-            table: [None; MAX_PLY],
+            length: [0; MAX_PLY + 1],
+            table: [[EMPTY_MOVE; MAX_PLY + 1]; MAX_PLY + 1],
         }
     }
 
-    pub fn store(&mut self, board: &Board, mv: Move, old: &Self) {
-        let mv = reverse_castling_move(board, mv);
-        self.table[0] = Some(mv);
-        self.table[1..=old.length].copy_from_slice(&old.table[..old.length]);
-        self.length = old.length + 1;
+    /// Empties the PV of a node about to be searched
+    pub fn clear(&mut self, ply: usize) {
+        self.length[ply] = ply;
+    }
+
+    /// Sets the PV at `ply` to the move followed by the child's PV
+    pub fn store(&mut self, board: &Board, mv: Move, ply: usize) {
+        let (parents, children) = self.table.split_at_mut(ply + 1);
+        let child_length = self.length[ply + 1].max(ply + 1);
+
+        parents[ply][ply] = reverse_castling_move(board, mv);
+        parents[ply][ply + 1..child_length].copy_from_slice(&children[0][ply + 1..child_length]);
+        self.length[ply] = child_length;
     }
 
-    pub fn moves(&self) -> &[Option<Move>] {
-        &self.table[..self.length]
+    /// Principal variation from the given ply on
+    pub fn line(&self, ply: usize) -> &[Move] {
+        &self.table[ply][ply..self.length[ply].max(ply)]
     }
 
-    pub fn pv_string(&self) -> String {
+    pub fn pv_string(&self, ply: usize) -> String {
         let mut pv = String::new();
-        for &mv in self.moves() {
+        for &mv in self.line(ply) {
             pv.push(' ');
-            pv.push_str(mv.unwrap().to_string().as_str());
+            pv.push_str(mv.to_string().as_str());
         }
 
         pv
     }
 
     pub fn best_move(&self) -> Option<Move> {
-        self.table[0]
+        self.line(0).first().copied()
     }
 }
 
@@ -46,3 +63,36 @@ impl Default for PVTable {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangular_pv() {
+        let board = Board::default();
+        let moves: Vec<Move> = ["e2e4", "e7e5", "g1f3"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        let mut pv = PVTable::new();
+        for ply in 0..=3 {
+            pv.clear(ply);
+        }
+        assert_eq!(pv.best_move(), None);
+
+        // Leaf first, each parent picks up its child's line
+        for ply in (0..3).rev() {
+            pv.store(&board, moves[ply], ply);
+        }
+        assert_eq!(pv.line(0), moves.as_slice());
+        assert_eq!(pv.line(1), &moves[1..]);
+        assert_eq!(pv.pv_string(0), " e2e4 e7e5 g1f3");
+
+        // A new best move at the root drops the old line below it
+        pv.clear(1);
+        pv.store(&board, moves[1], 0);
+        assert_eq!(pv.line(0), &moves[1..2]);
+    }
+}
@@ -0,0 +1,40 @@
+// Search reports
+// Everything the search has to tell the outside world goes through a SearchReporter, which
+// decides how to present it: UCI lines, the pretty printer, or an embedding program's callback.
+// Every method does nothing by default.
+use cozy_chess::{Board, Move};
+
+/// A completed iteration of the main thread
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IterationReport<'a> {
+    pub depth: usize,
+    pub seldepth: usize,
+    pub score: i32,
+    // Set for a move that failed high in an interrupted iteration
+    pub lower_bound: bool,
+    pub nodes: u64,
+    // Milliseconds since the start of the search
+    pub time: u64,
+    pub pv: &'a [Move],
+    // The position searched, the PV being played from it
+    pub board: &'a Board,
+}
+
+pub trait SearchReporter: Send {
+    fn depth_completed(&mut self, _report: &IterationReport) {}
+
+    /// The root move about to be searched and its number in the move ordering, starting at 1.
+    /// Only reported once the search has been running for a while.
+    fn currmove(&mut self, _mv: Move, _number: usize, _depth: i32) {}
+
+    /// A root move scoring no better than the best one so far, followed by the line refuting it,
+    /// which can be empty. Only reported when refutations are turned on.
+    fn refutation(&mut self, _line: &[Move]) {}
+
+    /// The move chosen at the end of the search, `None` without legal moves, and the reply
+    /// expected to it when pondering is on
+    fn bestmove(&mut self, _best_move: Option<Move>, _ponder: Option<Move>) {}
+
+    /// Anything else worth telling, such as warnings about the search
+    fn message(&mut self, _message: &str) {}
+}
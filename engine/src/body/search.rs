@@ -1,73 +1,134 @@
 use super::movegen::Picker;
 use super::nnue::inference::NNUEState;
-use super::position::{is_capture, is_quiet, play_move};
+use super::position::{
+    is_capture, is_passed_push, is_quiet, update_accumulator, Material, NodeInfo,
+};
 use super::{
+    diagnostics::Diagnostics,
+    endgame,
     history::History,
+    keys::Keys,
     lmr::LMRTable,
     movegen,
     pv_table::PVTable,
+    report::{IterationReport, SearchReporter},
+    see::see_pinned,
     stat_vec::StaticVec,
+    stats::{Phase, SearchStats, Timer},
+    trace::{NodeKind, Reason, Trace, TraceNode},
     tt::{score_from_tt, AgeAndFlag, PackedMove, TTFlag, TT},
 };
 
 use crate::definitions::*;
-use crate::uci::handler::SearchType;
+use crate::tunables;
+use crate::uci::{
+    clock::Instant,
+    handler::{check_castling_move, reverse_castling_move, SearchType},
+    timeman::{load_ponder, TimeManager},
+};
 
-use cozy_chess::{BitBoard, Board, Color, GameStatus, Move, Piece};
+use cozy_chess::{BitBoard, Board, Color, GameStatus, Move, Piece, Square};
 use once_cell::sync::Lazy;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::time::Instant;
 
 static LMR: Lazy<LMRTable> = Lazy::new(LMRTable::new);
 static STOP: AtomicBool = AtomicBool::new(false);
 static NODES: AtomicU64 = AtomicU64::new(0);
 
-const RFP_MARGIN: i32 = 75;
-const LMP_TABLE: [usize; 4] = [0, 5, 8, 18];
-const FP_COEFFICIENT: i32 = 100;
-const FP_MARGIN: i32 = 75;
-const FP_DEPTH: i32 = 6;
+// Threads count nodes locally and add them to the shared count this often, bumping a shared
+// counter at every node would have all threads contending for it
+const NODE_FLUSH_INTERVAL: u64 = 2048;
+
+// Milliseconds into the search before root moves are reported
+const CURRMOVE_DELAY: u64 = 3000;
+
+// Plies quiescence can add to a line, and so to the seldepth, past the main search's leaves.
+// Long chains of checks and recaptures otherwise blow up in wild tactical positions.
+const QSEARCH_PLIES: usize = 16;
 
+// Positions with at most this many pieces besides kings and pawns are late endgames
+const LATE_ENDGAME_PIECES: usize = 2;
+
+// Reported PVs are filled in from the TT up to this many moves
+const MAX_PV_LENGTH: usize = 64;
+
+/// Search state of a single ply, the stack is indexed by ply
 pub struct StackEntry {
     pub eval: i32,
+    pub killers: [Option<Move>; 2],
+    // Skipped by the move loop, for verification searches without the move
+    pub excluded: Option<Move>,
+    // Move played from this ply, and the piece and square continuation histories are indexed by
+    pub current_move: Option<Move>,
+    pub piece_to: Option<(Piece, Square)>,
+    pub captured: bool,
+    pub keys: Keys,
+    // Plies of extensions granted along the line from the root to this node, its own included
+    pub extensions: i32,
 }
 
 impl Default for StackEntry {
     fn default() -> Self {
-        StackEntry { eval: NONE }
+        StackEntry {
+            eval: NONE,
+            killers: [None; 2],
+            excluded: None,
+            current_move: None,
+            piece_to: None,
+            captured: false,
+            keys: Keys::default(),
+            extensions: 0,
+        }
     }
 }
 
 pub struct SearchInfo {
     pub search_type: SearchType,
-    pub timer: Option<Instant>,
-    pub base_optimum: Option<u64>,
-    pub max_time: Option<u64>,
+    pub time_manager: TimeManager,
     pub nodes: u64,
     prev_nodes: u64,
     pub node_table: [[u64; 64]; 64],
     pub seldepth: usize,
+    // Depth of the iteration being searched, lines can't be extended past twice as deep
+    pub root_depth: i32,
+    // The last root move of the current iteration to finish above alpha. Taken as a whole at
+    // that point, so that its score and PV can't be mixed with those of any other move.
+    pub partial: Option<SearchResult>,
+    // Root moves searched to an exact score in the current call at the root, see `variety_margin`
+    root_scores: Vec<(Move, i32)>,
+    /// Root moves within the variety margin of the best one in the last completed iteration,
+    /// the best one included, with their scores
+    pub candidates: Vec<(Move, i32)>,
     pub game_history: Vec<u64>,
-    pub killers: [[Option<Move>; 2]; MAX_PLY],
     pub history: History,
-    pub stack: [StackEntry; MAX_PLY],
+    // One entry past the deepest ply, for the children of the deepest nodes
+    pub stack: [StackEntry; MAX_PLY + 1],
+    pub pv: PVTable,
+    pub diagnostics: Diagnostics,
+    pub stats: SearchStats,
+    pub trace: Trace,
 }
 
 impl SearchInfo {
     pub fn new() -> Self {
         SearchInfo {
             search_type: SearchType::Depth(0),
-            timer: None,
-            base_optimum: None,
-            max_time: None,
+            time_manager: TimeManager::new(),
             nodes: 0,
             prev_nodes: 0,
             node_table: [[0; 64]; 64],
             seldepth: 0,
+            root_depth: 0,
+            partial: None,
+            root_scores: vec![],
+            candidates: vec![],
             game_history: vec![],
-            killers: [[None; 2]; MAX_PLY],
             history: History::new(),
             stack: std::array::from_fn(|_| StackEntry::default()),
+            pv: PVTable::new(),
+            diagnostics: Diagnostics::default(),
+            stats: SearchStats::default(),
+            trace: Trace::default(),
         }
     }
 }
@@ -86,107 +147,178 @@ pub fn load_stop() -> bool {
     STOP.load(Ordering::SeqCst)
 }
 
+// Only ever summed up, nothing else is synchronized through it
 fn add_nodes(nodes: u64) {
-    NODES.fetch_add(nodes, Ordering::SeqCst);
+    NODES.fetch_add(nodes, Ordering::Relaxed);
 }
 
 fn load_nodes() -> u64 {
-    NODES.load(Ordering::SeqCst)
+    NODES.load(Ordering::Relaxed)
 }
 
+/// Outcome of the last completed iteration of a search, or of the root moves an interrupted
+/// one finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: i32,
+    /// Whether the score is only a lower bound, the best move having failed high in an
+    /// interrupted iteration
+    pub lower_bound: bool,
+    pub depth: usize,
+    pub seldepth: usize,
+    pub nodes: u64,
+    pub pv: Vec<Move>,
+}
+
+/// Asked every so often whether the search should stop, for embedders that can't
+/// set the stop flag from another thread
+pub type StopPoll = dyn FnMut() -> bool + Send;
+
 pub struct Search<'a> {
     pub nnue: Box<NNUEState>,
     pub tt: &'a TT,
     pub info: SearchInfo,
+    pub stop_poll: Option<&'a mut StopPoll>,
+    // Only the main thread reports, nothing is reported without one
+    pub reporter: Option<&'a mut dyn SearchReporter>,
+    pub show_refutations: bool,
+    // Root moves scoring within this many centipawns of the best one are searched to an exact
+    // score instead of only failing low, for variety to pick among. 0 when variety is off.
+    pub variety_margin: i32,
 }
 
 impl<'a> Search<'a> {
-    #[allow(clippy::borrowed_box, clippy::ptr_arg)]
+    /// A search using `nnue` as its accumulator stack. Stacks are large, callers allocate one
+    /// per thread and hand it from search to search.
+    #[allow(clippy::ptr_arg)]
     pub fn new(
         tt: &'a TT,
-        nnue: &Box<NNUEState>,
+        nnue: Box<NNUEState>,
         history: &History,
         game_history: &Vec<u64>,
     ) -> Self {
         let mut s = Search {
             tt,
-            nnue: nnue.clone(),
+            nnue,
             info: SearchInfo::new(),
+            stop_poll: None,
+            reporter: None,
+            show_refutations: false,
+            variety_margin: 0,
         };
 
         s.info.history = history.clone();
         s.info.game_history = game_history.clone();
+        // Positions searched are pushed onto it, it must not reallocate mid search
+        s.info.game_history.reserve(MAX_PLY);
 
         s
     }
 
+    fn count_node(&mut self) {
+        self.info.nodes += 1;
+        if self.info.nodes.is_multiple_of(NODE_FLUSH_INTERVAL) {
+            self.flush_nodes();
+        }
+    }
+
+    fn flush_nodes(&mut self) {
+        add_nodes(self.info.nodes - self.info.prev_nodes);
+        self.info.prev_nodes = self.info.nodes;
+    }
+
+    // Every so often, check if the hard limit has been reached or the embedder wants to stop
+    fn limit_reached(&mut self) -> bool {
+        self.info
+            .nodes
+            .is_multiple_of(self.info.time_manager.check_interval())
+            && (self.info.time_manager.hard_limit_reached(self.info.nodes)
+                || self.stop_poll.as_mut().is_some_and(|poll| poll()))
+    }
+
     /*
         Zero Window Search - A way to reduce the search space in alpha-beta like search algorithms,
         to perform a boolean test, whether a move produces a worse or better score than a passed value.
         (https://www.chessprogramming.org/Null_Window)
     */
     #[must_use]
-    #[allow(clippy::too_many_arguments)]
     fn zw_search(
         &mut self,
         main_thread: bool,
         board: &Board,
-        pv: &mut PVTable,
         alpha: i32,
         beta: i32,
         depth: i32,
         ply: usize,
     ) -> i32 {
-        self.pvsearch::<false>(main_thread, board, pv, alpha, beta, depth, ply)
+        self.pvsearch::<false>(main_thread, board, alpha, beta, depth, ply)
     }
 
     #[must_use]
-    #[allow(clippy::too_many_arguments)]
     pub fn pvsearch<const PV: bool>(
         &mut self,
         main_thread: bool,
         board: &Board,
-        pv: &mut PVTable,
+        alpha: i32,
+        beta: i32,
+        depth: i32,
+        ply: usize,
+    ) -> i32 {
+        if !self.info.trace.enabled {
+            return self.negamax::<PV>(main_thread, board, alpha, beta, depth, ply);
+        }
+
+        let kind = match (self.info.stack[ply].excluded, PV) {
+            (Some(_), _) => NodeKind::Singular,
+            (None, true) => NodeKind::Pv,
+            (None, false) => NodeKind::NonPv,
+        };
+        let node = TraceNode::new(kind, self.info.root_depth, ply, depth, alpha, beta);
+        self.info.trace.enter(board, node);
+        let score = self.negamax::<PV>(main_thread, board, alpha, beta, depth, ply);
+        self.info.trace.exit(score, load_stop());
+        score
+    }
+
+    #[must_use]
+    fn negamax<const PV: bool>(
+        &mut self,
+        main_thread: bool,
+        board: &Board,
         mut alpha: i32,
         beta: i32,
         mut depth: i32,
         ply: usize,
     ) -> i32 {
-        // Every 1024 nodes, check if it's time to stop
-        if let (Some(timer), Some(max)) = (self.info.timer, self.info.max_time) {
-            if main_thread
-                && self.info.nodes % 1024 == 0
-                && timer.elapsed().as_millis() as u64 >= max
-            {
-                store_stop(true);
-            }
+        if main_thread && self.limit_reached() {
+            store_stop(true);
         }
 
         if load_stop() && ply > 0 {
             return 0;
         }
 
-        if self.info.nodes % 2048 == 0 {
-            add_nodes(self.info.nodes - self.info.prev_nodes);
-            self.info.prev_nodes = self.info.nodes;
-        }
-
         let stm = board.side_to_move();
 
         if ply >= MAX_PLY {
-            return self.nnue.evaluate(stm);
+            return self.evaluate(board, -INFINITY, INFINITY);
         }
 
         let hash_key = board.hash();
-        self.tt.prefetch(hash_key);
         self.info.seldepth = self.info.seldepth.max(ply);
         depth = depth.max(0);
-        let mut old_pv = PVTable::new();
-        pv.length = 0;
+        self.info.pv.clear(ply);
+        if ply == 0 {
+            self.info.stack[0].keys = Keys::new(board);
+        }
 
         match board.status() {
             GameStatus::Won => return ply as i32 - MATE,
-            GameStatus::Drawn => return 8 - (self.info.nodes as i32 & 7),
+            GameStatus::Drawn => {
+                self.info.trace.prune(Reason::Draw);
+                return 8 - (self.info.nodes as i32 & 7);
+            }
             _ => (),
         }
 
@@ -194,6 +326,7 @@ impl<'a> Search<'a> {
 
         if !root {
             if self.repetition(board, hash_key) {
+                self.info.trace.prune(Reason::Draw);
                 return 8 - (self.info.nodes as i32 & 7);
             }
 
@@ -201,26 +334,45 @@ impl<'a> Search<'a> {
             let mate_alpha = alpha.max(ply as i32 - MATE);
             let mate_beta = beta.min(MATE - (ply as i32 + 1));
             if mate_alpha >= mate_beta {
+                self.info.trace.prune(Reason::MateDistance);
                 return mate_alpha;
             }
         }
 
-        let in_check = !board.checkers().is_empty();
+        let node = NodeInfo::new(board);
+        let in_check = node.in_check();
 
-        // Escape condition
-        if depth == 0 && !in_check {
-            return self.qsearch::<PV>(board, alpha, beta, ply);
+        // Extension budget
+        // Extensions stop once the line has collected as many as the root depth, chains of
+        // them would otherwise keep a single line growing until the hard time limit.
+        let line_extensions = if root {
+            0
+        } else {
+            self.info.stack[ply - 1].extensions
+        };
+        let can_extend = line_extensions < self.info.root_depth;
+        let check_extension = i32::from(in_check && can_extend);
+        self.info.stack[ply].extensions = line_extensions + check_extension;
+
+        // Escape condition, checks out of budget are left to the evasions of quiescence
+        if depth == 0 && check_extension == 0 {
+            return self.qsearch::<PV>(board, alpha, beta, ply, 0);
         }
 
-        let tt_entry = self.tt.probe(hash_key);
-        let tt_hit = tt_entry.key == hash_key as u16;
-        let tt_score = score_from_tt(tt_entry.score, ply) as i32;
+        let timer = Timer::start();
+        let mut tt_entry = self.tt.probe(hash_key);
+        self.info.stats.record(Phase::Probe, timer);
+        let mut tt_hit = tt_entry.key == hash_key as u16;
+        let mut tt_score = score_from_tt(tt_entry.score, ply) as i32;
         let mut tt_move: Option<Move> = None;
+        self.info.stats.tt_probes += 1;
+        self.info.stats.tt_hits += u64::from(tt_hit);
 
+        let excluded = self.info.stack[ply].excluded;
         if tt_hit && u64::from(tt_entry) != 0 {
             tt_move = Some(PackedMove::unpack(tt_entry.mv));
 
-            if !PV && i32::from(tt_entry.depth) >= depth {
+            if !PV && excluded.is_none() && i32::from(tt_entry.depth) >= depth {
                 debug_assert!(tt_score != NONE && tt_entry.age_flag != AgeAndFlag(0));
                 let flag = tt_entry.age_flag.flag();
 
@@ -228,11 +380,14 @@ impl<'a> Search<'a> {
                     || (flag == TTFlag::LowerBound && tt_score >= beta)
                     || (flag == TTFlag::UpperBound && tt_score <= alpha)
                 {
+                    self.info.stats.tt_cutoffs += 1;
+                    self.info.trace.prune(Reason::TtCutoff);
                     return tt_score;
                 }
             }
         }
 
+        let mut raw_eval = None;
         let eval = if tt_hit {
             // Use the TT score if available since eval is expensive
             // and any score from the TT is better than the static eval
@@ -241,7 +396,12 @@ impl<'a> Search<'a> {
             // If we're in check, it's unstable to use the static eval
             -INFINITY
         } else {
-            self.nnue.evaluate(stm)
+            // Corrected by how far off the eval has been in similar positions
+            let raw = self.evaluate(board, -INFINITY, INFINITY);
+            raw_eval = Some(raw);
+            let keys = self.info.stack[ply].keys;
+            let corrected = self.info.history.correction.correct(board, &keys, raw);
+            corrected.clamp(-TB_WIN_IN_PLY + 1, TB_WIN_IN_PLY - 1)
         };
 
         // Internal Iterative Reduction (IIR)
@@ -249,22 +409,58 @@ impl<'a> Search<'a> {
             depth -= 1
         }
 
-        // Improving
-        // If the previous eval from our point of view is worse than what it currently is
-        // then we are improving our position. This is used in some heuristics to improve pruning.
-        self.info.stack[ply].eval = eval;
-        let mut improving = false;
-        if ply >= 2 {
-            improving = !in_check && eval > self.info.stack[ply - 2].eval;
+        // Internal Iterative Deepening (IID)
+        // PV nodes deep enough are worth a reduced search of their own just to find a move to
+        // try first, when the TT has none for them. The entry it leaves replaces the one probed,
+        // so that the TT move and everything else read from the entry agree.
+        if PV && !root && tt_move.is_none() && excluded.is_none() && depth >= tunables::iid_depth()
+        {
+            let iid_depth = depth - tunables::iid_reduction();
+            let _ = self.pvsearch::<PV>(main_thread, board, alpha, beta, iid_depth, ply);
+            self.info.pv.clear(ply);
+
+            tt_entry = self.tt.probe(hash_key);
+            tt_hit = tt_entry.key == hash_key as u16;
+            tt_score = score_from_tt(tt_entry.score, ply) as i32;
+            if tt_hit && tt_entry.mv != PackedMove::new(None) {
+                tt_move = Some(PackedMove::unpack(tt_entry.mv));
+            }
         }
 
-        if !PV && !in_check {
+        // Improving rate
+        // How fast the eval from our point of view has been changing over our last moves, from
+        // -100 when it's falling by improving_scale or more to 100 when it's rising as much.
+        // Pruning is bolder and reductions larger the faster the position gets worse.
+        self.info.stack[ply].eval = eval;
+        let improving_rate = self.improving_rate(ply, eval, in_check);
+        let improving_weight = improving_rate * tunables::improving_weight();
+
+        // Nodes searched without their TT move are only asking whether it is singular
+        if !PV && !in_check && excluded.is_none() {
+            // Razoring
+            // At shallow depths a static eval far below alpha is unlikely to recover. If the
+            // captures of quiescence can't bring it back above alpha either, we fail low here.
+            // Mate scores are left alone, no margin says anything about them.
+            if depth <= tunables::razor_depth()
+                && alpha.abs() < TB_WIN_IN_PLY
+                && eval.abs() < TB_WIN_IN_PLY
+                && eval + tunables::razor_margin() * depth <= alpha
+            {
+                let score = self.qsearch::<false>(board, alpha, alpha + 1, ply, 0);
+                if score <= alpha {
+                    self.info.trace.prune(Reason::Razoring);
+                    return score;
+                }
+            }
+
             // Reverse Futility Pruning (RFP)
             // If static eval plus a margin can beat beta, then we can safely prune this node.
             // The margin is multiplied by depth to make it harder to prune at higher depths
             // as pruning there can be inaccurate as it prunes a large amount of potential nodes
             // and static eval isn't the most accurate.
-            if depth < 9 && eval >= beta + RFP_MARGIN * depth / (i32::from(improving) + 1) {
+            let rfp_margin = tunables::rfp_margin() * depth * (10_000 - improving_weight) / 10_000;
+            if depth < tunables::rfp_depth() && eval >= beta + rfp_margin {
+                self.info.trace.prune(Reason::ReverseFutility);
                 return eval;
             }
 
@@ -273,20 +469,23 @@ impl<'a> Search<'a> {
             // we can safely prune this node. This does not work in zugzwang positions
             // because then it is always better to give a free move, hence some checks for it are needed.
             if depth >= 3 && eval >= beta && !self.non_pawn_material(board, stm).is_empty() {
-                let r = 3 + depth / 3 + 3.min((eval.saturating_sub(beta)) / 200);
+                let r = tunables::nmp_base()
+                    + depth / tunables::nmp_depth_divisor()
+                    + 3.min((eval.saturating_sub(beta)) / tunables::nmp_eval_divisor());
                 let new_b = board.null_move().unwrap();
+                self.tt.prefetch(new_b.hash());
+                self.info.stack[ply].current_move = None;
+                self.info.stack[ply].piece_to = None;
+                self.info.stack[ply].captured = false;
+                self.info.stack[ply + 1].keys = self.info.stack[ply].keys;
 
-                let score = -self.zw_search(
-                    main_thread,
-                    &new_b,
-                    &mut old_pv,
-                    -beta,
-                    -beta + 1,
-                    depth - r,
-                    ply + 1,
-                );
+                let score =
+                    -self.zw_search(main_thread, &new_b, -beta, -beta + 1, depth - r, ply + 1);
 
+                self.info.stats.null_tries += 1;
                 if score >= beta {
+                    self.info.stats.null_cutoffs += 1;
+                    self.info.trace.prune(Reason::NullMove);
                     return beta;
                 }
             }
@@ -294,24 +493,38 @@ impl<'a> Search<'a> {
 
         let old_alpha = alpha;
         let mut best_score = -INFINITY;
+        if root {
+            self.info.root_scores.clear();
+        }
         let mut best_move: Option<Move> = None;
         let mut moves_played = 0;
 
+        let timer = Timer::start();
         let move_list = movegen::all_moves(self, board, tt_move, ply);
+        self.info.stats.record(Phase::Movegen, timer);
         let mut quiet_moves = StaticVec::<Option<Move>, MAX_MOVES_POSITION>::new(None);
         let mut picker = Picker::new(move_list);
 
         let lmr_threshold = if PV { 5 } else { 3 };
+        let pieces = board.occupied() & !board.pieces(Piece::Pawn) & !board.pieces(Piece::King);
+        let late_endgame = pieces.len() as usize <= LATE_ENDGAME_PIECES;
         let mut quiets_checked = 0;
         let quiets_to_check = match depth {
-            d @ 1..=3 => LMP_TABLE[d as usize],
+            1 => tunables::lmp_depth_1() as usize,
+            2 => tunables::lmp_depth_2() as usize,
+            3 => tunables::lmp_depth_3() as usize,
             _ => MAX_MOVES_POSITION,
         };
 
         // Check extension
-        depth += i32::from(in_check);
+        depth += check_extension;
+        self.info.diagnostics.extensions += check_extension as u64;
 
         while let Some(mv) = picker.pick_move() {
+            if excluded == Some(mv) {
+                continue;
+            }
+
             let is_quiet = is_quiet(board, mv);
             let lmr_reduction = LMR.reduction(depth, moves_played.max(1));
             let lmr_depth = 0.max(depth - lmr_reduction);
@@ -323,13 +536,18 @@ impl<'a> Search<'a> {
                     // Late Move Pruning (LMP)
                     // If we have searched too many moves, we stop searching here
                     if quiets_checked >= quiets_to_check {
+                        self.info.trace.prune(Reason::LateMovePruning);
                         break;
                     }
 
                     // Futility Pruning (FP)
                     // If static eval plus a margin can't beat alpha, we stop searching here
-                    let fp_margin = lmr_depth * FP_COEFFICIENT + FP_MARGIN;
-                    if lmr_depth < FP_DEPTH && eval + fp_margin <= alpha {
+                    let fp_margin = (lmr_depth * tunables::fp_coefficient()
+                        + tunables::fp_margin())
+                        * (10_000 + improving_weight)
+                        / 10_000;
+                    if lmr_depth < tunables::fp_depth() && eval + fp_margin <= alpha {
+                        self.info.trace.prune(Reason::Futility);
                         break;
                     }
                 }
@@ -337,26 +555,110 @@ impl<'a> Search<'a> {
                 quiet_moves.push(Some(mv));
             }
 
-            let mut new_b = board.clone();
-            play_move(&mut new_b, &mut self.nnue, mv);
+            // Singular Extensions (SE)
+            // If every move but the TT move fails low against a margin below its TT score, the
+            // TT move is the only good one and is searched a ply deeper, two if far below outside
+            // PV. If the others beat beta anyway, several moves do and the node is cut (multicut).
+            let mut extension = 0;
+            if !root
+                && tt_hit
+                && tt_move == Some(mv)
+                && excluded.is_none()
+                && line_extensions + check_extension < self.info.root_depth
+                && depth >= tunables::se_depth()
+                && i32::from(tt_entry.depth) >= depth - tunables::se_tt_depth_slack()
+                && tt_entry.age_flag.flag() != TTFlag::UpperBound
+                && tt_score.abs() < TB_WIN_IN_PLY
+            {
+                let singular_beta = tt_score - tunables::se_margin() * depth;
+                let singular_depth = (depth - 1) / 2;
+
+                self.info.stack[ply].excluded = Some(mv);
+                let score = self.zw_search(
+                    main_thread,
+                    board,
+                    singular_beta - 1,
+                    singular_beta,
+                    singular_depth,
+                    ply,
+                );
+                self.info.stack[ply].excluded = None;
+                self.info.stats.singular_tries += 1;
+
+                if score < singular_beta {
+                    extension = 1;
+                    self.info.stats.singular_extensions += 1;
+
+                    if !PV
+                        && score < singular_beta - tunables::se_double_margin()
+                        && line_extensions + check_extension + 2 <= self.info.root_depth
+                    {
+                        extension = 2;
+                        self.info.stats.double_extensions += 1;
+                    }
+                } else if singular_beta >= beta {
+                    self.info.stats.multicuts += 1;
+                    self.info.trace.prune(Reason::Multicut);
+                    return singular_beta;
+                }
+            }
+
+            // Recapture extension
+            // Taking back on the square the opponent just captured on keeps the exchange from
+            // being cut off at the horizon, as long as it doesn't lose material. Only along PV
+            // lines, everywhere else the extra exchanges cost more than they resolve.
+            let capture = is_capture(board, mv);
+            if PV
+                && extension == 0
+                && capture
+                && line_extensions + check_extension < self.info.root_depth
+                && !root
+                && self.info.stack[ply - 1].captured
+                && self.info.stack[ply - 1].current_move.map(|prev| prev.to) == Some(mv.to)
+                && see_pinned(board, mv, node.pinned) >= 0
+            {
+                extension = 1;
+            }
+
+            // Passed pawn extension
+            // In late endgames, pushes to the seventh rank or out of reach of the enemy king
+            // decide promotion races that would otherwise be cut off at the horizon.
+            if extension == 0
+                && late_endgame
+                && line_extensions + check_extension < self.info.root_depth
+                && is_passed_push(board, mv)
+            {
+                extension = 1;
+            }
+
+            let new_b = self.make_move(board, mv, ply);
+            let entry = &mut self.info.stack[ply];
+            entry.extensions = line_extensions + check_extension + extension;
+            entry.current_move = Some(mv);
+            entry.piece_to = board.piece_on(mv.from).map(|piece| (piece, mv.to));
+            entry.captured = capture;
 
             moves_played += 1;
+            if root && main_thread {
+                self.report_currmove(board, mv, moves_played as usize, depth);
+            }
+
             self.info.game_history.push(board.hash());
-            self.info.nodes += 1;
+            self.count_node();
             let previous_nodes = self.info.nodes;
             let gives_check = !board.checkers().is_empty();
 
+            let new_depth = depth - 1 + extension;
+            // Root moves close enough to the best one for variety get exact scores as well
+            let floor = if root {
+                (alpha - self.variety_margin).max(old_alpha)
+            } else {
+                alpha
+            };
             let mut score: i32;
             if moves_played == 1 {
-                score = -self.pvsearch::<PV>(
-                    main_thread,
-                    &new_b,
-                    &mut old_pv,
-                    -beta,
-                    -alpha,
-                    depth - 1,
-                    ply + 1,
-                );
+                score =
+                    -self.pvsearch::<PV>(main_thread, &new_b, -beta, -alpha, new_depth, ply + 1);
             } else {
                 // Late Move Reduction (LMR)
                 // Assuming our move ordering is good, later moves will be worse
@@ -370,47 +672,49 @@ impl<'a> Search<'a> {
                     r += i32::from(!PV);
 
                     // Malus for capture moves and checks
-                    r -= i32::from(is_capture(board, mv));
+                    r -= i32::from(capture);
                     r -= i32::from(gives_check);
 
+                    // Less for positions getting better
+                    r -= improving_weight / 5000;
+
                     r.clamp(1, depth - 1)
                 } else {
                     1
                 };
+                self.info.stats.lmr_searches += u64::from(r > 1);
 
                 score = -self.zw_search(
                     main_thread,
                     &new_b,
-                    &mut old_pv,
-                    -alpha - 1,
-                    -alpha,
-                    depth - r,
+                    -floor - 1,
+                    -floor,
+                    depth + extension - r,
                     ply + 1,
                 );
 
                 // Three-fold LMR
                 // If the ZW beats alpha, then it might be
                 // worth looking at this good position fully
-                if score > alpha && r > 1 {
+                if score > floor && r > 1 {
+                    self.info.stats.lmr_researches += 1;
                     score = -self.zw_search(
                         main_thread,
                         &new_b,
-                        &mut old_pv,
-                        -alpha - 1,
-                        -alpha,
-                        depth - 1,
+                        -floor - 1,
+                        -floor,
+                        new_depth,
                         ply + 1,
                     );
                 }
 
-                if (alpha + 1..beta).contains(&score) {
+                if (floor + 1..beta).contains(&score) {
                     score = -self.pvsearch::<PV>(
                         main_thread,
                         &new_b,
-                        &mut old_pv,
                         -beta,
-                        -alpha,
-                        depth - 1,
+                        -floor,
+                        new_depth,
                         ply + 1,
                     );
                 }
@@ -419,10 +723,34 @@ impl<'a> Search<'a> {
             self.info.game_history.pop();
             self.nnue.pop();
 
+            #[cfg(feature = "debug_asserts")]
+            super::position::verify_state(
+                board,
+                &self.nnue,
+                format_args!("after undoing {mv} in {board}"),
+            );
+
             if root {
+                if self.variety_margin > 0 && score > floor && !load_stop() {
+                    let mv = reverse_castling_move(board, mv);
+                    self.info.root_scores.push((mv, score));
+                }
+
                 // Difference in node count
                 self.info.node_table[mv.from as usize][mv.to as usize] +=
                     self.info.nodes - previous_nodes;
+
+                if let Some(warning) = self.info.diagnostics.check(self.info.nodes) {
+                    let mv = reverse_castling_move(board, mv);
+                    self.report_message(&format!(
+                        "warning: {warning}, line {mv}{}",
+                        self.info.pv.pv_string(ply + 1)
+                    ));
+                }
+
+                if main_thread && moves_played > 1 && score <= alpha {
+                    self.report_refutation(board, &new_b, mv);
+                }
             }
 
             if score <= best_score {
@@ -436,14 +764,33 @@ impl<'a> Search<'a> {
             // New best move
             alpha = score;
             best_move = Some(mv);
-            pv.store(board, mv, &old_pv);
+            self.info.pv.store(board, mv, ply);
+            self.info.trace.best(board, mv);
+
+            // Scores of moves whose search was cut short are meaningless
+            if root && !load_stop() {
+                self.info.partial = Some(SearchResult {
+                    best_move: self.info.pv.line(0)[0],
+                    score,
+                    lower_bound: score >= beta,
+                    depth: self.info.root_depth as usize,
+                    seldepth: self.info.seldepth,
+                    nodes: self.info.nodes,
+                    pv: self.info.pv.line(0).to_vec(),
+                });
+            }
 
             // Fail-high
             if score >= beta {
+                self.info.stats.beta_cutoffs += 1;
+                self.info.stats.first_move_cutoffs += u64::from(moves_played == 1);
+                self.info.stats.cutoff_moves += moves_played as u64;
+
                 if is_quiet {
                     // Killer moves
-                    self.info.killers[ply][1] = self.info.killers[ply][0];
-                    self.info.killers[ply][0] = Some(mv);
+                    let killers = &mut self.info.stack[ply].killers;
+                    killers[1] = killers[0];
+                    killers[0] = Some(mv);
 
                     // History Heuristic
                     self.info.history.update_table::<true>(board, mv, depth);
@@ -473,7 +820,26 @@ impl<'a> Search<'a> {
 
         debug_assert!((-INFINITY..=INFINITY).contains(&best_score));
 
-        if !load_stop() {
+        // Correction histories
+        // Results off the eval in the direction their bound can tell, and not down to a
+        // capture or a mate, are what the corrections learn from.
+        if let Some(raw) = raw_eval {
+            if !load_stop()
+                && best_move.is_none_or(|mv| is_quiet(board, mv))
+                && best_score.abs() < TB_WIN_IN_PLY
+                && !(flag == TTFlag::LowerBound && best_score <= eval)
+                && !(flag == TTFlag::UpperBound && best_score >= eval)
+            {
+                let keys = self.info.stack[ply].keys;
+                self.info
+                    .history
+                    .correction
+                    .update(board, &keys, depth, best_score, raw);
+            }
+        }
+
+        // Scores without the excluded move don't belong to the position
+        if !load_stop() && excluded.is_none() {
             self.tt.store(
                 hash_key,
                 best_move,
@@ -491,43 +857,72 @@ impl<'a> Search<'a> {
     fn qsearch<const PV: bool>(
         &mut self,
         board: &Board,
-        mut alpha: i32,
+        alpha: i32,
         beta: i32,
         ply: usize,
+        qs_ply: usize,
     ) -> i32 {
-        if let (Some(timer), Some(max)) = (self.info.timer, self.info.max_time) {
-            if self.info.nodes % 1024 == 0 && timer.elapsed().as_millis() as u64 >= max {
-                store_stop(true);
-                return 0;
-            }
+        if !self.info.trace.enabled {
+            return self.quiescence::<PV>(board, alpha, beta, ply, qs_ply);
         }
 
-        if load_stop() && ply > 0 {
+        let node = TraceNode::new(
+            NodeKind::Quiescence,
+            self.info.root_depth,
+            ply,
+            0,
+            alpha,
+            beta,
+        );
+        self.info.trace.enter(board, node);
+        let score = self.quiescence::<PV>(board, alpha, beta, ply, qs_ply);
+        self.info.trace.exit(score, load_stop());
+        score
+    }
+
+    #[must_use]
+    fn quiescence<const PV: bool>(
+        &mut self,
+        board: &Board,
+        mut alpha: i32,
+        beta: i32,
+        ply: usize,
+        qs_ply: usize,
+    ) -> i32 {
+        if self.limit_reached() {
+            store_stop(true);
             return 0;
         }
 
-        if self.info.nodes % 2048 == 0 {
-            add_nodes(self.info.nodes - self.info.prev_nodes);
-            self.info.prev_nodes = self.info.nodes;
+        if load_stop() && ply > 0 {
+            return 0;
         }
 
-        let stm = board.side_to_move();
-
-        if ply >= MAX_PLY {
-            return self.nnue.evaluate(stm);
+        if ply >= MAX_PLY || qs_ply >= QSEARCH_PLIES {
+            return self.evaluate(board, -INFINITY, INFINITY);
         }
 
         let hash_key = board.hash();
-        self.tt.prefetch(hash_key);
         self.info.seldepth = self.info.seldepth.max(ply);
 
-        let stand_pat = self.nnue.evaluate(stm);
+        // Stand pat
+        // Unless in check, the side to move can decline every capture and keep the static eval.
+        // In check there is no such choice, every evasion is searched and none means mate.
+        let in_check = !board.checkers().is_empty();
+        let stand_pat = if in_check {
+            -INFINITY
+        } else {
+            self.evaluate(board, alpha, beta)
+        };
         alpha = alpha.max(stand_pat);
         if stand_pat >= beta {
+            self.info.trace.prune(Reason::StandPat);
             return stand_pat;
         }
 
+        let timer = Timer::start();
         let tt_entry = self.tt.probe(hash_key);
+        self.info.stats.record(Phase::Probe, timer);
         let tt_hit = tt_entry.key == hash_key as u16;
         let mut tt_move: Option<Move> = None;
 
@@ -543,26 +938,43 @@ impl<'a> Search<'a> {
                     || (flag == TTFlag::LowerBound && tt_score >= beta)
                     || (flag == TTFlag::UpperBound && tt_score <= alpha)
                 {
+                    self.info.trace.prune(Reason::TtCutoff);
                     return tt_score;
                 }
             }
         }
 
-        let captures = movegen::capture_moves(self, board, tt_move, ply);
-        let mut picker = Picker::new(captures);
+        let timer = Timer::start();
+        let moves = if in_check {
+            movegen::all_moves(self, board, tt_move, ply)
+        } else {
+            movegen::capture_moves(self, board, tt_move, ply)
+        };
+        self.info.stats.record(Phase::Movegen, timer);
+        if in_check && moves.len() == 0 {
+            return ply as i32 - MATE;
+        }
+
+        let mut picker = Picker::new(moves);
         let mut best_score = stand_pat;
         let mut best_move: Option<Move> = None;
 
         while let Some(mv) = picker.pick_move() {
-            let mut new_b = board.clone();
-            play_move(&mut new_b, &mut self.nnue, mv);
+            let new_b = self.make_move(board, mv, ply);
 
-            self.info.nodes += 1;
+            self.count_node();
 
-            let score = -self.qsearch::<PV>(&new_b, -beta, -alpha, ply + 1);
+            let score = -self.qsearch::<PV>(&new_b, -beta, -alpha, ply + 1, qs_ply + 1);
 
             self.nnue.pop();
 
+            #[cfg(feature = "debug_asserts")]
+            super::position::verify_state(
+                board,
+                &self.nnue,
+                format_args!("after undoing {mv} in {board}"),
+            );
+
             if score <= best_score {
                 continue;
             }
@@ -573,6 +985,7 @@ impl<'a> Search<'a> {
             }
             alpha = score;
             best_move = Some(mv);
+            self.info.trace.best(board, mv);
 
             if score >= beta {
                 break;
@@ -595,25 +1008,19 @@ impl<'a> Search<'a> {
         best_score
     }
 
+    /// Searches until the limits are reached or the search is stopped. Only the main thread
+    /// returns a result, and only if there are legal moves.
     pub fn iterative_deepening<const MAIN_THREAD: bool>(
         &mut self,
         board: &Board,
         st: SearchType,
-        pretty: bool,
-    ) {
+    ) -> Option<SearchResult> {
         let depth: usize;
-        let mut opt_time: Option<u64> = None;
         let mut goal_nodes: Option<u64> = None;
+        self.info.time_manager.start(st);
 
         match st {
-            SearchType::Time(opt, max) => {
-                depth = MAX_PLY;
-                self.info.timer = Some(Instant::now());
-                self.info.max_time = Some(max);
-                self.info.base_optimum = Some(opt);
-                opt_time = Some(opt);
-            }
-            SearchType::Infinite => {
+            SearchType::Time(..) | SearchType::Infinite => {
                 depth = MAX_PLY;
             }
             SearchType::Depth(d) => depth = d.min(MAX_PLY),
@@ -629,64 +1036,94 @@ impl<'a> Search<'a> {
             let mut phony_bm: Option<Move> = None;
 
             for d in 1..=depth {
-                s = self.aspiration_window(
-                    false,
-                    board,
-                    &mut PVTable::new(),
-                    s,
-                    d as i32,
-                    &mut phony_bm,
-                );
+                s = self.aspiration_window(false, board, s, d as i32, &mut phony_bm);
 
                 if load_stop() {
                     break;
                 }
             }
 
-            return;
+            self.flush_nodes();
+            return None;
         }
 
         let info_timer = Instant::now();
         let mut best_move: Option<Move> = None;
         let mut score = 0;
-        let mut pv = PVTable::new();
+        let mut result: Option<SearchResult> = None;
+        self.info.diagnostics.enabled = self.reporter.is_some();
+        NODES.store(0, Ordering::Relaxed);
 
         for d in 1..=depth {
             self.info.seldepth = 0;
-            score = self.aspiration_window(true, board, &mut pv, score, d as i32, &mut best_move);
+            self.info
+                .diagnostics
+                .start_iteration(d as i32, self.info.nodes);
+            self.info.partial = None;
+            score = self.aspiration_window(true, board, score, d as i32, &mut best_move);
+            self.info.diagnostics.finish_iteration(self.info.nodes);
 
             // Max time is up
             if load_stop() && d > 1 {
+                // The interrupted iteration is still worth more than the last one for the root
+                // moves it finished above alpha: the first move's score a ply deeper, or a later
+                // move beating it or failing high. It is reported as it was taken, so that the
+                // last info line agrees with the bestmove.
+                if let Some(mut partial) = self.info.partial.take() {
+                    self.flush_nodes();
+                    partial.nodes = load_nodes();
+
+                    if let Some(reporter) = self.reporter.as_deref_mut() {
+                        reporter.depth_completed(&IterationReport {
+                            depth: partial.depth,
+                            seldepth: partial.seldepth,
+                            score: partial.score,
+                            lower_bound: partial.lower_bound,
+                            nodes: partial.nodes,
+                            time: info_timer.elapsed().as_millis() as u64,
+                            pv: &partial.pv,
+                            board,
+                        });
+                    }
+                    result = Some(partial);
+                }
                 break;
             }
 
-            best_move = pv.best_move();
-
-            let mut n = load_nodes();
-            if n == 0 {
-                n = self.info.nodes;
+            best_move = self.info.pv.best_move();
+            let margin = self.variety_margin;
+            self.info.candidates = std::mem::take(&mut self.info.root_scores);
+            self.info
+                .candidates
+                .retain(|&(_, candidate)| candidate >= score - margin);
+
+            self.flush_nodes();
+            let n = load_nodes();
+            let pv = self.extended_pv(board);
+
+            if let Some(reporter) = self.reporter.as_deref_mut() {
+                reporter.depth_completed(&IterationReport {
+                    depth: d,
+                    seldepth: self.info.seldepth,
+                    score,
+                    lower_bound: false,
+                    nodes: n,
+                    time: info_timer.elapsed().as_millis() as u64,
+                    pv: &pv,
+                    board,
+                });
             }
 
-            if pretty {
-                crate::uci::handler::pretty_print(
-                    d,
-                    self.info.seldepth,
+            if let Some(bm) = best_move {
+                result = Some(SearchResult {
+                    best_move: bm,
                     score,
-                    load_nodes(),
-                    info_timer.elapsed().as_millis(),
-                    pv.pv_string(),
-                );
-            } else {
-                println!(
-                    "info depth {} seldepth {} score {} nodes {} time {} nps {} pv{}",
-                    d,
-                    self.info.seldepth,
-                    format_score(score),
-                    n,
-                    info_timer.elapsed().as_millis(),
-                    n / info_timer.elapsed().as_secs().max(1),
-                    pv.pv_string()
-                );
+                    lower_bound: false,
+                    depth: d,
+                    seldepth: self.info.seldepth,
+                    nodes: n,
+                    pv,
+                });
             }
 
             // Nodes search type
@@ -696,56 +1133,180 @@ impl<'a> Search<'a> {
                 }
             }
 
-            // Optimal time check
-            if let Some(mut opt) = opt_time {
+            // Soft limit check, we don't start a new depth past it
+            if let (SearchType::Time(..), Some(bm)) = (st, best_move) {
                 // Time bound adjustments
-                #[rustfmt::skip]
-                let best_move_fraction =
-                    self.info.node_table
-                    [best_move.unwrap().from as usize]
-                    [best_move.unwrap().to as usize] as f64
+                let best_move_fraction = self.info.node_table[bm.from as usize][bm.to as usize]
+                    as f64
                     / self.info.nodes as f64;
 
-                let time_factor = (1.5 - best_move_fraction) * 1.35;
-                opt = (self.info.base_optimum.unwrap() as f64 * time_factor) as u64;
+                self.info.time_manager.update(bm, best_move_fraction, score);
 
-                if info_timer.elapsed().as_millis() as u64 >= opt {
+                if self.info.time_manager.soft_limit_reached(self.info.nodes) {
                     break;
                 }
             }
         }
 
+        // We are not allowed to send a bestmove while pondering, so we wait for a stop or ponderhit
+        while load_ponder() && !load_stop() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
         store_stop(true);
 
-        println!("bestmove {}", best_move.unwrap());
+        // The first iteration can be cut short when we are very low on time
+        result.or_else(|| {
+            let best_move = self.fallback_move(board)?;
+            Some(SearchResult {
+                best_move,
+                score,
+                lower_bound: false,
+                depth: 0,
+                seldepth: self.info.seldepth,
+                nodes: self.info.nodes,
+                pv: vec![best_move],
+            })
+        })
+    }
+
+    fn report_message(&mut self, message: &str) {
+        if let Some(reporter) = self.reporter.as_deref_mut() {
+            reporter.message(message);
+        }
+    }
+
+    // PV nodes never take TT cutoffs, but the PV still ends early at quiescence, draws and
+    // children that failed high. It goes on with the TT moves of the positions it reaches, as
+    // long as they are legal and the line doesn't repeat.
+    fn extended_pv(&self, board: &Board) -> Vec<Move> {
+        let mut pv = self.info.pv.line(0).to_vec();
+        let mut board = board.clone();
+        let mut seen = vec![board.hash()];
+
+        for &mv in &pv {
+            board.play_unchecked(check_castling_move(&board, mv));
+            seen.push(board.hash());
+        }
+
+        while pv.len() < MAX_PV_LENGTH && !pv.is_empty() {
+            let entry = self.tt.probe(board.hash());
+            if entry.key != board.hash() as u16 || u64::from(entry) == 0 {
+                break;
+            }
+
+            let mv = entry.mv.unpack();
+            if !board.is_legal(mv) {
+                break;
+            }
+            pv.push(reverse_castling_move(&board, mv));
+            board.play_unchecked(mv);
+
+            if seen.contains(&board.hash()) {
+                break;
+            }
+            seen.push(board.hash());
+        }
+
+        pv
+    }
+
+    // Eval change since our previous move, or the one before if we were in check then
+    fn improving_rate(&self, ply: usize, eval: i32, in_check: bool) -> i32 {
+        if in_check {
+            return 0;
+        }
+
+        let previous = [2, 4]
+            .into_iter()
+            .filter(|&back| ply >= back)
+            .map(|back| self.info.stack[ply - back].eval)
+            .find(|&previous| previous != -INFINITY);
+
+        previous.map_or(0, |previous| {
+            ((eval - previous) * 100 / tunables::improving_scale()).clamp(-100, 100)
+        })
+    }
+
+    // The child failed high, so its PV starts with the move that refutes this one. Children
+    // cut off by the TT have no PV, their TT move is the refutation then.
+    fn report_refutation(&mut self, board: &Board, child: &Board, mv: Move) {
+        if !self.show_refutations || self.reporter.is_none() || load_stop() {
+            return;
+        }
+
+        let mut line = vec![reverse_castling_move(board, mv)];
+        line.extend_from_slice(self.info.pv.line(1));
+
+        let tt_entry = self.tt.probe(child.hash());
+        if line.len() == 1 && tt_entry.key == child.hash() as u16 && u64::from(tt_entry) != 0 {
+            let tt_move = tt_entry.mv.unpack();
+            if child.is_legal(tt_move) {
+                line.push(reverse_castling_move(child, tt_move));
+            }
+        }
+
+        if let Some(reporter) = self.reporter.as_deref_mut() {
+            reporter.refutation(&line);
+        }
+    }
+
+    // Reported only once the search has taken a while, fast searches would drown in them
+    fn report_currmove(&mut self, board: &Board, mv: Move, number: usize, depth: i32) {
+        let Some(reporter) = self.reporter.as_deref_mut() else {
+            return;
+        };
+
+        if self.info.time_manager.elapsed(self.info.nodes) >= CURRMOVE_DELAY {
+            reporter.currmove(reverse_castling_move(board, mv), number, depth);
+        }
+    }
+
+    // Best guess at a move without a completed iteration: the TT move if there is one,
+    // otherwise any legal move.
+    fn fallback_move(&self, board: &Board) -> Option<Move> {
+        let legal_moves = movegen::pure_moves(board);
+        let tt_entry = self.tt.probe(board.hash());
+
+        if tt_entry.key == board.hash() as u16 && u64::from(tt_entry) != 0 {
+            let tt_move = PackedMove::unpack(tt_entry.mv);
+            if legal_moves.contains(&tt_move) {
+                return Some(reverse_castling_move(board, tt_move));
+            }
+        }
+
+        legal_moves
+            .first()
+            .map(|&mv| reverse_castling_move(board, mv))
     }
 
     fn aspiration_window(
         &mut self,
         main_thread: bool,
         board: &Board,
-        pv: &mut PVTable,
         prev_eval: i32,
         mut depth: i32,
         best_move: &mut Option<Move>,
     ) -> i32 {
         let mut score: i32;
         let init_depth = depth;
+        self.info.root_depth = depth;
 
         // Window size
-        let mut delta = 25;
+        let mut delta = tunables::asp_delta();
 
         // Window bounds
         let mut alpha = -INFINITY;
         let mut beta = INFINITY;
 
-        if depth >= 5 {
-            alpha = (-INFINITY).max(prev_eval - delta);
+        // Wide enough below for the moves variety picks among to get their scores
+        if depth >= tunables::asp_depth() {
+            alpha = (-INFINITY).max(prev_eval - delta - self.variety_margin);
             beta = (INFINITY).min(prev_eval + delta);
         }
 
         loop {
-            score = self.pvsearch::<true>(main_thread, board, pv, alpha, beta, depth, 0);
+            score = self.pvsearch::<true>(main_thread, board, alpha, beta, depth, 0);
 
             if load_stop() {
                 return 0;
@@ -754,7 +1315,7 @@ impl<'a> Search<'a> {
             // Search failed low
             if score <= alpha {
                 beta = (alpha + beta) / 2;
-                alpha = (-INFINITY).max(score - delta);
+                alpha = (-INFINITY).max(score - delta - self.variety_margin);
                 depth = init_depth;
             }
             // Search failed high
@@ -763,18 +1324,63 @@ impl<'a> Search<'a> {
 
                 depth -= i32::from(score.abs() < MATE_IN);
 
-                *best_move = pv.best_move();
+                *best_move = self.info.pv.best_move();
             }
             // Search succeeded
             else {
                 return score;
             }
 
+            if let Some(warning) = self.info.diagnostics.research() {
+                let line = self.info.pv.pv_string(0);
+                self.report_message(&format!("warning: {warning}, line{line}"));
+            }
+
             delta += delta / 2;
             debug_assert!(alpha >= -INFINITY && beta <= INFINITY);
         }
     }
 
+    // Plays the move on a copy of the board, updating the network and keys along. The child's TT
+    // entry is prefetched as soon as its hash is known, it then loads while the rest is updated.
+    fn make_move(&mut self, board: &Board, mv: Move, ply: usize) -> Board {
+        self.info.trace.play(board, mv);
+        let mut new_b = board.clone();
+        new_b.play_unchecked(mv);
+        self.tt.prefetch(new_b.hash());
+
+        update_accumulator(board, &mut self.nnue, mv);
+        self.info.stack[ply + 1].keys = self.info.stack[ply].keys.after(board, mv);
+
+        #[cfg(feature = "debug_asserts")]
+        super::position::verify_state(&new_b, &self.nnue, format_args!("after {mv} in {board}"));
+
+        new_b
+    }
+
+    // Network evaluation corrected by endgame knowledge. With the material balance far enough
+    // outside the window, the network isn't worth asking: the balance is returned instead. The
+    // margin shrinks as pieces come off, material decides more of the eval in the endgame.
+    fn evaluate(&mut self, board: &Board, alpha: i32, beta: i32) -> i32 {
+        let timer = Timer::start();
+        self.info.stats.evals += 1;
+
+        let material = Material::new(board).balance(board.side_to_move());
+        let margin = tunables::lazy_eval_base()
+            + tunables::lazy_eval_piece() * board.occupied().len() as i32;
+        if material - margin >= beta || material + margin <= alpha {
+            self.info.stats.lazy_evals += 1;
+            self.info.stats.record(Phase::Eval, timer);
+            return material;
+        }
+
+        let eval = self.nnue.evaluate(board.side_to_move());
+        let eval = endgame::evaluate(board, eval);
+        self.info.stats.record(Phase::Eval, timer);
+
+        eval
+    }
+
     fn repetition(&self, board: &Board, hash: u64) -> bool {
         self.info
             .game_history
@@ -798,13 +1404,15 @@ impl<'a> Search<'a> {
     pub fn go_reset(&mut self) {
         STOP.store(false, Ordering::Relaxed);
         self.info.search_type = SearchType::Depth(0);
-        self.info.timer = None;
-        self.info.max_time = None;
-        self.info.base_optimum = None;
+        self.info.time_manager = TimeManager::new();
         self.info.nodes = 0;
+        self.info.prev_nodes = 0;
         self.info.node_table = [[0; 64]; 64];
         self.info.seldepth = 0;
-        self.info.killers = [[None; 2]; MAX_PLY];
+        self.info.stats = SearchStats::default();
+        for entry in &mut self.info.stack {
+            entry.killers = [None; 2];
+        }
         self.info.history.age_table();
     }
 
@@ -829,17 +1437,16 @@ impl<'a> Search<'a> {
         let mut best_move: Option<Move> = None;
 
         let mut score = 0;
-        let mut pv = PVTable::new();
 
         for d in 1..=depth {
             self.info.seldepth = 0;
-            score = self.aspiration_window(true, board, &mut pv, score, d as i32, &mut best_move);
+            score = self.aspiration_window(true, board, score, d as i32, &mut best_move);
 
             if load_stop() && d > 1 {
                 break;
             }
 
-            best_move = pv.best_move();
+            best_move = self.info.pv.best_move();
 
             if let Some(nodes) = goal_nodes {
                 if self.info.nodes >= nodes {
@@ -870,6 +1477,74 @@ pub fn format_score(score: i32) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn qsearch_in_check() {
+        let tt = TT::new(1);
+        let nnue = NNUEState::from_board(&Board::default());
+        let history = History::new();
+        let mut search = Search::new(&tt, nnue, &history, &vec![]);
+
+        // No stand pat for the mated side
+        let board = Board::from_fen("R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 1 1", false).unwrap();
+        search.nnue.refresh(&board);
+        assert_eq!(
+            search.qsearch::<true>(&board, -INFINITY, INFINITY, 3, 0),
+            3 - MATE
+        );
+
+        // Taking the checking queen is an evasion like any other
+        let board = Board::from_fen("6k1/5pQp/8/8/8/8/5PPP/6K1 b - - 0 1", false).unwrap();
+        search.nnue.refresh(&board);
+        let score = search.qsearch::<true>(&board, -INFINITY, INFINITY, 0, 0);
+        assert!((-MATE_IN..MATE_IN).contains(&score), "{score}");
+    }
+
+    #[test]
+    fn lazy_evals() {
+        let tt = TT::new(1);
+        let nnue = NNUEState::from_board(&Board::default());
+        let history = History::new();
+        let mut search = Search::new(&tt, nnue, &history, &vec![]);
+
+        // A queen up, the network isn't needed to know the window is out of reach
+        let board = Board::from_fen("3qk3/8/8/8/8/8/8/4K3 b - - 0 1", false).unwrap();
+        search.nnue.refresh(&board);
+        assert_eq!(search.evaluate(&board, -50, 50), 900);
+        assert_eq!(search.info.stats.lazy_evals, 1);
+
+        // The network still decides evals close to the window, and evals without one
+        search.evaluate(&board, 600, 800);
+        search.evaluate(&board, -INFINITY, INFINITY);
+        assert_eq!(search.info.stats.lazy_evals, 1);
+        assert_eq!(search.info.stats.evals, 3);
+    }
+
+    #[test]
+    fn pv_from_tt() {
+        let tt = TT::new(1);
+        let nnue = NNUEState::from_board(&Board::default());
+        let history = History::new();
+        let mut search = Search::new(&tt, nnue, &history, &vec![]);
+
+        let board = Board::default();
+        let moves: Vec<Move> = ["e2e4", "e7e5", "g1f3", "b8c6"]
+            .iter()
+            .map(|mv| mv.parse().unwrap())
+            .collect();
+
+        search.info.pv.clear(1);
+        search.info.pv.store(&board, moves[0], 0);
+
+        // The line stops at the first position without a TT move
+        let mut child = board.clone();
+        child.play_unchecked(moves[0]);
+        for &mv in &moves[1..3] {
+            tt.store(child.hash(), Some(mv), 0, 1, TTFlag::Exact, 0);
+            child.play_unchecked(mv);
+        }
+        assert_eq!(search.extended_pv(&board), &moves[..3]);
+    }
+
     #[test]
     fn repetitions() {
         const FENS: [&str; 3] = [
@@ -881,7 +1556,7 @@ mod tests {
         let tt = TT::new(16);
         let nnue = NNUEState::from_board(&Board::default());
         let history = History::new();
-        let mut search = Search::new(&tt, &nnue, &history, &vec![]);
+        let mut search = Search::new(&tt, nnue, &history, &vec![]);
 
         for fen in FENS.iter() {
             let board = Board::from_fen(fen, false).unwrap();
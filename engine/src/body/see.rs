@@ -0,0 +1,156 @@
+// Static exchange evaluation
+// Material outcome of the capture sequence a move starts on its target square, with both sides
+// recapturing with their least valuable attacker and free to stop whenever continuing loses.
+// X-rays are found by recomputing slider attacks as pieces leave the board. Pieces pinned to
+// their king may only capture along the pin, pins are taken from the initial position.
+use super::cpu;
+use super::position::{is_ep, NodeInfo};
+
+use cozy_chess::{
+    get_bishop_moves, get_king_moves, get_knight_moves, get_line_rays, get_pawn_attacks,
+    get_rook_moves, BitBoard, Board, Color, Move, Piece, Rank, Square,
+};
+
+pub const SEE_VALUES: [i32; 6] = [100, 300, 300, 500, 900, 0];
+
+const fn value(piece: Piece) -> i32 {
+    SEE_VALUES[piece as usize]
+}
+
+/// Material won by the side to move through the exchange started by `mv`
+#[must_use]
+pub fn see(board: &Board, mv: Move) -> i32 {
+    see_pinned(board, mv, NodeInfo::new(board).pinned)
+}
+
+/// Same as `see`, with the pins of both sides already known
+#[must_use]
+pub fn see_pinned(board: &Board, mv: Move, pinned: [BitBoard; 2]) -> i32 {
+    match cpu::kernels() {
+        // Only needs the CPU to have the features checked by the dispatch
+        #[cfg(target_arch = "x86_64")]
+        cpu::Kernels::Avx2 | cpu::Kernels::Avx512 => unsafe { exchange_bmi(board, mv, pinned) },
+        _ => exchange(board, mv, pinned),
+    }
+}
+
+// Bit scans and population counts as single instructions
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "popcnt,bmi1,bmi2")]
+fn exchange_bmi(board: &Board, mv: Move, pinned: [BitBoard; 2]) -> i32 {
+    exchange(board, mv, pinned)
+}
+
+#[inline(always)]
+fn exchange(board: &Board, mv: Move, pinned: [BitBoard; 2]) -> i32 {
+    let stm = board.side_to_move();
+    let target = mv.to;
+
+    // Castling never wins or loses material
+    if board.colors(stm).has(target) {
+        return 0;
+    }
+
+    let mut occupied = board.occupied() ^ mv.from.bitboard();
+    let mut gain = [0; 32];
+    let mut depth = 0;
+
+    gain[0] = if is_ep(board, mv) {
+        occupied ^= Square::new(target.file(), mv.from.rank()).bitboard();
+        value(Piece::Pawn)
+    } else {
+        board.piece_on(target).map_or(0, value)
+    };
+
+    // Value of the piece standing on the target square after each capture
+    let mut on_target = value(board.piece_on(mv.from).unwrap());
+    if let Some(promotion) = mv.promotion {
+        gain[0] += value(promotion) - value(Piece::Pawn);
+        on_target = value(promotion);
+    }
+
+    let mut color = !stm;
+    loop {
+        let attackers = attackers_of(board, color, target, occupied, pinned);
+        let Some((sq, piece)) = least_valuable(board, attackers) else {
+            break;
+        };
+
+        // The king can't capture into a square the opponent still controls
+        if piece == Piece::King && !attackers_of(board, !color, target, occupied, pinned).is_empty()
+        {
+            break;
+        }
+
+        depth += 1;
+        gain[depth] = on_target - gain[depth - 1];
+        on_target = value(piece);
+
+        // Pawns recapturing on the last rank promote
+        if piece == Piece::Pawn && target.rank() == Rank::Eighth.relative_to(color) {
+            gain[depth] += value(Piece::Queen) - value(Piece::Pawn);
+            on_target = value(Piece::Queen);
+        }
+
+        occupied ^= sq.bitboard();
+        color = !color;
+    }
+
+    // Each side stops the exchange once recapturing would lose more than standing pat
+    while depth > 0 {
+        gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+        depth -= 1;
+    }
+
+    gain[0]
+}
+
+// Pieces of both colors attacking the square through the given occupancy
+fn attackers(board: &Board, sq: Square, occupied: BitBoard) -> BitBoard {
+    let diagonal = board.pieces(Piece::Bishop) | board.pieces(Piece::Queen);
+    let orthogonal = board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    let pawns = board.pieces(Piece::Pawn);
+
+    (get_pawn_attacks(sq, Color::White) & pawns & board.colors(Color::Black))
+        | (get_pawn_attacks(sq, Color::Black) & pawns & board.colors(Color::White))
+        | (get_knight_moves(sq) & board.pieces(Piece::Knight))
+        | (get_king_moves(sq) & board.pieces(Piece::King))
+        | (get_bishop_moves(sq, occupied) & diagonal)
+        | (get_rook_moves(sq, occupied) & orthogonal)
+}
+
+// Attackers of one color, leaving out pinned pieces that can't legally capture
+fn attackers_of(
+    board: &Board,
+    color: Color,
+    sq: Square,
+    occupied: BitBoard,
+    pinned: [BitBoard; 2],
+) -> BitBoard {
+    attackers(board, sq, occupied)
+        & occupied
+        & board.colors(color)
+        & !illegal_pinned(board, color, pinned[color as usize], sq)
+}
+
+// Pinned pieces that would leave their pin line by capturing on the square
+fn illegal_pinned(board: &Board, color: Color, pinned: BitBoard, sq: Square) -> BitBoard {
+    let king = board.king(color);
+    let mut illegal = BitBoard::EMPTY;
+
+    for piece_sq in pinned {
+        if !get_line_rays(king, piece_sq).has(sq) {
+            illegal |= piece_sq.bitboard();
+        }
+    }
+
+    illegal
+}
+
+fn least_valuable(board: &Board, attackers: BitBoard) -> Option<(Square, Piece)> {
+    Piece::ALL.into_iter().find_map(|piece| {
+        (attackers & board.pieces(piece))
+            .next_square()
+            .map(|sq| (sq, piece))
+    })
+}
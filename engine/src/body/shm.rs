@@ -0,0 +1,142 @@
+// Shared memory segments
+// Named POSIX shared memory mapped into the process, for tables that several engine processes
+// use at once. A segment stays around after the processes using it exit, until it is unlinked
+// or the machine restarts. On Linux they are the files in /dev/shm.
+use std::ffi::CString;
+use std::ptr::NonNull;
+use std::time::{Duration, Instant};
+
+// How long to wait for a process creating a segment to size it
+const SIZE_WAIT: Duration = Duration::from_secs(1);
+
+pub struct Segment {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// SAFETY: The mapping is plain memory owned by the segment, what is stored in it decides how it
+// can be shared.
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Segment {
+    /// Maps the segment `name` of `len` bytes, creating it zeroed if it does not exist yet.
+    /// Also returns whether it was created, an existing segment must already be `len` bytes.
+    pub fn open(name: &str, len: usize) -> Result<(Self, bool), String> {
+        let path = segment_path(name)?;
+        let size = libc::off_t::try_from(len).map_err(|_| "segment too large".to_string())?;
+
+        // SAFETY: `path` is a valid C string, and every descriptor opened is closed below.
+        unsafe {
+            let mut created = true;
+            let mut fd = libc::shm_open(
+                path.as_ptr(),
+                libc::O_RDWR | libc::O_CREAT | libc::O_EXCL,
+                0o600,
+            );
+            if fd < 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::EEXIST) {
+                created = false;
+                fd = libc::shm_open(path.as_ptr(), libc::O_RDWR, 0);
+            }
+            if fd < 0 {
+                return Err(os_error("could not open", name));
+            }
+
+            if created {
+                if libc::ftruncate(fd, size) != 0 {
+                    let e = os_error("could not size", name);
+                    libc::close(fd);
+                    libc::shm_unlink(path.as_ptr());
+                    return Err(e);
+                }
+            } else if let Err(e) = wait_for_size(fd, size, name) {
+                libc::close(fd);
+                return Err(e);
+            }
+
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            );
+            libc::close(fd);
+            if ptr == libc::MAP_FAILED {
+                return Err(os_error("could not map", name));
+            }
+
+            let ptr = NonNull::new(ptr.cast::<u8>()).expect("mmap never maps address 0");
+            Ok((Self { ptr, len }, created))
+        }
+    }
+
+    #[must_use]
+    pub const fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        // SAFETY: The mapping was made with this address and length and nothing borrows it past
+        // the segment.
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), self.len);
+        }
+    }
+}
+
+/// Removes the segment `name`, processes that have it mapped keep using it
+pub fn unlink(name: &str) -> Result<(), String> {
+    let path = segment_path(name)?;
+    // SAFETY: `path` is a valid C string.
+    if unsafe { libc::shm_unlink(path.as_ptr()) } != 0 {
+        return Err(os_error("could not remove", name));
+    }
+    Ok(())
+}
+
+// Segment names are a single slash followed by a name without any
+fn segment_path(name: &str) -> Result<CString, String> {
+    let bare = name.strip_prefix('/').unwrap_or(name);
+    if bare.is_empty() || bare.contains('/') {
+        return Err(format!("invalid shared memory name {name}"));
+    }
+    CString::new(format!("/{bare}")).map_err(|_| format!("invalid shared memory name {name}"))
+}
+
+// A segment someone else is creating can be seen before it is sized
+unsafe fn wait_for_size(fd: i32, size: libc::off_t, name: &str) -> Result<(), String> {
+    let start = Instant::now();
+    loop {
+        let mut stat: libc::stat = std::mem::zeroed();
+        if libc::fstat(fd, &mut stat) != 0 {
+            return Err(os_error("could not read", name));
+        }
+
+        match stat.st_size {
+            s if s == size => return Ok(()),
+            0 if start.elapsed() < SIZE_WAIT => std::thread::sleep(Duration::from_millis(10)),
+            s => return Err(format!("shared memory {name} is {s} bytes, not {size}")),
+        }
+    }
+}
+
+fn os_error(action: &str, name: &str) -> String {
+    format!(
+        "{action} shared memory {name}: {}",
+        std::io::Error::last_os_error()
+    )
+}
@@ -24,4 +24,8 @@ impl<T: Copy, const N: usize> StaticVec<T, N> {
     pub fn as_slice(&self) -> &[T] {
         &self.data[..self.len]
     }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data[..self.len]
+    }
 }
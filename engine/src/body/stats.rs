@@ -0,0 +1,254 @@
+// Search statistics
+// How often the search cut its tree short and how well its moves were ordered, and with the stats
+// feature the time spent evaluating, generating moves and probing the TT. The counters cost next
+// to nothing and are always kept, the timers read the clock a few times per node and are compiled
+// out otherwise. bench stats prints them summed over the bench, debug on after every search.
+#[cfg(feature = "stats")]
+use crate::uci::clock::Instant;
+
+use std::ops::AddAssign;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Eval,
+    Movegen,
+    Probe,
+}
+
+impl Phase {
+    pub const ALL: [Self; 3] = [Self::Eval, Self::Movegen, Self::Probe];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Eval => "eval",
+            Self::Movegen => "movegen",
+            Self::Probe => "tt probe",
+        }
+    }
+}
+
+/// Started before a phase and handed back to `SearchStats::record` after it
+#[cfg(feature = "stats")]
+pub struct Timer(Instant);
+
+#[cfg(not(feature = "stats"))]
+pub struct Timer;
+
+impl Timer {
+    #[must_use]
+    #[inline(always)]
+    pub fn start() -> Self {
+        #[cfg(feature = "stats")]
+        return Self(Instant::now());
+
+        #[cfg(not(feature = "stats"))]
+        Self
+    }
+}
+
+/// Counted by the main search only, quiescence nodes are left out except for evaluations
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SearchStats {
+    pub beta_cutoffs: u64,
+    pub first_move_cutoffs: u64,
+    // Sum of the move numbers, from 1, of the moves that failed high
+    pub cutoff_moves: u64,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    pub tt_cutoffs: u64,
+    // Late moves searched at a reduced depth, and those searched again at full depth
+    pub lmr_searches: u64,
+    pub lmr_researches: u64,
+    pub null_tries: u64,
+    pub null_cutoffs: u64,
+    pub singular_tries: u64,
+    pub singular_extensions: u64,
+    pub double_extensions: u64,
+    pub multicuts: u64,
+    pub evals: u64,
+    // Evaluations taken from the material balance alone, far outside the window
+    pub lazy_evals: u64,
+    // Nanoseconds per phase, always zero without the stats feature
+    pub times: [u64; 3],
+}
+
+impl SearchStats {
+    pub const TIMED: bool = cfg!(feature = "stats");
+
+    #[inline(always)]
+    pub fn record(&mut self, phase: Phase, timer: Timer) {
+        #[cfg(feature = "stats")]
+        {
+            self.times[phase as usize] += timer.0.elapsed().as_nanos() as u64;
+        }
+
+        #[cfg(not(feature = "stats"))]
+        let _ = (phase, timer);
+    }
+
+    #[must_use]
+    pub fn first_move_rate(&self) -> f64 {
+        percent(self.first_move_cutoffs, self.beta_cutoffs)
+    }
+
+    /// Average move number of the moves that failed high, 1 for perfect ordering
+    #[must_use]
+    pub fn average_cutoff_move(&self) -> f64 {
+        if self.beta_cutoffs == 0 {
+            0.0
+        } else {
+            self.cutoff_moves as f64 / self.beta_cutoffs as f64
+        }
+    }
+
+    #[must_use]
+    pub fn tt_hit_rate(&self) -> f64 {
+        percent(self.tt_hits, self.tt_probes)
+    }
+
+    #[must_use]
+    pub fn tt_cut_rate(&self) -> f64 {
+        percent(self.tt_cutoffs, self.tt_probes)
+    }
+
+    #[must_use]
+    pub fn lmr_research_rate(&self) -> f64 {
+        percent(self.lmr_researches, self.lmr_searches)
+    }
+
+    #[must_use]
+    pub fn null_cut_rate(&self) -> f64 {
+        percent(self.null_cutoffs, self.null_tries)
+    }
+
+    #[must_use]
+    pub fn lazy_eval_rate(&self) -> f64 {
+        percent(self.lazy_evals, self.evals)
+    }
+
+    /// The counters and the rates between them, a line each
+    #[must_use]
+    pub fn summary(&self) -> Vec<String> {
+        vec![
+            format!(
+                "beta cutoffs   {: >10}, {:.1}% on the first move, move {:.2} on average",
+                self.beta_cutoffs,
+                self.first_move_rate(),
+                self.average_cutoff_move()
+            ),
+            format!(
+                "tt probes      {: >10}, {:.1}% hits, {:.1}% cut",
+                self.tt_probes,
+                self.tt_hit_rate(),
+                self.tt_cut_rate()
+            ),
+            format!(
+                "null moves     {: >10}, {:.1}% cut",
+                self.null_tries,
+                self.null_cut_rate()
+            ),
+            format!(
+                "lmr searches   {: >10}, {:.1}% searched again",
+                self.lmr_searches,
+                self.lmr_research_rate()
+            ),
+            format!(
+                "singular tries {: >10}, {} extended, {} doubly, {} multicuts",
+                self.singular_tries,
+                self.singular_extensions,
+                self.double_extensions,
+                self.multicuts
+            ),
+            format!(
+                "evaluations    {: >10}, {:.1}% lazy",
+                self.evals,
+                self.lazy_eval_rate()
+            ),
+        ]
+    }
+}
+
+impl AddAssign for SearchStats {
+    fn add_assign(&mut self, other: Self) {
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        self.cutoff_moves += other.cutoff_moves;
+        self.tt_probes += other.tt_probes;
+        self.tt_hits += other.tt_hits;
+        self.tt_cutoffs += other.tt_cutoffs;
+        self.lmr_searches += other.lmr_searches;
+        self.lmr_researches += other.lmr_researches;
+        self.null_tries += other.null_tries;
+        self.null_cutoffs += other.null_cutoffs;
+        self.singular_tries += other.singular_tries;
+        self.singular_extensions += other.singular_extensions;
+        self.double_extensions += other.double_extensions;
+        self.multicuts += other.multicuts;
+        self.evals += other.evals;
+        self.lazy_evals += other.lazy_evals;
+        for (time, other) in self.times.iter_mut().zip(other.times) {
+            *time += other;
+        }
+    }
+}
+
+#[must_use]
+pub fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_sum() {
+        let mut total = SearchStats::default();
+        let stats = SearchStats {
+            beta_cutoffs: 10,
+            first_move_cutoffs: 9,
+            cutoff_moves: 15,
+            tt_probes: 40,
+            tt_hits: 20,
+            tt_cutoffs: 10,
+            lmr_searches: 8,
+            lmr_researches: 2,
+            null_tries: 4,
+            null_cutoffs: 1,
+            singular_tries: 5,
+            singular_extensions: 2,
+            double_extensions: 1,
+            multicuts: 1,
+            evals: 50,
+            lazy_evals: 5,
+            times: [1, 2, 3],
+        };
+
+        total += stats;
+        total += stats;
+        assert_eq!(total.beta_cutoffs, 20);
+        assert_eq!(total.times, [2, 4, 6]);
+        assert_eq!(total.singular_extensions, 4);
+        assert!((total.first_move_rate() - 90.0).abs() < 1e-9);
+        assert!((total.null_cut_rate() - 25.0).abs() < 1e-9);
+        assert!((total.lazy_eval_rate() - 10.0).abs() < 1e-9);
+        assert!((total.average_cutoff_move() - 1.5).abs() < 1e-9);
+        assert!((total.tt_hit_rate() - 50.0).abs() < 1e-9);
+        assert!((total.tt_cut_rate() - 25.0).abs() < 1e-9);
+        assert!((total.lmr_research_rate() - 25.0).abs() < 1e-9);
+        assert_eq!(SearchStats::default().null_cut_rate(), 0.0);
+        assert_eq!(SearchStats::default().average_cutoff_move(), 0.0);
+        assert_eq!(total.summary().len(), 6);
+
+        // Without the feature the timer records nothing
+        if !SearchStats::TIMED {
+            total.record(Phase::Eval, Timer::start());
+            assert_eq!(total.times, [2, 4, 6]);
+        }
+    }
+}
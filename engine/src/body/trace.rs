@@ -0,0 +1,308 @@
+// Search tree traces
+// Records the nodes of a search near the root: their type, window, depth, the move leading to
+// them, the score and best move they returned, and what pruned them if anything did. The tree
+// is written out as JSON for viewing offline, it shows why a line was cut or never searched.
+// Only the calls below are made from the search, each of them does nothing unless enabled.
+use crate::uci::handler::reverse_castling_move;
+
+use cozy_chess::{Board, Move};
+use std::fmt::Write;
+
+pub const DEFAULT_PLIES: usize = 4;
+pub const DEFAULT_NODES: usize = 100_000;
+
+/// How much of the tree is recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceLimits {
+    /// Nodes deeper than this many plies from the root are left out, along with their subtrees
+    pub plies: usize,
+    /// Recording stops after this many nodes
+    pub nodes: usize,
+}
+
+impl Default for TraceLimits {
+    fn default() -> Self {
+        Self {
+            plies: DEFAULT_PLIES,
+            nodes: DEFAULT_NODES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Pv,
+    NonPv,
+    /// Searched without the TT move, to tell whether it is singular
+    Singular,
+    Quiescence,
+}
+
+/// Why a node returned before searching all of its moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Draw,
+    MateDistance,
+    TtCutoff,
+    Razoring,
+    ReverseFutility,
+    NullMove,
+    Multicut,
+    LateMovePruning,
+    Futility,
+    StandPat,
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceNode {
+    pub parent: Option<usize>,
+    /// Depth of the iteration the node was searched in
+    pub iteration: i32,
+    pub ply: usize,
+    pub depth: i32,
+    pub kind: NodeKind,
+    /// Played in the parent to reach the node, none at the root, for null moves, and for
+    /// searches of the parent's own position
+    pub mv: Option<Move>,
+    pub alpha: i32,
+    pub beta: i32,
+    pub score: i32,
+    pub best: Option<Move>,
+    pub reason: Option<Reason>,
+}
+
+impl TraceNode {
+    #[must_use]
+    pub fn new(
+        kind: NodeKind,
+        iteration: i32,
+        ply: usize,
+        depth: i32,
+        alpha: i32,
+        beta: i32,
+    ) -> Self {
+        Self {
+            parent: None,
+            iteration,
+            ply,
+            depth,
+            kind,
+            mv: None,
+            alpha,
+            beta,
+            score: 0,
+            best: None,
+            reason: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Trace {
+    pub enabled: bool,
+    pub limits: TraceLimits,
+    pub fen: String,
+    pub nodes: Vec<TraceNode>,
+    /// Whether the node limit left nodes out
+    pub truncated: bool,
+    // The recorded node of every node being searched, none for the ones left out
+    open: Vec<Option<usize>>,
+    // Last move played, taken by the next node entered
+    pending: Option<Move>,
+}
+
+impl Trace {
+    #[must_use]
+    pub fn new(limits: TraceLimits) -> Self {
+        Self {
+            enabled: true,
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// A move about to be searched from `board`
+    #[inline]
+    pub fn play(&mut self, board: &Board, mv: Move) {
+        if self.enabled {
+            self.pending = Some(reverse_castling_move(board, mv));
+        }
+    }
+
+    /// A node being searched from `board`, until the matching `exit`
+    pub fn enter(&mut self, board: &Board, mut node: TraceNode) {
+        let mv = self.pending.take();
+        if node.ply > self.limits.plies || self.nodes.len() >= self.limits.nodes {
+            self.truncated |= node.ply <= self.limits.plies;
+            self.open.push(None);
+            return;
+        }
+
+        if self.nodes.is_empty() {
+            self.fen = board.to_string();
+        }
+        node.parent = self.open.last().copied().flatten();
+        node.mv = mv;
+        self.open.push(Some(self.nodes.len()));
+        self.nodes.push(node);
+    }
+
+    pub fn exit(&mut self, score: i32, stopped: bool) {
+        self.pending = None;
+        if let Some(Some(index)) = self.open.pop() {
+            let node = &mut self.nodes[index];
+            node.score = score;
+            if stopped {
+                node.reason = Some(Reason::Stopped);
+            }
+        }
+    }
+
+    /// The node being searched has a new best move
+    #[inline]
+    pub fn best(&mut self, board: &Board, mv: Move) {
+        if let Some(node) = self.current() {
+            node.best = Some(reverse_castling_move(board, mv));
+        }
+    }
+
+    /// The node being searched is cut short
+    #[inline]
+    pub fn prune(&mut self, reason: Reason) {
+        if let Some(node) = self.current() {
+            node.reason = Some(reason);
+        }
+    }
+
+    fn current(&mut self) -> Option<&mut TraceNode> {
+        if !self.enabled {
+            return None;
+        }
+        let index = self.open.last().copied().flatten()?;
+        Some(&mut self.nodes[index])
+    }
+
+    /// The tree as a JSON object: the root position, whether nodes were left out, and the nodes
+    /// in the order they were entered, each naming its parent by index
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        let quoted = |value: &dyn std::fmt::Display| format!("\"{value}\"");
+
+        let mut json = format!(
+            "{{\n\"fen\": \"{}\",\n\"truncated\": {},\n\"nodes\": [",
+            self.fen, self.truncated
+        );
+        for (id, node) in self.nodes.iter().enumerate() {
+            let bound = if node.score <= node.alpha {
+                "upper"
+            } else if node.score >= node.beta {
+                "lower"
+            } else {
+                "exact"
+            };
+
+            let separator = if id == 0 { "" } else { "," };
+            let _ = write!(
+                json,
+                "{separator}\n{{\"id\": {id}, \"parent\": {}, \"iteration\": {}, \"ply\": {}, \
+                 \"depth\": {}, \"type\": \"{}\", \"move\": {}, \"alpha\": {}, \"beta\": {}, \
+                 \"score\": {}, \"bound\": \"{bound}\", \"best\": {}, \"reason\": {}}}",
+                optional(node.parent.map(|p| p.to_string())),
+                node.iteration,
+                node.ply,
+                node.depth,
+                node.kind.name(),
+                optional(node.mv.map(|mv| quoted(&mv))),
+                node.alpha,
+                node.beta,
+                node.score,
+                optional(node.best.map(|mv| quoted(&mv))),
+                optional(node.reason.map(|r| quoted(&r.name()))),
+            );
+        }
+        json.push_str("\n]\n}\n");
+        json
+    }
+}
+
+impl NodeKind {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pv => "pv",
+            Self::NonPv => "non-pv",
+            Self::Singular => "singular",
+            Self::Quiescence => "qsearch",
+        }
+    }
+}
+
+impl Reason {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Draw => "draw",
+            Self::MateDistance => "mate distance",
+            Self::TtCutoff => "tt cutoff",
+            Self::Razoring => "razoring",
+            Self::ReverseFutility => "reverse futility",
+            Self::NullMove => "null move",
+            Self::Multicut => "multicut",
+            Self::LateMovePruning => "late move pruning",
+            Self::Futility => "futility",
+            Self::StandPat => "stand pat",
+            Self::Stopped => "stopped",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_tree() {
+        let board = Board::default();
+        let mut trace = Trace::new(TraceLimits { plies: 1, nodes: 3 });
+
+        trace.enter(&board, TraceNode::new(NodeKind::Pv, 2, 0, 2, -100, 100));
+        let e4 = "e2e4".parse().unwrap();
+        trace.play(&board, e4);
+        trace.enter(&board, TraceNode::new(NodeKind::Pv, 2, 1, 1, -100, 100));
+        // Beyond the ply limit, the pruning is not the parent's
+        trace.enter(
+            &board,
+            TraceNode::new(NodeKind::Quiescence, 2, 2, 0, -100, 100),
+        );
+        trace.prune(Reason::StandPat);
+        trace.exit(50, false);
+        trace.exit(-20, false);
+        trace.best(&board, e4);
+        trace.enter(&board, TraceNode::new(NodeKind::NonPv, 2, 1, 1, -21, -20));
+        trace.prune(Reason::NullMove);
+        trace.exit(-20, false);
+        // Past the node limit
+        trace.enter(&board, TraceNode::new(NodeKind::NonPv, 2, 1, 1, -21, -20));
+        trace.exit(0, false);
+        trace.exit(20, false);
+
+        assert_eq!(trace.nodes.len(), 3);
+        assert!(trace.truncated);
+        assert_eq!(trace.nodes[1].parent, Some(0));
+        assert_eq!(trace.nodes[1].mv, Some(e4));
+        assert_eq!(trace.nodes[1].reason, None);
+        assert_eq!(trace.nodes[2].mv, None);
+        assert_eq!(trace.nodes[2].reason, Some(Reason::NullMove));
+        assert_eq!(trace.nodes[0].best, Some(e4));
+
+        let json = trace.to_json();
+        assert!(json.contains("\"truncated\": true"));
+        assert!(json.contains(
+            "{\"id\": 2, \"parent\": 0, \"iteration\": 2, \"ply\": 1, \"depth\": 1, \
+             \"type\": \"non-pv\", \"move\": null, \"alpha\": -21, \"beta\": -20, \
+             \"score\": -20, \"bound\": \"lower\", \"best\": null, \"reason\": \"null move\"}"
+        ));
+    }
+}
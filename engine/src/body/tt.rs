@@ -1,7 +1,9 @@
+#[cfg(unix)]
+use super::shm;
 use crate::definitions::{NOMOVE, TB_LOSS_IN_PLY, TB_WIN_IN_PLY};
 
 use cozy_chess::{Move, Piece, Square};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum TTFlag {
@@ -123,48 +125,184 @@ impl From<TTEntry> for u64 {
     }
 }
 
+const BUCKET_SIZE: usize = 8;
+
+// Entries are stored a cache line at a time, with the line boundaries known an entry never
+// straddles two of them
+#[repr(C, align(64))]
+struct Bucket([AtomicU64; BUCKET_SIZE]);
+
+impl Bucket {
+    fn new() -> Self {
+        Self(std::array::from_fn(|_| AtomicU64::new(0)))
+    }
+}
+
+// Tables made by another layout than this one are refused when shared. Bump it with any change
+// to TTEntry, Bucket, Header, or how keys are mapped to entries.
+pub const LAYOUT_VERSION: u32 = 2;
+
+#[cfg(unix)]
+const MAGIC: u64 = u64::from_le_bytes(*b"SVARTTT\0");
+
+// Starts a shared table, with the buckets following it. The magic is written last, once the
+// rest can be read. The epoch is the table's, every process ages it for all of them.
+#[cfg(unix)]
+#[repr(C, align(64))]
+struct Header {
+    magic: AtomicU64,
+    version: AtomicU64,
+    entry_size: AtomicU64,
+    buckets: AtomicU64,
+    epoch: AtomicU8,
+}
+
+enum Memory {
+    Owned(Vec<Bucket>),
+    #[cfg(unix)]
+    Shared(shm::Segment),
+}
+
 pub struct TT {
-    pub entries: Vec<AtomicU64>,
-    pub epoch: u8,
+    memory: Memory,
+    // Only used for tables of its own, a shared one keeps its epoch in the header
+    epoch: AtomicU8,
 }
 
 impl TT {
     pub fn new(mb: u32) -> Self {
-        let hash_size = mb * 1024 * 1024;
-        let size = hash_size / std::mem::size_of::<TTEntry>() as u32;
-        let mut entries = Vec::with_capacity(size as usize);
+        let hash_size = mb as usize * 1024 * 1024;
+        let size = hash_size / std::mem::size_of::<Bucket>();
+        let mut buckets = Vec::with_capacity(size);
 
         for _ in 0..size {
-            entries.push(AtomicU64::new(0));
+            buckets.push(Bucket::new());
+        }
+
+        Self {
+            memory: Memory::Owned(buckets),
+            epoch: AtomicU8::new(0),
+        }
+    }
+
+    /// A table of `mb` megabytes in the shared memory segment `name`, which other processes
+    /// opening it with the same size share. Its entries are kept if it already exists.
+    #[cfg(unix)]
+    pub fn shared(mb: u32, name: &str) -> Result<Self, String> {
+        let buckets = mb as usize * 1024 * 1024 / std::mem::size_of::<Bucket>();
+        let len = std::mem::size_of::<Header>() + buckets * std::mem::size_of::<Bucket>();
+        let (segment, created) = shm::Segment::open(name, len)
+            .map_err(|e| format!("shared hash {name} of {mb} MB unavailable, {e}"))?;
+
+        // SAFETY: The mapping is page aligned, zeroed when created, and large enough for the
+        // header and the buckets, all of which any bit pattern is valid for.
+        let header = unsafe { &*segment.as_ptr().cast::<Header>() };
+        if created {
+            header
+                .version
+                .store(u64::from(LAYOUT_VERSION), Ordering::Relaxed);
+            header
+                .entry_size
+                .store(std::mem::size_of::<TTEntry>() as u64, Ordering::Relaxed);
+            header.buckets.store(buckets as u64, Ordering::Relaxed);
+            header.magic.store(MAGIC, Ordering::Release);
+        } else {
+            check_header(header, buckets).map_err(|e| format!("shared hash {name} {e}"))?;
+        }
+
+        Ok(Self {
+            memory: Memory::Shared(segment),
+            epoch: AtomicU8::new(0),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn shared(_mb: u32, _name: &str) -> Result<Self, String> {
+        Err("shared hash tables need a unix system".to_string())
+    }
+
+    /// Whether the table is in shared memory
+    #[must_use]
+    pub const fn is_shared(&self) -> bool {
+        !matches!(self.memory, Memory::Owned(_))
+    }
+
+    fn epoch_cell(&self) -> &AtomicU8 {
+        match &self.memory {
+            Memory::Owned(_) => &self.epoch,
+            #[cfg(unix)]
+            // SAFETY: Checked in `shared`, the mapping starts with the header.
+            Memory::Shared(segment) => unsafe { &(*segment.as_ptr().cast::<Header>()).epoch },
         }
+    }
 
-        Self { entries, epoch: 0 }
+    /// The age entries are stored with, advanced after every search
+    #[must_use]
+    pub fn epoch(&self) -> u8 {
+        self.epoch_cell().load(Ordering::Relaxed)
+    }
+
+    fn buckets(&self) -> &[Bucket] {
+        match &self.memory {
+            Memory::Owned(buckets) => buckets,
+            #[cfg(unix)]
+            Memory::Shared(segment) => {
+                let count =
+                    (segment.len() - std::mem::size_of::<Header>()) / std::mem::size_of::<Bucket>();
+                // SAFETY: Checked in `shared`, the buckets follow the header in the mapping.
+                unsafe {
+                    let first = segment.as_ptr().add(std::mem::size_of::<Header>());
+                    std::slice::from_raw_parts(first.cast::<Bucket>(), count)
+                }
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.buckets().len() * BUCKET_SIZE
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.buckets().is_empty()
     }
 
     #[must_use]
     pub fn index(&self, key: u64) -> usize {
-        // Cool hack Cosmo taught me
-        let key = key as u128;
-        let len = self.entries.len() as u128;
-        ((key * len) >> 64) as usize
+        table_index(key, self.len())
+    }
+
+    fn entry(&self, index: usize) -> &AtomicU64 {
+        &self.buckets()[index / BUCKET_SIZE].0[index % BUCKET_SIZE]
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &AtomicU64> {
+        self.buckets().iter().flat_map(|bucket| bucket.0.iter())
     }
 
     #[must_use]
     pub fn probe(&self, key: u64) -> TTEntry {
-        let atomic = &self.entries[self.index(key)];
+        let atomic = self.entry(self.index(key));
         let entry = atomic.load(Ordering::Relaxed);
 
         TTEntry::from(entry)
     }
 
-    pub fn age(&mut self) {
+    /// Advances the epoch, for the other processes too when the table is shared
+    pub fn age(&self) {
         // Cap at 63 for wrapping into 6 bits
         const EPOCH_MAX: u8 = 63;
 
-        if self.epoch == EPOCH_MAX {
-            self.epoch = 0;
+        let previous = self
+            .epoch_cell()
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |epoch| {
+                Some(if epoch == EPOCH_MAX { 1 } else { epoch + 1 })
+            })
+            .unwrap();
 
-            self.entries.iter_mut().for_each(|a| {
+        if previous == EPOCH_MAX {
+            self.entries().for_each(|a| {
                 let entry = a.load(Ordering::Relaxed);
                 let mut entry = TTEntry::from(entry);
 
@@ -173,8 +311,6 @@ impl TT {
                 a.store(entry.into(), Ordering::Relaxed);
             })
         }
-
-        self.epoch += 1;
     }
 
     pub fn store(
@@ -187,7 +323,7 @@ impl TT {
         ply: usize,
     ) {
         let target_index = self.index(key);
-        let target_atomic = &self.entries[target_index];
+        let target_atomic = self.entry(target_index);
         let mut target: TTEntry = target_atomic.load(Ordering::Relaxed).into();
 
         let entry = TTEntry {
@@ -195,7 +331,7 @@ impl TT {
             mv: PackedMove::new(mv),
             score: score_to_tt(score, ply),
             depth,
-            age_flag: AgeAndFlag::new(self.epoch, flag),
+            age_flag: AgeAndFlag::new(self.epoch(), flag),
         };
 
         // Only replace entries of similar or higher quality
@@ -221,18 +357,44 @@ impl TT {
         unsafe {
             use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
 
-            let index = self.index(key);
-            let entry = &self.entries[index];
+            let entry = self.entry(self.index(key));
 
             _mm_prefetch((entry as *const AtomicU64).cast::<i8>(), _MM_HINT_T0);
         }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = key;
     }
 
-    pub fn reset(&mut self) {
-        self.entries.iter().for_each(|a| {
+    pub fn reset(&self) {
+        self.entries().for_each(|a| {
             a.store(0, Ordering::Relaxed);
         })
     }
+
+    /// Empties the table and starts its aging over, the buckets split between `threads` threads.
+    /// A shared table is emptied for every process using it.
+    pub fn clear(&mut self, threads: usize) {
+        self.epoch_cell().store(0, Ordering::Relaxed);
+
+        // Without threads to spare, wasm among others, the caller does it
+        if threads <= 1 {
+            self.reset();
+            return;
+        }
+
+        let chunk = self.buckets().len().div_ceil(threads).max(1);
+        std::thread::scope(|s| {
+            for buckets in self.buckets().chunks(chunk) {
+                s.spawn(move || {
+                    buckets
+                        .iter()
+                        .flat_map(|bucket| bucket.0.iter())
+                        .for_each(|a| a.store(0, Ordering::Relaxed));
+                });
+            }
+        });
+    }
 }
 
 #[must_use]
@@ -258,6 +420,44 @@ pub fn score_from_tt(score: i16, ply: usize) -> i16 {
 }
 
 const _TT_TEST: () = assert!(std::mem::size_of::<TTEntry>() == 8);
+const _BUCKET_TEST: () =
+    assert!(std::mem::size_of::<Bucket>() == 64 && std::mem::align_of::<Bucket>() == 64);
+#[cfg(unix)]
+const _HEADER_TEST: () = assert!(std::mem::size_of::<Header>() == 64);
+
+// A table another process made can be read once its magic is there
+#[cfg(unix)]
+fn check_header(header: &Header, buckets: usize) -> Result<(), String> {
+    let start = std::time::Instant::now();
+    while header.magic.load(Ordering::Acquire) == 0 {
+        if start.elapsed() > std::time::Duration::from_secs(1) {
+            return Err("was never set up".to_string());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    if header.magic.load(Ordering::Acquire) != MAGIC {
+        return Err("is not a hash table".to_string());
+    }
+    let version = header.version.load(Ordering::Relaxed);
+    let entry_size = header.entry_size.load(Ordering::Relaxed);
+    if version != u64::from(LAYOUT_VERSION) || entry_size != std::mem::size_of::<TTEntry>() as u64 {
+        return Err(format!(
+            "has entry layout {version}, this engine uses layout {LAYOUT_VERSION}"
+        ));
+    }
+    if header.buckets.load(Ordering::Relaxed) != buckets as u64 {
+        return Err("is used with another size".to_string());
+    }
+    Ok(())
+}
+
+/// Index of `key` in a table of `len` entries, spreading keys over it without a modulo
+#[must_use]
+pub fn table_index(key: u64, len: usize) -> usize {
+    // Cool hack Cosmo taught me
+    ((u128::from(key) * len as u128) >> 64) as usize
+}
 
 #[cfg(test)]
 mod tests {
@@ -265,7 +465,7 @@ mod tests {
 
     #[test]
     fn tt_reset() {
-        let mut tt = TT::new(1);
+        let tt = TT::new(1);
         let mv = Move {
             from: Square::A1,
             to: Square::A2,
@@ -275,7 +475,7 @@ mod tests {
         assert_eq!(tt.probe(5).score, 1);
 
         tt.reset();
-        tt.entries.iter().for_each(|e| {
+        tt.entries().for_each(|e| {
             let e = e.load(Ordering::Relaxed);
             let e = TTEntry::from(e);
 
@@ -287,6 +487,22 @@ mod tests {
         });
     }
 
+    #[test]
+    fn tt_clear() {
+        let mut tt = TT::new(1);
+        for key in 0..1000 {
+            tt.store(key * 7919, None, 1, 3, TTFlag::Exact, 0);
+        }
+        tt.age();
+
+        for threads in [1, 3] {
+            tt.store(5, None, 1, 3, TTFlag::Exact, 0);
+            tt.clear(threads);
+            assert_eq!(tt.epoch(), 0);
+            assert!(tt.entries().all(|e| e.load(Ordering::Relaxed) == 0));
+        }
+    }
+
     #[test]
     fn packed_moves() {
         let mv = Move {
@@ -368,4 +584,47 @@ mod tests {
         assert_eq!(entry.age_flag.age(), 0);
         assert_eq!(entry.age_flag.flag(), TTFlag::LowerBound);
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_tables() {
+        let name = format!("svart-tt-test-{}", std::process::id());
+        let first = TT::shared(1, &name).unwrap();
+        let second = TT::shared(1, &name).unwrap();
+        assert!(first.is_shared() && !TT::new(1).is_shared());
+        assert_eq!(first.len(), TT::new(1).len());
+
+        first.store(5, None, 1, 3, TTFlag::Exact, 0);
+        assert_eq!(second.probe(5).depth, 3);
+
+        // Aging through one process ages the table for all of them
+        let epoch = second.epoch();
+        first.age();
+        assert_eq!(second.epoch(), epoch + 1);
+        second.store(6, None, 1, 3, TTFlag::Exact, 0);
+        assert_eq!(first.probe(6).age_flag.age(), epoch + 1);
+        drop((first, second));
+
+        // The entries outlive the processes using them, as long as they all agree on the layout
+        let third = TT::shared(1, &name).unwrap();
+        assert_eq!(third.probe(5).depth, 3);
+        assert!(TT::shared(2, &name).is_err());
+        if let Memory::Shared(segment) = &third.memory {
+            let header = unsafe { &*segment.as_ptr().cast::<Header>() };
+            header
+                .version
+                .store(u64::from(LAYOUT_VERSION + 1), Ordering::Relaxed);
+        }
+        assert!(TT::shared(1, &name).is_err_and(|e| e.contains("layout")));
+
+        shm::unlink(&name).unwrap();
+        assert!(TT::shared(1, "svart/tt").is_err());
+    }
+
+    #[test]
+    fn bucket_alignment() {
+        let tt = TT::new(1);
+        assert_eq!(tt.len(), 1024 * 1024 / 8);
+        assert_eq!(tt.buckets().as_ptr() as usize % 64, 0);
+    }
 }
@@ -0,0 +1,81 @@
+// Opening variety
+// An engine playing people, or other bots, plays the same game every time it gets the same moves.
+// For the first plies of a game the move played can instead be drawn among the root moves scoring
+// close to the best one, the closer the more likely. The search scores them within its own limits,
+// like MultiPV would, by searching root moves close to the best with an open window. Given a seed,
+// the draw depends only on it and the position, and games repeat move for move.
+use cozy_chess::Move;
+
+/// Widest margin from the best score, in centipawns
+pub const MAX_VARIETY: i32 = 200;
+pub const DEFAULT_VARIETY_PLIES: u32 = 16;
+pub const MAX_VARIETY_PLIES: u32 = 200;
+/// Seeds fit the spin options of GUIs that store them as 32-bit integers
+pub const MAX_SEED: u64 = i32::MAX as u64;
+
+/// Draws one of the candidates with `random` in [0, 1). Weights fall off exponentially with the
+/// distance from the best score, a move `margin` behind it is picked e^2 times less often.
+#[must_use]
+pub fn pick(candidates: &[(Move, i32)], margin: i32, random: f64) -> Option<Move> {
+    let best = candidates.iter().map(|&(_, score)| score).max()?;
+    let temperature = f64::from(margin.max(1)) / 2.0;
+    let weights: Vec<f64> = candidates
+        .iter()
+        .map(|&(_, score)| (f64::from(score - best) / temperature).exp())
+        .collect();
+
+    let mut target = random * weights.iter().sum::<f64>();
+    for (&(mv, _), weight) in candidates.iter().zip(weights) {
+        if target < weight {
+            return Some(mv);
+        }
+        target -= weight;
+    }
+
+    candidates.last().map(|&(mv, _)| mv)
+}
+
+/// A number in [0, 1) for `pick`, the same every time for the seed and the position's hash
+#[must_use]
+pub const fn seeded_random(seed: u64, hash: u64) -> f64 {
+    // splitmix64 finalizer
+    let mut z = (seed ^ hash).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_pick() {
+        let e4: Move = "e2e4".parse().unwrap();
+        let d4: Move = "d2d4".parse().unwrap();
+        let c4: Move = "c2c4".parse().unwrap();
+
+        assert_eq!(pick(&[], 50, 0.5), None);
+        assert_eq!(pick(&[(e4, 30)], 50, 0.99), Some(e4));
+
+        // Equal scores split evenly
+        let candidates = [(e4, 30), (d4, 30)];
+        assert_eq!(pick(&candidates, 50, 0.49), Some(e4));
+        assert_eq!(pick(&candidates, 50, 0.51), Some(d4));
+
+        // c4 is a full margin behind, with a weight of e^-2 out of 2 + e^-2
+        let candidates = [(e4, 30), (d4, 30), (c4, -20)];
+        assert_eq!(pick(&candidates, 50, 0.9), Some(d4));
+        assert_eq!(pick(&candidates, 50, 0.95), Some(c4));
+
+        // Seeded draws repeat, and spread over [0, 1)
+        assert_eq!(seeded_random(7, 12345), seeded_random(7, 12345));
+        assert_ne!(seeded_random(7, 12345), seeded_random(8, 12345));
+        let draws: Vec<f64> = (0..1000).map(|hash| seeded_random(7, hash)).collect();
+        assert!(draws.iter().all(|r| (0.0..1.0).contains(r)));
+        let mean = draws.iter().sum::<f64>() / draws.len() as f64;
+        assert!((mean - 0.5).abs() < 0.05, "{mean}");
+    }
+}
@@ -12,10 +12,15 @@ pub mod definitions {
     pub const TB_WIN_IN_PLY: i32 = TB_WIN - MAX_PLY as i32;
     pub const TB_LOSS_IN_PLY: i32 = -TB_WIN_IN_PLY;
 
+    // Endgames won by knowledge rather than search
+    pub const KNOWN_WIN: i32 = 10_000;
+
     pub const TIME_OVERHEAD: u64 = 5;
 
     pub const NOMOVE: u16 = 0b0000_0000_0000_0000;
 }
 
+pub mod api;
 pub mod body;
+pub mod tunables;
 pub mod uci;
@@ -1,11 +1,119 @@
 fn main() {
     #![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
-    let binding = std::env::args().nth(1);
-    let arg = binding.as_deref();
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // --config <file> comes before any command, without it svart.toml is looked for instead
+    let config_path = if args.get(1).map(String::as_str) == Some("--config") {
+        let path = args.get(2).cloned();
+        args.drain(1..args.len().min(3));
+        if path.is_none() {
+            println!("info string usage: --config <file>");
+            return;
+        }
+        path
+    } else {
+        None
+    };
+
+    let config = engine::uci::config::Config::load(config_path.as_deref()).unwrap_or_else(|e| {
+        println!("info string {e}, using the default configuration");
+        engine::uci::config::Config::default()
+    });
+
+    let arg = args.get(1).map(String::as_str);
+
     if arg == Some("bench") {
-        engine::uci::bench::bench();
+        bench(&args[2..]);
+        return;
+    }
+
+    // OpenBench passes the whole command as a single argument
+    if let Some(cmd) = arg.filter(|a| a.starts_with("genfens")) {
+        let words: Vec<&str> = cmd.split_whitespace().collect();
+        engine::uci::genfens::genfens(&words);
+        return;
+    }
+
+    // perft suite [depth] [hash <mb>]
+    if arg == Some("perft") && args.get(2).map(String::as_str) == Some("suite") {
+        engine::uci::perft::perft_suite(&args[3..]);
+        return;
+    }
+
+    // annotate <game.pgn> [movetime] [--graph <file>]
+    if arg == Some("annotate") {
+        engine::uci::annotate::annotate(&args[2..], &config);
+        return;
+    }
+
+    // play [movetime] [--black] [--depth <d>]
+    if arg == Some("play") {
+        engine::uci::play::play(&args[2..], &config);
+        return;
+    }
+
+    // selfplay [movetime] [games] [--output <file>]
+    if arg == Some("selfplay") {
+        engine::uci::selfplay::selfplay(&args[2..], &config);
+        return;
+    }
+
+    // packnet <directory> <output>
+    if arg == Some("packnet") {
+        engine::uci::packnet::packnet(&args[2..]);
+        return;
+    }
+
+    if arg == Some("batch") {
+        engine::uci::batch::batch(&args[2..]);
+        return;
+    }
+
+    // Prints the configuration in effect, a starting point for svart.toml
+    if arg == Some("config") {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    // serve [address]
+    if arg == Some("serve") {
+        let address = args
+            .get(2)
+            .map_or(engine::uci::server::DEFAULT_ADDRESS, String::as_str);
+
+        engine::uci::server::serve(address, &config);
+        return;
+    }
+
+    // --script <file>
+    if arg == Some("--script") {
+        match args.get(2) {
+            Some(path) => engine::uci::script::replay(path, &config),
+            None => println!("info string usage: --script <file>"),
+        }
         return;
     }
 
-    engine::uci::handler::uci_loop();
+    engine::uci::handler::uci_loop(&config);
+}
+
+// bench and its variants, given the arguments after bench
+fn bench(args: &[String]) {
+    use engine::uci::bench;
+
+    let depth =
+        |i: usize, default: usize| args.get(i).and_then(|d| d.parse().ok()).unwrap_or(default);
+
+    match args.first().map(String::as_str) {
+        // bench make [depth]
+        Some("make") => bench::make_bench(depth(1, bench::DEFAULT_MAKE_DEPTH)),
+        // bench eval [depth]
+        Some("eval") => bench::eval_bench(depth(1, bench::DEFAULT_EVAL_DEPTH)),
+        // bench stats [depth]
+        Some("stats") => bench::stats_bench(depth(1, bench::DEFAULT_BENCH_DEPTH)),
+        // bench verify <expected nodes> [depth], bench save <file> [depth] or bench diff <file>
+        Some("verify" | "save" | "diff") => bench::regression_bench(args),
+        // bench [depth]
+        _ => bench::bench(depth(0, bench::DEFAULT_BENCH_DEPTH)),
+    }
 }
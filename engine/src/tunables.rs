@@ -0,0 +1,215 @@
+// Search, evaluation and time management parameters
+// Normal builds compile every parameter down to a constant. Builds with the `tune` feature
+// store them in atomics instead, expose them as UCI spin options and can print them in the
+// OpenBench SPSA input format. Fractional parameters are scaled by 100.
+#[cfg(feature = "tune")]
+use crate::uci::handler::UciOutput;
+
+macro_rules! tunables {
+    ($($name:ident: $value:expr, $min:expr, $max:expr, $step:expr;)*) => {
+        $(
+            #[cfg(not(feature = "tune"))]
+            #[inline(always)]
+            #[must_use]
+            pub const fn $name() -> i32 {
+                $value
+            }
+        )*
+
+        #[cfg(feature = "tune")]
+        mod storage {
+            use super::Tunable;
+            use std::sync::atomic::AtomicI32;
+
+            $(
+                #[allow(non_upper_case_globals)]
+                pub static $name: Tunable = Tunable {
+                    name: stringify!($name),
+                    value: AtomicI32::new($value),
+                    default: $value,
+                    min: $min,
+                    max: $max,
+                    step: $step,
+                };
+            )*
+        }
+
+        $(
+            #[cfg(feature = "tune")]
+            #[inline(always)]
+            #[must_use]
+            pub fn $name() -> i32 {
+                storage::$name.get()
+            }
+        )*
+
+        #[cfg(feature = "tune")]
+        pub static TUNABLES: &[&Tunable] = &[$(&storage::$name),*];
+
+        /// Every parameter with its current value
+        #[must_use]
+        pub fn values() -> Vec<(&'static str, i32)> {
+            vec![$((stringify!($name), $name())),*]
+        }
+    };
+}
+
+tunables! {
+    // Late move reductions
+    lmr_base: 75, 0, 200, 10;
+    lmr_divisor: 225, 100, 400, 15;
+
+    // Internal iterative deepening
+    iid_depth: 8, 5, 12, 1;
+    iid_reduction: 4, 2, 6, 1;
+
+    // Singular extensions: minimum depth, how much shallower the TT entry may be, the margin
+    // below the TT score per ply of depth, and the margin for a double extension
+    se_depth: 8, 5, 12, 1;
+    se_tt_depth_slack: 3, 1, 6, 1;
+    se_margin: 2, 1, 6, 1;
+    se_double_margin: 20, 0, 60, 3;
+
+    // Improving rate: the eval gain counted as fully improving, and how much that scales the
+    // pruning margins (percent) and LMR (percent of a ply)
+    improving_scale: 100, 40, 250, 10;
+    improving_weight: 50, 0, 100, 5;
+
+    // Reverse futility pruning
+    rfp_depth: 9, 4, 14, 1;
+    rfp_margin: 75, 30, 150, 6;
+
+    // Razoring
+    razor_depth: 3, 1, 6, 1;
+    razor_margin: 250, 100, 500, 20;
+
+    // Null move pruning
+    nmp_base: 3, 1, 6, 1;
+    nmp_depth_divisor: 3, 2, 6, 1;
+    nmp_eval_divisor: 200, 100, 400, 15;
+
+    // Late move pruning, indexed by depth
+    lmp_depth_1: 5, 2, 12, 1;
+    lmp_depth_2: 8, 4, 20, 1;
+    lmp_depth_3: 18, 8, 36, 2;
+
+    // Futility pruning
+    fp_coefficient: 100, 40, 200, 8;
+    fp_margin: 75, 20, 200, 8;
+    fp_depth: 6, 3, 10, 1;
+
+    // Aspiration windows
+    asp_delta: 25, 10, 60, 3;
+    asp_depth: 5, 2, 9, 1;
+
+    // Correction histories, weights in percent
+    corr_pawn_weight: 30, 0, 100, 5;
+    corr_non_pawn_weight: 20, 0, 100, 5;
+    corr_material_weight: 10, 0, 100, 5;
+    corr_minor_weight: 20, 0, 100, 5;
+
+    // Evaluation, drawish endings scaled in percent
+    eval_tempo: 10, 0, 30, 2;
+    scale_opposite_bishops: 50, 10, 100, 5;
+    scale_rook_pawn_up: 75, 30, 100, 5;
+    scale_wrong_bishop: 10, 0, 50, 5;
+
+    // Lazy evaluation: how far outside the window the material balance has to be for the
+    // network to be skipped, plus so much per piece on the board
+    lazy_eval_base: 500, 200, 1000, 40;
+    lazy_eval_piece: 20, 0, 60, 3;
+
+    // History bonus
+    hist_bonus_mul: 16, 4, 40, 2;
+    hist_bonus_max: 1200, 400, 2400, 100;
+
+    // Time management
+    tm_time_divisor: 20, 10, 40, 2;
+    tm_inc: 75, 40, 100, 5;
+    tm_soft: 60, 30, 100, 5;
+    tm_hard: 200, 100, 400, 15;
+    tm_node_base: 150, 100, 250, 8;
+    tm_node_scale: 135, 50, 250, 10;
+    tm_panic_divisor: 100, 40, 250, 10;
+    tm_panic_max: 250, 100, 400, 15;
+}
+
+#[cfg(feature = "tune")]
+pub struct Tunable {
+    pub name: &'static str,
+    value: std::sync::atomic::AtomicI32,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+    pub step: i32,
+}
+
+#[cfg(feature = "tune")]
+impl Tunable {
+    #[inline(always)]
+    #[must_use]
+    pub fn get(&self) -> i32 {
+        self.value.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn set(&self, value: i32) {
+        self.value
+            .store(value, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// Sets a parameter by name, returning whether it exists and the value is within bounds
+#[cfg(feature = "tune")]
+pub fn set(name: &str, value: i32) -> bool {
+    match TUNABLES.iter().find(|t| t.name == name) {
+        Some(t) if (t.min..=t.max).contains(&value) => {
+            t.set(value);
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(feature = "tune")]
+pub fn print_options(out: &UciOutput) {
+    for t in TUNABLES {
+        out.send(format_args!(
+            "option name {} type spin default {} min {} max {}",
+            t.name, t.default, t.min, t.max
+        ));
+    }
+}
+
+// name, int, default, min, max, c_end, r_end
+#[cfg(feature = "tune")]
+pub fn print_spsa(out: &UciOutput) {
+    for t in TUNABLES {
+        out.send(format_args!(
+            "{}, int, {}, {}, {}, {}, 0.002",
+            t.name,
+            t.get(),
+            t.min,
+            t.max,
+            t.step
+        ));
+    }
+}
+
+#[cfg(all(test, feature = "tune"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tunables_in_bounds() {
+        for t in TUNABLES {
+            assert!(t.min <= t.default && t.default <= t.max, "{}", t.name);
+            assert!(t.step > 0, "{}", t.name);
+        }
+
+        assert!(set("rfp_margin", 80));
+        assert_eq!(rfp_margin(), 80);
+        assert!(!set("rfp_margin", 10_000));
+        assert!(!set("no_such_param", 0));
+        assert!(set("rfp_margin", 75));
+    }
+}
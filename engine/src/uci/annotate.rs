@@ -0,0 +1,347 @@
+// annotate <game.pgn> [movetime] [--graph <file>]
+// Replays the games of a PGN, searching every position for movetime milliseconds, and prints
+// them back annotated: the evaluation after each move, and ?!, ? or ?? with the engine's choice
+// for inaccuracies, mistakes and blunders.
+//
+// --graph also writes the evaluation after every move as CSV, for plotting:
+//
+//     game,ply,move,san,cp,mate,depth,time
+//     1,1,1,e4,15,,14,1000
+//
+// Scores are white relative. Mates are capped to +-1000 cp, with the moves to mate in the mate
+// column, 0 once the game ended in checkmate. Depth and time are those of the search.
+use super::{
+    clock::Instant,
+    config::Config,
+    handler::check_castling_move,
+    pgn::{mate_moves, pgn_eval, wrap_movetext, PgnGame, PgnReader},
+    san::to_san,
+};
+use crate::api::{Engine, Limits};
+use crate::definitions::MATE;
+
+use cozy_chess::{Board, Color, Move};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+const USAGE: &str = "info string usage: annotate <game.pgn> [movetime] [--graph <file>]";
+
+const GRAPH_HEADER: &str = "game,ply,move,san,cp,mate,depth,time";
+
+pub const DEFAULT_MOVETIME: u64 = 1000;
+
+// Scores are capped when judging moves, a won position is still won a few pawns later
+const SCORE_CAP: i32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    /// Judges a move by the centipawns it lost compared to the engine's choice
+    #[must_use]
+    pub const fn from_loss(loss: i32) -> Option<Self> {
+        match loss {
+            300.. => Some(Self::Blunder),
+            100.. => Some(Self::Mistake),
+            50.. => Some(Self::Inaccuracy),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Inaccuracy => "Inaccuracy",
+            Self::Mistake => "Mistake",
+            Self::Blunder => "Blunder",
+        }
+    }
+
+    // Numeric annotation glyphs for ?!, ? and ??
+    const fn nag(self) -> &'static str {
+        match self {
+            Self::Inaccuracy => "$6",
+            Self::Mistake => "$2",
+            Self::Blunder => "$4",
+        }
+    }
+}
+
+/// Search of one position of a game, the score from the side to move's perspective
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Analysis {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: usize,
+    // Milliseconds
+    pub time: u64,
+}
+
+impl Analysis {
+    /// Centipawns the move played from this position lost, given the analysis of the next one
+    #[must_use]
+    pub fn loss(&self, mv: Move, next: &Self) -> i32 {
+        if self.best_move == Some(mv) {
+            return 0;
+        }
+
+        let cap = |score: i32| score.clamp(-SCORE_CAP, SCORE_CAP);
+        (cap(self.score) + cap(next.score)).max(0)
+    }
+}
+
+pub fn annotate(args: &[String], config: &Config) {
+    let Some(path) = args.first() else {
+        println!("{USAGE}");
+        return;
+    };
+    let mut movetime = DEFAULT_MOVETIME;
+    let mut graph_path: Option<&str> = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let parsed = match arg.as_str() {
+            "--graph" => rest.next().map(|path| graph_path = Some(path)).is_some(),
+            arg => arg.parse().map(|m| movetime = m).is_ok(),
+        };
+
+        if !parsed {
+            println!("{USAGE}");
+            return;
+        }
+    }
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("info string could not open {path}: {e}");
+            return;
+        }
+    };
+
+    let mut graph = match graph_path.map(File::create).transpose() {
+        Ok(graph) => graph.map(BufWriter::new),
+        Err(e) => {
+            println!("info string could not create {}: {e}", graph_path.unwrap());
+            return;
+        }
+    };
+    if let Some(graph) = &mut graph {
+        let _ = writeln!(graph, "{GRAPH_HEADER}");
+    }
+
+    let mut engine = Engine::new();
+    if let Err(e) = config.apply(&mut engine) {
+        println!("info string {e}");
+    }
+    let limits = Limits {
+        movetime: Some(movetime),
+        ..Default::default()
+    };
+
+    let mut games = 0;
+    for game in PgnReader::new(BufReader::new(file)) {
+        match game {
+            Ok(Ok(game)) => {
+                games += 1;
+                let analyses = analyse(&mut engine, &game, limits);
+                println!("{}", annotated_pgn(&game, &analyses));
+
+                if let Some(graph) = &mut graph {
+                    for row in graph_rows(games, &game, &analyses) {
+                        let _ = writeln!(graph, "{row}");
+                    }
+                    let _ = graph.flush();
+                }
+            }
+            Ok(Err(e)) => println!("info string skipping game: {e}"),
+            Err(e) => {
+                println!("info string could not read {path}: {e}");
+                return;
+            }
+        }
+    }
+}
+
+/// Searches every position of the game, the one after the last move included
+pub fn analyse(engine: &mut Engine, game: &PgnGame, limits: Limits) -> Vec<Analysis> {
+    let fen = format!("{:#}", game.start);
+    let mut moves: Vec<String> = Vec::with_capacity(game.moves.len());
+    let mut analyses = Vec::with_capacity(game.moves.len() + 1);
+
+    engine.new_game();
+    for ply in 0..=game.moves.len() {
+        let words: Vec<&str> = moves.iter().map(String::as_str).collect();
+        engine
+            .set_position(&fen, &words)
+            .expect("the reader only returns legal games");
+
+        let timer = Instant::now();
+        let analysis = match engine.search(limits) {
+            Some(result) => Analysis {
+                // Back to the king capturing its rook, like the moves of the game
+                best_move: Some(check_castling_move(engine.board(), result.best_move)),
+                score: result.score,
+                depth: result.depth,
+                time: timer.elapsed().as_millis() as u64,
+            },
+            // Checkmate or stalemate
+            None => Analysis {
+                best_move: None,
+                score: if engine.board().checkers().is_empty() {
+                    0
+                } else {
+                    -MATE
+                },
+                depth: 0,
+                time: 0,
+            },
+        };
+        analyses.push(analysis);
+
+        if let Some(&(mv, _)) = game.moves.get(ply) {
+            moves.push(mv.to_string());
+        }
+    }
+
+    analyses
+}
+
+/// The game in PGN export format with the analysis of each move
+#[must_use]
+pub fn annotated_pgn(game: &PgnGame, analyses: &[Analysis]) -> String {
+    let mut pgn = String::new();
+    for (key, value) in &game.tags {
+        pgn.push_str(&format!("[{key} \"{value}\"]\n"));
+    }
+    pgn.push_str("[Annotator \"Svart\"]\n\n");
+
+    let mut tokens: Vec<String> = vec![];
+    let mut board = game.start.clone();
+    let mut after_comment = false;
+
+    for (ply, &(mv, _)) in game.moves.iter().enumerate() {
+        let number = board.fullmove_number();
+        if board.side_to_move() == Color::White {
+            tokens.push(format!("{number}."));
+        } else if ply == 0 || after_comment {
+            tokens.push(format!("{number}..."));
+        }
+        tokens.push(to_san(&board, mv));
+
+        let (before, after) = (&analyses[ply], &analyses[ply + 1]);
+        let judgement = Judgement::from_loss(before.loss(mv, after));
+        if let Some(judgement) = judgement {
+            tokens.push(judgement.nag().to_string());
+        }
+
+        let parent = board.clone();
+        board.play_unchecked(mv);
+
+        // Nothing to evaluate once the game is over
+        after_comment = after.best_move.is_some();
+        if after_comment {
+            let mut comment = format!("{{ [%eval {}]", pgn_eval(white_score(&board, after.score)));
+            if let (Some(judgement), Some(best)) = (judgement, before.best_move) {
+                comment.push_str(&format!(
+                    " {}. {} was best.",
+                    judgement.name(),
+                    to_san(&parent, best)
+                ));
+            }
+            comment.push_str(" }");
+            tokens.push(comment);
+        }
+    }
+
+    let result = game
+        .tags
+        .iter()
+        .find(|(key, _)| key == "Result")
+        .map_or("*", |(_, value)| value.as_str());
+    tokens.push(result.to_string());
+
+    pgn.push_str(&wrap_movetext(&tokens.join(" ")));
+    pgn
+}
+
+/// Evaluation graph rows of a game, numbered from 1, one for the position after each move
+#[must_use]
+pub fn graph_rows(game_number: usize, game: &PgnGame, analyses: &[Analysis]) -> Vec<String> {
+    let mut board = game.start.clone();
+    let mut rows = Vec::with_capacity(game.moves.len());
+
+    for (ply, (&(mv, _), after)) in game.moves.iter().zip(&analyses[1..]).enumerate() {
+        let number = board.fullmove_number();
+        let san = to_san(&board, mv);
+        board.play_unchecked(mv);
+
+        let score = white_score(&board, after.score);
+        let mate = mate_moves(score).map_or_else(String::new, |moves| moves.to_string());
+        rows.push(format!(
+            "{game_number},{},{number},{san},{},{mate},{},{}",
+            ply + 1,
+            score.clamp(-SCORE_CAP, SCORE_CAP),
+            after.depth,
+            after.time
+        ));
+    }
+
+    rows
+}
+
+fn white_score(board: &Board, score: i32) -> i32 {
+    match board.side_to_move() {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uci::pgn::LINE_LENGTH;
+
+    #[test]
+    fn annotated_game() {
+        let pgn = "[Event \"Test\"]\n[Result \"1-0\"]\n\n\
+                   1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0\n";
+        let game = PgnReader::new(pgn.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let mut engine = Engine::new();
+        let limits = Limits {
+            depth: Some(6),
+            ..Default::default()
+        };
+        let analyses = analyse(&mut engine, &game, limits);
+        assert_eq!(analyses.len(), 8);
+        assert_eq!(analyses[7].score, -MATE);
+        assert_eq!(analyses[7].best_move, None);
+
+        // Nf6 allows mate in one
+        let text = annotated_pgn(&game, &analyses);
+        assert!(
+            text.starts_with("[Event \"Test\"]\n[Result \"1-0\"]\n[Annotator \"Svart\"]\n\n1. e4")
+        );
+        assert!(text.contains("Nf6 $4 { [%eval #1] Blunder."), "{text}");
+        assert!(text.contains("Qxf7# 1-0"), "{text}");
+        assert!(text.lines().all(|line| line.len() <= LINE_LENGTH));
+
+        let rows = graph_rows(1, &game, &analyses);
+        assert_eq!(rows.len(), 7);
+        assert!(rows[0].starts_with("1,1,1,e4,"));
+        assert!(rows[5].starts_with("1,6,3,Nf6,1000,1,"), "{}", rows[5]);
+        assert_eq!(rows[6], "1,7,4,Qxf7#,1000,0,0,0");
+
+        assert_eq!(Judgement::from_loss(49), None);
+        assert_eq!(Judgement::from_loss(120), Some(Judgement::Mistake));
+    }
+}
@@ -0,0 +1,115 @@
+// batch <file|-> [--eval | --depth <d> | --nodes <n>] [--threads <t>] [--hash <mb>] [--output <file>]
+// Evaluates or searches FENs read one per line from a file or stdin, writing
+// "<fen> | <bestmove> | <score>" lines in the same order to stdout or the output file.
+// Scores are from the side to move's perspective, and the move is 0000 when there is none.
+use crate::api::{batch as run_batch, BatchJob, DEFAULT_HASH};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+const USAGE: &str = "info string usage: batch <file|-> [--eval | --depth <d> | --nodes <n>] \
+[--threads <t>] [--hash <mb>] [--output <file>]";
+
+const DEFAULT_NODES: u64 = 5000;
+
+// Positions handed to the threads at a time, results are written after each chunk
+const CHUNK: usize = 4096;
+
+pub fn batch(args: &[String]) {
+    let Some(input) = args.first() else {
+        println!("{USAGE}");
+        return;
+    };
+
+    let mut job = BatchJob::Nodes(DEFAULT_NODES);
+    let mut threads = 1;
+    let mut hash = DEFAULT_HASH;
+    let mut output: Option<&str> = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        if flag == "--eval" {
+            job = BatchJob::Evaluate;
+            continue;
+        }
+
+        let value = rest.next().map(String::as_str);
+        let parsed = match (flag.as_str(), value) {
+            ("--depth", Some(d)) => d.parse().map(|d| job = BatchJob::Depth(d)).is_ok(),
+            ("--nodes", Some(n)) => n.parse().map(|n| job = BatchJob::Nodes(n)).is_ok(),
+            ("--threads", Some(t)) => t.parse().map(|t| threads = t).is_ok(),
+            ("--hash", Some(h)) => h.parse().map(|h| hash = h).is_ok(),
+            ("--output", Some(path)) => {
+                output = Some(path);
+                true
+            }
+            _ => false,
+        };
+
+        if !parsed {
+            println!("{USAGE}");
+            return;
+        }
+    }
+
+    let reader: Box<dyn BufRead> = if input == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        match File::open(input) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                println!("info string could not read {input}: {e}");
+                return;
+            }
+        }
+    };
+
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                println!("info string could not create {path}: {e}");
+                return;
+            }
+        },
+        None => Box::new(std::io::stdout().lock()),
+    };
+
+    let mut lines = reader.lines().map_while(Result::ok);
+    loop {
+        let chunk: Vec<String> = lines
+            .by_ref()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .take(CHUNK)
+            .collect();
+        if chunk.is_empty() {
+            break;
+        }
+
+        let fens: Vec<&str> = chunk.iter().map(String::as_str).collect();
+        for (fen, result) in fens.iter().zip(run_batch(&fens, job, threads, hash)) {
+            let written = match result {
+                Ok(result) => {
+                    let mv = result
+                        .best_move
+                        .map_or_else(|| "0000".to_string(), |mv| mv.to_string());
+                    writeln!(out, "{fen} | {mv} | {}", result.score)
+                }
+                Err(e) => {
+                    println!("info string {e}");
+                    Ok(())
+                }
+            };
+
+            if let Err(e) = written {
+                println!("info string could not write results: {e}");
+                return;
+            }
+        }
+    }
+
+    if let Err(e) = out.flush() {
+        println!("info string could not write results: {e}");
+    }
+}
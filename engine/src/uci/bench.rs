@@ -1,10 +1,17 @@
-use std::time::Instant;
+use super::{clock::Instant, handler::SearchType};
+use crate::body::{
+    cpu,
+    keys::Keys,
+    movegen::pure_moves,
+    nnue::inference::NNUEState,
+    position::play_move,
+    search::Search,
+    stats::{percent, Phase, SearchStats},
+    tt::TT,
+};
+use cozy_chess::{Board, Move};
 
-use super::handler::SearchType;
-use crate::body::{nnue::inference::NNUEState, search::Search, tt::TT};
-use cozy_chess::Board;
-
-const FENS: [&str; 62] = [
+pub(super) const FENS: [&str; 62] = [
     "r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14",
     "4rrk1/2p1b1p1/p1p3q1/4p3/2P2n1p/1P1NR2P/PB3PP1/3R1QK1 b - - 2 24",
     "r3qbrk/6p1/2b2pPp/p3pP1Q/PpPpP2P/3P1B2/2PB3K/R5R1 w - - 16 42",
@@ -69,32 +76,407 @@ const FENS: [&str; 62] = [
     "3qk1b1/1p4r1/1n4r1/2P1b2B/p3N2p/P2Q3P/8/1R3R1K w - - 2 39",
 ];
 
-pub fn bench() {
-    let mut tt = TT::new(16);
+pub const DEFAULT_BENCH_DEPTH: usize = 12;
+
+pub const DEFAULT_MAKE_DEPTH: usize = 3;
+
+// Prints the node count and speed in the format OpenBench parses
+pub fn bench(depth: usize) {
+    let (counts, millis, _) = run_bench(depth);
+    print_bench(counts.iter().sum(), millis);
+}
+
+fn print_bench(nodes: u64, millis: u128) {
+    let nps = u128::from(nodes) * 1000 / millis.max(1);
+    println!("{nodes} nodes {nps} nps");
+}
+
+const REGRESSION_USAGE: &str =
+    "info string usage: bench verify <expected nodes> [depth], bench save <file> [depth] or bench diff <file>";
+
+// Functional regression checks, see below
+pub fn regression_bench(args: &[String]) {
+    let arg = |i: usize| args.get(i).map(String::as_str);
+    let depth = arg(2).map_or(Some(DEFAULT_BENCH_DEPTH), |d| d.parse().ok());
+
+    match (arg(0), arg(1), depth, args.len()) {
+        (Some("verify"), Some(expected), Some(depth), ..=3) => match expected.parse() {
+            Ok(expected) => verify_bench(expected, depth),
+            Err(_) => {
+                println!("{REGRESSION_USAGE}");
+                std::process::exit(2);
+            }
+        },
+        (Some("save"), Some(path), Some(depth), ..=3) => save_bench(path, depth),
+        (Some("diff"), Some(path), _, 2) => diff_bench(path),
+        _ => {
+            println!("{REGRESSION_USAGE}");
+            std::process::exit(2);
+        }
+    }
+}
+
+// bench verify <expected nodes> [depth]
+// The usual bench, exiting with a nonzero code unless it searches exactly the expected nodes.
+// Changes that mean to leave the search alone can be checked against the bench of the commit.
+fn verify_bench(expected: u64, depth: usize) {
+    let (counts, millis, _) = run_bench(depth);
+    let nodes = counts.iter().sum();
+    print_bench(nodes, millis);
+
+    if nodes != expected {
+        println!("info string bench mismatch: {nodes} nodes, expected {expected}");
+        std::process::exit(1);
+    }
+}
+
+// bench save <file> [depth]
+// bench diff <file>
+// Saves the nodes searched on each bench position, then runs the bench again at the saved depth
+// and lists the positions whose count changed, exiting with a nonzero code if any did. Where the
+// total only says that a patch changed the search, this says in which positions.
+fn save_bench(path: &str, depth: usize) {
+    let (counts, millis, _) = run_bench(depth);
+    print_bench(counts.iter().sum(), millis);
+
+    let mut saved = format!("depth {depth}\n");
+    for (fen, nodes) in FENS.iter().zip(counts) {
+        saved.push_str(&format!("{nodes} {fen}\n"));
+    }
+    if let Err(e) = std::fs::write(path, saved) {
+        println!("info string could not write {path}: {e}");
+    }
+}
+
+fn diff_bench(path: &str) {
+    let saved = match std::fs::read_to_string(path) {
+        Ok(saved) => saved,
+        Err(e) => {
+            println!("info string could not read {path}: {e}");
+            std::process::exit(2);
+        }
+    };
+    let (depth, saved) = match parse_saved(&saved) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            println!("info string {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let (counts, millis, _) = run_bench(depth);
+    print_bench(counts.iter().sum(), millis);
+
+    let current: Counts = FENS.iter().copied().zip(counts).collect();
+    let changes = diff_counts(&saved, &current);
+    for change in &changes {
+        println!("{change}");
+    }
+    println!(
+        "info string {} of {} positions changed",
+        changes.len(),
+        current.len()
+    );
+    if !changes.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+// Positions with the nodes searched on each
+type Counts<'a> = Vec<(&'a str, u64)>;
+
+// The depth and the nodes of every position in a saved bench
+fn parse_saved(saved: &str) -> Result<(usize, Counts<'_>), String> {
+    let mut lines = saved.lines();
+    let depth = lines
+        .next()
+        .and_then(|line| line.strip_prefix("depth "))
+        .and_then(|depth| depth.parse().ok())
+        .ok_or("saved bench does not start with its depth")?;
+
+    let counts = lines
+        .map(|line| {
+            line.split_once(' ')
+                .and_then(|(nodes, fen)| Some((fen, nodes.parse().ok()?)))
+                .ok_or(format!("invalid saved bench line {line}"))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok((depth, counts))
+}
+
+// A line for every position searched with a different node count, or not searched both times
+fn diff_counts(saved: &[(&str, u64)], current: &[(&str, u64)]) -> Vec<String> {
+    let mut changes = vec![];
+    for (i, &(fen, nodes)) in current.iter().enumerate() {
+        match saved.iter().find(|&&(saved_fen, _)| saved_fen == fen) {
+            Some(&(_, old)) if old == nodes => (),
+            Some(&(_, old)) => changes.push(format!(
+                "position {}: {old} -> {nodes} nodes ({:+}), {fen}",
+                i + 1,
+                nodes as i64 - old as i64
+            )),
+            None => changes.push(format!("position {}: new, {fen}", i + 1)),
+        }
+    }
+
+    for &(fen, _) in saved {
+        if !current.iter().any(|&(current_fen, _)| current_fen == fen) {
+            changes.push(format!("removed position {fen}"));
+        }
+    }
+    changes
+}
+
+// bench stats [depth]
+// The usual bench followed by how the search pruned, and with the stats feature where the time
+// went. Timing every phase slows the search down, the nps of such a build is not comparable.
+pub fn stats_bench(depth: usize) {
+    let (counts, millis, stats) = run_bench(depth);
+    print_bench(counts.iter().sum(), millis);
+
+    println!("kernels        {: >10}", cpu::kernels());
+    for line in stats.summary() {
+        println!("{line}");
+    }
+
+    if !SearchStats::TIMED {
+        println!("build with --features stats for the time spent per phase");
+        return;
+    }
+
+    for phase in Phase::ALL {
+        let nanos = stats.times[phase as usize];
+        println!(
+            "{: <14} {: >10} ms, {:.1}% of the search",
+            phase.name(),
+            nanos / 1_000_000,
+            percent(nanos, millis as u64 * 1_000_000)
+        );
+    }
+}
+
+// Node count of each bench position, time in milliseconds and statistics summed over them
+fn run_bench(depth: usize) -> (Vec<u64>, u128, SearchStats) {
+    let tt = TT::new(16);
     let b = Board::default();
     let nnue = NNUEState::from_board(&b);
     let history = crate::body::history::History::new();
 
-    let mut search = Search::new(&tt, &nnue, &history, &vec![b.hash()]);
-    let mut tot_nodes = 0;
+    let mut search = Search::new(&tt, nnue, &history, &vec![b.hash()]);
+    let mut counts = vec![];
     let mut tot_time = 0;
+    let mut stats = SearchStats::default();
 
     for fen in FENS.iter() {
         let board = Board::from_fen(fen, false).unwrap();
         search.nnue.refresh(&board);
 
         let timer = Instant::now();
-        search.iterative_deepening::<true>(&board, SearchType::Depth(12), false);
+        search.data_search(&board, SearchType::Depth(depth));
         tot_time += timer.elapsed().as_millis();
-        tot_nodes += search.info.nodes;
+        counts.push(search.info.nodes);
+        stats += search.info.stats;
 
         search.game_reset();
         tt.reset();
-        search = Search::new(&tt, &nnue, &history, &vec![board.hash()]);
+        search = Search::new(&tt, search.nnue, &history, &vec![board.hash()]);
     }
 
-    println!(
-        "Bench: {tot_time} ms {tot_nodes} nodes {} nps",
-        tot_nodes / (tot_time as u64 / 1000).max(1)
-    );
+    (counts, tot_time, stats)
+}
+
+pub const DEFAULT_EVAL_DEPTH: usize = 2;
+
+// bench eval [depth]
+// Times the network alone on every position up to a depth from the bench positions, once
+// refreshing the accumulators from scratch for each and once updating them incrementally from
+// the parent the way the search does, with every set of kernels the CPU runs. The incremental
+// timing includes generating and playing the moves. All of them add up to the same evals.
+pub fn eval_bench(depth: usize) {
+    let mut positions = vec![];
+    for fen in FENS.iter() {
+        collect_positions(&Board::from_fen(fen, false).unwrap(), depth, &mut positions);
+    }
+
+    let mut nnue = NNUEState::from_board(&Board::default());
+    let mut sums = vec![];
+    for kernels in cpu::available() {
+        cpu::set_kernels(kernels).unwrap();
+
+        let timer = Instant::now();
+        let mut refresh_sum = 0;
+        for board in &positions {
+            nnue.refresh(board);
+            refresh_sum += i64::from(nnue.evaluate(board.side_to_move()));
+        }
+        let refresh_micros = timer.elapsed().as_micros();
+
+        let timer = Instant::now();
+        let mut incremental_sum = 0;
+        for fen in FENS.iter() {
+            let board = Board::from_fen(fen, false).unwrap();
+            nnue.refresh(&board);
+            incremental_sum += incremental_evals(&board, &mut nnue, depth);
+        }
+        let incremental_micros = timer.elapsed().as_micros();
+
+        let evals = positions.len() as u128 * 1_000_000;
+        println!(
+            "{kernels: <8} refresh {} evals/s, incremental {} evals/s",
+            evals / refresh_micros.max(1),
+            evals / incremental_micros.max(1)
+        );
+        sums.extend([refresh_sum, incremental_sum]);
+    }
+    cpu::set_kernels(cpu::detected()).unwrap();
+
+    println!("{} positions", positions.len());
+    if sums.windows(2).any(|pair| pair[0] != pair[1]) {
+        println!("evals differ between kernels or paths: {sums:?}");
+    }
+}
+
+fn collect_positions(board: &Board, depth: usize, positions: &mut Vec<Board>) {
+    positions.push(board.clone());
+    if depth == 0 {
+        return;
+    }
+
+    for mv in pure_moves(board) {
+        let mut child = board.clone();
+        child.play_unchecked(mv);
+        collect_positions(&child, depth - 1, positions);
+    }
+}
+
+// Sum of the evals of the position and everything below it, reached through the search's updates
+fn incremental_evals(board: &Board, nnue: &mut Box<NNUEState>, depth: usize) -> i64 {
+    let mut sum = i64::from(nnue.evaluate(board.side_to_move()));
+    if depth == 0 {
+        return sum;
+    }
+
+    for mv in pure_moves(board) {
+        let mut child = board.clone();
+        play_move(&mut child, nnue, mv);
+        sum += incremental_evals(&child, nnue, depth - 1);
+        nnue.pop();
+    }
+
+    sum
+}
+
+// bench make [depth]
+// Plays every move of the bench positions to a fixed depth the way the search does, with the
+// network and keys updated along, once copying the board for each child and once playing moves
+// on a single board taken back through an undo stack. The search uses copy-make: cozy-chess
+// boards can't take moves back, so the undo stack holds whole boards and saves no copying.
+pub fn make_bench(depth: usize) {
+    let board = Board::default();
+    let mut nnue = NNUEState::from_board(&board);
+    let mut results = [(0, 0); 2];
+
+    for fen in FENS.iter() {
+        let board = Board::from_fen(fen, false).unwrap();
+        nnue.refresh(&board);
+
+        let timer = Instant::now();
+        results[0].0 += copy_make(&board, &mut nnue, Keys::new(&board), depth);
+        results[0].1 += timer.elapsed().as_micros();
+
+        let timer = Instant::now();
+        let mut undo = UndoStack::new(board);
+        results[1].0 += undo.walk(&mut nnue, depth);
+        results[1].1 += timer.elapsed().as_micros();
+    }
+
+    for ((nodes, micros), name) in results.into_iter().zip(["copy-make", "make/unmake"]) {
+        let nps = u128::from(nodes) * 1_000_000 / micros.max(1);
+        println!("{name: <12} {nodes} nodes {nps} nps");
+    }
+}
+
+fn copy_make(board: &Board, nnue: &mut Box<NNUEState>, keys: Keys, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let mut nodes = 0;
+    for mv in pure_moves(board) {
+        let child_keys = keys.after(board, mv);
+        let mut child = board.clone();
+        play_move(&mut child, nnue, mv);
+        nodes += copy_make(&child, nnue, child_keys, depth - 1);
+        nnue.pop();
+    }
+
+    nodes
+}
+
+struct UndoStack {
+    board: Board,
+    keys: Keys,
+    undo: Vec<(Board, Keys)>,
+}
+
+impl UndoStack {
+    fn new(board: Board) -> Self {
+        Self {
+            keys: Keys::new(&board),
+            undo: Vec::with_capacity(crate::definitions::MAX_PLY),
+            board,
+        }
+    }
+
+    fn make(&mut self, nnue: &mut Box<NNUEState>, mv: Move) {
+        self.undo.push((self.board.clone(), self.keys));
+        self.keys = self.keys.after(&self.board, mv);
+        play_move(&mut self.board, nnue, mv);
+    }
+
+    fn unmake(&mut self, nnue: &mut Box<NNUEState>) {
+        (self.board, self.keys) = self.undo.pop().unwrap();
+        nnue.pop();
+    }
+
+    fn walk(&mut self, nnue: &mut Box<NNUEState>, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let mut nodes = 0;
+        for mv in pure_moves(&self.board) {
+            self.make(nnue, mv);
+            nodes += self.walk(nnue, depth - 1);
+            self.unmake(nnue);
+        }
+
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_diffs() {
+        let (depth, saved) = parse_saved("depth 5\n100 fen a\n200 fen b\n300 fen c\n").unwrap();
+        assert_eq!(depth, 5);
+        assert_eq!(saved, [("fen a", 100), ("fen b", 200), ("fen c", 300)]);
+        assert!(parse_saved("100 fen a\n").is_err());
+        assert!(parse_saved("depth 5\nfen a\n").is_err());
+
+        assert!(diff_counts(&saved, &saved).is_empty());
+        let current = [("fen a", 100), ("fen b", 150), ("fen d", 10)];
+        assert_eq!(
+            diff_counts(&saved, &current),
+            [
+                "position 2: 200 -> 150 nodes (-50), fen b",
+                "position 3: new, fen d",
+                "removed position fen c",
+            ]
+        );
+    }
 }
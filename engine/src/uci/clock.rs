@@ -0,0 +1,29 @@
+// Wall clock for the time manager and search reports
+// std::time::Instant panics on wasm32-unknown-unknown, where the time comes from JavaScript instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub use wasm::Instant;
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Instant(f64);
+
+    impl Instant {
+        #[must_use]
+        pub fn now() -> Self {
+            Self(js_sys::Date::now())
+        }
+
+        #[must_use]
+        pub fn elapsed(&self) -> Duration {
+            // Date.now() can go backwards when the system clock is adjusted
+            let millis = (js_sys::Date::now() - self.0).max(0.0);
+            Duration::from_secs_f64(millis / 1000.0)
+        }
+    }
+}
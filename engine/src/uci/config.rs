@@ -0,0 +1,159 @@
+// Engine configuration
+// Every option the engine has, loaded from a svart.toml next to the binary or from the file given
+// with --config. It sets the starting values of each session, setoption still overrides them.
+//
+//     hash = 256
+//     threads = 8
+//     pretty = false
+//
+//     [search]
+//     lmr_base = 80
+//
+// Search parameters are constants outside of tune builds, where the file can only repeat them.
+use crate::api::{Engine, DEFAULT_HASH};
+use crate::body::variety::DEFAULT_VARIETY_PLIES;
+use crate::tunables;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub const FILE_NAME: &str = "svart.toml";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub hash: u32,
+    /// Shared memory segment the hash table is placed in, shared with other engine processes
+    pub shared_hash: Option<String>,
+    pub threads: usize,
+    pub ponder: bool,
+    pub nodestime: u64,
+    pub variety: i32,
+    pub variety_plies: u32,
+    pub seed: u64,
+    pub debug_log_file: Option<String>,
+    /// Colored search output before the GUI sends uci, UCI lines otherwise
+    pub pretty: bool,
+    pub search: BTreeMap<String, i32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            hash: DEFAULT_HASH,
+            shared_hash: None,
+            threads: 1,
+            ponder: false,
+            nodestime: 0,
+            variety: 0,
+            variety_plies: DEFAULT_VARIETY_PLIES,
+            seed: 0,
+            debug_log_file: None,
+            pretty: true,
+            search: tunables::values()
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the file given, or svart.toml next to the binary. Only the latter may be missing.
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match std::env::current_exe() {
+                Ok(exe) if exe.with_file_name(FILE_NAME).exists() => exe.with_file_name(FILE_NAME),
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        Self::from_toml(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.message().to_string())
+    }
+
+    #[must_use]
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("the configuration is always representable")
+    }
+
+    /// Sets up an engine with this configuration, stopping at the first invalid value
+    pub fn apply(&self, engine: &mut Engine) -> Result<(), String> {
+        engine.set_hash(self.hash)?;
+        if let Some(name) = &self.shared_hash {
+            engine.set_shared_hash(name)?;
+        }
+        engine.set_threads(self.threads)?;
+
+        engine.set_nodestime(self.nodestime)?;
+        engine.set_variety(self.variety)?;
+        engine.set_variety_plies(self.variety_plies)?;
+        engine.set_seed(self.seed)?;
+        engine.set_ponder(self.ponder);
+
+        for (name, &value) in &self.search {
+            set_parameter(name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tune")]
+fn set_parameter(name: &str, value: i32) -> Result<(), String> {
+    if tunables::set(name, value) {
+        Ok(())
+    } else {
+        Err(format!("invalid search parameter {name} = {value}"))
+    }
+}
+
+#[cfg(not(feature = "tune"))]
+fn set_parameter(name: &str, value: i32) -> Result<(), String> {
+    match tunables::values().iter().find(|&&(n, _)| n == name) {
+        Some(&(_, v)) if v == value => Ok(()),
+        Some(_) => Err(format!(
+            "changing {name} needs a build with the tune feature"
+        )),
+        None => Err(format!("invalid search parameter {name} = {value}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let config = Config {
+            hash: 64,
+            threads: 4,
+            debug_log_file: Some("svart.log".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(Config::from_toml(&config.to_toml()), Ok(config));
+
+        // Missing fields keep their defaults
+        let config = Config::from_toml("nodestime = 500\npretty = false").unwrap();
+        assert_eq!(config.nodestime, 500);
+        assert!(!config.pretty);
+        assert_eq!(config.hash, DEFAULT_HASH);
+        assert_eq!(config.search, Config::default().search);
+
+        assert!(Config::from_toml("hashsize = 64").is_err());
+        assert!(Config::from_toml("hash = \"big\"").is_err());
+    }
+
+    #[test]
+    fn search_parameters() {
+        assert!(set_parameter("lmr_base", tunables::lmr_base()).is_ok());
+        assert!(set_parameter("not_a_parameter", 0).is_err());
+    }
+}
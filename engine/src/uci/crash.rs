@@ -0,0 +1,74 @@
+// Crash fallback
+// A search that panics would leave the GUI waiting for a bestmove until the clock runs out. The
+// hook installed for UCI sessions on stdin says what went wrong and still plays a move: the best
+// one of the last completed iteration, or any legal move before the first one completes.
+use super::handler::UciOutput;
+
+use cozy_chess::Move;
+use std::any::Any;
+use std::sync::{Mutex, PoisonError};
+
+// Only set while a search is running, so that a crash never sends a second bestmove
+static FALLBACK: Mutex<Option<Move>> = Mutex::new(None);
+
+pub fn set_fallback(mv: Option<Move>) {
+    *FALLBACK.lock().unwrap_or_else(PoisonError::into_inner) = mv;
+}
+
+fn take_fallback() -> Option<Move> {
+    FALLBACK
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+}
+
+/// Reports panics to the GUI before exiting, after the default hook has printed its message
+pub fn install_hook(out: UciOutput) {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        let location = info
+            .location()
+            .map_or_else(String::new, |l| format!(" at {}:{}", l.file(), l.line()));
+        out.send(format_args!(
+            "info string error: {}{location}",
+            describe(info.payload())
+        ));
+
+        if let Some(mv) = take_fallback() {
+            out.send(format_args!("bestmove {mv}"));
+        }
+
+        default_hook(info);
+        std::process::exit(1);
+    }));
+}
+
+// Panic payloads are the message string for every panic! with one
+fn describe(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "the search panicked"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_messages() {
+        let payload =
+            std::panic::catch_unwind(|| panic!("index {} out of bounds", 64)).unwrap_err();
+        assert_eq!(describe(payload.as_ref()), "index 64 out of bounds");
+
+        let payload = std::panic::catch_unwind(|| panic!("no moves")).unwrap_err();
+        assert_eq!(describe(payload.as_ref()), "no moves");
+
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(7)).unwrap_err();
+        assert_eq!(describe(payload.as_ref()), "the search panicked");
+    }
+}
@@ -0,0 +1,133 @@
+use super::handler::SearchType;
+use crate::body::{history::History, movegen, nnue::inference::NNUEState, search::Search, tt::TT};
+
+use cozy_chess::{Board, GameStatus};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+// Random plies played on top of the starting position
+const RANDOM_PLIES: usize = 8;
+// Exits with a verification score beyond this are discarded
+const MAX_EXIT_SCORE: i32 = 1000;
+const VERIFICATION_DEPTH: usize = 6;
+
+// genfens N seed S book <file|None>
+// Outputs N random opening FENs for OpenBench-style distributed datagen.
+pub fn genfens(words: &[&str]) {
+    let value = |token: &str| -> Option<&str> {
+        let idx = words.iter().position(|&x| x == token)?;
+        words.get(idx + 1).copied()
+    };
+
+    let Some(count) = words.get(1).and_then(|n| n.parse::<usize>().ok()) else {
+        println!("info string usage: genfens N seed S book <file|None>");
+        return;
+    };
+    let seed = value("seed")
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    let book = match value("book") {
+        Some(path) if path != "None" => match read_book(path) {
+            Ok(book) => book,
+            Err(e) => {
+                println!("info string could not read book {path}: {e}");
+                return;
+            }
+        },
+        _ => vec![],
+    };
+
+    for fen in generate(count, seed, &book) {
+        println!("info string genfens {fen}");
+    }
+}
+
+pub fn read_book(path: &str) -> std::io::Result<Vec<Board>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut book = vec![];
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        // EPD lines don't carry move counters
+        let fields: Vec<&str> = line.split_whitespace().take(6).collect();
+        let fen = if fields.len() >= 6 {
+            fields.join(" ")
+        } else {
+            format!("{} 0 1", fields[..fields.len().min(4)].join(" "))
+        };
+
+        if let Ok(board) = Board::from_fen(&fen, false) {
+            book.push(board);
+        }
+    }
+
+    Ok(book)
+}
+
+#[must_use]
+pub fn generate(count: usize, seed: u64, book: &[Board]) -> Vec<String> {
+    let rng = fastrand::Rng::with_seed(seed);
+    let tt = TT::new(16);
+    let nnue = NNUEState::from_board(&Board::default());
+    let history = History::new();
+    let mut search = Search::new(&tt, nnue, &history, &vec![]);
+
+    let mut fens = Vec::with_capacity(count);
+    'fens: while fens.len() < count {
+        let mut board = if book.is_empty() {
+            Board::default()
+        } else {
+            book[rng.usize(..book.len())].clone()
+        };
+
+        for _ in 0..RANDOM_PLIES + rng.usize(..=1) {
+            let moves = movegen::pure_moves(&board);
+            if moves.is_empty() {
+                continue 'fens;
+            }
+
+            board.play_unchecked(moves[rng.usize(..moves.len())]);
+        }
+
+        // Make sure the position is playable and not absurdly lopsided
+        if board.status() != GameStatus::Ongoing {
+            continue;
+        }
+
+        search.game_reset();
+        search.info.game_history = vec![board.hash()];
+        search.nnue.refresh(&board);
+        let (score, _) = search.data_search(&board, SearchType::Depth(VERIFICATION_DEPTH));
+        if score.abs() > MAX_EXIT_SCORE {
+            continue;
+        }
+
+        fens.push(board.to_string());
+    }
+
+    fens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn genfens_seeded() {
+        let fens = generate(4, 42, &[]);
+
+        assert_eq!(fens.len(), 4);
+        assert_eq!(fens, generate(4, 42, &[]));
+
+        for fen in fens {
+            let board = Board::from_fen(&fen, false).unwrap();
+            assert_eq!(board.status(), GameStatus::Ongoing);
+        }
+    }
+}
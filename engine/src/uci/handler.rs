@@ -1,9 +1,40 @@
-use super::timeman::time_for_move;
-
-use crate::body::{history::History, nnue::inference::NNUEState, search::Search, tt::TT};
-use crate::definitions::MATE;
-
-use cozy_chess::{Board, Color, Move, Piece, Square};
+use super::{
+    config::Config,
+    reporter::{PrettyReporter, UciReporter},
+    timeman::{store_ponder, DEFAULT_THINK_TIME},
+};
+
+use crate::api::{
+    chess960_fen, Engine, Limits, StopHandle, EMBEDDED_NET, MAX_HASH, MAX_NODESTIME, MAX_THREADS,
+    START_FEN,
+};
+use crate::body::{
+    movegen::pure_moves,
+    search::{load_stop, store_stop},
+    see::see,
+    variety::{MAX_SEED, MAX_VARIETY, MAX_VARIETY_PLIES},
+};
+use crate::definitions::TIME_OVERHEAD;
+#[cfg(feature = "tune")]
+use crate::tunables;
+
+use cozy_chess::{Board, Color, Move, Piece, Rank, Square};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Searches started by go whose thread isn't done with them yet, sessions in tests run side by side
+static SEARCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether a search started by go is still running
+#[must_use]
+pub fn searching() -> bool {
+    SEARCHES.load(Ordering::Acquire) > 0
+}
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum SearchType {
@@ -14,56 +45,190 @@ pub enum SearchType {
     Infinite,
 }
 
-struct UCIOptions {
-    hash: u32,
-    threads: u32,
+/// Line oriented output, shared by the command loop and the searching thread. Can tee the
+/// session to a debug log.
+#[derive(Clone)]
+pub struct UciOutput {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    log: Arc<Mutex<Option<File>>>,
 }
 
-impl UCIOptions {
-    fn new() -> Self {
+impl UciOutput {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
         Self {
-            hash: 16,
-            threads: 1,
+            out: Arc::new(Mutex::new(Box::new(out))),
+            log: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Nobody is left to tell if the other end is gone, so write errors are ignored
+    pub fn send(&self, line: impl Display) {
+        let _ = self.clone().write_all(format!("{line}\n").as_bytes());
+    }
+
+    /// Appends every line received and sent from now on to the file, an empty path or <empty>
+    /// stops logging
+    pub fn set_log(&self, path: &str) -> Result<(), String> {
+        let file = match path {
+            "" | "<empty>" => None,
+            path => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("could not open {path}: {e}"))?,
+            ),
+        };
+
+        *self.log.lock().unwrap() = file;
+        Ok(())
+    }
+
+    fn received(&self, line: &str) {
+        self.log_lines(">>", line);
+    }
+
+    // Stockfish's convention: >> for input, << for output
+    fn log_lines(&self, direction: &str, text: &str) {
+        if let Some(log) = self.log.lock().unwrap().as_mut() {
+            let time = timestamp();
+            for line in text.lines() {
+                let _ = writeln!(log, "[{time}] {direction} {line}");
+            }
         }
     }
 }
 
-impl Default for UCIOptions {
-    fn default() -> Self {
-        Self::new()
+// Each write goes out whole, so lines from different threads never interleave
+impl Write for UciOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_all(buf).map(|()| buf.len())
     }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        let mut out = self.out.lock().unwrap();
+        self.log_lines("<<", &String::from_utf8_lossy(buf));
+        out.write_all(buf)?;
+        out.flush()
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.lock().unwrap().flush()
+    }
+}
+
+// UTC time of day with milliseconds
+fn timestamp() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000 % 24,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// How a search hands control back to the command loop
+enum AfterSearch {
+    Continue,
+    // The first command received after the search finished on its own
+    Command(String),
+    // quit, or the end of input
+    Quit,
 }
 
-fn id() {
-    println!("id name Svart 6");
-    println!("id author Cristopher Torgrip");
+fn id(out: &UciOutput) {
+    out.send("id name Svart 6");
+    out.send("id author Cristopher Torgrip");
 }
 
-fn options() {
-    println!("option name Hash type spin default 16 min 1 max 1000000");
-    println!("option name Threads type spin default 1 min 1 max 256");
+// Defaults are whatever the configuration starts the session with
+fn options(out: &UciOutput, config: &Config) {
+    out.send(format_args!(
+        "option name Hash type spin default {} min 0 max {MAX_HASH}",
+        config.hash
+    ));
+    out.send(format_args!(
+        "option name Threads type spin default {} min 1 max {MAX_THREADS}",
+        config.threads
+    ));
+    out.send("option name Clear Hash type button");
+    out.send("option name KeepHash type check default false");
+    out.send(format_args!(
+        "option name SharedHash type string default {}",
+        config.shared_hash.as_deref().unwrap_or("<empty>")
+    ));
+    out.send(format_args!(
+        "option name Ponder type check default {}",
+        config.ponder
+    ));
+    out.send(format_args!(
+        "option name nodestime type spin default {} min 0 max {MAX_NODESTIME}",
+        config.nodestime
+    ));
+    out.send(format_args!(
+        "option name Variety type spin default {} min 0 max {MAX_VARIETY}",
+        config.variety
+    ));
+    out.send(format_args!(
+        "option name Variety Plies type spin default {} min 0 max {MAX_VARIETY_PLIES}",
+        config.variety_plies
+    ));
+    out.send(format_args!(
+        "option name Seed type spin default {} min 0 max {MAX_SEED}",
+        config.seed
+    ));
+    out.send("option name UCI_ShowRefutations type check default false");
+    out.send(format_args!(
+        "option name EvalFile type string default {EMBEDDED_NET}"
+    ));
+    out.send(format_args!(
+        "option name Debug Log File type string default {}",
+        config.debug_log_file.as_deref().unwrap_or("<empty>")
+    ));
+
+    #[cfg(feature = "tune")]
+    tunables::print_options(out);
 }
 
-pub fn uci_loop() {
-    // This should (and will be) made into an object in and of itself later
+pub fn uci_loop(config: &Config) {
+    let out = UciOutput::new(std::io::stdout());
+    super::crash::install_hook(out.clone());
+    super::signals::install_handler();
+
+    run_session(std::io::stdin().lock(), out, config);
+}
+
+/// Answers UCI commands until quit or the end of input, with an engine of its own set up
+/// from the configuration
+pub fn run_session(mut input: impl BufRead, out: UciOutput, config: &Config) {
     let mut uci_set = false;
     let mut board_set = false;
-    let mut board = Board::default();
     let mut stored_input: Option<String> = None;
-
-    let mut uci_options = UCIOptions::default();
-    let mut tt = TT::new(uci_options.hash);
-
-    let mut nnue = NNUEState::from_board(&board);
-    let mut history = History::new();
-    let mut game_history = vec![];
+    let mut engine = Engine::new();
+    if let Some(path) = &config.debug_log_file {
+        if let Err(e) = out.set_log(path) {
+            out.send(format_args!("info string {e}"));
+        }
+    }
+    match config.apply(&mut engine) {
+        Ok(()) if config.hash == 0 => auto_hash_info(&out, &engine),
+        Ok(()) => (),
+        Err(e) => out.send(format_args!("info string {e}")),
+    }
 
     loop {
-        let line = if let Some(si) = stored_input.clone() {
-            stored_input = None;
+        let line = if let Some(si) = stored_input.take() {
             si
         } else {
-            read_input().unwrap()
+            match read_input(&mut input, &out) {
+                Some(line) => line,
+                None => break,
+            }
         };
 
         let words: Vec<&str> = line.split_whitespace().collect();
@@ -74,36 +239,40 @@ pub fn uci_loop() {
         if !uci_set {
             match words[0] {
                 "uci" => {
-                    id();
-                    options();
-                    println!("uciok");
+                    id(&out);
+                    options(&out, config);
+                    out.send(format_args!("info string net {}", engine.net()));
+                    out.send("uciok");
                     uci_set = true;
                 }
                 "quit" => {
                     break;
                 }
                 "bench" => {
-                    super::bench::bench();
+                    super::bench::bench(super::bench::DEFAULT_BENCH_DEPTH);
                     break;
                 }
-                "position" => set_position(
-                    &mut board,
-                    &mut nnue,
-                    &mut game_history,
-                    &mut board_set,
-                    words,
-                ),
+                "genfens" => super::genfens::genfens(&words),
+                "selftest" => super::selftest::selftest(&words),
+                #[cfg(feature = "tune")]
+                "spsa" => tunables::print_spsa(&out),
+                "position" => set_position(&mut engine, &out, &mut board_set, &words),
+                "go" if words.get(1) == Some(&"perft") => {
+                    super::perft::go_perft(engine.board(), &out, &words, engine.threads());
+                }
+                "trace" => super::trace::trace(&mut engine, &out, &words),
                 "go" => {
-                    let mut search = Search::new(&tt, &nnue, &history, &game_history);
-
-                    if board_set {
-                        search.iterative_deepening::<true>(&board, SearchType::Infinite, true);
+                    if config.pretty {
+                        engine.set_reporter(Some(Box::new(PrettyReporter::new(out.clone()))));
                     } else {
-                        search.iterative_deepening::<true>(
-                            &Board::default(),
-                            SearchType::Infinite,
-                            true,
-                        );
+                        engine.set_reporter(Some(Box::new(UciReporter::new(out.clone()))));
+                    }
+
+                    // Endless, until stop or quit
+                    match go(&mut engine, &mut input, &out, Limits::default()) {
+                        AfterSearch::Continue => (),
+                        AfterSearch::Command(line) => stored_input = Some(line),
+                        AfterSearch::Quit => break,
                     }
                 }
                 _ => (),
@@ -111,235 +280,185 @@ pub fn uci_loop() {
         } else {
             match words[0] {
                 "uci" => {
-                    id();
-                    options();
-                    println!("uciok");
+                    id(&out);
+                    options(&out, config);
+                    out.send(format_args!("info string net {}", engine.net()));
+                    out.send("uciok");
 
                     continue;
                 }
                 "isready" => {
-                    println!("readyok");
+                    out.send("readyok");
                     continue;
                 }
                 "ucinewgame" => {
-                    board = Board::default();
-                    tt = TT::new(uci_options.hash);
-                    nnue.refresh(&board);
-                    history = History::new();
-                    game_history = vec![board.hash()];
+                    engine.new_game();
                     board_set = true;
 
                     continue;
                 }
                 "setoption" => {
-                    if words[1] == "name" && words[2] == "Hash" && words[3] == "value" {
-                        if let Ok(s) = words[4].parse::<u32>() {
-                            if s > 1_000_000 {
-                                continue;
+                    if words[1..].starts_with(&["name", "Hash", "value"]) {
+                        if let Some(Ok(s)) = words.get(4).map(|s| s.parse::<u32>()) {
+                            match engine.set_hash(s) {
+                                Ok(()) if s == 0 => auto_hash_info(&out, &engine),
+                                Ok(()) => (),
+                                Err(e) => out.send(format_args!("info string {e}")),
                             }
+                        }
+                    }
 
-                            uci_options.hash = s;
-                            tt = TT::new(uci_options.hash);
+                    if words[1..].starts_with(&["name", "Threads", "value"]) {
+                        if let Some(Ok(t)) = words.get(4).map(|t| t.parse::<usize>()) {
+                            if let Err(e) = engine.set_threads(t) {
+                                out.send(format_args!("info string {e}"));
+                            }
                         }
                     }
 
-                    if words[1] == "name" && words[2] == "Threads" && words[3] == "value" {
-                        if let Ok(t) = words[4].parse::<u32>() {
-                            if !(1..=256).contains(&t) {
-                                continue;
+                    if words[1..].starts_with(&["name", "nodestime", "value"]) {
+                        match words.get(4).map(|n| n.parse::<u64>()) {
+                            Some(Ok(n)) => {
+                                if let Err(e) = engine.set_nodestime(n) {
+                                    out.send(format_args!("info string {e}"));
+                                }
                             }
+                            Some(Err(_)) => out.send(format_args!(
+                                "info string ignoring invalid nodestime {}",
+                                words[4]
+                            )),
+                            None => (),
+                        }
+                    }
 
-                            uci_options.threads = t;
+                    if words[1..].starts_with(&["name", "Variety", "value"]) {
+                        if let Some(Ok(v)) = words.get(4).map(|v| v.parse::<i32>()) {
+                            if let Err(e) = engine.set_variety(v) {
+                                out.send(format_args!("info string {e}"));
+                            }
+                        }
+                    }
+
+                    if words[1..].starts_with(&["name", "Variety", "Plies", "value"]) {
+                        if let Some(Ok(v)) = words.get(5).map(|v| v.parse::<u32>()) {
+                            if let Err(e) = engine.set_variety_plies(v) {
+                                out.send(format_args!("info string {e}"));
+                            }
+                        }
+                    }
+
+                    if words[1..].starts_with(&["name", "Seed", "value"]) {
+                        if let Some(Ok(s)) = words.get(4).map(|s| s.parse::<u64>()) {
+                            if let Err(e) = engine.set_seed(s) {
+                                out.send(format_args!("info string {e}"));
+                            }
+                        }
+                    }
+
+                    if words[1..] == ["name", "Clear", "Hash"] {
+                        engine.clear_hash();
+                    }
+
+                    if words[1..].starts_with(&["name", "KeepHash", "value"]) {
+                        engine.set_keep_hash(words.get(4) == Some(&"true"));
+                    }
+
+                    if words[1..].starts_with(&["name", "SharedHash"]) {
+                        let name = match line.split_once(" value ").map(|(_, name)| name.trim()) {
+                            None | Some("<empty>") => "",
+                            Some(name) => name,
+                        };
+                        if let Err(e) = engine.set_shared_hash(name) {
+                            out.send(format_args!("info string {e}"));
+                        }
+                    }
+
+                    if words[1..].starts_with(&["name", "Ponder", "value"]) {
+                        engine.set_ponder(words.get(4) == Some(&"true"));
+                    }
+
+                    if words[1..].starts_with(&["name", "UCI_ShowRefutations", "value"]) {
+                        engine.set_show_refutations(words.get(4) == Some(&"true"));
+                    }
+
+                    // The path is everything after value, spaces included
+                    if words[1..].starts_with(&["name", "EvalFile"]) {
+                        let path = line
+                            .split_once(" value ")
+                            .map_or("", |(_, path)| path.trim());
+                        match engine.load_net(path) {
+                            Ok(net) => out.send(format_args!("info string net {net}")),
+                            Err(e) => out.send(format_args!("info string {e}")),
+                        }
+                    }
+
+                    if words[1..].starts_with(&["name", "Debug", "Log", "File"]) {
+                        let path = line
+                            .split_once(" value ")
+                            .map_or("", |(_, path)| path.trim());
+                        if let Err(e) = out.set_log(path) {
+                            out.send(format_args!("info string {e}"));
+                        }
+                    }
+
+                    #[cfg(feature = "tune")]
+                    if let [_, "name", name, "value", value, ..] = words[..] {
+                        if let Ok(v) = value.parse::<i32>() {
+                            tunables::set(name, v);
                         }
                     }
 
                     continue;
                 }
-                "position" => set_position(
-                    &mut board,
-                    &mut nnue,
-                    &mut game_history,
-                    &mut board_set,
-                    words,
-                ),
+                "position" => set_position(&mut engine, &out, &mut board_set, &words),
+                "go" if words.get(1) == Some(&"perft") => {
+                    super::perft::go_perft(engine.board(), &out, &words, engine.threads());
+                    continue;
+                }
                 "go" => {
                     if board_set {
-                        // Static depth search
-                        if words.iter().any(|&x| x == "depth") {
-                            if let Ok(d) = words
-                                [words.iter().position(|&x| x == "depth").unwrap() + 1]
-                                .parse::<usize>()
-                            {
-                                go(
-                                    &board,
-                                    SearchType::Depth(d),
-                                    &mut tt,
-                                    &nnue,
-                                    &mut history,
-                                    &game_history,
-                                    &mut stored_input,
-                                    &uci_options,
-                                );
-                            }
-                        } else if words.iter().any(|&x| x == "nodes") {
-                            if let Ok(n) = words
-                                [words.iter().position(|&x| x == "nodes").unwrap() + 1]
-                                .parse::<u64>()
-                            {
-                                go(
-                                    &board,
-                                    SearchType::Nodes(n),
-                                    &mut tt,
-                                    &nnue,
-                                    &mut history,
-                                    &game_history,
-                                    &mut stored_input,
-                                    &uci_options,
-                                );
-                            }
-                        // Infinite search
-                        } else if words.iter().any(|&x| x == "infinite") {
-                            go(
-                                &board,
-                                SearchType::Infinite,
-                                &mut tt,
-                                &nnue,
-                                &mut history,
-                                &game_history,
-                                &mut stored_input,
-                                &uci_options,
-                            );
-                        // Static time search
-                        } else if words.iter().any(|&x| x == "movetime") {
-                            if let Ok(t) = words
-                                [words.iter().position(|&x| x == "movetime").unwrap() + 1]
-                                .parse::<u64>()
-                            {
-                                go(
-                                    &board,
-                                    SearchType::Time(t, t),
-                                    &mut tt,
-                                    &nnue,
-                                    &mut history,
-                                    &game_history,
-                                    &mut stored_input,
-                                    &uci_options,
-                                );
-                            }
-                        // Time search
-                        } else if words.iter().any(|&x| x == "wtime" || x == "btime") {
-                            if board.side_to_move() == Color::White {
-                                match words[words.iter().position(|&x| x == "wtime").unwrap() + 1]
-                                    .parse::<u64>()
-                                {
-                                    Ok(t) => {
-                                        // Increment
-                                        let inc = if words.iter().any(|&x| x == "winc") {
-                                            match words[words
-                                                .iter()
-                                                .position(|&x| x == "winc")
-                                                .unwrap()
-                                                + 1]
-                                            .parse::<u64>()
-                                            {
-                                                Ok(i) => i,
-                                                Err(_) => panic!("Could not parse increment"),
-                                            }
-                                        } else {
-                                            0
-                                        };
-
-                                        let mtg = if words.iter().any(|&x| x == "movestogo") {
-                                            match words[words
-                                                .iter()
-                                                .position(|&x| x == "movestogo")
-                                                .unwrap()
-                                                + 1]
-                                            .parse::<u8>()
-                                            {
-                                                Ok(m) => Some(m),
-                                                Err(_) => None,
-                                            }
-                                        } else {
-                                            None
-                                        };
-
-                                        let (opt, max) = time_for_move(t, inc, mtg);
-
-                                        go(
-                                            &board,
-                                            SearchType::Time(opt, max),
-                                            &mut tt,
-                                            &nnue,
-                                            &mut history,
-                                            &game_history,
-                                            &mut stored_input,
-                                            &uci_options,
-                                        );
-                                    }
-                                    Err(_) => (),
-                                }
-                            } else {
-                                match words[words.iter().position(|&x| x == "btime").unwrap() + 1]
-                                    .parse::<u64>()
-                                {
-                                    Ok(t) => {
-                                        // Increment
-                                        let inc = if words.iter().any(|&x| x == "binc") {
-                                            match words[words
-                                                .iter()
-                                                .position(|&x| x == "binc")
-                                                .unwrap()
-                                                + 1]
-                                            .parse::<u64>()
-                                            {
-                                                Ok(i) => i,
-                                                Err(_) => panic!("Could not parse increment"),
-                                            }
-                                        } else {
-                                            0
-                                        };
-
-                                        let mtg = if words.iter().any(|&x| x == "movestogo") {
-                                            match words[words
-                                                .iter()
-                                                .position(|&x| x == "movestogo")
-                                                .unwrap()
-                                                + 1]
-                                            .parse::<u8>()
-                                            {
-                                                Ok(m) => Some(m),
-                                                Err(_) => None,
-                                            }
-                                        } else {
-                                            None
-                                        };
-
-                                        let (opt, max) = time_for_move(t, inc, mtg);
-
-                                        go(
-                                            &board,
-                                            SearchType::Time(opt, max),
-                                            &mut tt,
-                                            &nnue,
-                                            &mut history,
-                                            &game_history,
-                                            &mut stored_input,
-                                            &uci_options,
-                                        );
-                                    }
-                                    Err(_) => (),
-                                }
-                            };
-                        } else {
-                            continue;
+                        let limits = parse_go(&words, engine.board().side_to_move(), &out);
+                        // Time limits are only enforced after a ponderhit
+                        store_ponder(words.contains(&"ponder"));
+
+                        engine.set_reporter(Some(Box::new(UciReporter::new(out.clone()))));
+                        match go(&mut engine, &mut input, &out, limits) {
+                            AfterSearch::Continue => (),
+                            AfterSearch::Command(line) => stored_input = Some(line),
+                            AfterSearch::Quit => break,
                         }
                     }
                     continue;
                 }
                 "eval" => {
-                    println!("{}", nnue.evaluate(board.side_to_move()));
+                    out.send(engine.evaluate());
+                }
+                "debug" => engine.set_debug(words.get(1) == Some(&"on")),
+                "trace" if board_set => super::trace::trace(&mut engine, &out, &words),
+                "makemove" => match words.get(1) {
+                    Some(mv) => match engine.make_move(mv) {
+                        Ok(()) => board_set = true,
+                        Err(e) => out.send(format_args!("info string {e}")),
+                    },
+                    None => out.send("info string usage: makemove <move>"),
+                },
+                "undo" => {
+                    if let Err(e) = engine.undo() {
+                        out.send(format_args!("info string {e}"));
+                    }
                 }
+                "see" => {
+                    let board = engine.board();
+                    let mv = words.get(1).and_then(|mv| mv.parse::<Move>().ok());
+                    match mv.map(|mv| check_castling_move(board, mv)) {
+                        Some(mv) if board.is_legal(mv) => out.send(see(board, mv)),
+                        _ => out.send("info string usage: see <legal move>"),
+                    }
+                }
+                "genfens" => super::genfens::genfens(&words),
+                "selftest" => super::selftest::selftest(&words),
+                #[cfg(feature = "tune")]
+                "spsa" => tunables::print_spsa(&out),
                 "quit" => {
                     break;
                 }
@@ -349,19 +468,102 @@ pub fn uci_loop() {
             }
         }
     }
+
+    // The search threads have all been joined by now, only the output may be left behind
+    let _ = out.clone().flush();
 }
 
-fn check_castling_move(board: &Board, mut mv: Move) -> Move {
-    if board.piece_on(mv.from) == Some(Piece::King) {
-        mv.to = match (mv.from, mv.to) {
-            (Square::E1, Square::G1) => Square::H1,
-            (Square::E8, Square::G8) => Square::H8,
-            (Square::E1, Square::C1) => Square::A1,
-            (Square::E8, Square::C8) => Square::A8,
-            _ => mv.to,
-        };
+// Malformed values are reported and the tokens they follow ignored
+fn parse_go(words: &[&str], stm: Color, out: &UciOutput) -> Limits {
+    let mut limits = Limits::default();
+
+    // Static depth search
+    if let Some(d) = go_value(words, "depth", out) {
+        limits.depth = Some(d);
+        return limits;
     }
-    mv
+
+    if let Some(n) = go_value(words, "nodes", out) {
+        limits.nodes = Some(n);
+        return limits;
+    }
+
+    // Infinite search
+    if words.contains(&"infinite") {
+        return limits;
+    }
+
+    // Static time search
+    if let Some(t) = go_value(words, "movetime", out) {
+        limits.movetime = Some(t);
+        return limits;
+    }
+
+    // Time search
+    limits.wtime = go_value(words, "wtime", out);
+    limits.btime = go_value(words, "btime", out);
+    limits.winc = go_value(words, "winc", out).unwrap_or(0);
+    limits.binc = go_value(words, "binc", out).unwrap_or(0);
+    limits.movestogo = go_value(words, "movestogo", out);
+
+    // Sparse go lines still get a move in reasonable time instead of an endless search. Without
+    // our own clock the opponent's stands in for it, then our increment alone, then a fixed time.
+    let (own_time, their_time, inc) = match stm {
+        Color::White => (limits.wtime, limits.btime, limits.winc),
+        Color::Black => (limits.btime, limits.wtime, limits.binc),
+    };
+    if own_time.is_none() {
+        match (their_time, inc) {
+            (Some(time), _) if stm == Color::White => limits.wtime = Some(time),
+            (Some(time), _) => limits.btime = Some(time),
+            (None, 0) => limits.movetime = Some(DEFAULT_THINK_TIME),
+            (None, inc) => limits.movetime = Some(inc.saturating_sub(TIME_OVERHEAD).max(1)),
+        }
+    }
+
+    limits
+}
+
+// The value following `token`, if any
+fn go_value<T: FromStr>(words: &[&str], token: &str, out: &UciOutput) -> Option<T> {
+    let idx = words.iter().position(|&x| x == token)?;
+    let value = words.get(idx + 1)?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        out.send(format_args!("info string ignoring invalid {token} {value}"));
+    }
+    parsed
+}
+
+/// Castling as the king capturing its rook, like the board expects. Castling written as the king
+/// moving to its destination, e1g1 or b1g1 in Chess960, is read as the castle it stands for
+/// unless the king can go there anyway. Other moves are left as they are.
+pub fn check_castling_move(board: &Board, mv: Move) -> Move {
+    let stm = board.side_to_move();
+    if board.piece_on(mv.from) != Some(Piece::King)
+        || board.color_on(mv.from) != Some(stm)
+        || board.is_legal(mv)
+    {
+        return mv;
+    }
+
+    let back_rank = Rank::First.relative_to(stm);
+    let rights = board.castle_rights(stm);
+    [
+        (rights.short, cozy_chess::File::G),
+        (rights.long, cozy_chess::File::C),
+    ]
+    .into_iter()
+    .filter_map(|(rook, king_file)| {
+        let rook = rook?;
+        (mv.to == Square::new(king_file, back_rank)).then_some(Move {
+            from: mv.from,
+            to: Square::new(rook, back_rank),
+            promotion: None,
+        })
+    })
+    .find(|&castle| board.is_legal(castle))
+    .unwrap_or(mv)
 }
 
 pub fn reverse_castling_move(board: &Board, mut mv: Move) -> Move {
@@ -377,198 +579,401 @@ pub fn reverse_castling_move(board: &Board, mut mv: Move) -> Move {
     mv
 }
 
-fn read_input() -> Result<String, ()> {
-    let mut line = String::new();
-    let bytes_read = std::io::stdin().read_line(&mut line).unwrap();
+// The size Hash 0 picked
+fn auto_hash_info(out: &UciOutput, engine: &Engine) {
+    out.send(format_args!(
+        "info string Hash 0 is {} MB with the memory available",
+        engine.hash()
+    ));
+}
 
-    if bytes_read == 0 {
-        // EOF
-        return Err(());
+// None at the end of input, a broken connection counts as one
+fn read_input(input: &mut impl BufRead, out: &UciOutput) -> Option<String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            out.received(line.trim_end());
+            Some(line.trim().to_string())
+        }
     }
-
-    Ok(line.trim().to_string())
 }
 
-#[allow(clippy::borrowed_box, clippy::too_many_arguments)]
+// The search runs on its own thread so that stop, ponderhit and isready are answered meanwhile.
 fn go(
-    board: &Board,
-    st: SearchType,
-    tt: &mut TT,
-    nnue: &Box<NNUEState>,
-    history: &mut History,
-    game_history: &Vec<u64>,
-    stored_input: &mut Option<String>,
-    uci_options: &UCIOptions,
-) {
-    let mut search = Search::new(tt, nnue, history, game_history);
-    let mut secondary_searchers = vec![];
-
-    for _ in 0..uci_options.threads - 1 {
-        secondary_searchers.push(Search::new(tt, nnue, history, game_history));
-    }
-
+    engine: &mut Engine,
+    input: &mut impl BufRead,
+    out: &UciOutput,
+    limits: Limits,
+) -> AfterSearch {
+    let board = engine.board();
+    let any_move = pure_moves(board)
+        .first()
+        .map(|&mv| reverse_castling_move(board, mv));
+    super::crash::set_fallback(any_move);
+
+    store_stop(false);
+    SEARCHES.fetch_add(1, Ordering::AcqRel);
     std::thread::scope(|s| {
-        s.spawn(|| {
-            search.iterative_deepening::<true>(board, st, false);
+        let search = s.spawn(|| {
+            engine.search(limits);
+            SEARCHES.fetch_sub(1, Ordering::AcqRel);
         });
 
-        for searcher in secondary_searchers.iter_mut() {
-            s.spawn(|| {
-                searcher.iterative_deepening::<false>(board, st, false);
-            });
-        }
-
-        *stored_input = handle_stop_and_quit();
-    });
-
-    *history = search.info.history;
-    history.age_table();
-    tt.age();
-
-    crate::body::search::store_stop(false);
+        handle_stop_and_quit(input, out, || search.is_finished())
+    })
 }
 
-fn handle_stop_and_quit() -> Option<String> {
+fn handle_stop_and_quit(
+    input: &mut impl BufRead,
+    out: &UciOutput,
+    finished: impl Fn() -> bool,
+) -> AfterSearch {
     loop {
-        let line = read_input().unwrap();
+        let Some(line) = read_input(input, out) else {
+            StopHandle.stop();
+            return AfterSearch::Quit;
+        };
 
-        match line.as_str().trim() {
+        match line.as_str() {
             "stop" => {
-                crate::body::search::store_stop(true);
-                return None;
+                StopHandle.stop();
+                return AfterSearch::Continue;
+            }
+            "ponderhit" => store_ponder(false),
+            "quit" => {
+                StopHandle.stop();
+                return AfterSearch::Quit;
             }
-            "quit" => std::process::exit(0),
-            "isready" => println!("readyok"),
+            "isready" => out.send("readyok"),
+            // The search raises the stop flag before its bestmove and tidies up after it, a
+            // command sent right after the bestmove waits for the thread to end instead of being
+            // dropped
             _ => {
-                if crate::body::search::load_stop() {
-                    return Some(line);
+                if finished() || load_stop() {
+                    return AfterSearch::Command(line);
                 }
             }
         }
     }
 }
 
-fn set_position(
-    board: &mut Board,
-    nnue: &mut NNUEState,
-    game_history: &mut Vec<u64>,
-    board_set: &mut bool,
-    words: Vec<&str>,
-) {
-    if words[1] == "startpos" {
-        *board = Board::default();
-        *board_set = true;
-        *game_history = vec![board.hash()]
-    } else if words[1] == "fen" {
+fn set_position(engine: &mut Engine, out: &UciOutput, board_set: &mut bool, words: &[&str]) {
+    let moves_idx = words.iter().position(|&x| x == "moves");
+    let moves = moves_idx.map_or(&[][..], |idx| &words[idx + 1..]);
+
+    // frc <n> and dfrc <white> <black> set up numbered Chess960 start positions
+    let numbers = || -> Option<Vec<u32>> {
+        words[2..moves_idx.unwrap_or(words.len())]
+            .iter()
+            .map(|n| n.parse().ok())
+            .collect()
+    };
+    let fen = match words.get(1) {
+        Some(&"startpos") => START_FEN.to_string(),
         // Put together the split fen string
-        let mut fen = String::new();
-        for word in words.iter().skip(2) {
-            if *word == "moves" {
-                break;
+        Some(&"fen") => words[2..moves_idx.unwrap_or(words.len())].join(" "),
+        Some(&kind @ ("frc" | "dfrc")) => {
+            let fen = match (kind, numbers().as_deref()) {
+                ("frc", Some(&[n])) => chess960_fen(n, n),
+                ("dfrc", Some(&[white, black])) => chess960_fen(white, black),
+                ("frc", _) => Err("usage: position frc <number>".to_string()),
+                _ => Err("usage: position dfrc <white number> <black number>".to_string()),
+            };
+            match fen {
+                Ok(fen) => fen,
+                Err(e) => return out.send(format_args!("info string {e}")),
             }
-            fen.push_str(word);
-            fen.push(' ');
         }
+        _ => return,
+    };
 
-        if let Ok(b) = Board::from_fen(fen.trim(), false) {
-            *board = b;
-            *board_set = true;
-            *game_history = vec![board.hash()]
-        }
+    match engine.set_position(&fen, moves) {
+        Ok(()) => *board_set = true,
+        Err(e) => out.send(format_args!("info string {e}")),
     }
+}
 
-    if words.iter().any(|&x| x == "moves") && *board_set {
-        for word in words
-            .iter()
-            .skip(words.iter().position(|&x| x == "moves").unwrap() + 1)
-        {
-            let mut mv: Move = word.parse().unwrap();
-            mv = check_castling_move(board, mv);
-            board.play_unchecked(mv);
-            game_history.push(board.hash());
+#[cfg(test)]
+mod tests {
+    use super::super::script::Script;
+    use super::*;
+    use std::io::BufReader;
+
+    // Collects the session's output for inspection
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
 
-    if *board_set {
-        nnue.refresh(board);
+    #[test]
+    fn sparse_go() {
+        let buffer = Buffer::default();
+        let out = UciOutput::new(buffer.clone());
+        let go = |line: &str, stm| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            parse_go(&words, stm, &out)
+        };
+
+        let full = go(
+            "go wtime 60000 btime 50000 winc 1000 binc 500",
+            Color::Black,
+        );
+        assert_eq!(
+            (full.btime, full.binc, full.movetime),
+            (Some(50000), 500, None)
+        );
+
+        // The opponent's clock stands in for ours
+        let theirs = go("go wtime 60000 winc 1000", Color::Black);
+        assert_eq!((theirs.btime, theirs.movetime), (Some(60000), None));
+
+        let increment = go("go winc 2000", Color::White);
+        assert_eq!(increment.movetime, Some(2000 - TIME_OVERHEAD));
+
+        assert_eq!(go("go", Color::White).movetime, Some(DEFAULT_THINK_TIME));
+        assert_eq!(
+            go("go ponder", Color::White).movetime,
+            Some(DEFAULT_THINK_TIME)
+        );
+
+        // Explicit limits are left alone
+        assert_eq!(go("go infinite", Color::White), Limits::default());
+        assert_eq!(go("go depth 5", Color::White).depth, Some(5));
+        assert_eq!(go("go movetime 300", Color::White).movetime, Some(300));
+
+        // A malformed value is reported and ignored, not the whole command
+        let malformed = go("go wtime 60000 btime 50000 winc 1x binc 500", Color::White);
+        assert_eq!((malformed.wtime, malformed.winc), (Some(60000), 0));
+        assert_eq!(
+            go("go depth x", Color::White).movetime,
+            Some(DEFAULT_THINK_TIME)
+        );
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "info string ignoring invalid winc 1x\ninfo string ignoring invalid depth x\n"
+        );
     }
-}
 
-pub fn pretty_print(
-    depth: usize,
-    seldepth: usize,
-    score: i32,
-    nodes: u64,
-    timer: u128,
-    pv: String,
-) {
-    const DEFAULT: &str = "\x1b[0m";
-    const GREY: &str = "\x1b[90m";
-    const GREEN: &str = "\x1b[32m";
-    const BRIGHT_GREEN: &str = "\x1b[92m";
-    const BRIGHT_CYAN: &str = "\x1b[96m";
-    const BRIGHT_YELLOW: &str = "\x1b[93m";
-    const RED: &str = "\x1b[31m";
-    const BRIGHT_RED: &str = "\x1b[91m";
-
-    let t = match timer {
-        0..=999 => {
-            format!("{GREY}{}ms{DEFAULT}", timer as f64)
-        }
-        1000..=59_999 => {
-            format!("{GREY}{:.2}s{DEFAULT}", timer as f64 / 1000.)
-        }
-        60_000..=3_599_999 => {
-            format!("{GREY}{:.2}m{DEFAULT}", timer as f64 / 60_000.)
+    #[test]
+    fn session() {
+        // Cut short setoption commands are ignored
+        let input = "uci\nsetoption\nsetoption name Hash\nsetoption name SharedHash\n\
+                     setoption name Seed value\nsetoption name nodestime value 20000\n\
+                     setoption name nodestime value x\nisready\nposition fen 8/8 w\n\
+                     position startpos moves e2e4\nsee d7d5\nquit\nisready\n";
+        let buffer = Buffer::default();
+        let config = Config {
+            hash: 32,
+            ..Default::default()
+        };
+        run_session(input.as_bytes(), UciOutput::new(buffer.clone()), &config);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output
+            .lines()
+            .any(|line| line.starts_with("option name Hash type spin default 32 ")));
+        #[cfg(feature = "tune")]
+        assert!(output.contains("option name rfp_margin type spin"));
+
+        let lines: Vec<&str> = output
+            .lines()
+            .filter(|line| !line.starts_with("option"))
+            .collect();
+
+        // Nothing is answered after quit
+        let net = format!("info string net {}", Engine::new().net());
+        assert_eq!(
+            lines,
+            [
+                "id name Svart 6",
+                "id author Cristopher Torgrip",
+                &net,
+                "uciok",
+                "info string nodestime must be at most 10000",
+                "info string ignoring invalid nodestime x",
+                "readyok",
+                "info string Invalid FEN 8/8 w",
+                "0"
+            ]
+        );
+    }
+
+    #[test]
+    fn quit_during_search() {
+        // Sessions end once the search has stopped and played its move, whether on quit or at
+        // the end of input, under UCI or not
+        for input in [
+            "go\nquit\nisready\n",
+            "uci\nucinewgame\ngo infinite\nquit\nisready\n",
+            "uci\nucinewgame\ngo infinite\n",
+        ] {
+            let buffer = Buffer::default();
+            run_session(
+                input.as_bytes(),
+                UciOutput::new(buffer.clone()),
+                &Config::default(),
+            );
+
+            let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            assert!(
+                output.lines().last().unwrap().starts_with("bestmove "),
+                "{output}"
+            );
         }
-        3_600_000..=86_399_999 => {
-            format!("{GREY}{:.2}h{DEFAULT}", timer as f64 / 3_600_000.)
+    }
+
+    #[test]
+    fn debug_statistics() {
+        // Search statistics come just before the bestmove once debug is on
+        let input = "uci\ndebug on\nposition startpos\ngo depth 3\n";
+        let buffer = Buffer::default();
+        run_session(
+            input.as_bytes(),
+            UciOutput::new(buffer.clone()),
+            &Config::default(),
+        );
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().rev().take(7).collect();
+        assert!(lines[0].starts_with("bestmove "), "{output}");
+        assert!(lines[6].starts_with("info string beta cutoffs"), "{output}");
+    }
+
+    #[test]
+    fn ponder_moves() {
+        // The ponder move only goes along with the bestmove once the GUI turns pondering on
+        let input = "uci\nposition startpos\ngo depth 4\n#wait\n\
+                     setoption name Ponder value true\ngo depth 4\n";
+        let buffer = Buffer::default();
+        run_session(
+            BufReader::new(Script::new(input.as_bytes())),
+            UciOutput::new(buffer.clone()),
+            &Config::default(),
+        );
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let bestmoves: Vec<Vec<&str>> = output
+            .lines()
+            .filter(|l| l.starts_with("bestmove"))
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(bestmoves.len(), 2, "{output}");
+        assert_eq!(bestmoves[0].len(), 2, "{output}");
+        assert_eq!(bestmoves[1][2], "ponder", "{output}");
+
+        let mut board = Board::default();
+        board.play(bestmoves[1][1].parse().unwrap());
+        assert!(board.is_legal(bestmoves[1][3].parse().unwrap()));
+    }
+
+    #[test]
+    fn script_replay() {
+        // Commands sent during a search are dropped, unless the script waits for its bestmove
+        let script = "uci\nposition startpos\ngo depth 2\n#wait\ngo depth 1\n";
+        let buffer = Buffer::default();
+        run_session(
+            BufReader::new(Script::new(script.as_bytes())),
+            UciOutput::new(buffer.clone()),
+            &Config::default(),
+        );
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let bestmoves = output.lines().filter(|l| l.starts_with("bestmove")).count();
+        assert_eq!(bestmoves, 2, "{output}");
+    }
+
+    #[test]
+    fn chess960_positions() {
+        let out = UciOutput::new(Buffer::default());
+        let mut engine = Engine::new();
+        let mut board_set = false;
+
+        set_position(
+            &mut engine,
+            &out,
+            &mut board_set,
+            &["position", "frc", "518"],
+        );
+        assert_eq!(engine.board(), &Board::default());
+
+        let words = ["position", "dfrc", "0", "959", "moves", "b2b3"];
+        set_position(&mut engine, &out, &mut board_set, &words);
+        assert_eq!(
+            format!("{:#}", engine.board()),
+            "rkrnnqbb/pppppppp/8/8/8/1P6/P1PPPPPP/BBQNNRKR b HFca - 0 1"
+        );
+
+        // Out of range or missing numbers leave the position alone
+        for words in [&["position", "frc", "960"][..], &["position", "dfrc", "5"]] {
+            set_position(&mut engine, &out, &mut board_set, words);
+            assert_eq!(engine.board().fullmove_number(), 1);
+            assert_eq!(engine.board().side_to_move(), cozy_chess::Color::Black);
         }
-        86_400_000.. => {
-            format!("{GREY}{:.2}d{DEFAULT}", timer as f64 / 86_400_000.)
+    }
+
+    #[test]
+    fn castling_notation() {
+        let mut engine = Engine::new();
+        let standard = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        for moves in [["e1g1", "e8c8"], ["e1h1", "e8a8"]] {
+            engine.set_position(standard, &moves).unwrap();
+            assert_eq!(
+                format!("{}", engine.board()),
+                "2kr3r/8/8/8/8/8/8/R4RK1 w - - 2 2"
+            );
         }
-    };
 
-    let mate = ((MATE - score) / 2) + ((MATE - score) & 1);
-    let norm_score = score as f32 / 100.;
-    let sc = match score {
-        501..=15_000 => format!("{BRIGHT_CYAN}+{:.2}{DEFAULT}", norm_score),
-        101..=500 => format!("{GREEN}+{:.2}{DEFAULT}", norm_score),
-        11..=100 => format!("{BRIGHT_GREEN}+{:.2}{DEFAULT}", norm_score),
-        0..=10 => format!("{GREY}+{:.2}{DEFAULT}", norm_score),
-        -10..=-1 => format!("{GREY}{:.2}{DEFAULT}", norm_score),
-        -100..=-11 => format!("{BRIGHT_RED}{:.2}{DEFAULT}", norm_score),
-        -15000..=-101 => format!("{RED}{:.2}{DEFAULT}", norm_score),
-
-        15_001..=32_000 => format!("{BRIGHT_YELLOW}#{}{DEFAULT}", mate),
-        -32_000..=-15_001 => format!("{BRIGHT_YELLOW}#-{}{DEFAULT}", mate),
-
-        _ => unreachable!(),
-    };
+        // Chess960, the king on b1 and its rooks on a1 and h1
+        let chess960 = "rk5r/8/8/8/8/8/8/RK5R w HAha - 0 1";
+        for moves in [["b1g1", "b8a8"], ["b1h1", "b8a8"]] {
+            engine.set_position(chess960, &moves).unwrap();
+            assert_eq!(
+                format!("{}", engine.board()),
+                "2kr3r/8/8/8/8/8/8/R4RK1 w - - 2 2"
+            );
+        }
 
-    let d = format!("{}/{}", depth, seldepth);
+        // A king that can step to c1 does, rather than castle
+        engine.set_position(chess960, &["b1c1"]).unwrap();
+        assert_eq!(engine.board().king(Color::White), Square::C1);
+        assert_eq!(engine.board().piece_on(Square::A1), Some(Piece::Rook));
+        assert!(engine.set_position(chess960, &["b1d1"]).is_err());
+    }
 
-    let timer = timer.max(1);
-    let knps: String;
-    let n: String;
-    if nodes < 1000 {
-        knps = format!(
-            "{GREY}{}no/s{DEFAULT}",
-            nodes / (timer as u64 / 1000).max(1)
+    #[test]
+    fn debug_log() {
+        let path = std::env::temp_dir().join(format!("svart-debug-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let input = format!(
+            "uci\nsetoption name Debug Log File value {}\nisready\nsetoption name Debug Log File value\nisready\n",
+            path.display()
+        );
+        run_session(
+            input.as_bytes(),
+            UciOutput::new(Buffer::default()),
+            &Config::default(),
         );
-        n = format!("{nodes}");
-    } else {
-        knps = format!("{GREY}{}kn/s{DEFAULT}", nodes / timer as u64);
-        n = format!("{}k", nodes / 1000);
-    }
-
-    let str = pv.as_str();
-    let pv_width = 125;
-    let pv = if str.len() > pv_width {
-        str[..pv_width].to_string()
-    } else {
-        str.to_string()
-    };
 
-    println!("{d: <7} {sc: <8} {n: <8} {knps: <18} {t: <15} {pv}");
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().map(|line| &line[15..]).collect();
+        assert_eq!(
+            lines,
+            [
+                ">> isready",
+                "<< readyok",
+                ">> setoption name Debug Log File value"
+            ]
+        );
+    }
 }
@@ -1,3 +1,21 @@
+pub mod annotate;
+pub mod batch;
 pub mod bench;
+pub mod clock;
+pub mod config;
+pub mod crash;
+pub mod genfens;
 pub mod handler;
-mod timeman;
+pub mod packnet;
+pub mod perft;
+pub mod pgn;
+pub mod play;
+pub mod reporter;
+pub mod san;
+pub mod script;
+pub mod selfplay;
+pub mod selftest;
+pub mod server;
+pub mod signals;
+pub mod timeman;
+pub mod trace;
@@ -0,0 +1,160 @@
+// packnet <directory> <output> [--i8]
+// Packs the raw parameter files a training run exports, the same ones embedded from
+// src/body/nnue/net, into a single net file with a header that the EvalFile option can load.
+// The hidden size is read off the size of the feature biases, and nets with hidden layers after
+// the feature transformer have layer1_weights.bin and layer1_bias.bin, then layer2 and so on,
+// their sizes read off the biases. Nets whose first layer only has inputs for one accumulator
+// are single perspective. --i8 stores the feature weights as i8s, provided every one of them
+// fits.
+use crate::body::nnue::header::{
+    Activation, Architecture, Layers, NetHeader, Quantization, MAX_LAYERS,
+};
+
+use std::path::Path;
+
+const USAGE: &str = "info string usage: packnet <directory> <output> [--i8]";
+
+// In the order they follow the header, with the hidden layers' files between
+const FEATURE_PARTS: [&str; 2] = ["feature_weights.bin", "feature_bias.bin"];
+const OUTPUT_PARTS: [&str; 2] = ["output_weights.bin", "output_bias.bin"];
+
+pub fn packnet(args: &[String]) {
+    let (directory, output, quantization) = match args {
+        [directory, output] => (directory, output, Quantization::I16),
+        [directory, output, flag] if flag == "--i8" => (directory, output, Quantization::I8),
+        _ => {
+            println!("{USAGE}");
+            return;
+        }
+    };
+
+    match pack(Path::new(directory), quantization).and_then(|(header, net)| {
+        std::fs::write(output, net).map_err(|e| format!("could not write {output}: {e}"))?;
+        Ok(header)
+    }) {
+        Ok(header) => println!("info string wrote {output}, net {header}"),
+        Err(e) => println!("info string {e}"),
+    }
+}
+
+/// The header and the whole net file for the parameter files in `directory`, the feature
+/// weights quantized as asked
+pub fn pack(directory: &Path, quantization: Quantization) -> Result<(NetHeader, Vec<u8>), String> {
+    let read = |name: &str| {
+        let path = directory.join(name);
+        std::fs::read(&path).map_err(|e| format!("could not read {}: {e}", path.display()))
+    };
+
+    let mut parts = FEATURE_PARTS
+        .iter()
+        .map(|name| read(name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut sizes = vec![];
+    for n in 1..=MAX_LAYERS {
+        if !directory.join(format!("layer{n}_bias.bin")).exists() {
+            break;
+        }
+        parts.push(read(&format!("layer{n}_weights.bin"))?);
+        parts.push(read(&format!("layer{n}_bias.bin"))?);
+        sizes.push(parts[parts.len() - 1].len() / 2);
+    }
+
+    for name in OUTPUT_PARTS {
+        parts.push(read(name)?);
+    }
+
+    if quantization == Quantization::I8 {
+        parts[0] = narrow(&parts[0])?;
+    }
+
+    let parts: Vec<&[u8]> = parts.iter().map(Vec::as_slice).collect();
+    let hidden = parts[1].len() / 2;
+    let first_outputs = sizes.first().copied().unwrap_or(1);
+    let architecture = if parts[2].len() / 2 == hidden * first_outputs {
+        Architecture::Single
+    } else {
+        Architecture::Perspective
+    };
+    let header = NetHeader::new(
+        architecture,
+        hidden,
+        Layers::new(&sizes)?,
+        Activation::CReLU,
+        quantization,
+        &parts,
+    );
+    header.verify(&parts)?;
+
+    let mut net = header.to_bytes();
+    for part in parts {
+        net.extend_from_slice(part);
+    }
+    Ok((header, net))
+}
+
+// Little endian i16s to i8s
+fn narrow(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    bytes
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let value = i16::from_le_bytes([bytes[0], bytes[1]]);
+            i8::try_from(value)
+                .map(|value| value as u8)
+                .map_err(|_| format!("feature weight {i} is {value}, too wide for an i8"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::nnue::inference;
+
+    #[test]
+    fn pack_embedded() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/body/nnue/net");
+        let (header, net) = pack(&directory, Quantization::I16).unwrap();
+
+        // The embedded net packs into a file with the embedded header
+        let embedded = std::fs::read(directory.join("header.bin")).unwrap();
+        assert_eq!(header.to_bytes(), embedded);
+        assert_eq!(inference::net_header(), header);
+        assert_eq!(inference::load_net(&net), Ok(header));
+
+        let mut truncated = net.clone();
+        truncated.pop();
+        assert!(inference::load_net(&truncated).is_err());
+
+        inference::use_embedded_net();
+
+        // Hidden layers' files are packed between the feature transformer's and the output's
+        let layered = std::env::temp_dir().join(format!("svart_packnet_{}", std::process::id()));
+        std::fs::create_dir_all(&layered).unwrap();
+        for (name, size) in [
+            ("feature_weights.bin", 768 * 8 * 2),
+            ("feature_bias.bin", 8 * 2),
+            ("layer1_weights.bin", 16 * 4 * 2),
+            ("layer1_bias.bin", 4 * 2),
+            ("output_weights.bin", 4 * 2),
+            ("output_bias.bin", 2),
+        ] {
+            std::fs::write(layered.join(name), vec![1; size]).unwrap();
+        }
+        let (header, net) = pack(&layered, Quantization::I16).unwrap();
+        assert_eq!(header.layers.sizes(), [4]);
+        assert_eq!(header.architecture, Architecture::Perspective);
+        assert_eq!(NetHeader::parse(&net), Ok(header));
+
+        // Half the first layer's inputs is a single perspective net
+        std::fs::write(layered.join("layer1_weights.bin"), vec![1; 8 * 4 * 2]).unwrap();
+        let (header, _) = pack(&layered, Quantization::I16).unwrap();
+        assert_eq!(header.architecture, Architecture::Single);
+        std::fs::remove_dir_all(&layered).unwrap();
+
+        // Weights wider than an i8 can't be packed as one
+        assert_eq!(narrow(&[5, 0, 0xfb, 0xff]), Ok(vec![5, 0xfb]));
+        assert!(narrow(&[0, 1]).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+// go perft <depth> [hash <mb>]
+// Counts the leaf nodes of the legal move tree, for validating move generation. The root moves
+// are split across the Threads setting and their subtotals printed like a divide. Subtrees
+// reached again by transposition are looked up in a hash table instead of walked, hash 0 walks
+// every one of them for strict validation.
+//
+// perft suite [depth] [hash <mb>]
+// Checks the leaf counts of a bundled suite of standard positions, Kiwipete, Chess960 starts
+// and en passant, pin and promotion edge cases among them, up to the given depth. Exits with a
+// nonzero code on any mismatch, so it can gate changes to move generation.
+use super::handler::{reverse_castling_move, UciOutput};
+use crate::api::MAX_HASH;
+use crate::body::tt::table_index;
+
+use cozy_chess::{Board, Move};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const USAGE: &str = "info string usage: go perft <depth> [hash <mb>]";
+
+const SUITE_USAGE: &str = "info string usage: perft suite [depth] [hash <mb>]";
+
+pub const DEFAULT_PERFT_HASH: u32 = 64;
+
+// A FEN per line, followed by ;D<depth> <leaf count> fields
+const SUITE: &str = include_str!("perft.epd");
+
+// Entries are the zobrist key xored with the data, and the data: a torn write from another
+// thread fails the key check instead of returning a wrong count
+pub struct PerftTable {
+    entries: Vec<[AtomicU64; 2]>,
+}
+
+impl PerftTable {
+    #[must_use]
+    pub fn new(mb: u32) -> Self {
+        let size = mb as usize * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>();
+        let entries = (0..size)
+            .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+            .collect();
+
+        Self { entries }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        table_index(key, self.entries.len())
+    }
+
+    // Node counts take the upper 56 bits of the data, the depth the lower 8
+    fn probe(&self, key: u64, depth: usize) -> Option<u64> {
+        let [check, data] = &self.entries[self.index(key)];
+        let data = data.load(Ordering::Relaxed);
+
+        let hit = check.load(Ordering::Relaxed) ^ data == key && data & 0xFF == depth as u64;
+        hit.then_some(data >> 8)
+    }
+
+    fn store(&self, key: u64, depth: usize, nodes: u64) {
+        let [check, entry] = &self.entries[self.index(key)];
+        let data = nodes << 8 | depth as u64;
+
+        check.store(key ^ data, Ordering::Relaxed);
+        entry.store(data, Ordering::Relaxed);
+    }
+}
+
+/// Leaf nodes at `depth`, counting the legal moves of the last ply instead of playing them
+#[must_use]
+pub fn perft(board: &Board, depth: usize, table: Option<&PerftTable>) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let mut nodes = 0;
+    if depth == 1 {
+        board.generate_moves(|moves| {
+            nodes += moves.len() as u64;
+            false
+        });
+        return nodes;
+    }
+
+    if let Some(nodes) = table.and_then(|t| t.probe(board.hash(), depth)) {
+        return nodes;
+    }
+
+    board.generate_moves(|moves| {
+        for mv in moves {
+            let mut child = board.clone();
+            child.play_unchecked(mv);
+            nodes += perft(&child, depth - 1, table);
+        }
+        false
+    });
+
+    if let Some(table) = table {
+        table.store(board.hash(), depth, nodes);
+    }
+    nodes
+}
+
+/// Leaf nodes under each root move, with the root moves shared out between `threads` threads
+#[must_use]
+pub fn divide(board: &Board, depth: usize, threads: usize, hash: u32) -> Vec<(Move, u64)> {
+    let mut root_moves = vec![];
+    board.generate_moves(|moves| {
+        root_moves.extend(moves);
+        false
+    });
+
+    let table = (hash > 0).then(|| PerftTable::new(hash.min(MAX_HASH)));
+    let next = AtomicUsize::new(0);
+    let counts = Mutex::new(vec![0; root_moves.len()]);
+
+    let worker = || loop {
+        let idx = next.fetch_add(1, Ordering::Relaxed);
+        let Some(&mv) = root_moves.get(idx) else {
+            break;
+        };
+
+        let mut child = board.clone();
+        child.play_unchecked(mv);
+        let nodes = perft(&child, depth.saturating_sub(1), table.as_ref());
+        counts.lock().unwrap()[idx] = nodes;
+    };
+
+    // Like batches, a single thread stays on the caller's
+    let threads = threads.clamp(1, root_moves.len().max(1));
+    if threads == 1 {
+        worker();
+    } else {
+        std::thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(worker);
+            }
+        });
+    }
+
+    root_moves
+        .into_iter()
+        .zip(counts.into_inner().unwrap())
+        .collect()
+}
+
+pub fn go_perft(board: &Board, out: &UciOutput, words: &[&str], threads: usize) {
+    let value = |token: &str| -> Option<&str> {
+        let idx = words.iter().position(|&x| x == token)?;
+        words.get(idx + 1).copied()
+    };
+
+    let Some(Ok(depth)) = value("perft").map(str::parse::<usize>) else {
+        out.send(USAGE);
+        return;
+    };
+    let hash = match value("hash").map(str::parse::<u32>) {
+        None => DEFAULT_PERFT_HASH,
+        Some(Ok(hash)) => hash,
+        Some(Err(_)) => {
+            out.send(USAGE);
+            return;
+        }
+    };
+
+    let start = super::clock::Instant::now();
+    let counts = divide(board, depth.max(1), threads, hash);
+    let elapsed = start.elapsed().as_millis().max(1) as u64;
+
+    let mut total = 0;
+    for (mv, nodes) in counts {
+        out.send(format_args!(
+            "{}: {nodes}",
+            reverse_castling_move(board, mv)
+        ));
+        total += nodes;
+    }
+
+    out.send("");
+    out.send(format_args!("Nodes searched: {total}"));
+    out.send(format_args!(
+        "info string perft {depth} in {elapsed} ms, {} nps",
+        total * 1000 / elapsed
+    ));
+}
+
+pub fn perft_suite(args: &[String]) {
+    let mut max_depth = usize::MAX;
+    let mut hash = DEFAULT_PERFT_HASH;
+
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let parsed = match arg.as_str() {
+            "hash" => rest
+                .next()
+                .and_then(|h| h.parse().ok())
+                .map(|h| hash = h)
+                .is_some(),
+            arg => arg.parse().map(|d| max_depth = d).is_ok(),
+        };
+
+        if !parsed {
+            println!("{SUITE_USAGE}");
+            std::process::exit(2);
+        }
+    }
+
+    let start = super::clock::Instant::now();
+    let (checks, failures) = run_suite(SUITE, max_depth, hash);
+    println!(
+        "info string perft suite: {checks} checks, {failures} failures in {} ms",
+        start.elapsed().as_millis()
+    );
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+// Checks every count of the suite up to the depth, returning the number of checks and of
+// failures and printing each failure
+fn run_suite(suite: &str, max_depth: usize, hash: u32) -> (usize, usize) {
+    let table = (hash > 0).then(|| PerftTable::new(hash.min(MAX_HASH)));
+    let mut checks = 0;
+    let mut failures = 0;
+
+    for line in suite.lines().filter(|line| !line.trim().is_empty()) {
+        let (board, counts) = match parse_epd(line) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                println!("info string {e}");
+                failures += 1;
+                continue;
+            }
+        };
+
+        for (depth, expected) in counts.into_iter().filter(|&(d, _)| d <= max_depth) {
+            checks += 1;
+            let nodes = perft(&board, depth, table.as_ref());
+            if nodes != expected {
+                println!("info string perft mismatch: {board} depth {depth} {nodes}, expected {expected}");
+                failures += 1;
+            }
+        }
+    }
+
+    (checks, failures)
+}
+
+fn parse_epd(line: &str) -> Result<(Board, Vec<(usize, u64)>), String> {
+    let mut fields = line.split(';');
+    let fen = fields.next().unwrap_or_default().trim();
+    let board = fen
+        .parse::<Board>()
+        .map_err(|_| format!("invalid perft suite fen {fen}"))?;
+
+    let counts = fields
+        .map(|field| {
+            let (depth, nodes) = field.trim().split_once(' ').unwrap_or_default();
+            match (depth.strip_prefix('D').map(str::parse), nodes.parse()) {
+                (Some(Ok(depth)), Ok(nodes)) => Ok((depth, nodes)),
+                _ => Err(format!("invalid perft suite count {field} for {fen}")),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok((board, counts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perft_counts() {
+        let kiwipete: Board =
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"
+                .parse()
+                .unwrap();
+
+        assert_eq!(perft(&Board::default(), 4, None), 197_281);
+        assert_eq!(perft(&kiwipete, 3, None), 97_862);
+
+        let table = PerftTable::new(1);
+        assert_eq!(perft(&kiwipete, 3, Some(&table)), 97_862);
+        assert_eq!(perft(&kiwipete, 3, Some(&table)), 97_862);
+
+        // Threads and the table change nothing but the speed
+        let counts = divide(&kiwipete, 3, 3, 1);
+        assert_eq!(counts.len(), 48);
+        assert_eq!(counts.iter().map(|&(_, n)| n).sum::<u64>(), 97_862);
+        assert_eq!(divide(&kiwipete, 3, 1, 0), counts);
+        assert_eq!(divide(&kiwipete, 3, 1, DEFAULT_PERFT_HASH), counts);
+
+        // Keys are spread over the whole table, the largest landing on its last entry
+        assert_eq!(table_index(0, 10), 0);
+        assert_eq!(table_index(u64::MAX, 10), 9);
+    }
+
+    #[test]
+    fn perft_suite() {
+        // The shallow end of the bundled suite, the whole of it takes a release build
+        assert_eq!(run_suite(SUITE, 3, 1), (33, 0));
+
+        let (board, counts) = parse_epd("4k3/8/8/8/8/8/8/4K3 w - - 0 1 ;D1 5 ;D2 25").unwrap();
+        assert_eq!(board, "4k3/8/8/8/8/8/8/4K3 w - - 0 1".parse().unwrap());
+        assert_eq!(counts, [(1, 5), (2, 25)]);
+        assert_eq!(
+            run_suite("4k3/8/8/8/8/8/8/4K3 w - - 0 1 ;D2 26", 3, 0),
+            (1, 1)
+        );
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K3 w - - 0 1 ;D1").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+// PGN reading and writing
+// Games are read one at a time so that multi-gigabyte database dumps can be streamed.
+// Variations and NAGs are skipped, `[%eval ...]` comments are kept as white relative
+// centipawn scores of the position reached by the move they follow. Games written out use the
+// same comments, with the movetext wrapped to the export format's line length.
+use super::san::parse_san;
+use crate::definitions::{MATE, MATE_IN};
+
+use cozy_chess::{Board, Move};
+use std::io::{self, BufRead};
+
+pub const LINE_LENGTH: usize = 80;
+
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub start: Board,
+    // Each move with the eval of the position after it, if annotated
+    pub moves: Vec<(Move, Option<i32>)>,
+    // 1.0 white win, 0.5 draw, 0.0 black win, None if unfinished
+    pub result: Option<f32>,
+}
+
+// Tag pairs and movetext of a game, before the moves are replayed
+type RawGame = (Vec<(String, String)>, String);
+
+pub struct PgnReader<R: BufRead> {
+    lines: io::Lines<R>,
+    // The first tag line of the next game, read while looking for the end of the previous one
+    pending: Option<String>,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            pending: None,
+        }
+    }
+
+    fn next_raw(&mut self) -> Option<io::Result<RawGame>> {
+        let mut tags = vec![];
+        let mut movetext = String::new();
+
+        loop {
+            let line = match self.pending.take() {
+                Some(line) => line,
+                None => match self.lines.next() {
+                    Some(Ok(line)) => line,
+                    Some(Err(e)) => return Some(Err(e)),
+                    None if tags.is_empty() && movetext.is_empty() => return None,
+                    None => return Some(Ok((tags, movetext))),
+                },
+            };
+            let line = line.trim();
+
+            if line.starts_with('[') && !line.starts_with("[%") {
+                if !movetext.trim().is_empty() {
+                    self.pending = Some(line.to_string());
+                    return Some(Ok((tags, movetext)));
+                }
+
+                if let Some(tag) = parse_tag(line) {
+                    tags.push(tag);
+                }
+            } else if !line.starts_with('%') {
+                movetext.push_str(line);
+                movetext.push('\n');
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    // Games that can't be replayed are returned as errors so callers can count them
+    type Item = io::Result<Result<PgnGame, String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(
+            self.next_raw()?
+                .map(|(tags, movetext)| parse_game(&tags, &movetext)),
+        )
+    }
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (key, value) = inner.split_once(' ')?;
+    Some((key.to_string(), value.trim().trim_matches('"').to_string()))
+}
+
+fn parse_game(tags: &[(String, String)], movetext: &str) -> Result<PgnGame, String> {
+    let tag = |key: &str| tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+
+    if let Some(variant) = tag("Variant") {
+        if variant != "Standard" {
+            return Err(format!("unsupported variant {variant}"));
+        }
+    }
+
+    let start = match tag("FEN") {
+        Some(fen) => Board::from_fen(fen, false).map_err(|_| format!("invalid FEN {fen}"))?,
+        None => Board::default(),
+    };
+    let result = match tag("Result") {
+        Some("1-0") => Some(1.),
+        Some("0-1") => Some(0.),
+        Some("1/2-1/2") => Some(0.5),
+        _ => None,
+    };
+
+    let mut board = start.clone();
+    let mut moves: Vec<(Move, Option<i32>)> = vec![];
+    let mut chars = movetext.chars();
+    let mut token = String::new();
+    let mut depth = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth > 0 => {}
+            c if c.is_whitespace() || c == '{' || c == ';' => {
+                if let Some(mv) = play_token(&mut board, &std::mem::take(&mut token))? {
+                    moves.push((mv, None));
+                }
+
+                if c == '{' {
+                    let comment: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    if let (Some(eval), Some(last)) = (parse_eval(&comment), moves.last_mut()) {
+                        last.1 = Some(eval);
+                    }
+                } else if c == ';' {
+                    chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if let Some(mv) = play_token(&mut board, &token)? {
+        moves.push((mv, None));
+    }
+
+    Ok(PgnGame {
+        tags: tags.to_vec(),
+        start,
+        moves,
+        result,
+    })
+}
+
+// Plays a movetext token if it's a move, skipping move numbers, results and NAGs
+fn play_token(board: &mut Board, token: &str) -> Result<Option<Move>, String> {
+    let san = token.rsplit('.').next().unwrap_or_default();
+    if san.is_empty() || san.starts_with('$') || matches!(san, "1-0" | "0-1" | "1/2-1/2" | "*") {
+        return Ok(None);
+    }
+
+    let mv = parse_san(board, san).ok_or_else(|| format!("illegal move {san}"))?;
+    board.play_unchecked(mv);
+    Ok(Some(mv))
+}
+
+// [%eval 0.35] in pawns or [%eval #-3] for mates, both from white's perspective
+fn parse_eval(comment: &str) -> Option<i32> {
+    let start = comment.find("[%eval")? + "[%eval".len();
+    let value = comment[start..].split([']', ',']).next()?.trim();
+
+    if let Some(mate) = value.strip_prefix('#') {
+        let mate: i32 = mate.parse().ok()?;
+        Some(if mate > 0 { MATE - mate } else { -MATE - mate })
+    } else {
+        let pawns: f64 = value.parse().ok()?;
+        Some((pawns * 100.).round() as i32)
+    }
+}
+
+/// Moves to mate for white relative mate scores, negative when black mates
+#[must_use]
+pub fn mate_moves(score: i32) -> Option<i32> {
+    let moves = (MATE - score.abs() + 1) / 2;
+    (score.abs() >= MATE_IN).then_some(if score > 0 { moves } else { -moves })
+}
+
+/// The value of an `[%eval]` comment for a white relative score
+#[must_use]
+pub fn pgn_eval(score: i32) -> String {
+    match mate_moves(score) {
+        Some(moves) => format!("#{moves}"),
+        None => format!("{:.2}", f64::from(score) / 100.0),
+    }
+}
+
+/// Breaks movetext tokens into lines of at most `LINE_LENGTH` characters
+#[must_use]
+pub fn wrap_movetext(text: &str) -> String {
+    let mut wrapped = String::new();
+    let mut line_length = 0;
+
+    for word in text.split(' ') {
+        if line_length > 0 && line_length + 1 + word.len() > LINE_LENGTH {
+            wrapped.push('\n');
+            line_length = 0;
+        } else if line_length > 0 {
+            wrapped.push(' ');
+            line_length += 1;
+        }
+        wrapped.push_str(word);
+        line_length += word.len();
+    }
+
+    wrapped.push('\n');
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pgn_reading() {
+        let pgn = r#"[Event "Rated Blitz game"]
+[Result "0-1"]
+
+1. e4 { [%eval 0.2] [%clk 0:03:00] } 1... e5 { [%eval 0.25] } 2. Qh5?! (2. Nf3 Nc6) 2... Nc6 $1
+3. Bc4 Nf6?? { [%eval #1] } 4. Qxf7# 0-1
+
+[Event "Second game"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+[Result "1/2-1/2"]
+
+1. e4 Kd7 { [%eval -12.5] } 1/2-1/2
+"#;
+        let games: Vec<_> = PgnReader::new(pgn.as_bytes())
+            .map(|g| g.unwrap().unwrap())
+            .collect();
+        assert_eq!(games.len(), 2);
+
+        let game = &games[0];
+        assert_eq!(game.result, Some(0.));
+        assert_eq!(
+            game.tags[0],
+            ("Event".to_string(), "Rated Blitz game".to_string())
+        );
+        assert_eq!(game.moves.len(), 7);
+        assert_eq!(game.moves[0].1, Some(20));
+        assert_eq!(game.moves[1].1, Some(25));
+        assert_eq!(game.moves[2].1, None);
+        assert_eq!(game.moves[5].1, Some(MATE - 1));
+        assert_eq!(game.moves[6].0.to_string(), "h5f7");
+
+        let game = &games[1];
+        assert_eq!(game.result, Some(0.5));
+        assert_eq!(game.moves.len(), 2);
+        assert_eq!(game.moves[1].1, Some(-1250));
+
+        assert_eq!(pgn_eval(MATE - 1), "#1");
+        assert_eq!(pgn_eval(-MATE + 4), "#-2");
+        assert_eq!(
+            parse_eval(&format!("[%eval {}]", pgn_eval(-1250))),
+            Some(-1250)
+        );
+
+        // Illegal moves make the game unreadable rather than ending the stream
+        let pgn = "[Result \"*\"]\n\n1. e5 *\n";
+        assert!(PgnReader::new(pgn.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap()
+            .is_err());
+    }
+}
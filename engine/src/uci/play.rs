@@ -0,0 +1,277 @@
+// play [movetime] [--black] [--depth <d>]
+// A game against the engine in the terminal, for when there's no GUI around. Moves are entered
+// in SAN or UCI notation, and between moves the commands below change the engine's strength or
+// take moves back.
+use super::{
+    config::Config,
+    handler::check_castling_move,
+    pgn::mate_moves,
+    san::{parse_move, to_san},
+    selfplay::result_string,
+};
+use crate::api::{game_over, Engine, Limits, START_FEN};
+
+use cozy_chess::{Board, Color, File, Move, Rank, Square};
+use std::io::BufRead;
+
+const USAGE: &str = "info string usage: play [movetime] [--black] [--depth <d>]";
+
+const HELP: &str = "Enter moves as e4, Nf3, O-O or e2e4. Other commands:
+  hint          the move the engine would play
+  undo          take back your last move
+  time <ms>     engine time per move
+  depth <d>     engine depth limit, 0 for none
+  flip          play the other side
+  new           start a new game
+  quit          leave";
+
+pub const DEFAULT_MOVETIME: u64 = 1000;
+
+struct Game {
+    engine: Engine,
+    moves: Vec<Move>,
+    human: Color,
+    movetime: u64,
+    depth: Option<usize>,
+}
+
+impl Game {
+    fn board(&self) -> Board {
+        let mut board = Board::default();
+        for &mv in &self.moves {
+            board.play_unchecked(mv);
+        }
+        board
+    }
+
+    // The result and how it came about once the game is over
+    fn result(&self) -> Option<String> {
+        let mut board = Board::default();
+        let mut history = vec![board.hash()];
+        for &mv in &self.moves {
+            board.play_unchecked(mv);
+            history.push(board.hash());
+        }
+
+        let (result, reason) = game_over(&board, &history)?;
+        Some(format!(
+            "{} by {}",
+            result_string(result),
+            reason.description()
+        ))
+    }
+
+    // Best move for the side to move, with its score from that side's perspective
+    fn search(&mut self) -> Option<(Move, i32)> {
+        let moves: Vec<String> = self.moves.iter().map(Move::to_string).collect();
+        let words: Vec<&str> = moves.iter().map(String::as_str).collect();
+        self.engine
+            .set_position(START_FEN, &words)
+            .expect("only legal moves are played");
+
+        let limits = Limits {
+            depth: self.depth,
+            movetime: self.depth.is_none().then_some(self.movetime),
+            ..Default::default()
+        };
+        let result = self.engine.search(limits)?;
+        Some((
+            check_castling_move(self.engine.board(), result.best_move),
+            result.score,
+        ))
+    }
+
+    // The engine replies if it's its turn, then the board is shown
+    fn reply(&mut self) {
+        let board = self.board();
+        if board.side_to_move() != self.human && self.result().is_none() {
+            if let Some((mv, score)) = self.search() {
+                println!("Svart plays {} ({})", to_san(&board, mv), score_text(score));
+                self.moves.push(mv);
+            }
+        }
+
+        println!("{}", render(&self.board(), self.human));
+        if let Some(result) = self.result() {
+            println!("Game over: {result}. Type new to play again.");
+        }
+    }
+}
+
+pub fn play(args: &[String], config: &Config) {
+    let mut movetime = DEFAULT_MOVETIME;
+    let mut human = Color::White;
+    let mut depth = None;
+
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let parsed = match arg.as_str() {
+            "--black" => {
+                human = Color::Black;
+                true
+            }
+            "--depth" => rest
+                .next()
+                .and_then(|d| d.parse().ok())
+                .map(|d| depth = Some(d))
+                .is_some(),
+            arg => arg.parse().map(|m| movetime = m).is_ok(),
+        };
+
+        if !parsed {
+            println!("{USAGE}");
+            return;
+        }
+    }
+
+    let mut engine = Engine::new();
+    if let Err(e) = config.apply(&mut engine) {
+        println!("info string {e}");
+    }
+    engine.new_game();
+    let mut game = Game {
+        engine,
+        moves: vec![],
+        human,
+        movetime,
+        depth,
+    };
+
+    println!("{HELP}\n");
+    game.reply();
+
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = words.first() else {
+            continue;
+        };
+        let value = words.get(1).and_then(|v| v.parse::<u64>().ok());
+        let board = game.board();
+
+        match (command, value) {
+            ("quit" | "exit", _) => break,
+            ("help", _) => println!("{HELP}"),
+            ("time", Some(ms)) => {
+                game.movetime = ms.max(1);
+                game.depth = None;
+                println!("Svart now thinks {} ms per move", game.movetime);
+            }
+            ("depth", Some(0)) => {
+                game.depth = None;
+                println!("Svart now thinks {} ms per move", game.movetime);
+            }
+            ("depth", Some(d)) => {
+                game.depth = Some(d as usize);
+                println!("Svart now searches {d} plies deep");
+            }
+            ("new", _) => {
+                game.moves.clear();
+                game.engine.new_game();
+                game.reply();
+            }
+            ("flip", _) => {
+                game.human = !game.human;
+                game.reply();
+            }
+            ("undo", _) => {
+                // Back to the last position the human was to move in, moves alternating
+                let parity = usize::from(game.human == Color::Black);
+                match (0..game.moves.len()).rev().find(|ply| ply % 2 == parity) {
+                    Some(ply) => {
+                        game.moves.truncate(ply);
+                        game.reply();
+                    }
+                    None => println!("Nothing to take back"),
+                }
+            }
+            ("hint", _) if game.result().is_none() => {
+                if let Some((mv, score)) = game.search() {
+                    println!("Hint: {} ({})", to_san(&board, mv), score_text(score));
+                }
+            }
+            _ if game.result().is_some() => println!("The game is over, type new to play again"),
+            _ => match parse_move(&board, command) {
+                Some(mv) if board.side_to_move() == game.human => {
+                    game.moves.push(mv);
+                    game.reply();
+                }
+                Some(_) => println!("It's Svart's move, type flip to play this side"),
+                None => println!("Illegal move or unknown command {command}, type help for help"),
+            },
+        }
+    }
+}
+
+/// Text diagram of the board, the side at the bottom being `perspective`
+#[must_use]
+pub fn render(board: &Board, perspective: Color) -> String {
+    let mut ranks: Vec<Rank> = Rank::ALL.into_iter().rev().collect();
+    let mut files: Vec<File> = File::ALL.to_vec();
+    if perspective == Color::Black {
+        ranks.reverse();
+        files.reverse();
+    }
+
+    let mut text = String::from("  +-----------------+\n");
+    for &rank in &ranks {
+        text.push_str(&format!("{} |", char::from(rank)));
+        for &file in &files {
+            let square = Square::new(file, rank);
+            let symbol = match (board.piece_on(square), board.color_on(square)) {
+                (Some(piece), Some(Color::White)) => char::from(piece).to_ascii_uppercase(),
+                (Some(piece), _) => char::from(piece),
+                _ => '.',
+            };
+            text.push(' ');
+            text.push(symbol);
+        }
+        text.push_str(" |\n");
+    }
+    text.push_str("  +-----------------+\n   ");
+    for &file in &files {
+        text.push(' ');
+        text.push(char::from(file));
+    }
+
+    let to_move = match board.side_to_move() {
+        Color::White => "White",
+        Color::Black => "Black",
+    };
+    text.push_str(&format!("\n{to_move} to move"));
+    text
+}
+
+// Pawns or moves to mate, from the side to move's perspective
+fn score_text(score: i32) -> String {
+    match mate_moves(score) {
+        Some(moves) => format!("mate in {moves}"),
+        None => format!("{:+.2}", f64::from(score) / 100.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn board_diagram() {
+        let board = Board::default();
+        let white = render(&board, Color::White);
+        let lines: Vec<&str> = white.lines().collect();
+        assert_eq!(lines[1], "8 | r n b q k b n r |");
+        assert_eq!(lines[8], "1 | R N B Q K B N R |");
+        assert_eq!(lines[10], "    a b c d e f g h");
+        assert_eq!(lines[11], "White to move");
+
+        let black = render(&board, Color::Black);
+        let lines: Vec<&str> = black.lines().collect();
+        assert_eq!(lines[1], "1 | R N B K Q B N R |");
+        assert_eq!(lines[10], "    h g f e d c b a");
+
+        assert_eq!(score_text(35), "+0.35");
+        assert_eq!(score_text(crate::definitions::MATE - 3), "mate in 2");
+    }
+}
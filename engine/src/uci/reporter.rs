@@ -0,0 +1,271 @@
+// Search reporters for the command line
+// UCI lines for GUIs, and colored human readable ones when the engine is used interactively.
+use super::{crash, san::line_san};
+use crate::api::{IterationReport, SearchReporter};
+use crate::body::search::format_score;
+use crate::definitions::MATE;
+
+use cozy_chess::Move;
+use std::io::Write;
+
+pub struct UciReporter<W: Write + Send> {
+    out: W,
+}
+
+impl<W: Write + Send> UciReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    // The GUI has nobody to tell if stdout is gone, so write errors are ignored.
+    // Lines are written in one go so that they can't interleave with other output.
+    fn line(&mut self, line: std::fmt::Arguments) {
+        let _ = self.out.write_all(format!("{line}\n").as_bytes());
+        let _ = self.out.flush();
+    }
+}
+
+impl<W: Write + Send> SearchReporter for UciReporter<W> {
+    fn depth_completed(&mut self, report: &IterationReport) {
+        if let Some(&mv) = report.pv.first() {
+            crash::set_fallback(Some(mv));
+        }
+        self.line(format_args!("{}", info_line(report)));
+    }
+
+    fn currmove(&mut self, mv: Move, number: usize, depth: i32) {
+        self.line(format_args!(
+            "info depth {depth} currmove {mv} currmovenumber {number}"
+        ));
+    }
+
+    fn refutation(&mut self, line: &[Move]) {
+        let line: Vec<String> = line.iter().map(Move::to_string).collect();
+        self.line(format_args!("info refutation {}", line.join(" ")));
+    }
+
+    // Without legal moves there is nothing to play, UCI uses a null move for that
+    fn bestmove(&mut self, best_move: Option<Move>, ponder: Option<Move>) {
+        crash::set_fallback(None);
+        match (best_move, ponder) {
+            (Some(mv), Some(ponder)) => self.line(format_args!("bestmove {mv} ponder {ponder}")),
+            (Some(mv), None) => self.line(format_args!("bestmove {mv}")),
+            (None, _) => self.line(format_args!("bestmove 0000")),
+        }
+    }
+
+    fn message(&mut self, message: &str) {
+        self.line(format_args!("info string {message}"));
+    }
+}
+
+pub struct PrettyReporter<W: Write + Send> {
+    uci: UciReporter<W>,
+}
+
+impl<W: Write + Send> PrettyReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            uci: UciReporter::new(out),
+        }
+    }
+}
+
+// Only the iterations look different from UCI
+impl<W: Write + Send> SearchReporter for PrettyReporter<W> {
+    fn depth_completed(&mut self, report: &IterationReport) {
+        self.uci.line(format_args!("{}", pretty_line(report)));
+    }
+
+    fn bestmove(&mut self, best_move: Option<Move>, ponder: Option<Move>) {
+        self.uci.bestmove(best_move, ponder);
+    }
+
+    fn message(&mut self, message: &str) {
+        self.uci.line(format_args!("{message}"));
+    }
+}
+
+/// The UCI info line of a completed iteration
+#[must_use]
+pub fn info_line(report: &IterationReport) -> String {
+    format!(
+        "info depth {} seldepth {} score {}{} nodes {} time {} nps {} pv{}",
+        report.depth,
+        report.seldepth,
+        format_score(report.score),
+        if report.lower_bound {
+            " lowerbound"
+        } else {
+            ""
+        },
+        report.nodes,
+        report.time,
+        report.nodes / (report.time / 1000).max(1),
+        pv_string(report.pv)
+    )
+}
+
+fn pv_string(pv: &[Move]) -> String {
+    pv.iter().map(|mv| format!(" {mv}")).collect()
+}
+
+fn pretty_line(report: &IterationReport) -> String {
+    const DEFAULT: &str = "\x1b[0m";
+    const GREY: &str = "\x1b[90m";
+    const GREEN: &str = "\x1b[32m";
+    const BRIGHT_GREEN: &str = "\x1b[92m";
+    const BRIGHT_CYAN: &str = "\x1b[96m";
+    const BRIGHT_YELLOW: &str = "\x1b[93m";
+    const RED: &str = "\x1b[31m";
+    const BRIGHT_RED: &str = "\x1b[91m";
+
+    let IterationReport {
+        depth,
+        seldepth,
+        score,
+        nodes,
+        time: timer,
+        ..
+    } = *report;
+
+    let t = match timer {
+        0..=999 => {
+            format!("{GREY}{}ms{DEFAULT}", timer as f64)
+        }
+        1000..=59_999 => {
+            format!("{GREY}{:.2}s{DEFAULT}", timer as f64 / 1000.)
+        }
+        60_000..=3_599_999 => {
+            format!("{GREY}{:.2}m{DEFAULT}", timer as f64 / 60_000.)
+        }
+        3_600_000..=86_399_999 => {
+            format!("{GREY}{:.2}h{DEFAULT}", timer as f64 / 3_600_000.)
+        }
+        86_400_000.. => {
+            format!("{GREY}{:.2}d{DEFAULT}", timer as f64 / 86_400_000.)
+        }
+    };
+
+    let mate = ((MATE - score) / 2) + ((MATE - score) & 1);
+    let norm_score = score as f32 / 100.;
+    let sc = match score {
+        501..=15_000 => format!("{BRIGHT_CYAN}+{:.2}{DEFAULT}", norm_score),
+        101..=500 => format!("{GREEN}+{:.2}{DEFAULT}", norm_score),
+        11..=100 => format!("{BRIGHT_GREEN}+{:.2}{DEFAULT}", norm_score),
+        0..=10 => format!("{GREY}+{:.2}{DEFAULT}", norm_score),
+        -10..=-1 => format!("{GREY}{:.2}{DEFAULT}", norm_score),
+        -100..=-11 => format!("{BRIGHT_RED}{:.2}{DEFAULT}", norm_score),
+        -15000..=-101 => format!("{RED}{:.2}{DEFAULT}", norm_score),
+
+        15_001..=32_000 => format!("{BRIGHT_YELLOW}#{}{DEFAULT}", mate),
+        -32_000..=-15_001 => format!("{BRIGHT_YELLOW}#-{}{DEFAULT}", mate),
+
+        _ => unreachable!(),
+    };
+    let sc = if report.lower_bound {
+        format!(">{sc}")
+    } else {
+        sc
+    };
+
+    let d = format!("{}/{}", depth, seldepth);
+
+    let timer = timer.max(1);
+    let knps: String;
+    let n: String;
+    if nodes < 1000 {
+        knps = format!("{GREY}{}no/s{DEFAULT}", nodes / (timer / 1000).max(1));
+        n = format!("{nodes}");
+    } else {
+        knps = format!("{GREY}{}kn/s{DEFAULT}", nodes / timer);
+        n = format!("{}k", nodes / 1000);
+    }
+
+    let str = line_san(report.board, report.pv).join(" ");
+    let pv_width = 125;
+    let pv = if str.len() > pv_width {
+        str[..pv_width].to_string()
+    } else {
+        str
+    };
+
+    format!("{d: <7} {sc: <8} {n: <8} {knps: <18} {t: <15} {pv}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uci_lines() {
+        let mut reporter = UciReporter::new(Vec::new());
+        let pv: Vec<Move> = vec!["e2e4".parse().unwrap(), "e7e5".parse().unwrap()];
+        let board = cozy_chess::Board::default();
+
+        reporter.depth_completed(&IterationReport {
+            depth: 2,
+            seldepth: 3,
+            score: 25,
+            lower_bound: false,
+            nodes: 3000,
+            time: 1500,
+            pv: &pv,
+            board: &board,
+        });
+        reporter.depth_completed(&IterationReport {
+            depth: 3,
+            seldepth: 3,
+            score: MATE - 3,
+            lower_bound: false,
+            nodes: 100,
+            time: 0,
+            pv: &pv[..1],
+            board: &board,
+        });
+        reporter.depth_completed(&IterationReport {
+            depth: 4,
+            seldepth: 5,
+            score: 40,
+            lower_bound: true,
+            nodes: 200,
+            time: 0,
+            pv: &pv,
+            board: &board,
+        });
+        reporter.currmove(pv[0], 1, 3);
+        reporter.message("hello");
+        reporter.bestmove(Some(pv[0]), None);
+        reporter.bestmove(Some(pv[0]), Some(pv[1]));
+        reporter.bestmove(None, None);
+
+        assert_eq!(
+            String::from_utf8(reporter.out).unwrap(),
+            "info depth 2 seldepth 3 score cp 25 nodes 3000 time 1500 nps 3000 pv e2e4 e7e5\n\
+             info depth 3 seldepth 3 score mate 2 nodes 100 time 0 nps 100 pv e2e4\n\
+             info depth 4 seldepth 5 score cp 40 lowerbound nodes 200 time 0 nps 200 pv e2e4 e7e5\n\
+             info depth 3 currmove e2e4 currmovenumber 1\n\
+             info string hello\n\
+             bestmove e2e4\n\
+             bestmove e2e4 ponder e7e5\n\
+             bestmove 0000\n"
+        );
+    }
+
+    #[test]
+    fn pretty_pv() {
+        let pv: Vec<Move> = vec!["g1f3".parse().unwrap(), "d7d5".parse().unwrap()];
+        let board = cozy_chess::Board::default();
+        let line = pretty_line(&IterationReport {
+            depth: 2,
+            seldepth: 2,
+            score: 25,
+            lower_bound: false,
+            nodes: 3000,
+            time: 1500,
+            pv: &pv,
+            board: &board,
+        });
+        assert!(line.ends_with(" Nf3 d5"), "{line}");
+    }
+}
@@ -0,0 +1,274 @@
+// Standard algebraic notation
+// Moves are returned in cozy-chess form, castling being the king capturing its own rook.
+use super::handler::check_castling_move;
+use crate::body::movegen::pure_moves;
+
+use cozy_chess::{Board, File, Move, Piece, Rank, Square};
+
+/// Parses a SAN move such as `Nbxd7+`, `e8=Q` or `O-O`, returning `None` unless it
+/// describes exactly one legal move.
+#[must_use]
+pub fn parse_san(board: &Board, san: &str) -> Option<Move> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let moves = pure_moves(board);
+    let stm = board.side_to_move();
+
+    // Castling
+    let short = matches!(san, "O-O" | "0-0");
+    let long = matches!(san, "O-O-O" | "0-0-0");
+    if short || long {
+        let king = board.king(stm);
+        return moves.into_iter().find(|mv| {
+            mv.from == king && board.colors(stm).has(mv.to) && (mv.to.file() > king.file()) == short
+        });
+    }
+
+    let (piece, rest) = match san.chars().next()? {
+        'N' => (Piece::Knight, &san[1..]),
+        'B' => (Piece::Bishop, &san[1..]),
+        'R' => (Piece::Rook, &san[1..]),
+        'Q' => (Piece::Queen, &san[1..]),
+        'K' => (Piece::King, &san[1..]),
+        _ => (Piece::Pawn, san),
+    };
+
+    // Promotion, with or without the '='
+    let (rest, promotion) = match rest.chars().last()? {
+        c @ ('N' | 'B' | 'R' | 'Q') => {
+            let promotion = match c {
+                'N' => Piece::Knight,
+                'B' => Piece::Bishop,
+                'R' => Piece::Rook,
+                _ => Piece::Queen,
+            };
+            (
+                rest[..rest.len() - 1].trim_end_matches('='),
+                Some(promotion),
+            )
+        }
+        _ => (rest, None),
+    };
+
+    if rest.len() < 2 || !rest.is_char_boundary(rest.len() - 2) {
+        return None;
+    }
+    let (from_hint, to) = rest.split_at(rest.len() - 2);
+    let to: Square = to.parse().ok()?;
+
+    // Disambiguation by file, rank or both
+    let mut from_file: Option<File> = None;
+    let mut from_rank: Option<Rank> = None;
+    for c in from_hint.chars().filter(|&c| c != 'x') {
+        match c {
+            'a'..='h' => from_file = Some(File::index(c as usize - 'a' as usize)),
+            '1'..='8' => from_rank = Some(Rank::index(c as usize - '1' as usize)),
+            _ => return None,
+        }
+    }
+
+    let mut candidates = moves.into_iter().filter(|mv| {
+        board.piece_on(mv.from) == Some(piece)
+            && mv.to == to
+            && mv.promotion == promotion
+            && from_file.is_none_or(|f| mv.from.file() == f)
+            && from_rank.is_none_or(|r| mv.from.rank() == r)
+            // King moves onto their own rooks are castling, written as O-O
+            && !board.colors(stm).has(mv.to)
+    });
+
+    let mv = candidates.next()?;
+    candidates.next().is_none().then_some(mv)
+}
+
+/// Reads a legal move in UCI or SAN notation, castling as king to g1 or king captures rook alike
+#[must_use]
+pub fn parse_move(board: &Board, text: &str) -> Option<Move> {
+    text.parse()
+        .ok()
+        .map(|mv| check_castling_move(board, mv))
+        .filter(|&mv| board.is_legal(mv))
+        .or_else(|| parse_san(board, text))
+}
+
+/// SAN of a legal move, with + or # for checks and mates
+#[must_use]
+pub fn to_san(board: &Board, mv: Move) -> String {
+    let stm = board.side_to_move();
+    let piece = board.piece_on(mv.from).unwrap();
+    let mut san = String::new();
+
+    if board.colors(stm).has(mv.to) {
+        san.push_str(if mv.to.file() > mv.from.file() {
+            "O-O"
+        } else {
+            "O-O-O"
+        });
+    } else {
+        let capture =
+            board.occupied().has(mv.to) || (piece == Piece::Pawn && mv.from.file() != mv.to.file());
+
+        if piece == Piece::Pawn {
+            if capture {
+                san.push(char::from(mv.from.file()));
+            }
+        } else {
+            san.push(char::from(piece).to_ascii_uppercase());
+
+            // Other pieces of the kind reaching the square, told apart by file if possible
+            let others: Vec<Square> = pure_moves(board)
+                .into_iter()
+                .filter(|other| {
+                    other.to == mv.to
+                        && other.from != mv.from
+                        && board.piece_on(other.from) == Some(piece)
+                })
+                .map(|other| other.from)
+                .collect();
+            let same_file = others.iter().any(|sq| sq.file() == mv.from.file());
+            let same_rank = others.iter().any(|sq| sq.rank() == mv.from.rank());
+
+            if !others.is_empty() && (!same_file || same_rank) {
+                san.push(char::from(mv.from.file()));
+            }
+            if same_file {
+                san.push(char::from(mv.from.rank()));
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&mv.to.to_string());
+
+        if let Some(promotion) = mv.promotion {
+            san.push('=');
+            san.push(char::from(promotion).to_ascii_uppercase());
+        }
+    }
+
+    let mut child = board.clone();
+    child.play_unchecked(mv);
+    if !child.checkers().is_empty() {
+        san.push(if pure_moves(&child).is_empty() {
+            '#'
+        } else {
+            '+'
+        });
+    }
+
+    san
+}
+
+/// SAN of a line of UCI moves played from `board`, up to the first move that isn't legal
+#[must_use]
+pub fn line_san(board: &Board, line: &[Move]) -> Vec<String> {
+    let mut board = board.clone();
+    let mut sans = Vec::with_capacity(line.len());
+    for &mv in line {
+        let mv = check_castling_move(&board, mv);
+        if !board.is_legal(mv) {
+            break;
+        }
+        sans.push(to_san(&board, mv));
+        board.play_unchecked(mv);
+    }
+    sans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn san_parsing() {
+        let board = Board::default();
+        let parse = |board: &Board, san: &str| parse_san(board, san).map(|mv| mv.to_string());
+
+        assert_eq!(parse(&board, "e4"), Some("e2e4".to_string()));
+        assert_eq!(parse(&board, "Nf3"), Some("g1f3".to_string()));
+        assert_eq!(parse(&board, "e5"), None);
+        assert_eq!(parse(&board, "Ke2"), None);
+
+        // Disambiguation, captures and check suffixes
+        let board = Board::from_fen("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", false).unwrap();
+        assert_eq!(parse(&board, "Rd1"), None);
+        assert_eq!(parse(&board, "Rad1"), Some("a1d1".to_string()));
+        assert_eq!(parse(&board, "Rhxf1"), Some("h1f1".to_string()));
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", false).unwrap();
+        assert_eq!(parse(&board, "O-O"), Some("e1h1".to_string()));
+        assert_eq!(parse(&board, "O-O-O"), Some("e1a1".to_string()));
+        assert_eq!(parse(&board, "Ra8+"), Some("a1a8".to_string()));
+
+        let board = Board::from_fen("1n2k3/2P5/8/8/8/8/8/4K3 w - - 0 1", false).unwrap();
+        assert_eq!(parse(&board, "cxb8=Q+"), Some("c7b8q".to_string()));
+        assert_eq!(parse(&board, "c8N"), Some("c7c8n".to_string()));
+        assert_eq!(parse(&board, "c8"), None);
+    }
+
+    #[test]
+    fn move_input() {
+        let board: Board = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".parse().unwrap();
+        let castle = "e1h1".parse().unwrap();
+
+        assert_eq!(parse_move(&board, "O-O"), Some(castle));
+        assert_eq!(parse_move(&board, "e1g1"), Some(castle));
+        assert_eq!(parse_move(&board, "e1h1"), Some(castle));
+        assert_eq!(parse_move(&board, "Ra8+"), "a1a8".parse().ok());
+        assert_eq!(parse_move(&board, "a1a8"), "a1a8".parse().ok());
+        assert_eq!(parse_move(&board, "e2e4"), None);
+        assert_eq!(parse_move(&board, "undo"), None);
+
+        // Lines stop at the first illegal move
+        let line: Vec<Move> = ["e1g1", "e8c8", "a1a8", "c8b7", "e2e4"]
+            .iter()
+            .map(|mv| mv.parse().unwrap())
+            .collect();
+        assert_eq!(line_san(&board, &line), ["O-O", "O-O-O", "Ra8+", "Kb7"]);
+    }
+
+    #[test]
+    fn san_printing() {
+        let san = |fen: &str, mv: &str| {
+            let board = Board::from_fen(fen, false).unwrap();
+            to_san(&board, mv.parse().unwrap())
+        };
+
+        assert_eq!(san(crate::api::START_FEN, "g1f3"), "Nf3");
+        assert_eq!(san("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1"), "Rad1");
+        assert_eq!(san("4k3/8/8/8/8/8/R3K3/R7 w - - 0 1", "a1a4"), "R1a4");
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1a1"), "O-O-O");
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "a1a8"), "Ra8+");
+        assert_eq!(san("1n2k3/2P5/8/8/8/8/8/4K3 w - - 0 1", "c7b8q"), "cxb8=Q+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+        assert_eq!(
+            san(
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                "e5f6"
+            ),
+            "exf6"
+        );
+
+        // Three queens reaching one square, some need both the file and the rank
+        let queens = "6k1/8/8/8/Q6Q/8/8/Q3K3 w - - 0 1";
+        assert_eq!(san(queens, "a4d4"), "Qa4d4");
+        assert_eq!(san(queens, "h4d4"), "Qhd4");
+        assert_eq!(san(queens, "a1d4"), "Q1d4");
+
+        // Every move reads back as itself
+        for fen in [
+            crate::api::START_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ] {
+            let board = Board::from_fen(fen, false).unwrap();
+            for mv in pure_moves(&board) {
+                assert_eq!(
+                    parse_san(&board, &to_san(&board, mv)),
+                    Some(mv),
+                    "{mv} in {fen}"
+                );
+            }
+        }
+    }
+}
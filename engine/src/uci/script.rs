@@ -0,0 +1,161 @@
+// --script <file>
+// Replays a recorded session into the engine, - reading it from stdin, so that bug reports with
+// GUI logs can be reproduced along with their timing. Lines are UCI commands, or lines of a
+// Debug Log File: the commands received are replayed as far apart as they were logged, and the
+// engine's own output is left out. Directives start with #:
+//   #sleep <ms>   waits before the next command
+//   #wait         waits for the running search to send its bestmove
+// Other lines starting with # are comments. A GUI waits for the last bestmove before it closes,
+// so does the end of the script.
+use super::{
+    config::Config,
+    handler::{run_session, searching, UciOutput},
+};
+
+use std::io::{BufRead, BufReader, Read};
+use std::thread::sleep;
+use std::time::Duration;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+pub fn replay(path: &str, config: &Config) {
+    let input: Box<dyn BufRead> = if path == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        match std::fs::File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                println!("info string could not open {path}: {e}");
+                return;
+            }
+        }
+    };
+
+    let out = UciOutput::new(std::io::stdout());
+    super::crash::install_hook(out.clone());
+    super::signals::install_handler();
+    run_session(BufReader::new(Script::new(input)), out, config);
+}
+
+/// Commands of a recorded session, each given out when it is due
+pub struct Script<R> {
+    lines: R,
+    // The command being given out
+    pending: Vec<u8>,
+    // Time of day the last logged command was received at
+    last_logged: Option<u64>,
+}
+
+impl<R: BufRead> Script<R> {
+    pub fn new(lines: R) -> Self {
+        Self {
+            lines,
+            pending: vec![],
+            last_logged: None,
+        }
+    }
+
+    // The next command, once it is due, or none at the end of the script
+    fn next_command(&mut self) -> Option<String> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.lines.read_line(&mut line).ok()? == 0 {
+                wait_for_search();
+                return None;
+            }
+
+            let line = line.trim();
+            if let Some(logged) = line.strip_prefix('[') {
+                // [hh:mm:ss.mmm] >> received or << sent
+                let Some((time, rest)) = logged.split_once(']') else {
+                    continue;
+                };
+                let (Some(time), Some(command)) =
+                    (parse_time(time), rest.trim().strip_prefix(">>"))
+                else {
+                    continue;
+                };
+
+                if let Some(last) = self.last_logged {
+                    sleep(Duration::from_millis((time + DAY_MS - last) % DAY_MS));
+                }
+                self.last_logged = Some(time);
+                return Some(command.trim().to_string());
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["#sleep", ms] => {
+                    if let Ok(ms) = ms.parse() {
+                        sleep(Duration::from_millis(ms));
+                    }
+                }
+                ["#wait"] => wait_for_search(),
+                [] => (),
+                [first, ..] if first.starts_with('#') => (),
+                _ => return Some(line.to_string()),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for Script<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.next_command() {
+                Some(command) => self.pending = format!("{command}\n").into_bytes(),
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+fn wait_for_search() {
+    while searching() {
+        sleep(Duration::from_millis(1));
+    }
+}
+
+// Milliseconds into the day of a hh:mm:ss.mmm time
+fn parse_time(time: &str) -> Option<u64> {
+    let (hms, ms) = time.split_once('.')?;
+    let fields = hms
+        .split(':')
+        .map(str::parse::<u64>)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+
+    match fields.as_slice() {
+        [h, m, s] => Some(((h * 60 + m) * 60 + s) * 1000 + ms.parse::<u64>().ok()?),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn script_commands() {
+        let script = "# a comment\nuci\n\n#sleep 20\n[10:00:00.990] >> isready\n\
+                      [10:00:00.995] << readyok\n[10:00:01.020] >> stop\n";
+        let start = Instant::now();
+        let mut commands = String::new();
+        BufReader::new(Script::new(script.as_bytes()))
+            .read_to_string(&mut commands)
+            .unwrap();
+
+        assert_eq!(commands, "uci\nisready\nstop\n");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        assert_eq!(parse_time("10:00:01.020"), Some(36_001_020));
+        assert_eq!(parse_time("10:00"), None);
+    }
+}
@@ -0,0 +1,258 @@
+// selfplay [movetime] [games] [--output <file>]
+// The engine plays itself from the start position and writes the games as PGN, with the eval
+// and the time taken after every move as [%eval] and [%emt] comments. Variety tells the games
+// apart, at SELFPLAY_VARIETY unless svart.toml sets it.
+use super::{
+    clock::Instant,
+    config::Config,
+    handler::check_castling_move,
+    pgn::{pgn_eval, wrap_movetext},
+    san::to_san,
+};
+use crate::api::{adjudicate, AdjudicationRules, Engine, GameResult, Limits, Reason, START_FEN};
+
+use cozy_chess::{Board, Color, Move};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const USAGE: &str = "info string usage: selfplay [movetime] [games] [--output <file>]";
+
+pub const DEFAULT_MOVETIME: u64 = 100;
+pub const DEFAULT_GAMES: usize = 1;
+const DEFAULT_OUTPUT: &str = "selfplay.pgn";
+
+// Centipawns, enough to vary the openings without throwing games
+const SELFPLAY_VARIETY: i32 = 30;
+
+// Games going nowhere are drawn after this many plies
+const MAX_PLIES: usize = 600;
+
+const PLAYER: &str = "Svart 6";
+
+/// One move of a game, scored from the side to move's perspective before it was played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayedMove {
+    pub mv: Move,
+    pub score: i32,
+    // Milliseconds
+    pub time: u64,
+}
+
+pub struct SelfPlayGame {
+    pub moves: Vec<PlayedMove>,
+    pub result: GameResult,
+    pub reason: Reason,
+}
+
+pub fn selfplay(args: &[String], config: &Config) {
+    let mut movetime = DEFAULT_MOVETIME;
+    let mut games = DEFAULT_GAMES;
+    let mut output = DEFAULT_OUTPUT;
+
+    let mut numbers = 0;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let parsed = match (arg.as_str(), numbers) {
+            ("--output", _) => rest.next().map(|path| output = path).is_some(),
+            (arg, 0) => arg.parse().map(|m| movetime = m).is_ok(),
+            (arg, 1) => arg.parse().map(|g| games = g).is_ok(),
+            _ => false,
+        };
+        numbers += usize::from(!arg.starts_with("--"));
+
+        if !parsed {
+            println!("{USAGE}");
+            return;
+        }
+    }
+
+    let mut file = match File::create(output) {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            println!("info string could not create {output}: {e}");
+            return;
+        }
+    };
+
+    let mut engine = Engine::new();
+    if let Err(e) = config.apply(&mut engine) {
+        println!("info string {e}");
+    }
+    if config.variety == 0 {
+        engine.set_variety(SELFPLAY_VARIETY).unwrap();
+    }
+    let limits = Limits {
+        movetime: Some(movetime),
+        ..Default::default()
+    };
+
+    for round in 1..=games {
+        let game = play_game(&mut engine, limits);
+        let pgn = game_pgn(&game, round, movetime);
+        if let Err(e) = writeln!(file, "{pgn}").and_then(|()| file.flush()) {
+            println!("info string could not write {output}: {e}");
+            return;
+        }
+
+        println!(
+            "info string game {round} of {games}: {} by {} after {} plies",
+            result_string(game.result),
+            game.reason.description(),
+            game.moves.len()
+        );
+    }
+}
+
+/// Plays one game against itself from the start position, until it's over or adjudicated
+pub fn play_game(engine: &mut Engine, limits: Limits) -> SelfPlayGame {
+    let rules = AdjudicationRules {
+        max_plies: MAX_PLIES,
+        ..AdjudicationRules::NONE
+    };
+
+    let mut board = Board::default();
+    let mut history = vec![board.hash()];
+    let mut moves: Vec<String> = vec![];
+    let mut played = vec![];
+    let mut scores = vec![];
+
+    engine.new_game();
+    loop {
+        if let Some((result, reason)) = adjudicate(&board, &history, &scores, &rules) {
+            return SelfPlayGame {
+                moves: played,
+                result,
+                reason,
+            };
+        }
+
+        let words: Vec<&str> = moves.iter().map(String::as_str).collect();
+        engine
+            .set_position(START_FEN, &words)
+            .expect("the engine only plays legal moves");
+
+        let timer = Instant::now();
+        let result = engine
+            .search(limits)
+            .expect("games with no legal moves are over");
+        let time = timer.elapsed().as_millis() as u64;
+
+        // Back to the king capturing its rook, like the board expects
+        let mv = check_castling_move(&board, result.best_move);
+        played.push(PlayedMove {
+            mv,
+            score: result.score,
+            time,
+        });
+        scores.push(match board.side_to_move() {
+            Color::White => result.score,
+            Color::Black => -result.score,
+        });
+
+        moves.push(result.best_move.to_string());
+        board.play_unchecked(mv);
+        history.push(board.hash());
+    }
+}
+
+/// The game in PGN export format, with the eval and time of every move
+#[must_use]
+pub fn game_pgn(game: &SelfPlayGame, round: usize, movetime: u64) -> String {
+    let result = result_string(game.result);
+    let mut pgn = String::new();
+    for (key, value) in [
+        ("Event", format!("Svart self-play, {movetime} ms per move")),
+        ("Site", "?".to_string()),
+        ("Date", "????.??.??".to_string()),
+        ("Round", round.to_string()),
+        ("White", PLAYER.to_string()),
+        ("Black", PLAYER.to_string()),
+        ("Result", result.to_string()),
+        ("Termination", game.reason.description().to_string()),
+        ("PlyCount", game.moves.len().to_string()),
+    ] {
+        pgn.push_str(&format!("[{key} \"{value}\"]\n"));
+    }
+    pgn.push('\n');
+
+    let mut tokens = vec![];
+    let mut board = Board::default();
+    for played in &game.moves {
+        let stm = board.side_to_move();
+        if stm == Color::White {
+            tokens.push(format!("{}.", board.fullmove_number()));
+        } else {
+            tokens.push(format!("{}...", board.fullmove_number()));
+        }
+        tokens.push(to_san(&board, played.mv));
+
+        let score = match stm {
+            Color::White => played.score,
+            Color::Black => -played.score,
+        };
+        tokens.push(format!(
+            "{{ [%eval {}] [%emt {}] }}",
+            pgn_eval(score),
+            elapsed_time(played.time)
+        ));
+        board.play_unchecked(played.mv);
+    }
+    tokens.push(result.to_string());
+
+    pgn.push_str(&wrap_movetext(&tokens.join(" ")));
+    pgn
+}
+
+#[must_use]
+pub const fn result_string(result: GameResult) -> &'static str {
+    match result {
+        GameResult::WhiteWins => "1-0",
+        GameResult::BlackWins => "0-1",
+        GameResult::Draw => "1/2-1/2",
+    }
+}
+
+// [%emt] takes h:mm:ss, with tenths of a second for fast games
+fn elapsed_time(millis: u64) -> String {
+    let tenths = millis / 100;
+    let seconds = tenths / 10;
+
+    format!(
+        "{}:{:02}:{:02}.{}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        tenths % 10
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uci::pgn::PgnReader;
+
+    #[test]
+    fn selfplay_game() {
+        let mut engine = Engine::new();
+        let limits = Limits {
+            depth: Some(2),
+            ..Default::default()
+        };
+        let game = play_game(&mut engine, limits);
+        assert!(!game.moves.is_empty());
+
+        // The PGN reads back as the same game
+        let pgn = game_pgn(&game, 1, 100);
+        let read = PgnReader::new(pgn.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let moves: Vec<Move> = read.moves.iter().map(|&(mv, _)| mv).collect();
+        assert_eq!(moves, game.moves.iter().map(|p| p.mv).collect::<Vec<_>>());
+        assert!(read.moves.iter().all(|&(_, eval)| eval.is_some()));
+
+        assert_eq!(elapsed_time(100), "0:00:00.1");
+        assert_eq!(elapsed_time(3_723_000), "1:02:03.0");
+    }
+}
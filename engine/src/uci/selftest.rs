@@ -0,0 +1,198 @@
+// selftest eval|see
+// Consistency checks for the network integration, run over the bench positions and a short
+// line of play from each. The evaluation of a position must match that of its color-flipped
+// mirror, and incrementally updated accumulators must match a refresh from scratch.
+// The SEE suite checks exchanges that are easy to get wrong against their known outcome.
+use super::bench::FENS;
+use crate::body::{
+    endgame, movegen::pure_moves, nnue::inference::NNUEState, position::play_move, see::see,
+};
+
+use cozy_chess::{Board, Move};
+
+// Plies played from each suite position, checking every legal move along the way
+const WALK_PLIES: usize = 16;
+
+// Position, move, expected exchange outcome
+const SEE_SUITE: [(&str, &str, i32); 12] = [
+    // Undefended pawn
+    (
+        "1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1",
+        "e1e5",
+        100,
+    ),
+    // Defended pawn, knight for pawn with the rest of the exchange not worth continuing
+    (
+        "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+        "d3e5",
+        -200,
+    ),
+    // The defender is pinned to its king
+    ("4k3/3n4/8/1B2p3/8/8/8/4RK2 w - - 0 1", "e1e5", 100),
+    // Pinned along the capture line, the defender can still recapture
+    ("4k3/3b4/8/1Q6/8/8/8/2R1K3 w - - 0 1", "c1c6", -200),
+    // X-ray support from a rook behind the capturer
+    ("4k3/4r3/8/4p3/8/8/4R3/4RK2 w - - 0 1", "e2e5", 100),
+    // X-ray support for the defender
+    ("4k3/4q3/4r3/4p3/8/8/4R3/4RK2 w - - 0 1", "e2e5", -400),
+    // The king can only recapture undefended pieces
+    ("4k3/3p4/8/8/8/8/8/3RK3 w - - 0 1", "d1d7", -400),
+    ("4k3/3p4/8/1B6/8/8/8/3RK3 w - - 0 1", "d1d7", 100),
+    // Promotions, with and without a capture or a defender
+    ("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q", 800),
+    ("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7a8q", 1300),
+    ("2r1k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8n", -100),
+    // En passant
+    ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6", 100),
+];
+
+pub fn selftest(words: &[&str]) {
+    match words.get(1).copied() {
+        Some("eval") => {
+            let failures = eval_selftest(&FENS, WALK_PLIES);
+            println!("info string selftest eval: {failures} failures");
+        }
+        Some("see") => {
+            let failures = see_selftest();
+            println!("info string selftest see: {failures} failures");
+        }
+        _ => println!("info string usage: selftest eval|see"),
+    }
+}
+
+// Returns the number of failed checks, printing each of them
+fn see_selftest() -> usize {
+    let mut failures = 0;
+
+    for (fen, mv, expected) in SEE_SUITE {
+        let board = Board::from_fen(fen, false).unwrap();
+        let mv: Move = mv.parse().unwrap();
+        assert!(board.is_legal(mv), "illegal suite move {mv} in {fen}");
+
+        let value = see(&board, mv);
+        if value != expected {
+            println!("info string see mismatch: {fen} {mv} {value}, expected {expected}");
+            failures += 1;
+        }
+    }
+
+    failures
+}
+
+// Returns the number of failed checks, printing each of them
+fn eval_selftest(fens: &[&str], plies: usize) -> usize {
+    let mut failures = 0;
+    let mut scratch = NNUEState::from_board(&Board::default());
+
+    for fen in fens {
+        let mut board = Board::from_fen(fen, false).unwrap();
+        let mut nnue = NNUEState::from_board(&board);
+
+        for ply in 0..plies {
+            let eval = evaluate(&board);
+            let flipped = flip(&board);
+            let flipped_eval = evaluate(&flipped);
+            if eval != flipped_eval {
+                println!("info string eval asymmetry: {board} {eval}, {flipped} {flipped_eval}");
+                failures += 1;
+            }
+
+            let moves = pure_moves(&board);
+            if moves.is_empty() {
+                break;
+            }
+
+            for &mv in &moves {
+                let mut child = board.clone();
+                play_move(&mut child, &mut nnue, mv);
+                scratch.refresh(&child);
+
+                if nnue.accumulators[nnue.current_acc] != scratch.accumulators[0] {
+                    println!("info string accumulator mismatch: {board} {mv}");
+                    failures += 1;
+                }
+                nnue.pop();
+            }
+
+            // Deterministic but varied line, the accumulator stack grows along it
+            let mv = moves[(ply * 7) % moves.len()];
+            play_move(&mut board, &mut nnue, mv);
+        }
+    }
+
+    failures
+}
+
+fn evaluate(board: &Board) -> i32 {
+    let nnue = NNUEState::from_board(board);
+    endgame::evaluate(board, nnue.evaluate(board.side_to_move()))
+}
+
+// The same position with colors swapped and the board mirrored vertically
+fn flip(board: &Board) -> Board {
+    let fen = board.to_string();
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+
+    let swap_case = |s: &str| -> String {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii_uppercase() {
+                    c.to_ascii_lowercase()
+                } else {
+                    c.to_ascii_uppercase()
+                }
+            })
+            .collect()
+    };
+
+    let placement = fields[0]
+        .split('/')
+        .rev()
+        .map(swap_case)
+        .collect::<Vec<_>>()
+        .join("/");
+    let stm = if fields[1] == "w" { "b" } else { "w" };
+
+    // White's rights are listed first
+    let mut castling: Vec<char> = swap_case(fields[2]).chars().collect();
+    castling.sort_by_key(char::is_ascii_lowercase);
+    let castling: String = castling.into_iter().collect();
+
+    let ep = match fields[3].as_bytes() {
+        [file, b'3'] => format!("{}6", *file as char),
+        [file, b'6'] => format!("{}3", *file as char),
+        _ => "-".to_string(),
+    };
+
+    let fen = format!(
+        "{placement} {stm} {castling} {ep} {} {}",
+        fields[4], fields[5]
+    );
+    Board::from_fen(&fen, false).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_consistency() {
+        let board = Board::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K1R1 w Qkq - 0 1",
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            flip(&board).to_string(),
+            "r3k1r1/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b KQq - 0 1"
+        );
+        assert_eq!(flip(&flip(&board)), board);
+
+        assert_eq!(eval_selftest(&FENS[..4], 4), 0);
+    }
+
+    #[test]
+    fn see_suite() {
+        assert_eq!(see_selftest(), 0);
+    }
+}
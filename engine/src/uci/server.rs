@@ -0,0 +1,56 @@
+// serve [address]
+// Speaks UCI over TCP so the engine can run as a remote analysis backend. Connections are
+// served one at a time, later ones wait their turn, and each gets a fresh engine set up from the
+// configuration. A session ends on quit or when the client disconnects, stopping any search it left
+// running, and the server goes back to listening.
+use super::config::Config;
+use super::handler::{run_session, UciOutput};
+use super::timeman::store_ponder;
+
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:5000";
+
+pub fn serve(address: &str, config: &Config) {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("could not listen on {address}: {e}");
+            return;
+        }
+    };
+
+    match listener.local_addr() {
+        Ok(local) => println!("listening on {local}"),
+        Err(_) => println!("listening on {address}"),
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => session(stream, config),
+            Err(e) => println!("connection failed: {e}"),
+        }
+    }
+}
+
+fn session(stream: TcpStream, config: &Config) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "unknown peer".to_string(), |peer| peer.to_string());
+
+    let writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            println!("{peer}: {e}");
+            return;
+        }
+    };
+
+    // A session that ended mid ponder must not leave the next one pondering
+    store_ponder(false);
+
+    println!("{peer} connected");
+    run_session(BufReader::new(stream), UciOutput::new(writer), config);
+    println!("{peer} disconnected");
+}
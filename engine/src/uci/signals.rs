@@ -0,0 +1,35 @@
+// Termination signals
+// SIGINT, SIGTERM and SIGHUP during a search stop it the way a stop command does, so that the
+// bestmove still goes out instead of the game being lost on time when a bot framework or a
+// container shuts the engine down. A second signal before the search has wound down, or one
+// while no search is running, exits right away.
+#[cfg(not(target_arch = "wasm32"))]
+use super::handler::searching;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{api::StopHandle, body::search::load_stop};
+
+// Shells report processes ended by SIGINT with this code
+#[cfg(not(target_arch = "wasm32"))]
+const SIGNAL_EXIT_CODE: i32 = 130;
+
+/// Handles termination signals for the rest of the process, the session on stdin being the only
+/// one searching
+#[cfg(not(target_arch = "wasm32"))]
+pub fn install_handler() {
+    let result = ctrlc::set_handler(|| {
+        // The stop flag stays up until the search is over
+        if searching() && !load_stop() {
+            StopHandle.stop();
+        } else {
+            std::process::exit(SIGNAL_EXIT_CODE);
+        }
+    });
+
+    if let Err(e) = result {
+        println!("info string could not handle termination signals: {e}");
+    }
+}
+
+// Nobody sends wasm32 signals
+#[cfg(target_arch = "wasm32")]
+pub const fn install_handler() {}
@@ -1,28 +1,419 @@
+use super::{clock::Instant, handler::SearchType};
 use crate::definitions::TIME_OVERHEAD;
+use crate::tunables;
+
+use cozy_chess::Move;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PONDER: AtomicBool = AtomicBool::new(false);
+
+// Soft limit scale indexed by how many consecutive iterations kept the same best move
+const STABILITY_SCALE: [f64; 5] = [2.50, 1.20, 0.90, 0.80, 0.75];
+
+// Fraction of the remaining time kept out of the allocation in repeating time controls
+const MTG_BUFFER: f64 = 0.05;
+// No single move may use more than this fraction of the remaining time in repeating time controls
+const MTG_MAX_USAGE: f64 = 0.8;
+
+// Below this much remaining time (plus overhead) we only try to survive: the search is cut
+// down to a single iteration and the clock is checked far more often.
+const SURVIVAL_TIME: u64 = 100;
+const CHECK_INTERVAL: u64 = 1024;
+const SURVIVAL_CHECK_INTERVAL: u64 = 64;
+
+/// Milliseconds to think for when a go command gives neither limits nor any clock
+pub const DEFAULT_THINK_TIME: u64 = 1000;
+
+// Fractional parameters are tuned as percentages
+fn percent(value: i32) -> f64 {
+    f64::from(value) / 100.
+}
 
 #[must_use]
 pub fn time_for_move(time: u64, inc: u64, moves_to_go: Option<u8>) -> (u64, u64) {
     // Accounting for overhead
-    let time = time - TIME_OVERHEAD;
-    let opt_time: f64;
-    let max_time: f64;
+    let time = time.saturating_sub(TIME_OVERHEAD);
+    let soft_time: f64;
+    let hard_time: f64;
+
+    // Low time survival mode
+    // A soft limit of zero means we never start a second iteration, the TT move
+    // from previous searches keeps the first iteration's move ordering sane.
+    if time < SURVIVAL_TIME {
+        let hard = (time / 4 + inc / 2).min(time / 2);
+        return (0, hard);
+    }
 
     // repeating TC
     if let Some(mtg) = moves_to_go {
-        let mtg = mtg.min(50);
-        let scale = 0.7 / f64::from(mtg);
-        let eight = 0.8 * time as f64;
+        let mtg = mtg.clamp(1, 50);
+        let time = time as f64;
+
+        // Keep a safety buffer out of the division so that we don't
+        // flag on the last move before the time control.
+        let usable = time * (1. - MTG_BUFFER);
+        let per_move = usable / f64::from(mtg) + inc as f64 * percent(tunables::tm_inc());
+        let cap = time * MTG_MAX_USAGE;
 
-        opt_time = (scale * time as f64).min(eight);
-        max_time = (5. * opt_time).min(eight);
+        soft_time = (0.7 * per_move).min(cap);
+        hard_time = (3. * per_move).min(cap);
     // normal TC
     } else {
-        let temp = ((time / 20) + (inc * 3 / 4)) as f64;
-        opt_time = 0.6 * temp;
-        max_time = (temp * 2.).min(time as f64);
+        let divisor = tunables::tm_time_divisor() as u64;
+        let temp = (time / divisor) as f64 + inc as f64 * percent(tunables::tm_inc());
+        soft_time = percent(tunables::tm_soft()) * temp;
+        hard_time = (temp * percent(tunables::tm_hard())).min(time as f64);
+    }
+
+    // The soft limit is checked between iterations in the ID loop, we don't start a new depth past it.
+    // The hard limit is checked inside the search function as a global stop light.
+    (soft_time.min(hard_time) as u64, hard_time as u64)
+}
+
+pub fn store_ponder(ponder: bool) {
+    PONDER.store(ponder, Ordering::SeqCst);
+}
+
+pub fn load_ponder() -> bool {
+    PONDER.load(Ordering::SeqCst)
+}
+
+// Elapsed time is either measured on the wall clock or, for reproducible
+// games, as a virtual clock that advances by one millisecond every N nodes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Clock {
+    Wall,
+    Nodes(u64),
+}
+
+pub struct TimeManager {
+    timer: Instant,
+    clock: Clock,
+    // Our own clock only starts ticking on ponderhit, until then this is zero
+    clock_start: u64,
+    pondering: bool,
+    base_soft: Option<u64>,
+    soft: Option<u64>,
+    hard: Option<u64>,
+    // Node limits are exact, they are checked at every node
+    max_nodes: Option<u64>,
+    prev_best_move: Option<Move>,
+    stability: usize,
+    prev_score: Option<i32>,
+    prev_move_score: Option<i32>,
+}
+
+impl TimeManager {
+    pub fn new() -> Self {
+        Self {
+            timer: Instant::now(),
+            clock: Clock::Wall,
+            clock_start: 0,
+            pondering: false,
+            base_soft: None,
+            soft: None,
+            hard: None,
+            max_nodes: None,
+            prev_best_move: None,
+            stability: 0,
+            prev_score: None,
+            prev_move_score: None,
+        }
+    }
+
+    /// Starts the clock and sets the limits for the given search type
+    pub fn start(&mut self, st: SearchType) {
+        self.timer = Instant::now();
+        self.clock_start = 0;
+        self.pondering = load_ponder();
+
+        (self.base_soft, self.soft, self.hard) = match st {
+            SearchType::Time(soft, hard) => (Some(soft), Some(soft), Some(hard)),
+            _ => (None, None, None),
+        };
+        self.max_nodes = match st {
+            SearchType::Nodes(nodes) => Some(nodes),
+            _ => None,
+        };
+        self.prev_best_move = None;
+        self.stability = 0;
+        self.prev_score = None;
+    }
+
+    /// A `nodestime` of zero uses the wall clock, otherwise it is the number of nodes per millisecond
+    pub fn set_nodestime(&mut self, nodestime: u64) {
+        self.clock = match nodestime {
+            0 => Clock::Wall,
+            n => Clock::Nodes(n),
+        };
+    }
+
+    /// Remembers the score of the previous search, which is carried over between moves
+    pub fn set_prev_move_score(&mut self, score: Option<i32>) {
+        self.prev_move_score = score;
+    }
+
+    /// Score of the last completed iteration
+    #[must_use]
+    pub fn last_score(&self) -> Option<i32> {
+        self.prev_score
+    }
+
+    /// Milliseconds since the start of the search, on whichever clock is in use
+    #[must_use]
+    pub fn elapsed(&self, nodes: u64) -> u64 {
+        match self.clock {
+            Clock::Wall => self.timer.elapsed().as_millis() as u64,
+            Clock::Nodes(nodes_per_ms) => nodes / nodes_per_ms,
+        }
+    }
+
+    /// How many nodes are searched between clock checks
+    #[must_use]
+    pub fn check_interval(&self) -> u64 {
+        match self.hard {
+            _ if self.max_nodes.is_some() => 1,
+            Some(hard) if hard < SURVIVAL_TIME => SURVIVAL_CHECK_INTERVAL,
+            _ => CHECK_INTERVAL,
+        }
+    }
+
+    // Picks up a ponderhit, from then on the limits computed from the clocks
+    // given in the original `go ponder` are enforced.
+    fn sync_ponder(&mut self, nodes: u64) {
+        if self.pondering && !load_ponder() {
+            self.pondering = false;
+            self.clock_start = self.elapsed(nodes);
+        }
+    }
+
+    /// The hard limit is measured from the moment our clock started running
+    #[must_use]
+    pub fn hard_limit_reached(&mut self, nodes: u64) -> bool {
+        self.sync_ponder(nodes);
+        !self.pondering
+            && (self.max_nodes.is_some_and(|max| nodes >= max)
+                || self
+                    .hard
+                    .is_some_and(|hard| self.elapsed(nodes) - self.clock_start >= hard))
+    }
+
+    /// The soft limit is measured from the start of the search, crediting
+    /// the work already done while pondering.
+    #[must_use]
+    pub fn soft_limit_reached(&mut self, nodes: u64) -> bool {
+        self.sync_ponder(nodes);
+        !self.pondering && self.soft.is_some_and(|soft| self.elapsed(nodes) >= soft)
+    }
+
+    /// Feeds the results of a completed iteration into the time manager, rescaling the soft limit.
+    /// `best_move_fraction` is the share of the nodes spent on the best move's subtree.
+    pub fn update(&mut self, best_move: Move, best_move_fraction: f64, score: i32) {
+        if self.prev_best_move == Some(best_move) {
+            self.stability = (self.stability + 1).min(STABILITY_SCALE.len() - 1);
+        } else {
+            self.stability = 0;
+        }
+        self.prev_best_move = Some(best_move);
+
+        // A sharp drop versus the previous iteration or the previous move's search
+        // hints at an emerging problem that is worth spending more time on.
+        let drop = [self.prev_score, self.prev_move_score]
+            .iter()
+            .flatten()
+            .map(|&prev| prev - score)
+            .max()
+            .unwrap_or(0)
+            .max(0);
+        self.prev_score = Some(score);
+
+        let node_factor = (percent(tunables::tm_node_base()) - best_move_fraction)
+            * percent(tunables::tm_node_scale());
+        let stability_factor = STABILITY_SCALE[self.stability];
+        // Score drops extend the soft limit, capped at a multiple of the base allocation
+        let panic_factor = (1. + f64::from(drop) / f64::from(tunables::tm_panic_divisor()))
+            .min(percent(tunables::tm_panic_max()));
+
+        self.scale_soft(node_factor * stability_factor * panic_factor);
+    }
+
+    /// Scales the soft limit relative to the base allocation, never beyond the hard limit
+    fn scale_soft(&mut self, factor: f64) {
+        if let (Some(base), Some(hard)) = (self.base_soft, self.hard) {
+            self.soft = Some(((base as f64 * factor) as u64).min(hard));
+        }
+    }
+}
+
+impl Default for TimeManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cozy_chess::Square;
+
+    // Virtual communication lag per move, below the overhead we account for
+    const LAG: u64 = TIME_OVERHEAD / 2;
+
+    // A recorded iteration of the ID loop
+    struct Iteration {
+        best_move: Move,
+        best_move_fraction: f64,
+        score: i32,
+        nodes: u64,
     }
 
-    // The optimum time is used right after a depth is cleared in the ID loop.
-    // Max time is used in the search function as usual for a global stop light.
-    (opt_time as u64, max_time as u64)
+    fn mv(from: Square, to: Square) -> Move {
+        Move {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+
+    // Synthetic trace with an exponentially growing tree, optionally with the
+    // best move flipping every iteration and the score collapsing halfway through.
+    fn trace(stable: bool, score_drop: bool) -> Vec<Iteration> {
+        (0..30)
+            .map(|i| Iteration {
+                best_move: if stable || i % 2 == 0 {
+                    mv(Square::E2, Square::E4)
+                } else {
+                    mv(Square::D2, Square::D4)
+                },
+                best_move_fraction: if stable { 0.9 } else { 0.4 },
+                score: if score_drop && i >= 10 { -150 } else { 30 },
+                nodes: 1 << (i / 2).min(20),
+            })
+            .collect()
+    }
+
+    // Replays a trace through the time manager like the ID loop would, on a
+    // virtual clock of one node per millisecond. Returns the time used.
+    fn simulate(st: SearchType, trace: &[Iteration]) -> u64 {
+        let mut tm = TimeManager::new();
+        tm.set_nodestime(1);
+        tm.start(st);
+
+        // The previous move's search agreed with the start of the trace
+        tm.set_prev_move_score(trace.first().map(|it| it.score));
+
+        let mut nodes = 0;
+        for it in trace {
+            nodes += it.nodes;
+
+            // The search is stopped as soon as the hard limit is reached
+            if tm.hard_limit_reached(nodes) {
+                let SearchType::Time(_, hard) = st else {
+                    unreachable!()
+                };
+                return hard;
+            }
+
+            tm.update(it.best_move, it.best_move_fraction, it.score);
+            if tm.soft_limit_reached(nodes) {
+                break;
+            }
+        }
+
+        nodes
+    }
+
+    #[test]
+    fn limits_within_clock() {
+        for time in [0, 1, 10, 50, 99, 100, 150, 1000, 10_000, 60_000, 3_600_000] {
+            for inc in [0, 10, 100, 1000] {
+                for mtg in [None, Some(1), Some(2), Some(10), Some(40)] {
+                    let (soft, hard) = time_for_move(time, inc, mtg);
+
+                    assert!(soft <= hard, "{time} {inc} {mtg:?}: {soft} > {hard}");
+                    assert!(
+                        hard + TIME_OVERHEAD <= time.max(TIME_OVERHEAD),
+                        "{time} {inc} {mtg:?}: {hard} exceeds the clock"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn never_exceeds_hard_limit() {
+        for (stable, score_drop) in [(true, false), (false, false), (true, true), (false, true)] {
+            for time in [50, 500, 5000, 50_000] {
+                let (soft, hard) = time_for_move(time, 0, None);
+                let used = simulate(SearchType::Time(soft, hard), &trace(stable, score_drop));
+
+                assert!(used <= hard, "{used} > {hard}");
+            }
+        }
+    }
+
+    #[test]
+    fn exact_node_limit() {
+        let mut tm = TimeManager::new();
+        tm.start(SearchType::Nodes(500));
+        assert_eq!(tm.check_interval(), 1);
+        assert!(!tm.hard_limit_reached(499));
+        assert!(tm.hard_limit_reached(500));
+
+        tm.start(SearchType::Depth(5));
+        assert_eq!(tm.check_interval(), CHECK_INTERVAL);
+        assert!(!tm.hard_limit_reached(u64::MAX));
+    }
+
+    #[test]
+    fn usage_curves() {
+        let (soft, hard) = time_for_move(60_000, 0, None);
+        let st = SearchType::Time(soft, hard);
+
+        let stable = simulate(st, &trace(true, false));
+        let unstable = simulate(st, &trace(false, false));
+        let panic = simulate(st, &trace(true, true));
+
+        // A stable best move saves time, instability and score drops spend more of it
+        assert!(stable < unstable, "{stable} >= {unstable}");
+        assert!(stable < panic, "{stable} >= {panic}");
+
+        // ... but never wildly more than the base allocation
+        assert!(unstable <= hard && panic <= hard);
+    }
+
+    #[test]
+    fn games_never_flag() {
+        for (time, inc, mtg) in [
+            (60_000, 0, None),
+            (10_000, 100, None),
+            (1000, 10, None),
+            (300, 5, None),
+            (60_000, 0, Some(40)),
+            (2000, 0, Some(10)),
+        ] {
+            for (stable, score_drop) in [(true, false), (false, true)] {
+                let mut clock = time;
+
+                // Sudden death without increment eventually flags on lag alone,
+                // so we stop at a realistic game length.
+                for played in 0..80 {
+                    let moves_to_go = mtg.map(|m: u8| m - (played % u64::from(m)) as u8);
+                    let (soft, hard) = time_for_move(clock, inc, moves_to_go);
+                    let used = simulate(SearchType::Time(soft, hard), &trace(stable, score_drop));
+
+                    assert!(
+                        used + LAG < clock,
+                        "flagged on move {played}: {used} used with {clock} left ({time}+{inc} {mtg:?})"
+                    );
+                    clock = clock - used - LAG + inc;
+
+                    // Time control reached
+                    if moves_to_go == Some(1) {
+                        clock += time;
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,53 @@
+// trace <file> [depth <d>] [plies <p>] [nodes <n>]
+// Searches the current position to a fixed depth and writes the search tree near the root to a
+// JSON file, for working out offline why a line was pruned or a move never looked at. Nodes
+// more than the given plies from the root are left out, and recording stops after the given
+// number of nodes.
+use super::handler::{reverse_castling_move, UciOutput};
+use crate::api::{Engine, Limits};
+use crate::body::trace::{TraceLimits, DEFAULT_NODES, DEFAULT_PLIES};
+
+const USAGE: &str = "info string usage: trace <file> [depth <d>] [plies <p>] [nodes <n>]";
+
+pub const DEFAULT_DEPTH: usize = 8;
+
+pub fn trace(engine: &mut Engine, out: &UciOutput, words: &[&str]) {
+    let value = |token: &str, default: usize| -> Option<usize> {
+        match words.iter().position(|&x| x == token) {
+            Some(idx) => words.get(idx + 1)?.parse().ok(),
+            None => Some(default),
+        }
+    };
+
+    let (Some(&file), Some(depth), Some(plies), Some(nodes)) = (
+        words.get(1),
+        value("depth", DEFAULT_DEPTH),
+        value("plies", DEFAULT_PLIES),
+        value("nodes", DEFAULT_NODES),
+    ) else {
+        out.send(USAGE);
+        return;
+    };
+
+    engine.set_reporter(None);
+    engine.trace_next_search(TraceLimits { plies, nodes });
+    let result = engine.search(Limits {
+        depth: Some(depth.max(1)),
+        ..Default::default()
+    });
+    let Some(trace) = engine.take_trace() else {
+        return;
+    };
+
+    match std::fs::write(file, trace.to_json()) {
+        Ok(()) => out.send(format_args!(
+            "info string wrote {} nodes{} of a depth {depth} search to {file}, best move {}",
+            trace.nodes.len(),
+            if trace.truncated { " (truncated)" } else { "" },
+            result.map_or("(none)".to_string(), |r| {
+                reverse_castling_move(engine.board(), r.best_move).to_string()
+            }),
+        )),
+        Err(e) => out.send(format_args!("info string could not write {file}: {e}")),
+    }
+}
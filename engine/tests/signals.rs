@@ -0,0 +1,39 @@
+// Termination signals
+// A signal during a search gets the bestmove out before anything else, the next one ends the
+// engine. Signals go to a process of the engine's own, started from the binary.
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+fn signal(engine: &Child) {
+    let status = Command::new("kill")
+        .args(["-TERM", &engine.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn stop_on_signal() {
+    let mut engine = Command::new(env!("CARGO_BIN_EXE_engine"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = engine.stdin.take().unwrap();
+    writeln!(stdin, "uci\nposition startpos\ngo infinite").unwrap();
+    let mut lines = BufReader::new(engine.stdout.take().unwrap()).lines();
+    assert!(lines.any(|line| line.unwrap().starts_with("info depth")));
+
+    signal(&engine);
+    assert!(lines.any(|line| line.unwrap().starts_with("bestmove")));
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(engine.try_wait().unwrap(), None);
+
+    signal(&engine);
+    let status = engine.wait().unwrap();
+    assert_eq!(status.code(), Some(130));
+}
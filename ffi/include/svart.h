@@ -0,0 +1,50 @@
+/*
+ * C interface to the Svart chess engine, built as a shared library with
+ *
+ *     cargo build --release -p svart-ffi
+ *
+ * Functions returning int use 0 for success, 1 when the position has no legal moves and
+ * -1 for errors. Moves are in UCI notation. Only one engine can search at a time.
+ */
+#ifndef SVART_H
+#define SVART_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SvartEngine SvartEngine;
+
+/* Receives each UCI info line of a search, called from the searching thread */
+typedef void (*svart_info_callback)(const char *line, void *user_data);
+
+/* A single threaded engine with a 16 MB hash table at the start position, NULL on failure */
+SvartEngine *svart_create(void);
+void svart_destroy(SvartEngine *engine);
+
+int svart_set_fen(SvartEngine *engine, const char *fen);
+int svart_set_hash(SvartEngine *engine, uint32_t mb);
+int svart_set_threads(SvartEngine *engine, uint32_t threads);
+
+/* Pass NULL to stop receiving info lines */
+int svart_set_info_callback(SvartEngine *engine, svart_info_callback callback, void *user_data);
+
+/*
+ * Blocks until the search is done. Zero leaves a limit unset, with none set the search
+ * runs until svart_stop is called from another thread.
+ */
+int svart_search(SvartEngine *engine, uint32_t depth, uint64_t nodes, uint64_t movetime_ms);
+void svart_stop(void);
+
+/* Results of the last search, the move is written NUL terminated into buf */
+int svart_bestmove(SvartEngine *engine, char *buf, size_t len);
+int svart_score(SvartEngine *engine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
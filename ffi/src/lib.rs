@@ -0,0 +1,266 @@
+// C bindings
+// A thin C ABI over the embedding API, see include/svart.h for the documented interface.
+// No panic may unwind into C, every entry point catches them and reports an error instead.
+// Release builds abort on panic (see the workspace profile), so this only helps debug builds.
+use engine::api::{Engine, IterationReport, Limits, SearchReporter, SearchResult, StopHandle};
+use engine::uci::reporter::info_line;
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+pub type InfoCallback = extern "C" fn(line: *const c_char, user_data: *mut c_void);
+
+pub struct SvartEngine {
+    engine: Engine,
+    result: Option<SearchResult>,
+}
+
+// Hands the UCI info line of every completed iteration to the callback
+struct CallbackReporter {
+    callback: InfoCallback,
+    user_data: UserData,
+}
+
+// The user data pointer is only ever handed back to the callback that came with it
+struct UserData(*mut c_void);
+unsafe impl Send for UserData {}
+
+impl SearchReporter for CallbackReporter {
+    fn depth_completed(&mut self, report: &IterationReport) {
+        // Info lines never contain NUL bytes
+        let line = std::ffi::CString::new(info_line(report)).unwrap();
+        (self.callback)(line.as_ptr(), self.user_data.0);
+    }
+}
+
+const OK: c_int = 0;
+const NO_MOVES: c_int = 1;
+const ERROR: c_int = -1;
+
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+
+    CStr::from_ptr(s).to_str().ok()
+}
+
+#[no_mangle]
+pub extern "C" fn svart_create() -> *mut SvartEngine {
+    guard(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(SvartEngine {
+            engine: Engine::new(),
+            result: None,
+        }))
+    })
+}
+
+/// # Safety
+/// `engine` must come from `svart_create` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn svart_destroy(engine: *mut SvartEngine) {
+    if !engine.is_null() {
+        guard((), || drop(Box::from_raw(engine)));
+    }
+}
+
+/// # Safety
+/// `engine` must come from `svart_create`, `fen` must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn svart_set_fen(engine: *mut SvartEngine, fen: *const c_char) -> c_int {
+    guard(ERROR, || {
+        let (Some(engine), Some(fen)) = (engine.as_mut(), str_arg(fen)) else {
+            return ERROR;
+        };
+
+        match engine.engine.set_position(fen, &[]) {
+            Ok(()) => OK,
+            Err(_) => ERROR,
+        }
+    })
+}
+
+/// # Safety
+/// `engine` must come from `svart_create`.
+#[no_mangle]
+pub unsafe extern "C" fn svart_set_hash(engine: *mut SvartEngine, mb: u32) -> c_int {
+    guard(ERROR, || {
+        let Some(engine) = engine.as_mut() else {
+            return ERROR;
+        };
+
+        match engine.engine.set_hash(mb) {
+            Ok(()) => OK,
+            Err(_) => ERROR,
+        }
+    })
+}
+
+/// # Safety
+/// `engine` must come from `svart_create`.
+#[no_mangle]
+pub unsafe extern "C" fn svart_set_threads(engine: *mut SvartEngine, threads: u32) -> c_int {
+    guard(ERROR, || {
+        let Some(engine) = engine.as_mut() else {
+            return ERROR;
+        };
+
+        match engine.engine.set_threads(threads as usize) {
+            Ok(()) => OK,
+            Err(_) => ERROR,
+        }
+    })
+}
+
+/// # Safety
+/// `engine` must come from `svart_create`. The callback is called from the searching thread
+/// and `user_data` must stay valid for as long as the callback is set.
+#[no_mangle]
+pub unsafe extern "C" fn svart_set_info_callback(
+    engine: *mut SvartEngine,
+    callback: Option<InfoCallback>,
+    user_data: *mut c_void,
+) -> c_int {
+    guard(ERROR, || {
+        let Some(engine) = engine.as_mut() else {
+            return ERROR;
+        };
+
+        let reporter = callback.map(|callback| {
+            Box::new(CallbackReporter {
+                callback,
+                user_data: UserData(user_data),
+            }) as Box<dyn SearchReporter>
+        });
+        engine.engine.set_reporter(reporter);
+
+        OK
+    })
+}
+
+/// # Safety
+/// `engine` must come from `svart_create`.
+#[no_mangle]
+pub unsafe extern "C" fn svart_search(
+    engine: *mut SvartEngine,
+    depth: u32,
+    nodes: u64,
+    movetime: u64,
+) -> c_int {
+    guard(ERROR, || {
+        let Some(engine) = engine.as_mut() else {
+            return ERROR;
+        };
+
+        // Zero leaves a limit unset
+        let limits = Limits {
+            depth: (depth > 0).then_some(depth as usize),
+            nodes: (nodes > 0).then_some(nodes),
+            movetime: (movetime > 0).then_some(movetime),
+            ..Default::default()
+        };
+
+        engine.result = engine.engine.search(limits);
+        if engine.result.is_some() {
+            OK
+        } else {
+            NO_MOVES
+        }
+    })
+}
+
+/// Stops a running search, safe to call from any thread.
+#[no_mangle]
+pub extern "C" fn svart_stop() {
+    guard((), || StopHandle.stop());
+}
+
+/// # Safety
+/// `engine` must come from `svart_create`, `buf` must point to at least `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn svart_bestmove(
+    engine: *mut SvartEngine,
+    buf: *mut c_char,
+    len: usize,
+) -> c_int {
+    guard(ERROR, || {
+        let Some(engine) = engine.as_mut() else {
+            return ERROR;
+        };
+        let Some(result) = &engine.result else {
+            return NO_MOVES;
+        };
+
+        let mv = result.best_move.to_string();
+        if buf.is_null() || len <= mv.len() {
+            return ERROR;
+        }
+
+        std::ptr::copy_nonoverlapping(mv.as_ptr().cast::<c_char>(), buf, mv.len());
+        *buf.add(mv.len()) = 0;
+        OK
+    })
+}
+
+/// # Safety
+/// `engine` must come from `svart_create`.
+#[no_mangle]
+pub unsafe extern "C" fn svart_score(engine: *mut SvartEngine) -> c_int {
+    guard(0, || {
+        engine
+            .as_mut()
+            .and_then(|engine| engine.result.as_ref())
+            .map_or(0, |result| result.score)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn count_lines(_line: *const c_char, user_data: *mut c_void) {
+        unsafe { *user_data.cast::<u32>() += 1 };
+    }
+
+    #[test]
+    fn c_api() {
+        unsafe {
+            let engine = svart_create();
+            assert!(!engine.is_null());
+
+            let fen = c"6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1";
+            assert_eq!(svart_set_fen(engine, fen.as_ptr()), OK);
+            assert_eq!(svart_set_fen(engine, c"8/8 w".as_ptr()), ERROR);
+            assert_eq!(svart_set_fen(std::ptr::null_mut(), fen.as_ptr()), ERROR);
+
+            let mut lines = 0u32;
+            let user_data = std::ptr::addr_of_mut!(lines).cast();
+            assert_eq!(
+                svart_set_info_callback(engine, Some(count_lines), user_data),
+                OK
+            );
+            assert_eq!(svart_search(engine, 3, 0, 0), OK);
+            assert_eq!(lines, 3);
+
+            let mut buf = [0 as c_char; 6];
+            assert_eq!(svart_bestmove(engine, buf.as_mut_ptr(), 2), ERROR);
+            assert_eq!(svart_bestmove(engine, buf.as_mut_ptr(), buf.len()), OK);
+            assert_eq!(CStr::from_ptr(buf.as_ptr()).to_str(), Ok("a1a8"));
+            assert!(svart_score(engine) > 0);
+
+            let mated = c"R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 1 1";
+            assert_eq!(svart_set_fen(engine, mated.as_ptr()), OK);
+            assert_eq!(svart_search(engine, 1, 0, 0), NO_MOVES);
+            assert_eq!(
+                svart_bestmove(engine, buf.as_mut_ptr(), buf.len()),
+                NO_MOVES
+            );
+
+            svart_destroy(engine);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// WebAssembly bindings
+// A wasm-bindgen API over the embedding API, for analysis boards running the engine client-side.
+// `make wasm` builds the module and the JS glue into wasm/pkg.
+//
+//     const engine = new Svart();
+//     engine.setPosition("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", ["e2e4"]);
+//     engine.onInfo((line) => console.log(line));
+//     const result = engine.search(12);
+//
+// Searches block the calling thread, so run the engine in a Web Worker. There are no other
+// threads to stop it from, instead the search polls the function given to setStopPoll.
+use engine::api::{Engine, IterationReport, Limits, SearchReporter, START_FEN};
+use engine::uci::reporter::info_line;
+
+use js_sys::Function;
+use wasm_bindgen::prelude::*;
+
+// wasm32-unknown-unknown is single threaded, JS values never actually cross threads
+struct JsCallback(Function);
+unsafe impl Send for JsCallback {}
+
+impl SearchReporter for JsCallback {
+    fn depth_completed(&mut self, report: &IterationReport) {
+        // Exceptions thrown by the callback are its own business
+        let line = JsValue::from_str(&info_line(report));
+        let _ = self.0.call1(&JsValue::NULL, &line);
+    }
+}
+
+#[wasm_bindgen]
+pub struct Svart {
+    engine: Engine,
+}
+
+/// Outcome of the last completed iteration of a search
+#[wasm_bindgen(getter_with_clone)]
+pub struct SearchOutput {
+    #[wasm_bindgen(js_name = bestMove)]
+    pub best_move: String,
+    pub score: i32,
+    pub depth: u32,
+    pub seldepth: u32,
+    pub nodes: f64,
+    pub pv: Vec<String>,
+}
+
+#[wasm_bindgen]
+impl Svart {
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            engine: Engine::new(),
+        }
+    }
+
+    /// Sets the position from a FEN and the UCI moves played from it
+    #[wasm_bindgen(js_name = setPosition)]
+    pub fn set_position(&mut self, fen: &str, moves: Vec<String>) -> Result<(), JsError> {
+        let moves: Vec<&str> = moves.iter().map(String::as_str).collect();
+        self.engine
+            .set_position(fen, &moves)
+            .map_err(|e| JsError::new(&e))
+    }
+
+    #[wasm_bindgen(js_name = newGame)]
+    pub fn new_game(&mut self) {
+        self.engine.new_game();
+    }
+
+    /// Resizes and clears the hash table, in megabytes
+    #[wasm_bindgen(js_name = setHash)]
+    pub fn set_hash(&mut self, mb: u32) -> Result<(), JsError> {
+        self.engine.set_hash(mb).map_err(|e| JsError::new(&e))
+    }
+
+    #[must_use]
+    pub fn fen(&self) -> String {
+        self.engine.board().to_string()
+    }
+
+    /// Static evaluation of the current position, from the side to move's perspective
+    #[must_use]
+    pub fn evaluate(&mut self) -> i32 {
+        self.engine.evaluate()
+    }
+
+    /// Called with every UCI info line of a search, pass nothing to stop receiving them
+    #[wasm_bindgen(js_name = onInfo)]
+    pub fn on_info(&mut self, callback: Option<Function>) {
+        let reporter = callback.map(|callback| Box::new(JsCallback(callback)) as Box<_>);
+        self.engine.set_reporter(reporter);
+    }
+
+    /// Called every few thousand nodes during a search, which stops once it returns true.
+    /// Reading an Atomics flag on a SharedArrayBuffer lets the page stop a worker's search.
+    #[wasm_bindgen(js_name = setStopPoll)]
+    pub fn set_stop_poll(&mut self, poll: Option<Function>) {
+        let poll = poll.map(|poll| {
+            let poll = JsCallback(poll);
+            Box::new(move || {
+                poll.0
+                    .call0(&JsValue::NULL)
+                    .is_ok_and(|stop| stop.is_truthy())
+            }) as Box<_>
+        });
+        self.engine.set_stop_poll(poll);
+    }
+
+    /// Searches the current position with the limits given, all of them optional. With none
+    /// set the search only ends through the stop poll. Returns nothing without legal moves.
+    pub fn search(
+        &mut self,
+        depth: Option<u32>,
+        nodes: Option<f64>,
+        movetime: Option<f64>,
+    ) -> Option<SearchOutput> {
+        let limits = Limits {
+            depth: depth.map(|depth| depth as usize),
+            nodes: nodes.map(|nodes| nodes as u64),
+            movetime: movetime.map(|movetime| movetime as u64),
+            ..Default::default()
+        };
+
+        self.engine.search(limits).map(|result| SearchOutput {
+            best_move: result.best_move.to_string(),
+            score: result.score,
+            depth: result.depth as u32,
+            seldepth: result.seldepth as u32,
+            nodes: result.nodes as f64,
+            pv: result.pv.iter().map(ToString::to_string).collect(),
+        })
+    }
+}
+
+/// The standard starting position
+#[wasm_bindgen(js_name = startFen)]
+#[must_use]
+pub fn start_fen() -> String {
+    START_FEN.to_string()
+}